@@ -0,0 +1,136 @@
+//! Receive the CSI stream of `embassy_wifi` and print it as CSV
+//!
+//! Usage: `csi_decode [address] [--raw]`, the address defaults to
+//! `0.0.0.0:5500`. Every line has the header fields followed by the
+//! amplitude of each subcarrier, or with `--raw` the `real:imaginary`
+//! pairs.
+
+use std::net::UdpSocket;
+use std::process::ExitCode;
+
+#[allow(dead_code)]
+mod format;
+
+use format::{FLAG_FIRST_WORD_INVALID, FLAG_TRUNCATED, Header};
+
+const DEFAULT_ADDRESS: &str = "0.0.0.0:5500";
+
+fn main() -> ExitCode {
+    let mut address = DEFAULT_ADDRESS.to_string();
+    let mut raw = false;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--raw" => raw = true,
+            "-h" | "--help" => {
+                eprintln!("usage: csi_decode [address] [--raw]");
+                return ExitCode::SUCCESS;
+            }
+            _ => address = arg,
+        }
+    }
+
+    let socket = match UdpSocket::bind(&address) {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("csi_decode: can't bind {}: {}", address, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    eprintln!("csi_decode: listening on {}", address);
+
+    println!("seq,lost,dropped,time,timestamp,mac,rssi,channel,flags,subcarriers");
+    let mut buf = [0; 2048];
+    let mut next_seq = None;
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) => {
+                eprintln!("csi_decode: receive error: {}", e);
+                return ExitCode::FAILURE;
+            }
+        };
+
+        let (header, data) = match format::decode(&buf[..len]) {
+            Ok(frame) => frame,
+            Err(e) => {
+                eprintln!("csi_decode: bad datagram from {}: {:?}", from, e);
+                continue;
+            }
+        };
+
+        // Datagrams lost on the way, as opposed to frames the device dropped.
+        let lost = next_seq.map_or(0, |next: u32| header.seq.wrapping_sub(next));
+        next_seq = Some(header.seq.wrapping_add(1));
+
+        println!("{}", line(&header, data, lost, raw));
+    }
+}
+
+fn line(header: &Header, data: &[u8], lost: u32, raw: bool) -> String {
+    // The first subcarrier is garbage if the first word is invalid.
+    let skip = if header.flags & FLAG_FIRST_WORD_INVALID != 0 {
+        2
+    } else {
+        0
+    };
+    let subcarriers: Vec<String> = format::subcarriers(data)
+        .skip(skip)
+        .map(|(real, imaginary)| {
+            if raw {
+                format!("{}:{}", real, imaginary)
+            } else {
+                let amplitude = (real as f32).hypot(imaginary as f32);
+                format!("{:.1}", amplitude)
+            }
+        })
+        .collect();
+
+    let mac: Vec<String> = header.mac.iter().map(|b| format!("{:02x}", b)).collect();
+    let mut flags = String::new();
+    if header.flags & FLAG_FIRST_WORD_INVALID != 0 {
+        flags.push('i');
+    }
+    if header.flags & FLAG_TRUNCATED != 0 {
+        flags.push('t');
+    }
+
+    format!(
+        "{},{},{},{},{},{},{},{},{},{}",
+        header.seq,
+        lost,
+        header.dropped,
+        header.time,
+        header.timestamp,
+        mac.join(":"),
+        header.rssi,
+        header.channel,
+        flags,
+        subcarriers.join(" ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line() {
+        let header = Header {
+            flags: FLAG_FIRST_WORD_INVALID,
+            seq: 3,
+            rssi: -60,
+            channel: 11,
+            mac: [0xaa, 0xbb, 0xcc, 0, 1, 2],
+            ..Default::default()
+        };
+        let data = [0, 0, 0, 0, 4, 3, 0xfd, 0];
+        assert_eq!(
+            line(&header, &data, 1, false),
+            "3,1,0,0,0,aa:bb:cc:00:01:02,-60,11,i,5.0 3.0"
+        );
+        assert_eq!(
+            line(&header, &data, 0, true),
+            "3,0,0,0,0,aa:bb:cc:00:01:02,-60,11,i,3:4 0:-3"
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! Battery Service (0x180F) values
+//!
+//! Battery Level (0x2A19) is a `uint8` percentage. It is estimated from the
+//! voltage of a single Li-ion cell at rest, interpolating linearly in
+//! [`DISCHARGE_CURVE`].
+
+pub const SERVICE: u16 = 0x180F;
+
+/// Millivolts of the cell and the charge left, from full to empty.
+pub const DISCHARGE_CURVE: &[(u16, u8)] = &[
+    (4200, 100),
+    (4100, 90),
+    (4000, 79),
+    (3900, 66),
+    (3800, 52),
+    (3700, 38),
+    (3600, 22),
+    (3500, 10),
+    (3400, 4),
+    (3300, 0),
+];
+
+/// The level in percent of a cell at `millivolts`.
+pub fn level(millivolts: u16) -> u8 {
+    let mut upper = DISCHARGE_CURVE[0];
+    if millivolts >= upper.0 {
+        return upper.1;
+    }
+    for &lower in &DISCHARGE_CURVE[1..] {
+        if millivolts >= lower.0 {
+            let (high_mv, high) = upper;
+            let (low_mv, low) = lower;
+            let span = (high - low) as u32 * (millivolts - low_mv) as u32;
+            return low + (span / (high_mv - low_mv) as u32) as u8;
+        }
+        upper = lower;
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level() {
+        assert_eq!(level(4300), 100);
+        assert_eq!(level(4200), 100);
+        assert_eq!(level(4150), 95);
+        assert_eq!(level(3750), 45);
+        assert_eq!(level(3700), 38);
+        assert_eq!(level(3300), 0);
+        assert_eq!(level(3000), 0);
+        assert_eq!(level(0), 0);
+
+        let mut last = 0;
+        for millivolts in (3000..4300).step_by(10) {
+            let level = level(millivolts);
+            assert!(level >= last);
+            last = level;
+        }
+    }
+}
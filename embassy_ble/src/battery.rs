@@ -0,0 +1,105 @@
+//! Battery level from an ADC reading
+//!
+//! The cell is connected to GPIO3 through a divider of two equal
+//! resistors, which keeps a full cell inside the range of the ADC at 11 dB
+//! attenuation. The `battery` task averages [`SAMPLES`] calibrated readings
+//! every [`INTERVAL`], leaving out failed ones, and keeps the level for the
+//! GATT read callback. Without any reading the level stays as it was. When
+//! the level changes, [`LEVEL_CHANGED`] wakes the notifier. The first
+//! measurement is taken when the task starts, wait for it with
+//! [`measured`].
+
+use core::cell::Cell;
+
+use embassy_futures::yield_now;
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Ticker};
+use esp_hal::{
+    analog::adc::{Adc, AdcCalCurve, AdcConfig, Attenuation},
+    peripherals::{ADC1, GPIO3},
+};
+use esp_println::println;
+
+use crate::bas;
+
+pub const INTERVAL: Duration = Duration::from_secs(60);
+
+/// Readings averaged per measurement.
+pub const SAMPLES: u32 = 16;
+
+/// Cell voltage per volt at the pin.
+pub const DIVIDER: u32 = 2;
+
+static LEVEL: Mutex<CriticalSectionRawMutex, Cell<Option<u8>>> = Mutex::new(Cell::new(None));
+
+/// Signaled when the battery level changed.
+pub static LEVEL_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+static MEASURED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// In percent, 0 until the first measurement.
+pub fn level() -> u8 {
+    LEVEL.lock(Cell::get).unwrap_or(0)
+}
+
+/// Wait until the level was measured once.
+pub async fn measured() {
+    if LEVEL.lock(Cell::get).is_none() {
+        MEASURED.wait().await;
+    }
+}
+
+fn update(level: u8) {
+    let last = LEVEL.lock(|last| last.replace(Some(level)));
+    if last.is_none() {
+        MEASURED.signal(());
+    }
+    if last != Some(level) {
+        LEVEL_CHANGED.signal(());
+    }
+}
+
+#[embassy_executor::task]
+pub async fn battery(adc: ADC1<'static>, pin: GPIO3<'static>) {
+    let mut config = AdcConfig::new();
+    let mut pin =
+        config.enable_pin_with_cal::<_, AdcCalCurve<ADC1<'static>>>(pin, Attenuation::_11dB);
+    let mut adc = Adc::new(adc, config);
+    let mut ticker = Ticker::every(INTERVAL);
+
+    loop {
+        let mut sum = 0;
+        let mut count = 0;
+        for _ in 0..SAMPLES {
+            // Calibrated readings are in millivolts. Let the other tasks run
+            // while the ADC converts.
+            let reading = loop {
+                match adc.read_oneshot(&mut pin) {
+                    Ok(millivolts) => break Some(millivolts),
+                    Err(nb::Error::WouldBlock) => yield_now().await,
+                    Err(nb::Error::Other(_)) => break None,
+                }
+            };
+            if let Some(millivolts) = reading {
+                sum += millivolts as u32;
+                count += 1;
+            }
+        }
+        if count == 0 {
+            println!("battery: no reading");
+            // Don't hold up advertising, the level reads 0 until a
+            // measurement succeeds.
+            MEASURED.signal(());
+        } else {
+            let millivolts = sum / count * DIVIDER;
+            let level = bas::level(millivolts.min(u16::MAX as u32) as u16);
+            println!("battery: {} mV, {} %", millivolts, level);
+            update(level);
+        }
+
+        ticker.next().await;
+    }
+}
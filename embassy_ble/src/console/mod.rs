@@ -0,0 +1,302 @@
+//! A serial console over the Nordic UART Service
+//!
+//! Terminal apps like nRF Toolbox or Serial Bluetooth Terminal write lines
+//! to the RX characteristic and get the output as notifications of the TX
+//! characteristic, in chunks of [`CHUNK_LEN`] bytes. The `console` task runs
+//! each line as a command from [`REGISTRY`]. The command tables and the line
+//! input are copies of the ones of the shell of `embassy_wifi`. With the
+//! `log` feature, `log on` mirrors the `log` records to the console until
+//! the client disconnects.
+//!
+//! Any central in range can connect and write to RX, so `reboot` needs
+//! [`TOKEN`] as its argument and is off until it is set.
+//!
+//! `outbox` doesn't depend on esp-hal or bleps and is tested on the host.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::fmt::Write as _;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    channel::Channel,
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Timer};
+use esp_println::println;
+#[cfg(feature = "log")]
+use log::{LevelFilter, Log, Metadata};
+
+use crate::{provisioning, sensor};
+
+pub mod command;
+pub mod line;
+pub mod outbox;
+
+pub use command::{Command, Error, Handler, Registry, Reply, done, token_eq};
+pub use line::LineReader;
+pub use outbox::Outbox;
+
+/// Most bytes of a notification. bleps answers every MTU exchange with the
+/// default ATT MTU of 23 and doesn't tell what a client asked for, 3 bytes
+/// go to the opcode and the handle.
+///
+/// TODO: chunk by the negotiated MTU once bleps takes a larger MTU and
+/// reports the exchange, until then the console doesn't do MTU-aware
+/// chunking.
+pub const CHUNK_LEN: usize = 20;
+
+/// Shared secret for `reboot`, `None` disables it.
+pub const TOKEN: Option<&str> = None;
+
+const MAX_LINE_LEN: usize = 128;
+/// Lines waiting for the `console` task.
+const MAX_LINES: usize = 4;
+/// Output waiting to be notified, the oldest is dropped.
+const OUTBOX_LEN: usize = 1024;
+/// Before a reboot, to notify the output.
+const REBOOT_DELAY: Duration = Duration::from_millis(500);
+/// After a wrong token, to slow down guessing.
+const DENIED_DELAY: Duration = Duration::from_secs(2);
+
+#[cfg(feature = "log")]
+const RESET: &str = "\u{001B}[0m";
+
+pub static REGISTRY: Registry<Context> = Registry::new(&[COMMANDS, LOG_COMMANDS]);
+
+pub static COMMANDS: &[Command<Context>] = &[
+    Command::new("status", "", "uptime, Wi-Fi and sensor", status),
+    Command::new("heap", "", "heap usage", heap),
+    Command::new("reboot", "<token>", "restart the device", reboot),
+];
+
+#[cfg(feature = "log")]
+static LOG_COMMANDS: &[Command<Context>] = &[Command::new(
+    "log",
+    "[on|off]",
+    "show or change mirroring the log",
+    log_command,
+)];
+#[cfg(not(feature = "log"))]
+static LOG_COMMANDS: &[Command<Context>] = &[];
+
+static READER: Mutex<CriticalSectionRawMutex, RefCell<LineReader<MAX_LINE_LEN>>> =
+    Mutex::new(RefCell::new(LineReader::new()));
+static LINES: Channel<
+    CriticalSectionRawMutex,
+    Result<heapless::String<MAX_LINE_LEN>, line::Error>,
+    MAX_LINES,
+> = Channel::new();
+static OUTBOX: Mutex<CriticalSectionRawMutex, RefCell<Outbox<OUTBOX_LEN>>> =
+    Mutex::new(RefCell::new(Outbox::new()));
+
+/// Signaled when there is output, for the notifier.
+pub static OUTPUT: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Whether `log` records are copied to the console.
+static MIRROR: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "log")]
+static LOGGER: Logger = Logger;
+
+/// What commands get to see.
+pub struct Context {
+    reboot: Cell<bool>,
+}
+
+impl Context {
+    /// Restart the device after the output was sent.
+    pub fn reboot(&self) {
+        self.reboot.set(true);
+    }
+}
+
+/// The RX write callback, bytes of one or more lines.
+pub fn write_rx(_offset: usize, data: &[u8]) {
+    for &byte in data {
+        let line = READER.lock(|reader| {
+            let mut reader = reader.borrow_mut();
+            reader.push(byte).then(|| reader.line())
+        });
+        if let Some(line) = line {
+            if LINES.try_send(line).is_err() {
+                println!("console: busy, line dropped");
+            }
+        }
+    }
+}
+
+/// Queue `text` for the TX characteristic, lines end with `\n`.
+pub fn print(text: &str) {
+    OUTBOX.lock(|outbox| outbox.borrow_mut().write(text.as_bytes()));
+    OUTPUT.signal(());
+}
+
+/// The next notification, if there is output.
+pub fn next_chunk() -> Option<Vec<u8>> {
+    let chunk = OUTBOX.lock(|outbox| outbox.borrow_mut().take(CHUNK_LEN));
+    (!chunk.is_empty()).then_some(chunk)
+}
+
+/// Forget the partial line and the output, and stop mirroring the log.
+pub fn disconnected() {
+    READER.lock(|reader| reader.borrow_mut().clear());
+    OUTBOX.lock(|outbox| outbox.borrow_mut().clear());
+    MIRROR.store(false, Ordering::Relaxed);
+}
+
+#[embassy_executor::task]
+pub async fn console() {
+    let context = Context {
+        reboot: Cell::new(false),
+    };
+    let mut out = String::new();
+
+    loop {
+        let line = LINES.receive().await;
+        out.clear();
+        match line {
+            Ok(line) => match REGISTRY.run(&context, &line, &mut out).await {
+                Ok(()) => {}
+                Err(Error::UnknownCommand) => out.push_str("unknown command, try help\n"),
+                Err(Error::TooManyWords) => out.push_str("too many words\n"),
+                Err(Error::Usage) => REGISTRY.usage(&line, &mut out),
+                Err(Error::Failed(reason)) => {
+                    writeln!(out, "error: {}", reason).ok();
+                }
+            },
+            Err(line::Error::TooLong) => out.push_str("line too long\n"),
+            Err(line::Error::InvalidUtf8) => out.push_str("invalid UTF-8\n"),
+        }
+        print(&out);
+
+        if context.reboot.get() {
+            println!("console: rebooting");
+            Timer::after(REBOOT_DELAY).await;
+            esp_hal::system::software_reset();
+        }
+    }
+}
+
+fn status<'a>(_: &'a Context, args: &'a [&'a str], out: &'a mut String) -> Reply<'a> {
+    if !args.is_empty() {
+        return done(Err(Error::Usage));
+    }
+    let wifi = provisioning::status();
+    writeln!(out, "uptime      {} s", Instant::now().as_secs()).ok();
+    writeln!(out, "wifi        {:?} (reason {})", wifi.state, wifi.reason).ok();
+    match sensor::temperature() {
+        Some(t) => {
+            let sign = if t < 0 { "-" } else { "" };
+            let t = t.unsigned_abs();
+            writeln!(out, "temperature {}{}.{:02} C", sign, t / 100, t % 100).ok()
+        }
+        None => writeln!(out, "temperature unknown").ok(),
+    };
+    match sensor::humidity() {
+        Some(h) => writeln!(out, "humidity    {}.{:02} %", h / 100, h % 100).ok(),
+        None => writeln!(out, "humidity    unknown").ok(),
+    };
+    done(Ok(()))
+}
+
+fn heap<'a>(_: &'a Context, args: &'a [&'a str], out: &'a mut String) -> Reply<'a> {
+    if !args.is_empty() {
+        return done(Err(Error::Usage));
+    }
+    writeln!(
+        out,
+        "used {} free {}",
+        esp_alloc::HEAP.used(),
+        esp_alloc::HEAP.free()
+    )
+    .ok();
+    done(Ok(()))
+}
+
+fn reboot<'a>(context: &'a Context, args: &'a [&'a str], out: &'a mut String) -> Reply<'a> {
+    let [given] = args else {
+        return done(Err(Error::Usage));
+    };
+    let Some(token) = TOKEN else {
+        return done(Err(Error::Failed("set console::TOKEN to enable")));
+    };
+    if !token_eq(token, given) {
+        return Box::pin(async {
+            Timer::after(DENIED_DELAY).await;
+            Err(Error::Failed("denied"))
+        });
+    }
+    out.push_str("rebooting\n");
+    context.reboot();
+    done(Ok(()))
+}
+
+/// Install the logger, which prints like `esp_println`'s logger does and
+/// mirrors to the console after `log on`.
+#[cfg(feature = "log")]
+pub fn init_logger(level: LevelFilter) {
+    match log::set_logger(&LOGGER) {
+        Ok(()) => log::set_max_level(level),
+        Err(_) => println!("console: a logger is already installed"),
+    }
+}
+
+#[cfg(feature = "log")]
+fn log_command<'a>(_: &'a Context, args: &'a [&'a str], out: &'a mut String) -> Reply<'a> {
+    match args {
+        [] => {}
+        ["on"] => MIRROR.store(true, Ordering::Relaxed),
+        ["off"] => MIRROR.store(false, Ordering::Relaxed),
+        _ => return done(Err(Error::Usage)),
+    }
+    let mirror = MIRROR.load(Ordering::Relaxed);
+    writeln!(out, "log {}", if mirror { "on" } else { "off" }).ok();
+    done(Ok(()))
+}
+
+/// Writes mirrored records to the console output.
+#[cfg(feature = "log")]
+struct Mirror;
+
+#[cfg(feature = "log")]
+impl core::fmt::Write for Mirror {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        print(s);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "log")]
+struct Logger;
+
+#[cfg(feature = "log")]
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let level = record.level();
+        let color = match level {
+            log::Level::Error => "\u{001B}[31m",
+            log::Level::Warn => "\u{001B}[33m",
+            log::Level::Info => "\u{001B}[32m",
+            log::Level::Debug => "\u{001B}[34m",
+            log::Level::Trace => "\u{001B}[36m",
+        };
+        println!("{}{} - {}{}", color, level, record.args(), RESET);
+
+        if MIRROR.load(Ordering::Relaxed) {
+            writeln!(Mirror, "{} - {}", level, record.args()).ok();
+        }
+    }
+
+    fn flush(&self) {}
+}
@@ -0,0 +1,99 @@
+//! Output waiting to be notified on the TX characteristic
+//!
+//! Output is taken in chunks that fit a notification, ending after the last
+//! line end in the chunk if there is one, so terminal apps that show every
+//! notification on its own don't break lines in the middle.
+
+use alloc::vec::Vec;
+
+use heapless::Deque;
+
+/// Up to `N` bytes of output, when full the oldest bytes are dropped.
+pub struct Outbox<const N: usize> {
+    bytes: Deque<u8, N>,
+    dropped: usize,
+}
+
+impl<const N: usize> Default for Outbox<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Outbox<N> {
+    pub const fn new() -> Self {
+        Self {
+            bytes: Deque::new(),
+            dropped: 0,
+        }
+    }
+
+    pub fn write(&mut self, data: &[u8]) {
+        for &byte in data {
+            if self.bytes.is_full() {
+                self.bytes.pop_front();
+                self.dropped += 1;
+            }
+            self.bytes.push_back(byte).ok();
+        }
+    }
+
+    /// The next chunk of at most `max_len` bytes, empty if there is nothing
+    /// to send.
+    pub fn take(&mut self, max_len: usize) -> Vec<u8> {
+        let len = self.bytes.len().min(max_len);
+        let len = match self.bytes.iter().take(len).rposition(|&byte| byte == b'\n') {
+            Some(end) if len < self.bytes.len() => end + 1,
+            _ => len,
+        };
+        (0..len).filter_map(|_| self.bytes.pop_front()).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Bytes dropped because the outbox was full, since it was created.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take() {
+        let mut outbox = Outbox::<64>::new();
+        assert!(outbox.take(20).is_empty());
+
+        outbox.write(b"used 1024\nfree 2048\n");
+        // Ends after the line end that fits.
+        assert_eq!(outbox.take(12), b"used 1024\n");
+        // The rest fits, including its line end.
+        assert_eq!(outbox.take(12), b"free 2048\n");
+        assert!(outbox.is_empty());
+
+        // A line longer than a chunk is split.
+        outbox.write(b"a long line\n");
+        assert_eq!(outbox.take(4), b"a lo");
+        assert_eq!(outbox.take(20), b"ng line\n");
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut outbox = Outbox::<4>::new();
+        outbox.write(b"abcdef");
+        assert_eq!(outbox.dropped(), 2);
+        assert_eq!(outbox.take(20), b"cdef");
+
+        outbox.write(b"ab");
+        outbox.clear();
+        assert!(outbox.is_empty());
+    }
+}
@@ -0,0 +1,42 @@
+//! Device Information Service (0x180A) values
+//!
+//! All of the characteristics are UTF-8 strings without a terminator. The
+//! model number is the chip, the serial number the base MAC address from
+//! the eFuses, so fleet tools can tell devices apart without a custom
+//! service.
+
+use core::fmt::Write;
+
+pub const SERVICE: u16 = 0x180A;
+
+pub const MANUFACTURER: &str = "Espressif";
+
+/// `git describe` of the checkout the firmware was built from, see
+/// `build.rs`.
+pub const FIRMWARE: &str = match option_env!("FIRMWARE_VERSION") {
+    Some(version) => version,
+    None => "unknown",
+};
+
+/// The MAC address as 12 upper-case hex digits, e.g. `A0B1C2D3E4F5`.
+pub fn serial_number(mac: [u8; 6]) -> heapless::String<12> {
+    let mut serial = heapless::String::new();
+    for byte in mac {
+        write!(serial, "{:02X}", byte).ok();
+    }
+    serial
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serial_number() {
+        assert_eq!(
+            serial_number([0xa0, 0xb1, 0xc2, 0xd3, 0xe4, 0xf5]),
+            "A0B1C2D3E4F5"
+        );
+        assert_eq!(serial_number([0; 6]), "000000000000");
+    }
+}
@@ -0,0 +1,102 @@
+//! Environmental Sensing Service (0x181A) values
+//!
+//! The characteristics are encoded as the GATT Specification Supplement
+//! says: Temperature (0x2A6E) is a little-endian `sint16` in 0.01 °C and
+//! Humidity (0x2A6F) a little-endian `uint16` in 0.01 %, each with a value
+//! for "not known". Every characteristic has an ES Measurement descriptor
+//! (0x290C) that tells how the value is sampled.
+
+pub const SERVICE: u16 = 0x181A;
+
+/// Temperature while no measurement is known.
+pub const TEMPERATURE_UNKNOWN: i16 = i16::MIN;
+/// Humidity while no measurement is known.
+pub const HUMIDITY_UNKNOWN: u16 = 0xFFFF;
+
+/// Sampling functions of [`EsMeasurement`], the ones used here.
+pub mod sampling {
+    pub const INSTANTANEOUS: u8 = 0x01;
+}
+
+/// Applications of [`EsMeasurement`], the ones used here.
+pub mod application {
+    pub const AIR: u8 = 0x01;
+}
+
+/// Encode a temperature in 0.01 °C, clamped to the range of the
+/// characteristic.
+pub fn temperature(centi_celsius: Option<i32>) -> [u8; 2] {
+    let value = match centi_celsius {
+        Some(value) => value.clamp(TEMPERATURE_UNKNOWN as i32 + 1, i16::MAX as i32) as i16,
+        None => TEMPERATURE_UNKNOWN,
+    };
+    value.to_le_bytes()
+}
+
+/// Encode a relative humidity in 0.01 %, clamped to 100 %.
+pub fn humidity(centi_percent: Option<u16>) -> [u8; 2] {
+    centi_percent
+        .map_or(HUMIDITY_UNKNOWN, |value| value.min(10000))
+        .to_le_bytes()
+}
+
+/// The ES Measurement descriptor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EsMeasurement {
+    /// One of [`sampling`]
+    pub sampling: u8,
+    /// Time the value is sampled over in seconds, 0 if not in use
+    pub period_secs: u32,
+    /// Time between updates of the value in seconds, 0 if not in use
+    pub update_interval_secs: u32,
+    /// One of [`application`]
+    pub application: u8,
+    /// In the resolution of the value, 0xFF if not known
+    pub uncertainty: u8,
+}
+
+impl EsMeasurement {
+    pub fn encode(&self) -> [u8; 11] {
+        let mut out = [0; 11];
+        // Flags, all reserved
+        out[0..2].copy_from_slice(&0u16.to_le_bytes());
+        out[2] = self.sampling;
+        out[3..6].copy_from_slice(&self.period_secs.to_le_bytes()[..3]);
+        out[6..9].copy_from_slice(&self.update_interval_secs.to_le_bytes()[..3]);
+        out[9] = self.application;
+        out[10] = self.uncertainty;
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values() {
+        assert_eq!(temperature(Some(2345)), [0x29, 0x09]);
+        assert_eq!(temperature(Some(-4500)), (-4500i16).to_le_bytes());
+        assert_eq!(temperature(Some(i32::MIN)), [0x01, 0x80]);
+        assert_eq!(temperature(None), [0x00, 0x80]);
+
+        assert_eq!(humidity(Some(4567)), [0xD7, 0x11]);
+        assert_eq!(humidity(Some(10001)), [0x10, 0x27]);
+        assert_eq!(humidity(None), [0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_es_measurement() {
+        let descriptor = EsMeasurement {
+            sampling: sampling::INSTANTANEOUS,
+            period_secs: 0,
+            update_interval_secs: 0x012345,
+            application: application::AIR,
+            uncertainty: 20,
+        };
+        assert_eq!(
+            descriptor.encode(),
+            [0, 0, 0x01, 0, 0, 0, 0x45, 0x23, 0x01, 0x01, 20]
+        );
+    }
+}
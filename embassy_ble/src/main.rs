@@ -9,21 +9,28 @@
 
 extern crate alloc;
 
+use alloc::vec::Vec;
+
 use core::cell::RefCell;
 use core::ptr::addr_of_mut;
 
 use esp_hal::{
+    aes::Aes,
     clock::CpuClock,
+    efuse::Efuse,
     gpio::{Input, InputConfig, Level, Output, OutputConfig, Pull},
+    i2c::master::{BusTimeout, Config as I2cConfig, I2c},
     rng::Rng,
-    time,
+    time::{self, Rate},
     timer::timg::TimerGroup,
 };
 
 use esp_backtrace as _;
 use esp_println::println;
+use esp_storage::FlashStorage;
 
 use embassy_executor::Spawner;
+use embassy_futures::select::{Either, Either3, Either4, select, select3, select4};
 use embassy_time::{Duration, Timer};
 
 use esp_wifi::{EspWifiController, ble::controller::BleConnector, init};
@@ -34,10 +41,39 @@ use bleps::{
     },
     async_attribute_server::AttributeServer,
     asynch::Ble,
-    attribute_server::NotificationData,
+    attribute_server::{NotificationData, WorkResult},
     gatt,
 };
 
+use provisioning::{HwAes, Provisioning};
+use subscriptions::{Cccd, Subscriptions};
+
+pub mod bas;
+pub mod battery;
+pub mod console;
+pub mod dis;
+pub mod ess;
+pub mod provisioning;
+pub mod sensor;
+// A copy of the driver of the i2c-sht31 example, only the async
+// measurement is used.
+#[allow(dead_code)]
+pub mod sht3x;
+pub mod subscriptions;
+pub mod value;
+pub mod wifi;
+
+// Copies of the settings of embassy_wifi, which keep the provisioned access
+// point, and what they need. Only the Wi-Fi part is used here.
+#[allow(dead_code)]
+pub mod settings;
+#[allow(dead_code)]
+pub mod ota {
+    pub mod partition;
+}
+#[cfg(test)]
+pub mod mem_flash;
+
 macro_rules! mk_static {
     ($t:ty,$val:expr) => {{
         static STATIC_CELL: static_cell::StaticCell<$t> = static_cell::StaticCell::new();
@@ -48,7 +84,7 @@ macro_rules! mk_static {
 }
 
 fn init_heap() {
-    const HEAP_SIZE: usize = 64 * 1024;
+    const HEAP_SIZE: usize = 96 * 1024;
     static mut HEAP: core::mem::MaybeUninit<[u8; HEAP_SIZE]> = core::mem::MaybeUninit::uninit();
 
     unsafe {
@@ -67,8 +103,9 @@ async fn main(spawner: Spawner) {
     #[cfg(feature = "log")]
     {
         // The default log level can be specified here.
-        // You can see the esp-println documentation： https://docs.rs/esp-println
-        esp_println::logger::init_logger(log::LevelFilter::Info);
+        // Printed like esp-println's logger does, and mirrored to the BLE
+        // console after `log on`.
+        console::init_logger(log::LevelFilter::Info);
     }
 
     println!("Init!");
@@ -89,6 +126,8 @@ async fn main(spawner: Spawner) {
     let bluetooth = peripherals.BT;
 
     let connector = BleConnector::new(esp_wifi_ctrl, bluetooth);
+    // Wi-Fi shares the controller with BLE, for provisioning.
+    let (wifi_controller, _) = esp_wifi::wifi::new(esp_wifi_ctrl, peripherals.WIFI).unwrap();
 
     let now = || time::Instant::now().duration_since_epoch().as_millis();
     let mut ble = Ble::new(connector, now);
@@ -115,6 +154,19 @@ async fn main(spawner: Spawner) {
         InputConfig::default().with_pull(Pull::Down),
     );
 
+    let i2c = I2c::new(
+        peripherals.I2C0,
+        I2cConfig::default()
+            .with_frequency(Rate::from_khz(100))
+            .with_timeout(BusTimeout::Maximum),
+    )
+    .unwrap()
+    .with_sda(peripherals.GPIO4)
+    .with_scl(peripherals.GPIO5)
+    .into_async();
+
+    let mut aes = HwAes(Aes::new(peripherals.AES));
+
     let timg0 = TimerGroup::new(peripherals.TIMG0);
     esp_hal_embassy::init(timg0.timer0);
 
@@ -122,10 +174,30 @@ async fn main(spawner: Spawner) {
 
     spawner.spawn(run()).ok();
     spawner.spawn(toggle(led)).ok();
+    spawner.spawn(sensor::sensor(i2c)).ok();
+    let stored = settings::init(&mut FlashStorage::new()).unwrap_or_else(|e| {
+        println!("settings: read error: {:?}", e);
+        settings::Settings::default()
+    });
+    spawner.spawn(store_settings()).ok();
+    provisioning::set_provisioned(stored.wifi.is_some());
+    spawner
+        .spawn(wifi::connection(wifi_controller, stored.wifi))
+        .ok();
+    spawner.spawn(console::console()).ok();
+    spawner
+        .spawn(battery::battery(peripherals.ADC1, peripherals.GPIO3))
+        .ok();
+
+    let serial_number = dis::serial_number(Efuse::read_base_mac_address());
 
     let pin_ref = RefCell::new(button);
     let pin_ref = &pin_ref;
 
+    // Battery Level is always one byte, so have a measurement before a
+    // client can read it.
+    battery::measured().await;
+
     loop {
         println!("{:?}", ble.init().await);
         println!("{:?}", ble.cmd_set_le_advertising_parameters().await);
@@ -134,12 +206,12 @@ async fn main(spawner: Spawner) {
             ble.cmd_set_le_advertising_data(
                 create_advertising_data(&[
                     AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
-                    AdStructure::ServiceUuids16(&[Uuid::Uuid16(0x1809)]),
+                    AdStructure::ServiceUuids16(&[
+                        Uuid::Uuid16(dis::SERVICE),
+                        Uuid::Uuid16(bas::SERVICE),
+                        Uuid::Uuid16(ess::SERVICE),
+                    ]),
                     AdStructure::CompleteLocalName(esp_hal::chip!()),
-                    AdStructure::ServiceData16 {
-                        uuid: 0x1809,
-                        data: b"ESP-BLE",
-                    },
                 ])
                 .unwrap()
             )
@@ -169,27 +241,171 @@ async fn main(spawner: Spawner) {
             println!("RECEIVED: Offset {}, data {:?}", offset, data);
         };
 
-        gatt!([service {
-            uuid: "937312e0-2354-11eb-9f10-fbc30a62cf38",
-            characteristics: [
-                characteristic {
-                    uuid: "937312e0-2354-11eb-9f10-fbc30a62cf38",
-                    read: rf,
-                    write: wf,
-                },
-                characteristic {
-                    uuid: "957312e0-2354-11eb-9f10-fbc30a62cf38",
-                    write: wf2,
-                },
-                characteristic {
-                    name: "my_characteristic",
-                    uuid: "987312e0-2354-11eb-9f10-fbc30a62cf38",
+        let mut manufacturer_read = |offset: usize, data: &mut [u8]| {
+            value::read(dis::MANUFACTURER.as_bytes(), offset, data)
+        };
+        let mut model_read =
+            |offset: usize, data: &mut [u8]| value::read(esp_hal::chip!().as_bytes(), offset, data);
+        let mut serial_read =
+            |offset: usize, data: &mut [u8]| value::read(serial_number.as_bytes(), offset, data);
+        let mut firmware_read =
+            |offset: usize, data: &mut [u8]| value::read(dis::FIRMWARE.as_bytes(), offset, data);
+        let mut battery_level_read =
+            |offset: usize, data: &mut [u8]| value::read(&[battery::level()], offset, data);
+
+        let mut temperature_read = |offset: usize, data: &mut [u8]| {
+            value::read(&ess::temperature(sensor::temperature()), offset, data)
+        };
+        let mut temperature_measurement = |offset: usize, data: &mut [u8]| {
+            value::read(&sensor::TEMPERATURE_MEASUREMENT.encode(), offset, data)
+        };
+        let mut humidity_read = |offset: usize, data: &mut [u8]| {
+            value::read(&ess::humidity(sensor::humidity()), offset, data)
+        };
+        let mut humidity_measurement = |offset: usize, data: &mut [u8]| {
+            value::read(&sensor::HUMIDITY_MEASUREMENT.encode(), offset, data)
+        };
+
+        let provisioner = RefCell::new(Provisioning::new(rng));
+        let mut session_read =
+            |offset: usize, data: &mut [u8]| provisioner.borrow().read_public_key(offset, data);
+        let mut session_write =
+            |offset: usize, data: &[u8]| provisioner.borrow_mut().write_public_key(offset, data);
+        let mut credentials_write = |offset: usize, data: &[u8]| {
+            provisioner
+                .borrow_mut()
+                .write_credentials(&mut aes, offset, data)
+        };
+        let mut control_write =
+            |offset: usize, data: &[u8]| provisioning::write_control(offset, data);
+        let mut status_read =
+            |offset: usize, data: &mut [u8]| provisioning::read_status(offset, data);
+        let mut networks_read =
+            |offset: usize, data: &mut [u8]| provisioning::read_networks(offset, data);
+
+        let mut console_rx = |offset: usize, data: &[u8]| console::write_rx(offset, data);
+
+        // The macro only takes UUIDs as literals.
+        gatt!([
+            service {
+                uuid: "180A",
+                characteristics: [
+                    characteristic {
+                        uuid: "2A29",
+                        read: manufacturer_read,
+                    },
+                    characteristic {
+                        uuid: "2A24",
+                        read: model_read,
+                    },
+                    characteristic {
+                        uuid: "2A25",
+                        read: serial_read,
+                    },
+                    characteristic {
+                        uuid: "2A26",
+                        read: firmware_read,
+                    },
+                ],
+            },
+            service {
+                uuid: "180F",
+                characteristics: [characteristic {
+                    name: "battery_level",
+                    uuid: "2A19",
                     notify: true,
-                    read: rf3,
-                    write: wf3,
-                },
-            ],
-        },]);
+                    read: battery_level_read,
+                },],
+            },
+            service {
+                uuid: "181A",
+                characteristics: [
+                    characteristic {
+                        name: "temperature",
+                        uuid: "2A6E",
+                        notify: true,
+                        read: temperature_read,
+                        descriptors: [descriptor {
+                            uuid: "290C",
+                            read: temperature_measurement,
+                        },],
+                    },
+                    characteristic {
+                        name: "humidity",
+                        uuid: "2A6F",
+                        notify: true,
+                        read: humidity_read,
+                        descriptors: [descriptor {
+                            uuid: "290C",
+                            read: humidity_measurement,
+                        },],
+                    },
+                ],
+            },
+            service {
+                uuid: "c0de0000-7d4c-4b52-9a3e-5f0e1c2b3a40",
+                characteristics: [
+                    characteristic {
+                        uuid: "c0de0001-7d4c-4b52-9a3e-5f0e1c2b3a40",
+                        read: session_read,
+                        write: session_write,
+                    },
+                    characteristic {
+                        uuid: "c0de0002-7d4c-4b52-9a3e-5f0e1c2b3a40",
+                        write: credentials_write,
+                    },
+                    characteristic {
+                        uuid: "c0de0003-7d4c-4b52-9a3e-5f0e1c2b3a40",
+                        write: control_write,
+                    },
+                    characteristic {
+                        name: "provisioning_status",
+                        uuid: "c0de0004-7d4c-4b52-9a3e-5f0e1c2b3a40",
+                        notify: true,
+                        read: status_read,
+                    },
+                    characteristic {
+                        uuid: "c0de0005-7d4c-4b52-9a3e-5f0e1c2b3a40",
+                        read: networks_read,
+                    },
+                ],
+            },
+            service {
+                uuid: "6e400001-b5a3-f393-e0a9-e50e24dcca9e",
+                characteristics: [
+                    characteristic {
+                        uuid: "6e400002-b5a3-f393-e0a9-e50e24dcca9e",
+                        write: console_rx,
+                    },
+                    characteristic {
+                        name: "console_tx",
+                        uuid: "6e400003-b5a3-f393-e0a9-e50e24dcca9e",
+                        notify: true,
+                    },
+                ],
+            },
+            service {
+                uuid: "937312e0-2354-11eb-9f10-fbc30a62cf38",
+                characteristics: [
+                    characteristic {
+                        uuid: "937312e0-2354-11eb-9f10-fbc30a62cf38",
+                        read: rf,
+                        write: wf,
+                    },
+                    characteristic {
+                        uuid: "957312e0-2354-11eb-9f10-fbc30a62cf38",
+                        write: wf2,
+                    },
+                    characteristic {
+                        name: "my_characteristic",
+                        uuid: "987312e0-2354-11eb-9f10-fbc30a62cf38",
+                        notify: true,
+                        read: rf3,
+                        write: wf3,
+                    },
+                ],
+            },
+        ]);
 
         let mut rng = bleps::no_rng::NoRng;
         let mut srv = AttributeServer::new(&mut ble, &mut gatt_attributes, &mut rng);
@@ -197,29 +413,149 @@ async fn main(spawner: Spawner) {
         let counter = RefCell::new(0u8);
         let counter = &counter;
 
-        let mut notifier = || {
-            // TODO how to check if notifications are enabled for the characteristic?
-            // maybe pass something into the closure which just can query the characteristic
-            // value probably passing in the attribute server won't work?
-
-            async {
-                let mut button = pin_ref.borrow_mut();
-                button.wait_for_rising_edge().await;
-                println!("button pressed");
+        let subscriptions = RefCell::new(Subscriptions::new(&[
+            (battery_level_handle, battery_level_notify_enable_handle),
+            (temperature_handle, temperature_notify_enable_handle),
+            (humidity_handle, humidity_notify_enable_handle),
+            (
+                provisioning_status_handle,
+                provisioning_status_notify_enable_handle,
+            ),
+            (console_tx_handle, console_tx_notify_enable_handle),
+            (
+                my_characteristic_handle,
+                my_characteristic_notify_enable_handle,
+            ),
+        ]));
+        let subscriptions = &subscriptions;
+
+        // Waits for something to send to a subscribed characteristic.
+        let mut notifier = || async {
+            let mut button = pin_ref.borrow_mut();
+            loop {
+                // Console output is kept until the client subscribes.
+                if subscriptions.borrow().notifying(console_tx_handle) {
+                    if let Some(chunk) = console::next_chunk() {
+                        return NotificationData::new(console_tx_handle, &chunk);
+                    }
+                }
 
-                println!("sending notification");
-                let mut data = [0u8; 13];
-                data.copy_from_slice(b"Notification0");
+                let event = match select3(
+                    select4(
+                        button.wait_for_rising_edge(),
+                        sensor::TEMPERATURE_CHANGED.wait(),
+                        sensor::HUMIDITY_CHANGED.wait(),
+                        provisioning::STATUS_CHANGED.wait(),
+                    ),
+                    battery::LEVEL_CHANGED.wait(),
+                    console::OUTPUT.wait(),
+                )
+                .await
                 {
-                    let mut counter = counter.borrow_mut();
-                    data[data.len() - 1] += *counter;
-                    *counter = (*counter + 1) % 10;
+                    Either3::First(event) => event,
+                    Either3::Second(()) => {
+                        if subscriptions.borrow().notifying(battery_level_handle) {
+                            let level = battery::level();
+                            return NotificationData::new(battery_level_handle, &[level]);
+                        }
+                        continue;
+                    }
+                    Either3::Third(()) => continue,
+                };
+                let (handle, notification) = match event {
+                    Either4::First(_) => {
+                        println!("button pressed");
+                        provisioning::open_window();
+                        if !subscriptions.borrow().notifying(my_characteristic_handle) {
+                            continue;
+                        }
+
+                        println!("sending notification");
+                        let mut data = [0u8; 13];
+                        data.copy_from_slice(b"Notification0");
+                        {
+                            let mut counter = counter.borrow_mut();
+                            data[data.len() - 1] += *counter;
+                            *counter = (*counter + 1) % 10;
+                        }
+                        (
+                            my_characteristic_handle,
+                            NotificationData::new(my_characteristic_handle, &data),
+                        )
+                    }
+                    Either4::Second(_) => (
+                        temperature_handle,
+                        NotificationData::new(
+                            temperature_handle,
+                            &ess::temperature(sensor::temperature()),
+                        ),
+                    ),
+                    Either4::Third(_) => (
+                        humidity_handle,
+                        NotificationData::new(humidity_handle, &ess::humidity(sensor::humidity())),
+                    ),
+                    Either4::Fourth(_) => (
+                        provisioning_status_handle,
+                        NotificationData::new(
+                            provisioning_status_handle,
+                            &provisioning::status().encode(),
+                        ),
+                    ),
+                };
+                if subscriptions.borrow().notifying(handle) {
+                    return notification;
                 }
-                NotificationData::new(my_characteristic_handle, &data)
             }
         };
 
-        srv.run(&mut notifier).await.unwrap();
+        // Like `AttributeServer::run`, reading back the CCCDs after every
+        // request.
+        loop {
+            let result = match select(notifier(), srv.do_work()).await {
+                Either::First(notification) => {
+                    srv.do_work_with_notification(Some(notification)).await
+                }
+                Either::Second(result) => result,
+            };
+            match result {
+                Ok(WorkResult::DidWork) => {}
+                Ok(WorkResult::GotDisconnected) => break,
+                Err(e) => {
+                    println!("attribute server error: {:?}", e);
+                    break;
+                }
+            }
+
+            let mut subscriptions = subscriptions.borrow_mut();
+            for cccd in subscriptions.cccd_handles().collect::<Vec<_>>() {
+                let mut value = [0; 2];
+                let Some(len) = srv.get_characteristic_value(cccd, 0, &mut value) else {
+                    continue;
+                };
+                let config = Cccd::parse(&value[..len]);
+                if let Some(handle) = subscriptions.update(cccd, config) {
+                    println!("handle {}: {:?}", handle, config);
+                    // TODO: indicate once bleps can send indications and
+                    // report their confirmation.
+                    if config.indicate && !config.notify {
+                        println!("handle {}: indications aren't supported", handle);
+                    }
+                }
+            }
+        }
+        console::disconnected();
+        println!("disconnected");
+    }
+}
+
+/// Write the settings submitted by the `connection` task.
+#[embassy_executor::task]
+async fn store_settings() {
+    loop {
+        let new = settings::pending().await;
+        if let Err(e) = new.write(&mut FlashStorage::new()) {
+            println!("settings: write error: {:?}", e);
+        }
     }
 }
 
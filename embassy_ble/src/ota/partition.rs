@@ -0,0 +1,154 @@
+//! The ESP-IDF partition table, as flashed at [`TABLE_OFFSET`].
+//!
+//! See <https://docs.espressif.com/projects/esp-idf/en/stable/esp32c6/api-guides/partition-tables.html>
+
+use embedded_storage::nor_flash::ReadNorFlash;
+
+pub const TABLE_OFFSET: u32 = 0x8000;
+pub const MAX_PARTITIONS: usize = 16;
+
+const ENTRY_LEN: usize = 32;
+const MAGIC: [u8; 2] = [0xaa, 0x50];
+
+pub const TYPE_APP: u8 = 0x00;
+pub const TYPE_DATA: u8 = 0x01;
+
+pub const SUBTYPE_FACTORY: u8 = 0x00;
+pub const SUBTYPE_OTA_0: u8 = 0x10;
+pub const SUBTYPE_OTA_15: u8 = 0x1f;
+pub const SUBTYPE_DATA_OTA: u8 = 0x00;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Partition {
+    pub kind: u8,
+    pub subtype: u8,
+    pub offset: u32,
+    pub size: u32,
+    label: [u8; 16],
+}
+
+impl Partition {
+    fn parse(entry: &[u8; ENTRY_LEN]) -> Option<Self> {
+        if entry[..2] != MAGIC {
+            return None;
+        }
+        Some(Self {
+            kind: entry[2],
+            subtype: entry[3],
+            offset: u32::from_le_bytes(entry[4..8].try_into().unwrap()),
+            size: u32::from_le_bytes(entry[8..12].try_into().unwrap()),
+            label: entry[12..28].try_into().unwrap(),
+        })
+    }
+
+    pub fn label(&self) -> &str {
+        let len = self.label.iter().position(|&b| b == 0).unwrap_or(16);
+        core::str::from_utf8(&self.label[..len]).unwrap_or("")
+    }
+
+    /// `Some(n)` for the `ota_n` app partitions.
+    pub fn ota_index(&self) -> Option<u8> {
+        match (self.kind, self.subtype) {
+            (TYPE_APP, SUBTYPE_OTA_0..=SUBTYPE_OTA_15) => Some(self.subtype - SUBTYPE_OTA_0),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PartitionTable {
+    pub partitions: heapless::Vec<Partition, MAX_PARTITIONS>,
+}
+
+impl PartitionTable {
+    /// Parse entries up to the end marker, or the MD5 entry that follows
+    /// them. The bootloader already checked the MD5 sum.
+    pub fn parse(buf: &[u8]) -> Self {
+        let partitions = buf
+            .as_chunks::<ENTRY_LEN>()
+            .0
+            .iter()
+            .map_while(Partition::parse)
+            .take(MAX_PARTITIONS)
+            .collect();
+        Self { partitions }
+    }
+
+    pub fn read<F: ReadNorFlash>(flash: &mut F) -> Result<Self, F::Error> {
+        let mut buf = [0; ENTRY_LEN * MAX_PARTITIONS];
+        flash.read(TABLE_OFFSET, &mut buf)?;
+        Ok(Self::parse(&buf))
+    }
+
+    pub fn find(&self, kind: u8, subtype: u8) -> Option<&Partition> {
+        self.partitions
+            .iter()
+            .find(|p| p.kind == kind && p.subtype == subtype)
+    }
+
+    pub fn factory(&self) -> Option<&Partition> {
+        self.find(TYPE_APP, SUBTYPE_FACTORY)
+    }
+
+    pub fn otadata(&self) -> Option<&Partition> {
+        self.find(TYPE_DATA, SUBTYPE_DATA_OTA)
+    }
+
+    pub fn ota(&self, index: u8) -> Option<&Partition> {
+        self.find(TYPE_APP, SUBTYPE_OTA_0 + index)
+    }
+
+    /// Number of `ota_n` partitions, the bootloader expects them to be
+    /// numbered without gaps.
+    pub fn ota_count(&self) -> u8 {
+        (0..=SUBTYPE_OTA_15 - SUBTYPE_OTA_0)
+            .take_while(|&index| self.ota(index).is_some())
+            .count() as u8
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn entry(kind: u8, subtype: u8, offset: u32, size: u32, label: &str) -> [u8; 32] {
+        let mut entry = [0; 32];
+        entry[..2].copy_from_slice(&MAGIC);
+        entry[2] = kind;
+        entry[3] = subtype;
+        entry[4..8].copy_from_slice(&offset.to_le_bytes());
+        entry[8..12].copy_from_slice(&size.to_le_bytes());
+        entry[12..12 + label.len()].copy_from_slice(label.as_bytes());
+        entry
+    }
+
+    /// Same layout as `partitions.csv`.
+    pub(crate) fn table() -> [u8; 7 * 32] {
+        let mut table = [0xff; 7 * 32];
+        let entries = [
+            entry(TYPE_DATA, 0x02, 0x9000, 0x4000, "nvs"),
+            entry(TYPE_DATA, SUBTYPE_DATA_OTA, 0xd000, 0x2000, "otadata"),
+            entry(TYPE_DATA, 0x01, 0xf000, 0x1000, "phy_init"),
+            entry(TYPE_APP, SUBTYPE_OTA_0, 0x10000, 0x1c0000, "ota_0"),
+            entry(TYPE_APP, SUBTYPE_OTA_0 + 1, 0x1d0000, 0x1c0000, "ota_1"),
+            entry(0x40, 0x00, 0x390000, 0x4000, "settings"),
+        ];
+        for (chunk, entry) in table.as_chunks_mut::<32>().0.iter_mut().zip(entries) {
+            chunk.copy_from_slice(&entry);
+        }
+        table
+    }
+
+    #[test]
+    fn test_parse() {
+        let table = PartitionTable::parse(&table());
+        assert_eq!(table.partitions.len(), 6);
+        assert_eq!(table.otadata().unwrap().offset, 0xd000);
+        assert_eq!(table.otadata().unwrap().label(), "otadata");
+        assert_eq!(table.ota_count(), 2);
+        assert_eq!(table.ota(1).unwrap().offset, 0x1d0000);
+        assert_eq!(table.ota(1).unwrap().ota_index(), Some(1));
+        assert!(table.factory().is_none());
+        assert!(table.ota(2).is_none());
+    }
+}
@@ -0,0 +1,216 @@
+//! Wi-Fi provisioning over GATT
+//!
+//! A phone writes the credentials of an access point, which the `wifi`
+//! module joins, and follows the status notifications. It can scan first
+//! and read the networks nearby. To keep the password from anyone
+//! listening, the phone writes its public key to the session
+//! characteristic, reads the device's, and seals the credentials with the
+//! agreed key (see [`session`]). The values are described in [`protocol`].
+//!
+//! Credentials are only accepted while the device is unprovisioned, or for
+//! [`WINDOW`] after the button was pressed. With [`POP`] set, the key also
+//! depends on it, so only whoever can read it off the device can seal
+//! credentials, and plain ones are refused.
+//!
+//! `protocol` and `session` don't depend on esp-hal or bleps and are tested
+//! on the host.
+
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant};
+use esp_hal::{
+    aes::{Aes, Mode},
+    rng::Rng,
+};
+use esp_println::println;
+
+use crate::value::{self, Assembler};
+use crate::wifi;
+
+pub mod protocol;
+pub mod session;
+
+pub use protocol::{Credentials, Message, State, Status};
+pub use session::{BlockCipher, KeyPair, Session};
+
+/// Proof of possession, a secret printed on the device or its box, which
+/// the phone mixes into the session keys. Give every device its own, long
+/// enough not to be guessed. `None` accepts plain credentials.
+pub const POP: Option<&str> = None;
+
+/// How long credentials are accepted after pressing the button, once the
+/// device is provisioned.
+pub const WINDOW: Duration = Duration::from_secs(120);
+
+/// Longest credentials write, sealed.
+const MAX_MESSAGE_LEN: usize = 2
+    + session::IV_LEN
+    + 2
+    + protocol::MAX_SSID_LEN
+    + protocol::MAX_PASSWORD_LEN
+    + session::TAG_LEN;
+
+static STATUS: Mutex<CriticalSectionRawMutex, Cell<Status>> =
+    Mutex::new(Cell::new(Status::new(State::Idle)));
+static NETWORKS: Mutex<CriticalSectionRawMutex, RefCell<Vec<u8>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+/// An access point is stored.
+static PROVISIONED: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+/// End of the window opened with the button.
+static OPEN_UNTIL: Mutex<CriticalSectionRawMutex, Cell<Option<Instant>>> =
+    Mutex::new(Cell::new(None));
+
+/// Signaled when the status changed, for the notifier.
+pub static STATUS_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+pub fn status() -> Status {
+    STATUS.lock(Cell::get)
+}
+
+pub fn set_status(status: Status) {
+    println!("provisioning: {:?}", status);
+    STATUS.lock(|cell| cell.set(status));
+    STATUS_CHANGED.signal(());
+}
+
+/// An access point was stored, new credentials need the button from now.
+pub fn set_provisioned(provisioned: bool) {
+    PROVISIONED.lock(|cell| cell.set(provisioned));
+}
+
+/// Accept credentials for [`WINDOW`], when the button was pressed.
+pub fn open_window() {
+    println!("provisioning: accepting credentials for {}s", WINDOW.as_secs());
+    OPEN_UNTIL.lock(|cell| cell.set(Some(Instant::now() + WINDOW)));
+}
+
+fn accepting() -> bool {
+    !PROVISIONED.lock(Cell::get)
+        || OPEN_UNTIL.lock(Cell::get).is_some_and(|until| Instant::now() < until)
+}
+
+/// Replace the encoded networks of the last scan.
+pub fn set_networks(networks: Vec<u8>) {
+    NETWORKS.lock(|cell| *cell.borrow_mut() = networks);
+}
+
+pub fn read_status(offset: usize, data: &mut [u8]) -> usize {
+    value::read(&status().encode(), offset, data)
+}
+
+pub fn read_networks(offset: usize, data: &mut [u8]) -> usize {
+    NETWORKS.lock(|cell| value::read(&cell.borrow(), offset, data))
+}
+
+pub fn write_control(_offset: usize, data: &[u8]) {
+    match data {
+        [protocol::control::SCAN] => wifi::scan(),
+        _ => println!("provisioning: unknown control {:?}", data),
+    }
+}
+
+/// AES-128 of the AES peripheral.
+pub struct HwAes<'d>(pub Aes<'d>);
+
+impl BlockCipher for HwAes<'_> {
+    fn encrypt(&mut self, key: &[u8; 16], block: &mut [u8; 16]) {
+        self.0.process(block, Mode::Encryption128, *key);
+    }
+}
+
+/// The session and partial writes of one connection.
+pub struct Provisioning {
+    key_pair: KeyPair,
+    session: Option<Session>,
+    public_key: Assembler,
+    credentials: Assembler,
+}
+
+impl Provisioning {
+    /// With a new key pair.
+    pub fn new(mut rng: Rng) -> Self {
+        let key_pair = loop {
+            let mut secret = [0u8; 32];
+            for chunk in secret.chunks_mut(4) {
+                chunk.copy_from_slice(&rng.random().to_le_bytes());
+            }
+            if let Some(key_pair) = KeyPair::new(secret) {
+                break key_pair;
+            }
+        };
+        Self {
+            key_pair,
+            session: None,
+            public_key: Assembler::new(session::PUBLIC_KEY_LEN),
+            credentials: Assembler::new(MAX_MESSAGE_LEN),
+        }
+    }
+
+    pub fn read_public_key(&self, offset: usize, data: &mut [u8]) -> usize {
+        value::read(self.key_pair.public_key(), offset, data)
+    }
+
+    /// The public key of the phone, a new one replaces the session.
+    pub fn write_public_key(&mut self, offset: usize, data: &[u8]) {
+        let peer = self.public_key.write(offset, data);
+        if peer.len() < session::PUBLIC_KEY_LEN {
+            return;
+        }
+        match self.key_pair.agree(peer, POP.unwrap_or_default().as_bytes()) {
+            Ok(session) => {
+                println!("provisioning: session established");
+                self.session = Some(session);
+            }
+            Err(e) => {
+                println!("provisioning: {:?}", e);
+                self.session = None;
+            }
+        }
+        self.public_key.clear();
+    }
+
+    /// Join the access point once all of the credentials are written, if
+    /// they are accepted at all.
+    pub fn write_credentials(&mut self, cipher: &mut impl BlockCipher, offset: usize, data: &[u8]) {
+        let message = match Message::parse(self.credentials.write(offset, data)) {
+            Ok(Some(message)) => message,
+            Ok(None) => return,
+            Err(e) => {
+                println!("provisioning: invalid credentials: {:?}", e);
+                self.credentials.clear();
+                set_status(Status::new(State::Invalid));
+                return;
+            }
+        };
+        if !accepting() {
+            println!("provisioning: already provisioned, press the button first");
+            self.credentials.clear();
+            set_status(Status::new(State::Locked));
+            return;
+        }
+        let mut body = Vec::from(message.body);
+        let plaintext = match (message.kind, &self.session) {
+            (protocol::kind::PLAIN, None) if POP.is_none() => Ok(&body[..]),
+            (protocol::kind::PLAIN, _) => Err(session::Error::Plain),
+            (_, Some(session)) => session.open(cipher, &mut body),
+            (_, None) => Err(session::Error::NoSession),
+        };
+        match plaintext.map(Credentials::parse) {
+            Ok(Ok(credentials)) => wifi::connect(credentials.ssid, credentials.password),
+            Ok(Err(e)) => {
+                println!("provisioning: invalid credentials: {:?}", e);
+                set_status(Status::new(State::Invalid));
+            }
+            Err(e) => {
+                println!("provisioning: can't open credentials: {:?}", e);
+                set_status(Status::new(State::Invalid));
+            }
+        }
+        self.credentials.clear();
+    }
+}
@@ -0,0 +1,229 @@
+//! Values of the provisioning characteristics
+//!
+//! - Credentials, written: kind (0 plain, 1 sealed with the session), the
+//!   length of the rest and the rest. Plain credentials are the SSID and
+//!   the password, each prefixed with its length.
+//! - Control, written: [`control::SCAN`] scans for access points.
+//! - Status, read and notified: a [`State`] and a 16-bit little-endian
+//!   reason code of the driver for [`State::Failed`].
+//! - Networks, read: the access points of the last scan, strongest first,
+//!   each as SSID length, SSID, RSSI, [`auth`] method and channel.
+
+use alloc::vec::Vec;
+
+pub const MAX_SSID_LEN: usize = 32;
+pub const MAX_PASSWORD_LEN: usize = 64;
+
+pub mod kind {
+    pub const PLAIN: u8 = 0;
+    pub const SEALED: u8 = 1;
+}
+
+pub mod control {
+    pub const SCAN: u8 = 0x01;
+}
+
+/// Authentication methods of a network.
+pub mod auth {
+    pub const OPEN: u8 = 0;
+    pub const WEP: u8 = 1;
+    pub const WPA: u8 = 2;
+    pub const WPA2: u8 = 3;
+    pub const WPA_WPA2: u8 = 4;
+    pub const WPA2_ENTERPRISE: u8 = 5;
+    pub const WPA3: u8 = 6;
+    pub const WPA2_WPA3: u8 = 7;
+    pub const OTHER: u8 = 0xff;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    Truncated,
+    /// Bytes after the value
+    Trailing,
+    /// An unknown kind
+    Kind,
+    TooLong,
+    Utf8,
+}
+
+/// A complete credentials write, still sealed if it is.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Message<'a> {
+    pub kind: u8,
+    pub body: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    /// `Ok(None)` until the whole message is written.
+    pub fn parse(value: &'a [u8]) -> Result<Option<Self>, Error> {
+        let [kind, len, body @ ..] = value else {
+            return Ok(None);
+        };
+        if *kind != kind::PLAIN && *kind != kind::SEALED {
+            return Err(Error::Kind);
+        }
+        let len = *len as usize;
+        if body.len() < len {
+            return Ok(None);
+        }
+        if body.len() > len {
+            return Err(Error::Trailing);
+        }
+        Ok(Some(Self { kind: *kind, body }))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Credentials<'a> {
+    pub ssid: &'a str,
+    pub password: &'a str,
+}
+
+impl<'a> Credentials<'a> {
+    pub fn parse(body: &'a [u8]) -> Result<Self, Error> {
+        let (ssid, rest) = field(body, MAX_SSID_LEN)?;
+        let (password, rest) = field(rest, MAX_PASSWORD_LEN)?;
+        if !rest.is_empty() {
+            return Err(Error::Trailing);
+        }
+        Ok(Self { ssid, password })
+    }
+
+    #[cfg(test)]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for field in [self.ssid, self.password] {
+            out.push(field.len() as u8);
+            out.extend_from_slice(field.as_bytes());
+        }
+        out
+    }
+}
+
+fn field(data: &[u8], max_len: usize) -> Result<(&str, &[u8]), Error> {
+    let (&len, rest) = data.split_first().ok_or(Error::Truncated)?;
+    let len = len as usize;
+    if len > max_len {
+        return Err(Error::TooLong);
+    }
+    if rest.len() < len {
+        return Err(Error::Truncated);
+    }
+    let (value, rest) = rest.split_at(len);
+    let value = core::str::from_utf8(value).map_err(|_| Error::Utf8)?;
+    Ok((value, rest))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum State {
+    Idle = 0,
+    Scanning = 1,
+    ScanDone = 2,
+    Connecting = 3,
+    Connected = 4,
+    Failed = 5,
+    /// The credentials couldn't be read, e.g. sealed without a session
+    Invalid = 6,
+    /// Already provisioned, the button opens a window for new credentials
+    Locked = 7,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub state: State,
+    pub reason: u16,
+}
+
+impl Status {
+    pub const fn new(state: State) -> Self {
+        Self { state, reason: 0 }
+    }
+
+    pub fn encode(&self) -> [u8; 3] {
+        let [low, high] = self.reason.to_le_bytes();
+        [self.state as u8, low, high]
+    }
+}
+
+pub struct Network<'a> {
+    pub ssid: &'a str,
+    pub rssi: i8,
+    /// One of [`auth`]
+    pub auth: u8,
+    pub channel: u8,
+}
+
+impl Network<'_> {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let ssid = &self.ssid.as_bytes()[..self.ssid.len().min(MAX_SSID_LEN)];
+        out.push(ssid.len() as u8);
+        out.extend_from_slice(ssid);
+        out.extend_from_slice(&[self.rssi as u8, self.auth, self.channel]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message() {
+        assert_eq!(Message::parse(&[]), Ok(None));
+        assert_eq!(Message::parse(&[0, 3, 1, 2]), Ok(None));
+        assert_eq!(
+            Message::parse(&[1, 3, 1, 2, 3]),
+            Ok(Some(Message {
+                kind: kind::SEALED,
+                body: &[1, 2, 3]
+            }))
+        );
+        assert_eq!(Message::parse(&[0, 1, 1, 2]), Err(Error::Trailing));
+        assert_eq!(Message::parse(&[2, 0]), Err(Error::Kind));
+    }
+
+    #[test]
+    fn test_credentials() {
+        let credentials = Credentials {
+            ssid: "HOME",
+            password: "secret123",
+        };
+        let encoded = credentials.encode();
+        assert_eq!(encoded[..5], [4, b'H', b'O', b'M', b'E']);
+        assert_eq!(Credentials::parse(&encoded), Ok(credentials));
+
+        assert_eq!(
+            Credentials::parse(&[0, 0]),
+            Ok(Credentials {
+                ssid: "",
+                password: ""
+            })
+        );
+        assert_eq!(Credentials::parse(&[2, b'a']), Err(Error::Truncated));
+        assert_eq!(Credentials::parse(&[1, b'a']), Err(Error::Truncated));
+        assert_eq!(Credentials::parse(&[0, 0, 0]), Err(Error::Trailing));
+        assert_eq!(Credentials::parse(&[33]), Err(Error::TooLong));
+        assert_eq!(Credentials::parse(&[1, 0xff, 0]), Err(Error::Utf8));
+    }
+
+    #[test]
+    fn test_status_and_networks() {
+        let status = Status {
+            state: State::Failed,
+            reason: 202,
+        };
+        assert_eq!(status.encode(), [5, 202, 0]);
+        assert_eq!(Status::new(State::Connected).encode(), [4, 0, 0]);
+
+        let mut out = Vec::new();
+        Network {
+            ssid: "AP",
+            rssi: -60,
+            auth: auth::WPA2,
+            channel: 6,
+        }
+        .encode(&mut out);
+        assert_eq!(out, [2, b'A', b'P', (-60i8) as u8, 3, 6]);
+    }
+}
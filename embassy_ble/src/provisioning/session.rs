@@ -0,0 +1,242 @@
+//! Encryption of the credentials with a key agreed over ECDH
+//!
+//! Device and phone exchange uncompressed P-256 public keys and derive 48
+//! bytes with HKDF-SHA256 from the x coordinate of the shared point, salt
+//! [`SALT`] and the proof of possession as info, empty without one: the
+//! first 16 are an AES-128 key, the other 32 an HMAC-SHA256 key. The keys
+//! exchange alone doesn't tell who is on the other end, the proof of
+//! possession does: without it the tag of the credentials doesn't match.
+//! A sealed message is a random 16-byte IV, the plaintext encrypted with
+//! AES-128-CTR starting at the IV as a big-endian counter, and the first
+//! 16 bytes of the HMAC of IV and ciphertext.
+
+use hmac::{Hmac, Mac};
+use p256::{
+    NonZeroScalar, ProjectivePoint, PublicKey,
+    elliptic_curve::{point::AffineCoordinates, sec1::ToEncodedPoint},
+};
+use sha2::Sha256;
+
+pub const PUBLIC_KEY_LEN: usize = 65;
+pub const IV_LEN: usize = 16;
+pub const TAG_LEN: usize = 16;
+
+pub const SALT: &[u8] = b"embassy_ble provisioning";
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Not an uncompressed point on the curve
+    PublicKey,
+    /// Shorter than IV and tag
+    Truncated,
+    /// The tag doesn't match, wrong key or tampered with
+    Tag,
+    /// Sealed before a session was established
+    NoSession,
+    /// Plain credentials, but a session was established or a proof of
+    /// possession is required
+    Plain,
+}
+
+/// AES-128 encryption of one block, e.g. by the AES peripheral.
+pub trait BlockCipher {
+    fn encrypt(&mut self, key: &[u8; 16], block: &mut [u8; 16]);
+}
+
+/// The key pair of the device for one connection.
+pub struct KeyPair {
+    secret: NonZeroScalar,
+    public: [u8; PUBLIC_KEY_LEN],
+}
+
+impl KeyPair {
+    /// `None` if `secret` isn't a valid scalar, try again with other
+    /// random bytes.
+    pub fn new(secret: [u8; 32]) -> Option<Self> {
+        let secret = Option::<NonZeroScalar>::from(NonZeroScalar::from_repr(secret.into()))?;
+        let point = PublicKey::from_secret_scalar(&secret).to_encoded_point(false);
+        let mut public = [0; PUBLIC_KEY_LEN];
+        public.copy_from_slice(point.as_bytes());
+        Some(Self { secret, public })
+    }
+
+    /// Uncompressed SEC1 encoding.
+    pub fn public_key(&self) -> &[u8; PUBLIC_KEY_LEN] {
+        &self.public
+    }
+
+    /// Agree on the session keys with the public key of the phone and the
+    /// proof of possession both know.
+    pub fn agree(&self, peer: &[u8], pop: &[u8]) -> Result<Session, Error> {
+        if peer.len() != PUBLIC_KEY_LEN {
+            return Err(Error::PublicKey);
+        }
+        let peer = PublicKey::from_sec1_bytes(peer).map_err(|_| Error::PublicKey)?;
+        let shared = (ProjectivePoint::from(*peer.as_affine()) * *self.secret).to_affine();
+        Ok(Session::derive(&shared.x(), pop))
+    }
+}
+
+/// The keys of an established session.
+pub struct Session {
+    cipher_key: [u8; 16],
+    mac_key: [u8; 32],
+}
+
+impl Session {
+    fn derive(shared: &[u8], info: &[u8]) -> Self {
+        let prk = hmac(SALT, &[shared]);
+        let t1 = hmac(&prk, &[info, &[1]]);
+        let t2 = hmac(&prk, &[&t1, info, &[2]]);
+        let mut cipher_key = [0; 16];
+        cipher_key.copy_from_slice(&t1[..16]);
+        let mut mac_key = [0; 32];
+        mac_key[..16].copy_from_slice(&t1[16..]);
+        mac_key[16..].copy_from_slice(&t2[..16]);
+        Self {
+            cipher_key,
+            mac_key,
+        }
+    }
+
+    /// Check and decrypt `sealed` in place, returning the plaintext.
+    pub fn open<'a>(
+        &self,
+        cipher: &mut impl BlockCipher,
+        sealed: &'a mut [u8],
+    ) -> Result<&'a [u8], Error> {
+        if sealed.len() < IV_LEN + TAG_LEN {
+            return Err(Error::Truncated);
+        }
+        let (message, tag) = sealed.split_at_mut(sealed.len() - TAG_LEN);
+        let mut mac = HmacSha256::new_from_slice(&self.mac_key).unwrap();
+        mac.update(message);
+        mac.verify_truncated_left(tag).map_err(|_| Error::Tag)?;
+
+        let (iv, text) = message.split_at_mut(IV_LEN);
+        self.apply_keystream(cipher, iv.try_into().unwrap(), text);
+        Ok(text)
+    }
+
+    /// Encrypt `plaintext` with `iv`, which must not be used again.
+    #[cfg(test)]
+    pub fn seal(
+        &self,
+        cipher: &mut impl BlockCipher,
+        iv: [u8; IV_LEN],
+        plaintext: &[u8],
+    ) -> alloc::vec::Vec<u8> {
+        let mut sealed = alloc::vec::Vec::from(iv);
+        sealed.extend_from_slice(plaintext);
+        self.apply_keystream(cipher, iv, &mut sealed[IV_LEN..]);
+        let tag = hmac(&self.mac_key, &[&sealed]);
+        sealed.extend_from_slice(&tag[..TAG_LEN]);
+        sealed
+    }
+
+    fn apply_keystream(&self, cipher: &mut impl BlockCipher, iv: [u8; IV_LEN], text: &mut [u8]) {
+        let mut counter = u128::from_be_bytes(iv);
+        for chunk in text.chunks_mut(16) {
+            let mut block = counter.to_be_bytes();
+            cipher.encrypt(&self.cipher_key, &mut block);
+            for (byte, key) in chunk.iter_mut().zip(block) {
+                *byte ^= key;
+            }
+            counter = counter.wrapping_add(1);
+        }
+    }
+}
+
+fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(key).unwrap();
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Not AES, only for the mode of operation.
+    struct Xor;
+
+    impl BlockCipher for Xor {
+        fn encrypt(&mut self, key: &[u8; 16], block: &mut [u8; 16]) {
+            for (byte, key) in block.iter_mut().zip(key) {
+                *byte ^= key;
+            }
+        }
+    }
+
+    fn key_pair(byte: u8) -> KeyPair {
+        KeyPair::new([byte; 32]).unwrap()
+    }
+
+    #[test]
+    fn test_agree() {
+        assert!(KeyPair::new([0; 32]).is_none());
+        assert!(KeyPair::new([0xff; 32]).is_none());
+
+        let device = key_pair(1);
+        let phone = key_pair(2);
+        assert_eq!(device.public_key()[0], 0x04);
+        let a = device.agree(phone.public_key(), b"").unwrap();
+        let b = phone.agree(device.public_key(), b"").unwrap();
+        assert_eq!(a.cipher_key, b.cipher_key);
+        assert_eq!(a.mac_key, b.mac_key);
+        assert_ne!(
+            a.cipher_key,
+            key_pair(3).agree(phone.public_key(), b"").unwrap().cipher_key
+        );
+
+        assert_eq!(
+            device.agree(&phone.public_key()[..64], b"").err(),
+            Some(Error::PublicKey)
+        );
+        let mut off_curve = *phone.public_key();
+        off_curve[64] ^= 1;
+        assert_eq!(device.agree(&off_curve, b"").err(), Some(Error::PublicKey));
+    }
+
+    #[test]
+    fn test_seal_open() {
+        let session = key_pair(1).agree(key_pair(2).public_key(), b"").unwrap();
+        let iv = [0xff; IV_LEN];
+        let plaintext = b"a message longer than one block";
+
+        let mut sealed = session.seal(&mut Xor, iv, plaintext);
+        assert_eq!(sealed.len(), IV_LEN + plaintext.len() + TAG_LEN);
+        assert_ne!(&sealed[IV_LEN..IV_LEN + plaintext.len()], plaintext);
+        assert_eq!(
+            session.open(&mut Xor, &mut sealed.clone()).unwrap(),
+            plaintext
+        );
+
+        sealed[IV_LEN] ^= 1;
+        assert_eq!(session.open(&mut Xor, &mut sealed).err(), Some(Error::Tag));
+        assert_eq!(
+            session.open(&mut Xor, &mut [0; IV_LEN + TAG_LEN - 1]).err(),
+            Some(Error::Truncated)
+        );
+    }
+
+    #[test]
+    fn test_proof_of_possession() {
+        let device = key_pair(1);
+        let phone = key_pair(2);
+        let sealed = phone
+            .agree(device.public_key(), b"A1B2-C3D4")
+            .unwrap()
+            .seal(&mut Xor, [0; IV_LEN], b"credentials");
+
+        // Whoever doesn't know it can't seal credentials the device opens.
+        let session = device.agree(phone.public_key(), b"A1B2-C3D4").unwrap();
+        assert_eq!(session.open(&mut Xor, &mut sealed.clone()).unwrap(), b"credentials");
+        let session = device.agree(phone.public_key(), b"").unwrap();
+        assert_eq!(session.open(&mut Xor, &mut sealed.clone()).err(), Some(Error::Tag));
+    }
+}
@@ -0,0 +1,88 @@
+//! Temperature and humidity from the SHT3x
+//!
+//! The `sensor` task measures every [`INTERVAL`] and keeps the last
+//! measurement for the GATT read callbacks. When a value changes, its
+//! signal wakes the notifier.
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    signal::Signal,
+};
+use embassy_time::{Delay, Duration, Ticker};
+use esp_hal::{Async, i2c::master::I2c};
+use esp_println::println;
+
+use crate::ess::{self, EsMeasurement};
+use crate::sht3x::{self, Address, Measurement, Sht3x};
+
+pub const INTERVAL: Duration = Duration::from_secs(2);
+
+/// ES Measurement of the temperature, the SHT31 is accurate to ±0.2 °C.
+pub const TEMPERATURE_MEASUREMENT: EsMeasurement = EsMeasurement {
+    sampling: ess::sampling::INSTANTANEOUS,
+    period_secs: 0,
+    update_interval_secs: INTERVAL.as_secs() as u32,
+    application: ess::application::AIR,
+    uncertainty: 20,
+};
+
+/// ES Measurement of the humidity, the SHT31 is accurate to ±2 %.
+pub const HUMIDITY_MEASUREMENT: EsMeasurement = EsMeasurement {
+    uncertainty: 200,
+    ..TEMPERATURE_MEASUREMENT
+};
+
+static LAST: Mutex<CriticalSectionRawMutex, Cell<Option<Measurement>>> =
+    Mutex::new(Cell::new(None));
+
+/// Signaled when the temperature of the last measurement changed.
+pub static TEMPERATURE_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Signaled when the humidity of the last measurement changed.
+pub static HUMIDITY_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// In 0.01 °C, `None` until the first measurement or after an error.
+pub fn temperature() -> Option<i32> {
+    LAST.lock(Cell::get).map(|m| m.temperature)
+}
+
+/// In 0.01 %, `None` until the first measurement or after an error.
+pub fn humidity() -> Option<u16> {
+    LAST.lock(Cell::get).map(|m| m.humidity)
+}
+
+fn update(measurement: Option<Measurement>) {
+    let last = LAST.lock(|last| last.replace(measurement));
+    if last.map(|m| m.temperature) != measurement.map(|m| m.temperature) {
+        TEMPERATURE_CHANGED.signal(());
+    }
+    if last.map(|m| m.humidity) != measurement.map(|m| m.humidity) {
+        HUMIDITY_CHANGED.signal(());
+    }
+}
+
+#[embassy_executor::task]
+pub async fn sensor(mut i2c: I2c<'static, Async>) {
+    // Other tasks run during the transfers and the conversion time.
+    let mut sht3x = Sht3x::new(Address::Low, Delay);
+    let mut ticker = Ticker::every(INTERVAL);
+
+    loop {
+        match sht3x
+            .measure_async(
+                &mut i2c,
+                sht3x::ClockStretch::Enabled,
+                sht3x::Repeatability::High,
+            )
+            .await
+        {
+            Ok(measurement) => update(Some(measurement)),
+            Err(e) => {
+                println!("sensor: measure error: {:?}", e);
+                update(None);
+            }
+        }
+        ticker.next().await;
+    }
+}
@@ -0,0 +1,559 @@
+//! Settings stored as JSON in the `settings` partition
+//!
+//! The partition has the custom type [`PARTITION_TYPE`], see
+//! `partitions.csv`. It starts with a small header followed by the JSON,
+//! which may span several sectors to fit certificates. The header is
+//! written last, so an interrupted write reads back as missing settings and
+//! the defaults are used.
+
+use alloc::{string::String, vec, vec::Vec};
+use core::cell::RefCell;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    signal::Signal,
+};
+use embedded_storage::nor_flash::NorFlash;
+use serde::{Deserialize, Serialize};
+
+use crate::ota::partition::PartitionTable;
+
+pub const PARTITION_TYPE: u8 = 0x40;
+pub const PARTITION_SUBTYPE: u8 = 0x00;
+
+const MAGIC: [u8; 4] = *b"ESPS";
+const HEADER_LEN: u32 = 8;
+/// embassy-net keeps up to three DNS servers
+pub const MAX_DNS_SERVERS: usize = 3;
+
+/// Errors
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error<E> {
+    Flash(E),
+    /// The partition table has no settings partition
+    NoPartition,
+    /// Doesn't fit into the partition
+    TooLarge,
+    Invalid(&'static str),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Sent to the DHCP server and answered for with mDNS, `esp-xxxxxx` if
+    /// not set
+    pub hostname: Option<String>,
+    pub ipv4: Ipv4,
+    pub ipv6: Ipv6,
+    /// Pinged by the `health` task besides the gateway,
+    /// `health::DEFAULT_UPSTREAM` or `DEFAULT_UPSTREAM_V6` if not set
+    pub upstream: Option<IpAddr>,
+    pub power: Power,
+    /// The access point to join, `SSID` and `PASSWORD` from `main.rs` if
+    /// not set
+    pub wifi: Option<Wifi>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum Ipv4 {
+    #[default]
+    Dhcp,
+    Static {
+        address: Ipv4Addr,
+        prefix: u8,
+        gateway: Option<Ipv4Addr>,
+        #[serde(default)]
+        dns: Vec<Ipv4Addr>,
+    },
+    Disabled,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum Ipv6 {
+    #[default]
+    Disabled,
+    /// Stateless address autoconfiguration from router advertisements
+    Slaac,
+    Static {
+        address: Ipv6Addr,
+        prefix: u8,
+        gateway: Option<Ipv6Addr>,
+        #[serde(default)]
+        dns: Vec<Ipv6Addr>,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Power {
+    pub save: PowerSave,
+    /// Beacon intervals between wake-ups in `maximum` power save, 0 for the
+    /// driver default
+    pub listen_interval: u16,
+    /// Duty-cycled: publish once connected, then deep sleep this long
+    pub sleep_secs: Option<u32>,
+}
+
+/// Modem sleep between beacons
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerSave {
+    /// Radio always on, lowest latency
+    #[default]
+    None,
+    /// Wake up for every DTIM beacon
+    Minimum,
+    /// Wake up every `listen_interval` beacons
+    Maximum,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Wifi {
+    pub ssid: String,
+    pub auth: Auth,
+}
+
+/// How to authenticate with the access point
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "kebab-case")]
+pub enum Auth {
+    Open,
+    Wpa2Personal {
+        password: String,
+    },
+    /// SAE only, for access points without WPA2
+    Wpa3Personal {
+        password: String,
+    },
+    /// WPA3 if the access point offers it, WPA2 otherwise
+    Wpa2Wpa3Personal {
+        password: String,
+    },
+    /// 802.1X, for WPA2- and WPA3-Enterprise access points
+    Enterprise(Enterprise),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Enterprise {
+    pub eap: Eap,
+    /// Outer identity, e.g. `anonymous@example.com`, the username if not
+    /// set
+    pub identity: Option<String>,
+    /// Inner credentials of PEAP and TTLS
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// PEM, the server certificate isn't checked if not set
+    pub ca_cert: Option<String>,
+    /// PEM client certificate and private key, required by TLS
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    pub client_key_password: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Eap {
+    /// MSCHAPv2 inside a TLS tunnel
+    #[default]
+    Peap,
+    /// MSCHAPv2 inside a TTLS tunnel
+    Ttls,
+    /// Client certificate
+    Tls,
+}
+
+impl Auth {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Auth::Open => "open",
+            Auth::Wpa2Personal { .. } => "WPA2-Personal",
+            Auth::Wpa3Personal { .. } => "WPA3-Personal",
+            Auth::Wpa2Wpa3Personal { .. } => "WPA2/WPA3-Personal",
+            Auth::Enterprise(_) => "WPA2/WPA3-Enterprise",
+        }
+    }
+
+    /// The same with the secrets blanked, for showing it.
+    pub fn redacted(&self) -> Self {
+        let mut auth = self.clone();
+        match &mut auth {
+            Auth::Open => {}
+            Auth::Wpa2Personal { password }
+            | Auth::Wpa3Personal { password }
+            | Auth::Wpa2Wpa3Personal { password } => password.clear(),
+            Auth::Enterprise(enterprise) => {
+                let secrets = [
+                    &mut enterprise.password,
+                    &mut enterprise.client_key,
+                    &mut enterprise.client_key_password,
+                ];
+                for secret in secrets.into_iter().flatten() {
+                    secret.clear();
+                }
+            }
+        }
+        auth
+    }
+}
+
+impl Wifi {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.ssid.is_empty() || self.ssid.len() > 32 {
+            return Err("SSID must be 1 to 32 bytes");
+        }
+        match &self.auth {
+            Auth::Open => Ok(()),
+            Auth::Wpa2Personal { password }
+            | Auth::Wpa3Personal { password }
+            | Auth::Wpa2Wpa3Personal { password } => {
+                if password.len() < 8 || password.len() > 63 {
+                    return Err("password must be 8 to 63 characters");
+                }
+                Ok(())
+            }
+            Auth::Enterprise(enterprise) => enterprise.validate(),
+        }
+    }
+}
+
+impl Enterprise {
+    fn validate(&self) -> Result<(), &'static str> {
+        let too_long = |value: &Option<String>, max| value.as_ref().is_some_and(|v| v.len() > max);
+        if too_long(&self.identity, 128) || too_long(&self.username, 128) {
+            return Err("identity and username must be at most 128 bytes");
+        }
+        if too_long(&self.password, 64) {
+            return Err("EAP password must be at most 64 bytes");
+        }
+        let pem = |value: &Option<String>| {
+            value
+                .as_ref()
+                .is_none_or(|v| v.trim_start().starts_with("-----BEGIN "))
+        };
+        if !pem(&self.ca_cert) || !pem(&self.client_cert) || !pem(&self.client_key) {
+            return Err("certificates and keys must be PEM");
+        }
+        if self.client_cert.is_some() != self.client_key.is_some() {
+            return Err("client certificate and key go together");
+        }
+        match self.eap {
+            Eap::Peap | Eap::Ttls if self.username.is_none() || self.password.is_none() => {
+                Err("PEAP and TTLS need a username and password")
+            }
+            Eap::Tls if self.client_cert.is_none() => Err("TLS needs a client certificate"),
+            Eap::Tls if self.identity.is_none() => Err("TLS needs an identity"),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Settings {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if let Some(hostname) = &self.hostname {
+            let valid = !hostname.is_empty()
+                && hostname.len() <= 32
+                && !hostname.starts_with('-')
+                && hostname
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-');
+            if !valid {
+                return Err("hostname must be up to 32 letters, digits and dashes");
+            }
+        }
+        if let Ipv4::Static { prefix, dns, .. } = &self.ipv4 {
+            if *prefix > 32 {
+                return Err("IPv4 prefix must be at most 32");
+            }
+            if dns.len() > MAX_DNS_SERVERS {
+                return Err("at most 3 IPv4 DNS servers");
+            }
+        }
+        if let Ipv6::Static { prefix, dns, .. } = &self.ipv6 {
+            if *prefix > 128 {
+                return Err("IPv6 prefix must be at most 128");
+            }
+            if dns.len() > MAX_DNS_SERVERS {
+                return Err("at most 3 IPv6 DNS servers");
+            }
+        }
+        if self.power.sleep_secs == Some(0) {
+            return Err("sleep_secs must be at least 1");
+        }
+        if self.ipv4 == Ipv4::Disabled && self.ipv6 == Ipv6::Disabled {
+            return Err("IPv4 and IPv6 can't both be disabled");
+        }
+        if let Some(wifi) = &self.wifi {
+            wifi.validate()?;
+        }
+        Ok(())
+    }
+
+    /// The same with the Wi-Fi secrets blanked, for showing it.
+    pub fn redacted(&self) -> Self {
+        let mut settings = self.clone();
+        if let Some(wifi) = &mut settings.wifi {
+            wifi.auth = wifi.auth.redacted();
+        }
+        settings
+    }
+
+    /// Read the settings, `None` if there are none or they are unreadable.
+    pub fn read<F: NorFlash>(flash: &mut F) -> Result<Option<Self>, Error<F::Error>> {
+        let (offset, size) = partition(flash)?;
+
+        let mut header = [0; HEADER_LEN as usize];
+        flash.read(offset, &mut header).map_err(Error::Flash)?;
+        if header[..4] != MAGIC {
+            return Ok(None);
+        }
+        let len = u32::from_le_bytes(header[4..].try_into().unwrap());
+        if len > size - HEADER_LEN {
+            return Ok(None);
+        }
+
+        let mut json = vec![0; (len as usize).next_multiple_of(F::READ_SIZE)];
+        flash
+            .read(offset + HEADER_LEN, &mut json)
+            .map_err(Error::Flash)?;
+        Ok(serde_json::from_slice::<Self>(&json[..len as usize])
+            .ok()
+            .filter(|settings| settings.validate().is_ok()))
+    }
+
+    pub fn write<F: NorFlash>(&self, flash: &mut F) -> Result<(), Error<F::Error>> {
+        self.validate().map_err(Error::Invalid)?;
+        let (offset, size) = partition(flash)?;
+
+        let mut json = serde_json::to_vec(self).map_err(|_| Error::Invalid("unserializable"))?;
+        let len = json.len() as u32;
+        if len > size - HEADER_LEN {
+            return Err(Error::TooLarge);
+        }
+        json.resize(json.len().next_multiple_of(F::WRITE_SIZE), 0xff);
+
+        let mut header = [0; HEADER_LEN as usize];
+        header[..4].copy_from_slice(&MAGIC);
+        header[4..].copy_from_slice(&len.to_le_bytes());
+
+        let end = (HEADER_LEN + len).next_multiple_of(F::ERASE_SIZE as u32);
+        flash.erase(offset, offset + end).map_err(Error::Flash)?;
+        flash
+            .write(offset + HEADER_LEN, &json)
+            .map_err(Error::Flash)?;
+        flash.write(offset, &header).map_err(Error::Flash)
+    }
+}
+
+/// Offset and usable size of the settings partition.
+fn partition<F: NorFlash>(flash: &mut F) -> Result<(u32, u32), Error<F::Error>> {
+    let table = PartitionTable::read(flash).map_err(Error::Flash)?;
+    let partition = table
+        .find(PARTITION_TYPE, PARTITION_SUBTYPE)
+        .ok_or(Error::NoPartition)?;
+    if partition.size < F::ERASE_SIZE as u32 {
+        return Err(Error::NoPartition);
+    }
+    let size = partition.size - partition.size % F::ERASE_SIZE as u32;
+    Ok((partition.offset, size))
+}
+
+/// The settings read at boot, or saved since.
+static SETTINGS: Mutex<CriticalSectionRawMutex, RefCell<Option<Settings>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Load the settings from flash, the defaults if there are none. After an
+/// error, [`get`] returns the defaults too.
+pub fn init<F: NorFlash>(flash: &mut F) -> Result<Settings, Error<F::Error>> {
+    let settings = Settings::read(flash)?.unwrap_or_default();
+    SETTINGS.lock(|cell| cell.replace(Some(settings.clone())));
+    Ok(settings)
+}
+
+pub fn get() -> Settings {
+    SETTINGS.lock(|cell| cell.borrow().clone().unwrap_or_default())
+}
+
+/// Submitted settings waiting to be written to flash.
+static PENDING: Signal<CriticalSectionRawMutex, Settings> = Signal::new();
+
+/// Replace the settings, they take effect after a restart. Writing the flash
+/// blocks, so it is left to the task that waits for [`pending`].
+pub fn submit(settings: Settings) -> Result<(), &'static str> {
+    settings.validate()?;
+    SETTINGS.lock(|cell| cell.replace(Some(settings.clone())));
+    PENDING.signal(settings);
+    Ok(())
+}
+
+/// The settings to write next, the last ones if several were submitted.
+pub async fn pending() -> Settings {
+    PENDING.wait().await
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::mem_flash::{MemFlash, flash};
+
+    #[test]
+    fn test_json() {
+        let settings: Settings = serde_json::from_str(
+            r#"{
+                "hostname": "lab-1",
+                "ipv4": {"mode": "static", "address": "192.168.1.50", "prefix": 24,
+                         "gateway": "192.168.1.1", "dns": ["192.168.1.1"]},
+                "ipv6": {"mode": "slaac"}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.ipv4,
+            Ipv4::Static {
+                address: Ipv4Addr::new(192, 168, 1, 50),
+                prefix: 24,
+                gateway: Some(Ipv4Addr::new(192, 168, 1, 1)),
+                dns: Vec::from([Ipv4Addr::new(192, 168, 1, 1)]),
+            }
+        );
+        assert_eq!(settings.ipv6, Ipv6::Slaac);
+        assert_eq!(settings.validate(), Ok(()));
+
+        let defaults: Settings = serde_json::from_str("{}").unwrap();
+        assert_eq!(defaults, Settings::default());
+        assert_eq!(defaults.ipv4, Ipv4::Dhcp);
+
+        let mut invalid = settings.clone();
+        invalid.hostname = Some("lab 1".into());
+        assert!(invalid.validate().is_err());
+        invalid.hostname = None;
+        invalid.ipv4 = Ipv4::Disabled;
+        invalid.ipv6 = Ipv6::Disabled;
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_wifi() {
+        let settings: Settings = serde_json::from_str(
+            r#"{"wifi": {"ssid": "lab", "auth": {"method": "wpa3-personal",
+                                                 "password": "correct horse"}}}"#,
+        )
+        .unwrap();
+        let wifi = settings.wifi.clone().unwrap();
+        assert_eq!(
+            wifi.auth,
+            Auth::Wpa3Personal {
+                password: "correct horse".into()
+            }
+        );
+        assert_eq!(settings.validate(), Ok(()));
+        assert_eq!(
+            settings.redacted().wifi.unwrap().auth,
+            Auth::Wpa3Personal {
+                password: String::new()
+            }
+        );
+
+        let short = Wifi {
+            ssid: "lab".into(),
+            auth: Auth::Wpa2Personal {
+                password: "1234567".into(),
+            },
+        };
+        assert!(short.validate().is_err());
+
+        let mut enterprise: Wifi = serde_json::from_str(
+            r#"{"ssid": "eduroam", "auth": {"method": "enterprise", "eap": "ttls",
+                "identity": "anonymous@example.com", "username": "alice",
+                "password": "secret", "ca_cert": "-----BEGIN CERTIFICATE-----\n"}}"#,
+        )
+        .unwrap();
+        assert_eq!(enterprise.validate(), Ok(()));
+        let Auth::Enterprise(eap) = &mut enterprise.auth else {
+            panic!("not enterprise");
+        };
+        assert_eq!(eap.eap, Eap::Ttls);
+        eap.eap = Eap::Tls;
+        assert_eq!(enterprise.validate(), Err("TLS needs a client certificate"));
+        let Auth::Enterprise(eap) = &mut enterprise.auth else {
+            unreachable!()
+        };
+        eap.client_cert = Some("-----BEGIN CERTIFICATE-----\n".into());
+        assert_eq!(
+            enterprise.validate(),
+            Err("client certificate and key go together")
+        );
+        let Auth::Enterprise(eap) = &mut enterprise.auth else {
+            unreachable!()
+        };
+        eap.client_key = Some("MIIEvQIBADANBgkqhkiG9w0BAQEFAASC".into());
+        assert_eq!(
+            enterprise.validate(),
+            Err("certificates and keys must be PEM")
+        );
+    }
+
+    #[test]
+    fn test_flash() {
+        let mut flash = flash();
+        assert_eq!(Settings::read(&mut flash), Ok(None));
+
+        let settings = Settings {
+            hostname: Some("lab-1".into()),
+            ipv4: Ipv4::Disabled,
+            ipv6: Ipv6::Static {
+                address: "fd00::50".parse().unwrap(),
+                prefix: 64,
+                gateway: None,
+                dns: Vec::new(),
+            },
+            upstream: Some("fd00::1".parse().unwrap()),
+            power: Power {
+                save: PowerSave::Maximum,
+                listen_interval: 10,
+                sleep_secs: Some(300),
+            },
+            wifi: Some(Wifi {
+                ssid: "eduroam".into(),
+                auth: Auth::Enterprise(Enterprise {
+                    eap: Eap::Tls,
+                    identity: Some("device@example.com".into()),
+                    // Certificates take more than one sector.
+                    client_cert: Some(alloc::format!("-----BEGIN {}", "A".repeat(3000))),
+                    client_key: Some(alloc::format!("-----BEGIN {}", "B".repeat(3000))),
+                    ..Default::default()
+                }),
+            }),
+        };
+        settings.write(&mut flash).unwrap();
+        assert_eq!(Settings::read(&mut flash), Ok(Some(settings.clone())));
+
+        let mut large = settings.clone();
+        if let Some(Wifi {
+            auth: Auth::Enterprise(enterprise),
+            ..
+        }) = &mut large.wifi
+        {
+            enterprise.ca_cert = Some(alloc::format!("-----BEGIN {}", "C".repeat(0x4000)));
+        }
+        assert_eq!(large.write(&mut flash), Err(Error::TooLarge));
+
+        // Overwrite with something shorter.
+        Settings::default().write(&mut flash).unwrap();
+        assert_eq!(Settings::read(&mut flash), Ok(Some(Settings::default())));
+
+        // An interrupted write leaves no header.
+        flash.0[0x390000..0x390004].fill(0xff);
+        assert_eq!(Settings::read(&mut flash), Ok(None));
+
+        assert_eq!(
+            Settings::read(&mut MemFlash(vec![0xff; 0x9000])),
+            Err(Error::NoPartition)
+        );
+    }
+}
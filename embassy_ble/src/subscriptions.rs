@@ -0,0 +1,137 @@
+//! Client Characteristic Configuration of the connected client
+//!
+//! The attribute server keeps the CCCD values a client writes; they are
+//! created with the GATT table for every connection, so they start out
+//! unsubscribed. After each request the values are read back into
+//! [`Subscriptions`], which the notifier asks before sending.
+//!
+//! The indicate bit is recorded, but bleps can only send notifications: it
+//! has no Handle Value Indication and doesn't pass on confirmations. Until
+//! it does, a client that only asks for indications gets nothing.
+//!
+//! TODO: send indications and wait for each confirmation before the next
+//! one, once bleps supports it.
+
+use alloc::vec::Vec;
+
+/// Value of a Client Characteristic Configuration descriptor.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Cccd {
+    pub notify: bool,
+    pub indicate: bool,
+}
+
+impl Cccd {
+    const NOTIFY: u16 = 0x0001;
+    const INDICATE: u16 = 0x0002;
+
+    /// Little-endian, a short value has the missing bytes zero.
+    pub fn parse(value: &[u8]) -> Self {
+        let low = value.first().copied().unwrap_or(0);
+        let high = value.get(1).copied().unwrap_or(0);
+        let bits = u16::from_le_bytes([low, high]);
+        Self {
+            notify: bits & Self::NOTIFY != 0,
+            indicate: bits & Self::INDICATE != 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: u16,
+    cccd: u16,
+    config: Cccd,
+}
+
+/// The CCCD values of one connection, by characteristic value handle.
+#[derive(Debug)]
+pub struct Subscriptions {
+    entries: Vec<Entry>,
+}
+
+impl Subscriptions {
+    /// `characteristics` are pairs of value handle and CCCD handle, all
+    /// unsubscribed.
+    pub fn new(characteristics: &[(u16, u16)]) -> Self {
+        Self {
+            entries: characteristics
+                .iter()
+                .map(|&(value, cccd)| Entry {
+                    value,
+                    cccd,
+                    config: Cccd::default(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn cccd_handles(&self) -> impl Iterator<Item = u16> + '_ {
+        self.entries.iter().map(|entry| entry.cccd)
+    }
+
+    /// Record the value of the CCCD at `cccd`. Returns the value handle of
+    /// its characteristic if the value changed.
+    pub fn update(&mut self, cccd: u16, config: Cccd) -> Option<u16> {
+        let entry = self.entries.iter_mut().find(|entry| entry.cccd == cccd)?;
+        if entry.config == config {
+            return None;
+        }
+        entry.config = config;
+        Some(entry.value)
+    }
+
+    /// The configuration of the characteristic at `value`, unsubscribed for
+    /// unknown handles.
+    pub fn get(&self, value: u16) -> Cccd {
+        self.entries
+            .iter()
+            .find(|entry| entry.value == value)
+            .map_or(Cccd::default(), |entry| entry.config)
+    }
+
+    pub fn notifying(&self, value: u16) -> bool {
+        self.get(value).notify
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cccd() {
+        assert_eq!(Cccd::parse(&[]), Cccd::default());
+        assert!(Cccd::parse(&[0x01]).notify);
+        let both = Cccd::parse(&[0x03, 0x00]);
+        assert!(both.notify && both.indicate);
+        assert_eq!(
+            Cccd::parse(&[0x02, 0x00]),
+            Cccd {
+                notify: false,
+                indicate: true
+            }
+        );
+    }
+
+    #[test]
+    fn test_subscriptions() {
+        let mut subscriptions = Subscriptions::new(&[(3, 4), (7, 8)]);
+        assert!(subscriptions.cccd_handles().eq([4, 8]));
+        assert!(!subscriptions.notifying(3));
+
+        assert_eq!(subscriptions.update(4, Cccd::parse(&[0x01, 0x00])), Some(3));
+        assert_eq!(subscriptions.update(4, Cccd::parse(&[0x01, 0x00])), None);
+        assert!(subscriptions.notifying(3));
+        assert!(!subscriptions.notifying(7));
+
+        assert_eq!(subscriptions.update(8, Cccd::parse(&[0x02, 0x00])), Some(7));
+        assert!(subscriptions.get(7).indicate);
+        assert!(!subscriptions.notifying(7));
+
+        assert_eq!(subscriptions.update(4, Cccd::default()), Some(3));
+        assert!(!subscriptions.notifying(3));
+        assert_eq!(subscriptions.update(5, Cccd::default()), None);
+        assert!(!subscriptions.notifying(4));
+    }
+}
@@ -0,0 +1,77 @@
+//! Characteristic values for the read and write callbacks of the GATT table
+//!
+//! Values longer than the ATT MTU are read with Read Blob requests and
+//! written with Prepare Write requests, so the callbacks get an offset.
+
+use alloc::vec::Vec;
+
+/// Copy `value` from `offset` for a read callback, returning the length.
+pub fn read(value: &[u8], offset: usize, data: &mut [u8]) -> usize {
+    let value = value.get(offset..).unwrap_or(&[]);
+    let len = value.len().min(data.len());
+    data[..len].copy_from_slice(&value[..len]);
+    len
+}
+
+/// A value written in parts at increasing offsets.
+#[derive(Debug)]
+pub struct Assembler {
+    buffer: Vec<u8>,
+    max_len: usize,
+}
+
+impl Assembler {
+    pub const fn new(max_len: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            max_len,
+        }
+    }
+
+    /// Add the part of a write callback. A write at offset 0 starts over,
+    /// one that doesn't continue the value or makes it too long drops it.
+    /// Returns the value so far.
+    pub fn write(&mut self, offset: usize, data: &[u8]) -> &[u8] {
+        if offset == 0 {
+            self.buffer.clear();
+        }
+        if offset != self.buffer.len() || offset + data.len() > self.max_len {
+            self.buffer.clear();
+            return &[];
+        }
+        self.buffer.extend_from_slice(data);
+        &self.buffer
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let value = [1, 2, 3, 4, 5];
+        let mut data = [0; 4];
+        assert_eq!(read(&value, 0, &mut data), 4);
+        assert_eq!(data, [1, 2, 3, 4]);
+        assert_eq!(read(&value, 3, &mut data), 2);
+        assert_eq!(data[..2], [4, 5]);
+        assert_eq!(read(&value, 6, &mut data), 0);
+    }
+
+    #[test]
+    fn test_assembler() {
+        let mut assembler = Assembler::new(6);
+        assert_eq!(assembler.write(0, &[1, 2]), [1, 2]);
+        assert_eq!(assembler.write(2, &[3, 4]), [1, 2, 3, 4]);
+        assert_eq!(assembler.write(0, &[5]), [5]);
+        assert!(assembler.write(2, &[6]).is_empty());
+        assert_eq!(assembler.write(0, &[1, 2, 3, 4]), [1, 2, 3, 4]);
+        assert!(assembler.write(4, &[5, 6, 7]).is_empty());
+        assert_eq!(assembler.write(0, &[1]), [1]);
+    }
+}
@@ -0,0 +1,238 @@
+//! Joining the provisioned access point, next to BLE
+//!
+//! The `connection` task owns the `WifiController`, which shares the radio
+//! with BLE (the `coex` feature of esp-wifi). It scans and joins the access
+//! point when provisioning asks for it, and joins again after losing the
+//! access point. Every step is reported in the provisioning status.
+//!
+//! Configuring, joining and the disconnect reasons are the ones of the
+//! `connection` task of `embassy_wifi`: `station` and `reason` are copies
+//! of its `wifi/station.rs` and `wifi/reason.rs`.
+//!
+//! The authentication method is the one the access point advertised in the
+//! last scan, so WPA3-only access points can be joined too. When the access
+//! point wasn't scanned yet, the task scans before joining. Once joined, the
+//! access point is kept in the settings of `embassy_wifi` and joined again
+//! after a restart.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use embassy_futures::select::{Either3, select3};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::Timer;
+use esp_println::println;
+use esp_wifi::wifi::{
+    AccessPointInfo, AuthMethod, ClientConfiguration, Configuration, WifiController, WifiEvent,
+};
+
+use crate::provisioning::{self, State, Status, protocol};
+use crate::settings::{self, Auth, Settings};
+
+pub mod reason;
+// Skipping the scan is only for the deep sleep of embassy_wifi.
+#[allow(dead_code)]
+pub mod station;
+
+use station::Disconnect;
+
+/// Most access points reported by a scan.
+pub const MAX_RESULTS: usize = 10;
+
+static CREDENTIALS: Signal<CriticalSectionRawMutex, Credentials> = Signal::new();
+static SCAN: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// An access point to join, from provisioning.
+struct Credentials {
+    ssid: String,
+    password: String,
+}
+
+/// Join the access point `ssid`, open if `password` is empty.
+pub fn connect(ssid: &str, password: &str) {
+    CREDENTIALS.signal(Credentials {
+        ssid: ssid.into(),
+        password: password.into(),
+    });
+}
+
+/// Scan for access points, the result is in the provisioning networks.
+pub fn scan() {
+    SCAN.signal(());
+}
+
+/// `stored` is the access point from the settings, if one was provisioned.
+#[embassy_executor::task]
+pub async fn connection(mut controller: WifiController<'static>, stored: Option<settings::Wifi>) {
+    station::init();
+    controller
+        .set_configuration(&Configuration::Client(ClientConfiguration::default()))
+        .unwrap();
+    controller.start_async().await.unwrap();
+    println!("wifi: started");
+
+    let mut wifi = stored;
+    // Provisioned but not stored yet, it is once joined.
+    let mut unsaved = false;
+    // Of the last scan, for the authentication methods.
+    let mut access_points = Vec::new();
+
+    if let Some(wifi) = &wifi {
+        println!("wifi: joining the stored access point {}", wifi.ssid);
+        join(&mut controller, wifi).await;
+    }
+    loop {
+        match select3(
+            CREDENTIALS.wait(),
+            SCAN.wait(),
+            controller.wait_for_event(WifiEvent::StaDisconnected),
+        )
+        .await
+        {
+            Either3::First(credentials) => {
+                if find(&access_points, &credentials.ssid).is_none() {
+                    provisioning::set_status(Status::new(State::Scanning));
+                    match controller.scan_n_async(MAX_RESULTS).await {
+                        Ok(scanned) => access_points = scanned,
+                        Err(e) => println!("wifi: scan error: {:?}", e),
+                    }
+                }
+                let advertised =
+                    find(&access_points, &credentials.ssid).and_then(|ap| ap.auth_method);
+                let new = provisioned(credentials, advertised);
+                if let Err(reason) = new.validate() {
+                    println!("wifi: invalid credentials: {}", reason);
+                    provisioning::set_status(Status::new(State::Invalid));
+                    continue;
+                }
+                wifi = Some(new);
+                unsaved = true;
+            }
+            Either3::Second(()) => {
+                if let Some(scanned) = serve_scan(&mut controller).await {
+                    access_points = scanned;
+                }
+                continue;
+            }
+            Either3::Third(()) => {
+                if wifi.is_none() {
+                    continue;
+                }
+                failed(station::lost());
+                Timer::after(station::RECONNECT_DELAY).await;
+            }
+        }
+        if let Some(wifi) = &wifi
+            && join(&mut controller, wifi).await
+            && unsaved
+        {
+            save(wifi);
+            unsaved = false;
+        }
+    }
+}
+
+fn find<'a>(access_points: &'a [AccessPointInfo], ssid: &str) -> Option<&'a AccessPointInfo> {
+    access_points.iter().find(|ap| ap.ssid == ssid)
+}
+
+/// The access point of `credentials` with the method it advertised, WPA2
+/// if it wasn't found, e.g. because it is hidden.
+fn provisioned(credentials: Credentials, advertised: Option<AuthMethod>) -> settings::Wifi {
+    let password = credentials.password;
+    let auth = match advertised {
+        _ if password.is_empty() => Auth::Open,
+        Some(AuthMethod::WPA3Personal) => Auth::Wpa3Personal { password },
+        Some(AuthMethod::WPA2WPA3Personal) => Auth::Wpa2Wpa3Personal { password },
+        _ => Auth::Wpa2Personal { password },
+    };
+    settings::Wifi {
+        ssid: credentials.ssid,
+        auth,
+    }
+}
+
+/// Keep `wifi` in the settings, to join it again after a restart.
+fn save(wifi: &settings::Wifi) {
+    let settings = Settings {
+        wifi: Some(wifi.clone()),
+        ..settings::get()
+    };
+    match settings::submit(settings) {
+        Ok(()) => provisioning::set_provisioned(true),
+        Err(reason) => println!("wifi: can't save the access point: {}", reason),
+    }
+}
+
+/// Returns whether the access point was joined.
+async fn join(controller: &mut WifiController<'static>, wifi: &settings::Wifi) -> bool {
+    provisioning::set_status(Status::new(State::Connecting));
+    if matches!(controller.is_connected(), Ok(true)) {
+        controller.disconnect_async().await.ok();
+    }
+    if let Err(e) = controller.set_configuration(&station::configuration(wifi)) {
+        println!("wifi: invalid configuration: {:?}", e);
+        provisioning::set_status(Status::new(State::Invalid));
+        return false;
+    }
+    match station::connect(controller).await {
+        Ok(()) => {
+            provisioning::set_status(Status::new(State::Connected));
+            true
+        }
+        Err(disconnect) => {
+            failed(disconnect);
+            false
+        }
+    }
+}
+
+/// Report the reason code of the driver, 0 if it gave none.
+fn failed(disconnect: Option<Disconnect>) {
+    provisioning::set_status(Status {
+        state: State::Failed,
+        reason: disconnect.map_or(0, |disconnect| disconnect.reason),
+    });
+}
+
+/// Scan and publish the networks, returns the access points found.
+async fn serve_scan(controller: &mut WifiController<'static>) -> Option<Vec<AccessPointInfo>> {
+    let status = provisioning::status();
+    provisioning::set_status(Status::new(State::Scanning));
+    match controller.scan_n_async(MAX_RESULTS).await {
+        Ok(mut access_points) => {
+            access_points.sort_by_key(|ap| -(ap.signal_strength as i16));
+            let mut networks = Vec::new();
+            for ap in &access_points {
+                network(ap).encode(&mut networks);
+            }
+            provisioning::set_networks(networks);
+            provisioning::set_status(Status::new(State::ScanDone));
+            Some(access_points)
+        }
+        Err(e) => {
+            println!("wifi: scan error: {:?}", e);
+            provisioning::set_status(status);
+            None
+        }
+    }
+}
+
+fn network(ap: &AccessPointInfo) -> protocol::Network<'_> {
+    protocol::Network {
+        ssid: &ap.ssid,
+        rssi: ap.signal_strength,
+        auth: match ap.auth_method {
+            Some(AuthMethod::None) => protocol::auth::OPEN,
+            Some(AuthMethod::WEP) => protocol::auth::WEP,
+            Some(AuthMethod::WPA) => protocol::auth::WPA,
+            Some(AuthMethod::WPA2Personal) => protocol::auth::WPA2,
+            Some(AuthMethod::WPAWPA2Personal) => protocol::auth::WPA_WPA2,
+            Some(AuthMethod::WPA2Enterprise) => protocol::auth::WPA2_ENTERPRISE,
+            Some(AuthMethod::WPA3Personal) => protocol::auth::WPA3,
+            Some(AuthMethod::WPA2WPA3Personal) => protocol::auth::WPA2_WPA3,
+            _ => protocol::auth::OTHER,
+        },
+        channel: ap.channel,
+    }
+}
@@ -0,0 +1,22 @@
+//! Sets `FIRMWARE_VERSION` to `git describe` of the checkout, for the `fw`
+//! TXT entry of mDNS and the shell banner.
+
+use std::process::Command;
+
+fn main() {
+    let version = Command::new("git")
+        .args(["describe", "--tags", "--always", "--dirty"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|version| version.trim().to_string())
+        .filter(|version| !version.is_empty());
+
+    if let Some(version) = version {
+        println!("cargo:rustc-env=FIRMWARE_VERSION={}", version);
+    }
+    // A commit or a checkout changes these.
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/index");
+}
@@ -0,0 +1,288 @@
+//! Wall-clock time
+//!
+//! The clock is a linear model on top of the monotonic `embassy_time` tick,
+//! corrected by the `sntp` task. Until the first synchronization it falls
+//! back to the time since boot.
+
+use core::cell::RefCell;
+use core::fmt;
+
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::Instant;
+
+/// Upper bound for the estimated oscillator drift, anything larger is
+/// rather a step of the server clock than drift.
+const MAX_DRIFT_PPM: i64 = 500;
+
+static CLOCK: Mutex<CriticalSectionRawMutex, RefCell<Clock>> =
+    Mutex::new(RefCell::new(Clock::new()));
+
+/// Current time.
+pub fn now() -> Timestamp {
+    let local = Instant::now().as_micros();
+    CLOCK.lock(|clock| clock.borrow().timestamp(local))
+}
+
+/// Best estimate of the current time at the local instant `local`, see
+/// [`Clock::estimate`].
+pub fn estimate(local: u64) -> i64 {
+    CLOCK.lock(|clock| clock.borrow().estimate(local))
+}
+
+/// Whether the clock was synchronized at least once.
+pub fn is_synced() -> bool {
+    CLOCK.lock(|clock| clock.borrow().synced.is_some())
+}
+
+/// Set the offset of local time to UTC, e.g. `480` for UTC+8.
+pub fn set_utc_offset(minutes: i16) {
+    CLOCK.lock(|clock| clock.borrow_mut().utc_offset = minutes);
+}
+
+/// Apply an offset measured against a time server at the local instant
+/// `local` (microseconds since boot).
+pub fn adjust(local: u64, offset_micros: i64) {
+    CLOCK.lock(|clock| clock.borrow_mut().adjust(local, offset_micros));
+}
+
+/// Estimated drift of the local oscillator in parts per million.
+pub fn drift_ppm() -> i64 {
+    CLOCK.lock(|clock| clock.borrow().drift_ppm)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Sync {
+    /// Local time of the last adjustment, in microseconds since boot
+    local: u64,
+    /// UTC time at `local`, in microseconds since the Unix epoch
+    unix: i64,
+}
+
+#[derive(Debug)]
+pub struct Clock {
+    synced: Option<Sync>,
+    drift_ppm: i64,
+    utc_offset: i16,
+}
+
+impl Clock {
+    pub const fn new() -> Self {
+        Self {
+            synced: None,
+            drift_ppm: 0,
+            utc_offset: 0,
+        }
+    }
+
+    /// Best estimate of the current time at `local`.
+    ///
+    /// Before the first synchronization this is the time since boot, which
+    /// is also what an SNTP request sends as its transmit timestamp.
+    pub fn estimate(&self, local: u64) -> i64 {
+        match self.synced {
+            Some(sync) => {
+                let elapsed = local.saturating_sub(sync.local) as i64;
+                sync.unix + elapsed + elapsed * self.drift_ppm / 1_000_000
+            }
+            None => local as i64,
+        }
+    }
+
+    pub fn timestamp(&self, local: u64) -> Timestamp {
+        match self.synced {
+            Some(_) => Timestamp::Utc {
+                unix_micros: self.estimate(local),
+                utc_offset: self.utc_offset,
+            },
+            None => Timestamp::Uptime(local),
+        }
+    }
+
+    pub fn adjust(&mut self, local: u64, offset_micros: i64) {
+        let unix = self.estimate(local) + offset_micros;
+
+        if let Some(sync) = self.synced {
+            // The remaining offset is what the drift estimate got wrong
+            // since the last synchronization.
+            let elapsed = local.saturating_sub(sync.local) as i64;
+            if elapsed > 0 {
+                let correction = offset_micros.saturating_mul(1_000_000) / elapsed;
+                self.drift_ppm =
+                    (self.drift_ppm + correction).clamp(-MAX_DRIFT_PPM, MAX_DRIFT_PPM);
+            }
+        }
+
+        self.synced = Some(Sync { local, unix });
+    }
+}
+
+/// A point in time for stamping records.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Timestamp {
+    /// Synchronized UTC time and the configured local offset in minutes
+    Utc { unix_micros: i64, utc_offset: i16 },
+    /// Microseconds since boot, the clock was never synchronized
+    Uptime(u64),
+}
+
+impl Timestamp {
+    /// Microseconds since the Unix epoch, if the clock is synchronized.
+    pub fn unix_micros(&self) -> Option<i64> {
+        match *self {
+            Timestamp::Utc { unix_micros, .. } => Some(unix_micros),
+            Timestamp::Uptime(_) => None,
+        }
+    }
+
+    /// Local date and time, if the clock is synchronized.
+    pub fn date_time(&self) -> Option<DateTime> {
+        match *self {
+            Timestamp::Utc {
+                unix_micros,
+                utc_offset,
+            } => Some(DateTime::new(unix_micros, utc_offset)),
+            Timestamp::Uptime(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Timestamp::Utc {
+                unix_micros,
+                utc_offset,
+            } => DateTime::new(unix_micros, utc_offset).fmt(f),
+            Timestamp::Uptime(micros) => {
+                write!(f, "+{}.{:06}s", micros / 1_000_000, micros % 1_000_000)
+            }
+        }
+    }
+}
+
+/// Broken down calendar time.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i32,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub micros: u32,
+    /// Offset to UTC in minutes
+    pub utc_offset: i16,
+}
+
+impl DateTime {
+    /// Local time `utc_offset` minutes away from `unix_micros`.
+    pub fn new(unix_micros: i64, utc_offset: i16) -> Self {
+        let micros = unix_micros + utc_offset as i64 * 60_000_000;
+        let secs = micros.div_euclid(1_000_000);
+        let days = secs.div_euclid(86_400);
+        let rem = secs.rem_euclid(86_400);
+        let (year, month, day) = civil_from_days(days);
+
+        Self {
+            year,
+            month,
+            day,
+            hour: (rem / 3600) as u8,
+            minute: (rem % 3600 / 60) as u8,
+            second: (rem % 60) as u8,
+            micros: micros.rem_euclid(1_000_000) as u32,
+            utc_offset,
+        }
+    }
+}
+
+/// RFC 3339, e.g. `2024-05-01T12:30:00.000Z`.
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}",
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+            self.micros / 1000
+        )?;
+        match self.utc_offset {
+            0 => write!(f, "Z"),
+            offset => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                let offset = offset.unsigned_abs();
+                write!(f, "{}{:02}:{:02}", sign, offset / 60, offset % 60)
+            }
+        }
+    }
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) triple.
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+fn civil_from_days(days: i64) -> (i32, u8, u8) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year as i32, month, day)
+}
+
+/// Convert a (year, month, day) triple to days since 1970-01-01.
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+pub fn days_from_civil(year: i32, month: u8, day: u8) -> i64 {
+    let year = year as i64 - if month <= 2 { 1 } else { 0 };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_date_time() {
+        let dt = DateTime::new(1_714_566_600_123_456, 0);
+        assert_eq!(dt.to_string(), "2024-05-01T12:30:00.123Z");
+
+        let ts = Timestamp::Utc {
+            unix_micros: 951_825_600_000_000,
+            utc_offset: -330,
+        };
+        assert_eq!(ts.to_string(), "2000-02-29T06:30:00.000-05:30");
+        assert_eq!(Timestamp::Uptime(1_500_000).to_string(), "+1.500000s");
+
+        for days in [-1, 0, 11_016, 19_844, 60_000] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn test_drift() {
+        let mut clock = Clock::new();
+        clock.adjust(1_000_000, 1_700_000_000_000_000);
+        assert_eq!(clock.estimate(2_000_000), 1_700_000_001_000_000 + 1_000_000);
+
+        // The local clock runs 100 ppm slow.
+        let local = 1_000_000 + 100_000_000;
+        let offset = clock.estimate(local) - (1_700_000_001_000_000 + 100_010_000);
+        clock.adjust(local, -offset);
+        assert_eq!(clock.drift_ppm, 100);
+    }
+}
@@ -0,0 +1,92 @@
+//! Block-wise transfers (RFC 7959)
+//!
+//! Block2 splits large responses, the client asks for the blocks after the
+//! first one. Block1 splits large request payloads, each block is
+//! acknowledged with 2.31 Continue until the last one.
+
+/// Largest block size exponent, 2^(4 + 6) = 1024 bytes.
+pub const MAX_SZX: u8 = 6;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub num: u32,
+    pub more: bool,
+    /// Block size exponent, the size is 2^(4 + szx)
+    pub szx: u8,
+}
+
+impl Block {
+    pub const fn new(num: u32, more: bool, szx: u8) -> Self {
+        Self { num, more, szx }
+    }
+
+    /// Decode the option value, `None` for the reserved size exponent 7
+    /// or a block number over 20 bits.
+    pub fn decode(value: u32) -> Option<Self> {
+        let szx = (value & 0x07) as u8;
+        if szx > MAX_SZX || value >> 4 >= 1 << 20 {
+            return None;
+        }
+        Some(Self {
+            num: value >> 4,
+            more: value & 0x08 != 0,
+            szx,
+        })
+    }
+
+    pub const fn encode(self) -> u32 {
+        self.num << 4 | (self.more as u32) << 3 | self.szx as u32
+    }
+
+    pub const fn size(self) -> usize {
+        16 << self.szx
+    }
+
+    /// Offset of the block in the whole payload.
+    pub const fn offset(self) -> usize {
+        self.num as usize * self.size()
+    }
+}
+
+/// The block `num` of `payload` with blocks of size exponent `szx`, and the
+/// Block2 option to send with it. `None` if it starts past the end.
+pub fn slice(payload: &[u8], num: u32, szx: u8) -> Option<(&[u8], Block)> {
+    let block = Block::new(num, false, szx);
+    let start = block.offset();
+    if start >= payload.len() && !(num == 0 && payload.is_empty()) {
+        return None;
+    }
+    let end = payload.len().min(start + block.size());
+    Some((
+        &payload[start..end],
+        Block::new(num, end < payload.len(), szx),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block() {
+        let block = Block::decode(0x2e).unwrap();
+        assert_eq!(block, Block::new(2, true, 6));
+        assert_eq!(block.size(), 1024);
+        assert_eq!(block.offset(), 2048);
+        assert_eq!(block.encode(), 0x2e);
+        assert_eq!(Block::decode(0x07), None);
+        assert_eq!(Block::decode(0x01), Some(Block::new(0, false, 1)));
+
+        let payload = [0u8; 40];
+        let (data, block) = slice(&payload, 0, 0).unwrap();
+        assert_eq!((data.len(), block), (16, Block::new(0, true, 0)));
+        let (data, block) = slice(&payload, 2, 0).unwrap();
+        assert_eq!((data.len(), block), (8, Block::new(2, false, 0)));
+        assert_eq!(slice(&payload, 3, 0), None);
+        assert_eq!(
+            slice(&payload, 0, 2),
+            Some((&payload[..], Block::new(0, false, 2)))
+        );
+        assert_eq!(slice(&[], 0, 2), Some((&[][..], Block::new(0, false, 2))));
+    }
+}
@@ -0,0 +1,415 @@
+//! CoAP message encoding and decoding (RFC 7252, section 3)
+
+use core::fmt;
+
+pub const VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 4;
+pub const MAX_TOKEN_LEN: usize = 8;
+/// Options kept per message, more are an error.
+pub const MAX_OPTIONS: usize = 16;
+
+const PAYLOAD_MARKER: u8 = 0xff;
+
+/// Option numbers
+pub mod option {
+    pub const IF_MATCH: u16 = 1;
+    pub const URI_HOST: u16 = 3;
+    pub const ETAG: u16 = 4;
+    pub const OBSERVE: u16 = 6;
+    pub const URI_PORT: u16 = 7;
+    pub const URI_PATH: u16 = 11;
+    pub const CONTENT_FORMAT: u16 = 12;
+    pub const MAX_AGE: u16 = 14;
+    pub const URI_QUERY: u16 = 15;
+    pub const ACCEPT: u16 = 17;
+    pub const BLOCK2: u16 = 23;
+    pub const BLOCK1: u16 = 27;
+    pub const SIZE2: u16 = 28;
+    pub const SIZE1: u16 = 60;
+
+    /// Options a request must not carry unless they are understood.
+    pub const fn is_critical(number: u16) -> bool {
+        number & 1 != 0
+    }
+}
+
+/// Content-Format numbers
+pub mod content_format {
+    pub const TEXT: u16 = 0;
+    pub const LINK_FORMAT: u16 = 40;
+    pub const OCTET_STREAM: u16 = 42;
+    pub const JSON: u16 = 50;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Type {
+    Confirmable = 0,
+    NonConfirmable = 1,
+    Acknowledgement = 2,
+    Reset = 3,
+}
+
+/// A method or response code, `class.detail` packed like on the wire.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Code(pub u8);
+
+impl Code {
+    pub const EMPTY: Code = Code(0);
+    pub const GET: Code = Code(1);
+    pub const POST: Code = Code(2);
+    pub const PUT: Code = Code(3);
+    pub const DELETE: Code = Code(4);
+
+    pub const CHANGED: Code = Code::new(2, 4);
+    pub const CONTENT: Code = Code::new(2, 5);
+    pub const CONTINUE: Code = Code::new(2, 31);
+    pub const BAD_REQUEST: Code = Code::new(4, 0);
+    pub const BAD_OPTION: Code = Code::new(4, 2);
+    pub const NOT_FOUND: Code = Code::new(4, 4);
+    pub const METHOD_NOT_ALLOWED: Code = Code::new(4, 5);
+    pub const NOT_ACCEPTABLE: Code = Code::new(4, 6);
+    pub const REQUEST_ENTITY_INCOMPLETE: Code = Code::new(4, 8);
+    pub const REQUEST_ENTITY_TOO_LARGE: Code = Code::new(4, 13);
+    pub const INTERNAL_SERVER_ERROR: Code = Code::new(5, 0);
+    pub const SERVICE_UNAVAILABLE: Code = Code::new(5, 3);
+
+    pub const fn new(class: u8, detail: u8) -> Self {
+        Self(class << 5 | detail)
+    }
+
+    pub const fn class(self) -> u8 {
+        self.0 >> 5
+    }
+
+    pub const fn detail(self) -> u8 {
+        self.0 & 0x1f
+    }
+
+    pub const fn is_request(self) -> bool {
+        self.class() == 0 && self.0 != 0
+    }
+}
+
+impl fmt::Debug for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:02}", self.class(), self.detail())
+    }
+}
+
+/// Errors
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Shorter than the header, token or an option says
+    Truncated,
+    BadVersion,
+    BadTokenLength,
+    /// A reserved option delta or length, or a payload marker without
+    /// payload
+    BadOption,
+    /// More than [`MAX_OPTIONS`]
+    TooManyOptions,
+    /// The output buffer can't hold the message
+    BufferTooSmall,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Value<'a> {
+    Bytes(&'a [u8]),
+    /// Encoded in as few bytes as possible
+    Uint(u32),
+}
+
+impl<'a> Value<'a> {
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            Value::Uint(_) => None,
+        }
+    }
+
+    pub fn as_uint(&self) -> Option<u32> {
+        match self {
+            Value::Bytes(bytes) if bytes.len() <= 4 => {
+                Some(bytes.iter().fold(0, |value, b| value << 8 | *b as u32))
+            }
+            Value::Bytes(_) => None,
+            Value::Uint(value) => Some(*value),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Value::Bytes(bytes) => bytes.len(),
+            Value::Uint(value) => 4 - value.leading_zeros() as usize / 8,
+        }
+    }
+
+    fn write(&self, out: &mut [u8]) {
+        match self {
+            Value::Bytes(bytes) => out.copy_from_slice(bytes),
+            Value::Uint(value) => out.copy_from_slice(&value.to_be_bytes()[4 - out.len()..]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message<'a> {
+    pub ty: Type,
+    pub code: Code,
+    pub message_id: u16,
+    pub token: &'a [u8],
+    /// Sorted by number, options with the same number in their order
+    pub options: heapless::Vec<(u16, Value<'a>), MAX_OPTIONS>,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    pub fn new(ty: Type, code: Code, message_id: u16, token: &'a [u8]) -> Self {
+        Self {
+            ty,
+            code,
+            message_id,
+            token,
+            options: heapless::Vec::new(),
+            payload: &[],
+        }
+    }
+
+    /// Add an option after the others with the same number.
+    pub fn add_option(&mut self, number: u16, value: Value<'a>) -> Result<(), Error> {
+        let index = self.options.partition_point(|(n, _)| *n <= number);
+        self.options
+            .insert(index, (number, value))
+            .map_err(|_| Error::TooManyOptions)
+    }
+
+    /// The first option with `number`.
+    pub fn option(&self, number: u16) -> Option<Value<'a>> {
+        self.options(number).next()
+    }
+
+    /// All options with `number`, in order.
+    pub fn options(&self, number: u16) -> impl Iterator<Item = Value<'a>> + '_ {
+        self.options
+            .iter()
+            .filter(move |(n, _)| *n == number)
+            .map(|(_, value)| *value)
+    }
+
+    pub fn parse(buf: &'a [u8]) -> Result<Self, Error> {
+        let (ty, token_len, code, message_id) = parse_header(buf)?;
+        let token = buf
+            .get(HEADER_LEN..HEADER_LEN + token_len)
+            .ok_or(Error::Truncated)?;
+
+        let mut message = Self::new(ty, code, message_id, token);
+        let mut rest = &buf[HEADER_LEN + token_len..];
+        let mut number = 0u16;
+        while let [first, tail @ ..] = rest {
+            if *first == PAYLOAD_MARKER {
+                if tail.is_empty() {
+                    return Err(Error::BadOption);
+                }
+                message.payload = tail;
+                break;
+            }
+            let (delta, tail) = extended(first >> 4, tail)?;
+            let (len, tail) = extended(first & 0x0f, tail)?;
+            number = number.checked_add(delta).ok_or(Error::BadOption)?;
+            let value = tail.get(..len as usize).ok_or(Error::Truncated)?;
+            message
+                .options
+                .push((number, Value::Bytes(value)))
+                .map_err(|_| Error::TooManyOptions)?;
+            rest = &tail[len as usize..];
+        }
+        Ok(message)
+    }
+
+    /// Write the message into `buf`, returns its length.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.token.len() > MAX_TOKEN_LEN {
+            return Err(Error::BadTokenLength);
+        }
+        let mut out = Writer { buf, len: 0 };
+        out.put(&[
+            VERSION << 6 | (self.ty as u8) << 4 | self.token.len() as u8,
+            self.code.0,
+        ])?;
+        out.put(&self.message_id.to_be_bytes())?;
+        out.put(self.token)?;
+
+        let mut number = 0;
+        for (n, value) in &self.options {
+            let delta = n - number;
+            number = *n;
+            let len = value.len();
+            let (delta_nibble, delta_ext) = nibble(delta);
+            let (len_nibble, len_ext) = nibble(len as u16);
+            out.put(&[delta_nibble << 4 | len_nibble])?;
+            out.put(&delta_ext)?;
+            out.put(&len_ext)?;
+            let start = out.len;
+            out.put_zeros(len)?;
+            value.write(&mut out.buf[start..start + len]);
+        }
+
+        if !self.payload.is_empty() {
+            out.put(&[PAYLOAD_MARKER])?;
+            out.put(self.payload)?;
+        }
+        Ok(out.len)
+    }
+}
+
+/// Type, token length, code and message ID, checking the version.
+pub fn parse_header(buf: &[u8]) -> Result<(Type, usize, Code, u16), Error> {
+    let [first, code, id_high, id_low, ..] = *buf else {
+        return Err(Error::Truncated);
+    };
+    if first >> 6 != VERSION {
+        return Err(Error::BadVersion);
+    }
+    let ty = match (first >> 4) & 0x03 {
+        0 => Type::Confirmable,
+        1 => Type::NonConfirmable,
+        2 => Type::Acknowledgement,
+        _ => Type::Reset,
+    };
+    let token_len = (first & 0x0f) as usize;
+    if token_len > MAX_TOKEN_LEN {
+        return Err(Error::BadTokenLength);
+    }
+    Ok((
+        ty,
+        token_len,
+        Code(code),
+        u16::from_be_bytes([id_high, id_low]),
+    ))
+}
+
+/// Decode an option delta or length nibble with its extended bytes.
+fn extended(nibble: u8, buf: &[u8]) -> Result<(u16, &[u8]), Error> {
+    match nibble {
+        0..=12 => Ok((nibble as u16, buf)),
+        13 => match buf {
+            [b, rest @ ..] => Ok((*b as u16 + 13, rest)),
+            _ => Err(Error::Truncated),
+        },
+        14 => match buf {
+            [high, low, rest @ ..] => u16::from_be_bytes([*high, *low])
+                .checked_add(269)
+                .map(|value| (value, rest))
+                .ok_or(Error::BadOption),
+            _ => Err(Error::Truncated),
+        },
+        _ => Err(Error::BadOption),
+    }
+}
+
+/// Encode an option delta or length as a nibble and extended bytes.
+fn nibble(value: u16) -> (u8, heapless::Vec<u8, 2>) {
+    let mut ext = heapless::Vec::new();
+    match value {
+        0..=12 => (value as u8, ext),
+        13..=268 => {
+            ext.push((value - 13) as u8).ok();
+            (13, ext)
+        }
+        _ => {
+            ext.extend_from_slice(&(value - 269).to_be_bytes()).ok();
+            (14, ext)
+        }
+    }
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn put(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.buf
+            .get_mut(self.len..self.len + data.len())
+            .ok_or(Error::BufferTooSmall)?
+            .copy_from_slice(data);
+        self.len += data.len();
+        Ok(())
+    }
+
+    fn put_zeros(&mut self, len: usize) -> Result<(), Error> {
+        self.buf
+            .get_mut(self.len..self.len + len)
+            .ok_or(Error::BufferTooSmall)?
+            .fill(0);
+        self.len += len;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+
+    use super::*;
+
+    #[test]
+    fn test_message() {
+        // CON GET /sensors/rssi with Observe 0 and token 0x7d34.
+        let packet = [
+            0x42, 0x01, 0x12, 0x34, 0x7d, 0x34, 0x60, 0x57, b's', b'e', b'n', b's', b'o', b'r',
+            b's', 0x04, b'r', b's', b's', b'i',
+        ];
+        let message = Message::parse(&packet).unwrap();
+        assert_eq!(message.ty, Type::Confirmable);
+        assert_eq!(message.code, Code::GET);
+        assert_eq!(message.message_id, 0x1234);
+        assert_eq!(message.token, [0x7d, 0x34]);
+        assert_eq!(message.option(option::OBSERVE).unwrap().as_uint(), Some(0));
+        let path: heapless::Vec<_, 2> = message
+            .options(option::URI_PATH)
+            .map(|value| value.as_bytes().unwrap())
+            .collect();
+        assert_eq!(path, [&b"sensors"[..], b"rssi"]);
+
+        let mut buf = [0; 64];
+        let n = message.encode(&mut buf).unwrap();
+        assert_eq!(buf[..n], packet);
+
+        // Piggybacked response with extended option deltas and a payload.
+        let mut response = Message::new(Type::Acknowledgement, Code::CONTENT, 0x1234, &[0x7d]);
+        response
+            .add_option(option::SIZE1, Value::Uint(1024))
+            .unwrap();
+        response
+            .add_option(
+                option::CONTENT_FORMAT,
+                Value::Uint(content_format::TEXT.into()),
+            )
+            .unwrap();
+        response.payload = b"-52";
+        let n = response.encode(&mut buf).unwrap();
+        assert_eq!(
+            buf[..n],
+            [
+                0x61, 0x45, 0x12, 0x34, 0x7d, 0xc0, 0xd2, 0x23, 0x04, 0x00, 0xff, b'-', b'5', b'2'
+            ]
+        );
+        let parsed = Message::parse(&buf[..n]).unwrap();
+        assert_eq!(parsed.option(option::SIZE1).unwrap().as_uint(), Some(1024));
+        assert_eq!(parsed.payload, b"-52");
+        assert_eq!(format!("{:?}", parsed.code), "2.05");
+
+        assert_eq!(Message::parse(&[0x40, 0x01]), Err(Error::Truncated));
+        assert_eq!(Message::parse(&[0x80, 0x01, 0, 0]), Err(Error::BadVersion));
+        assert_eq!(
+            Message::parse(&[0x40, 0x01, 0, 0, 0xff]),
+            Err(Error::BadOption)
+        );
+        assert_eq!(
+            Message::parse(&[0x40, 0x01, 0, 0, 0xf0]),
+            Err(Error::BadOption)
+        );
+    }
+}
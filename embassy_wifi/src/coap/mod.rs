@@ -0,0 +1,158 @@
+//! CoAP server (RFC 7252)
+//!
+//! The `coap` task serves [`RESOURCES`] on UDP port [`PORT`]. Confirmable
+//! requests are answered with a piggybacked acknowledgement, non-confirmable
+//! ones with a non-confirmable response. Large payloads are transferred in
+//! blocks (RFC 7959), and clients can observe the sensor readings
+//! (RFC 7641), which are sent again every [`OBSERVE_INTERVAL`], the RSSI
+//! only when it changed. `/.well-known/core` lists the resources.
+//!
+//! `message`, `block` and `server` don't depend on esp-hal or embassy-net
+//! and are tested on the host.
+
+use embassy_futures::select::{Either4, select4};
+use embassy_net::{
+    Stack,
+    udp::{PacketMetadata, UdpSocket},
+};
+use embassy_time::{Duration, Instant, Ticker, Timer};
+use esp_hal::rng::Rng;
+use esp_println::println;
+use static_cell::ConstStaticCell;
+
+use crate::{led, mqtt};
+
+pub mod block;
+pub mod message;
+pub mod server;
+
+pub use message::{Code, content_format};
+pub use server::{Request, Resource, Response, Server};
+
+pub const PORT: u16 = 5683;
+
+/// How often observers get the current readings.
+pub const OBSERVE_INTERVAL: Duration = Duration::from_secs(10);
+
+pub static RESOURCES: &[Resource] = &[
+    Resource::new("/status", "rt=\"telemetry\";ct=50")
+        .get(get_status)
+        .observable(),
+    Resource::new("/sensors/rssi", "rt=\"rssi\";ct=0")
+        .get(get_rssi)
+        .observable(),
+    Resource::new("/led", "rt=\"led\";ct=0")
+        .get(get_led)
+        .put(put_led),
+];
+
+fn get_status(_: &Request) -> Response {
+    Response::content(content_format::JSON, mqtt::telemetry().as_bytes())
+}
+
+/// Signal strength in dBm.
+fn get_rssi(_: &Request) -> Response {
+    match crate::rssi() {
+        Some(rssi) => Response::content(
+            content_format::TEXT,
+            alloc::format!("{}", rssi).into_bytes(),
+        ),
+        None => Response::new(Code::SERVICE_UNAVAILABLE),
+    }
+}
+
+fn get_led(_: &Request) -> Response {
+    let state: &[u8] = if led::is_on() { b"on" } else { b"off" };
+    Response::content(content_format::TEXT, state)
+}
+
+/// `on` or `off` takes over the LED, `auto` gives it back to the status
+/// indicator.
+fn put_led(request: &Request) -> Response {
+    match request.payload {
+        b"on" | b"off" => {
+            led::set_manual(true);
+            led::set(request.payload == b"on");
+        }
+        b"auto" => led::set_manual(false),
+        _ => return Response::new(Code::BAD_REQUEST),
+    }
+    Response::new(Code::CHANGED)
+}
+
+fn now_ms() -> u64 {
+    Instant::now().as_millis()
+}
+
+#[embassy_executor::task]
+pub async fn coap(stack: Stack<'static>, mut rng: Rng) {
+    const DATAGRAM: [u8; server::MAX_DATAGRAM_LEN] = [0; server::MAX_DATAGRAM_LEN];
+    // Static, too large for the task arena.
+    static RX_BUFFER: ConstStaticCell<[u8; 2048]> = ConstStaticCell::new([0; 2048]);
+    static TX_BUFFER: ConstStaticCell<[u8; 4096]> = ConstStaticCell::new([0; 4096]);
+    static BUF: ConstStaticCell<[u8; server::MAX_DATAGRAM_LEN]> = ConstStaticCell::new(DATAGRAM);
+    static OUT: ConstStaticCell<[u8; server::MAX_DATAGRAM_LEN]> = ConstStaticCell::new(DATAGRAM);
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_meta = [PacketMetadata::EMPTY; 8];
+    let buf = BUF.take();
+    let out = OUT.take();
+
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        RX_BUFFER.take(),
+        &mut tx_meta,
+        TX_BUFFER.take(),
+    );
+    socket.bind(PORT).unwrap();
+
+    let mut server = Server::new(RESOURCES, rng.random() as u16);
+    let mut ticker = Ticker::every(OBSERVE_INTERVAL);
+    let mut rssi = crate::rssi();
+
+    // Observers stay registered while the address is gone.
+    loop {
+        stack.wait_config_up().await;
+        println!("coap: listening on port {}", PORT);
+
+        loop {
+            while let Some((endpoint, datagram)) = server.poll_transmit(now_ms()) {
+                if let Err(e) = socket.send_to(&datagram, endpoint).await {
+                    println!("coap: send error: {:?}", e);
+                }
+            }
+
+            let deadline = server
+                .next_deadline()
+                .map(Instant::from_millis)
+                .unwrap_or(Instant::MAX);
+            match select4(
+                socket.recv_from(&mut buf[..]),
+                ticker.next(),
+                Timer::at(deadline),
+                stack.wait_config_down(),
+            )
+            .await
+            {
+                Either4::First(Ok((len, meta))) => {
+                    if let Some(n) = server.handle(meta.endpoint, &buf[..len], &mut out[..])
+                        && let Err(e) = socket.send_to(&out[..n], meta.endpoint).await
+                    {
+                        println!("coap: send error: {:?}", e);
+                    }
+                }
+                Either4::First(Err(e)) => println!("coap: receive error: {:?}", e),
+                Either4::Second(()) => {
+                    server.notify("/status", now_ms());
+                    if crate::rssi() != rssi {
+                        rssi = crate::rssi();
+                        server.notify("/sensors/rssi", now_ms());
+                    }
+                }
+                Either4::Third(()) => {}
+                Either4::Fourth(()) => break,
+            }
+        }
+    }
+}
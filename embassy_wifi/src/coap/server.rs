@@ -0,0 +1,1022 @@
+//! Requests, observers and the retransmission of confirmable notifications
+//!
+//! The server is generic over the endpoint type and gets the time passed
+//! in, it only produces datagrams and leaves sending them to the caller.
+
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write as _;
+
+use super::block::{self, Block};
+use super::message::{self, Code, Message, Type, Value, content_format, option};
+
+/// Initial retransmission timeout, doubled after every retransmission
+/// (RFC 7252, 4.8).
+pub const ACK_TIMEOUT_MS: u64 = 2000;
+pub const MAX_RETRANSMIT: u8 = 4;
+
+/// Recommended datagram size limit (RFC 7252, 4.6).
+pub const MAX_DATAGRAM_LEN: usize = 1152;
+/// Block size exponent of responses, 512 bytes, unless the client asks for
+/// smaller blocks.
+pub const BLOCK_SZX: u8 = 5;
+/// Largest request payload reassembled from Block1 transfers.
+pub const MAX_BODY_LEN: usize = 1024;
+
+pub const MAX_OBSERVERS: usize = 8;
+/// Confirmable notifications waiting for their acknowledgement.
+pub const MAX_PENDING: usize = 4;
+/// Every this many notifications to an observer is confirmable, to find
+/// out whether it is still interested (RFC 7641, 4.5).
+pub const CONFIRMABLE_EVERY: u32 = 5;
+
+pub const WELL_KNOWN_CORE: &str = "/.well-known/core";
+
+const MAX_PATH_LEN: usize = 64;
+const MAX_QUERIES: usize = 4;
+/// Datagrams waiting to be sent, the oldest are dropped.
+const MAX_QUEUED: usize = 8;
+
+/// Options handled here, any other critical option is rejected.
+const KNOWN_OPTIONS: &[u16] = &[
+    option::URI_HOST,
+    option::ETAG,
+    option::OBSERVE,
+    option::URI_PORT,
+    option::URI_PATH,
+    option::CONTENT_FORMAT,
+    option::MAX_AGE,
+    option::URI_QUERY,
+    option::ACCEPT,
+    option::BLOCK2,
+    option::BLOCK1,
+    option::SIZE2,
+    option::SIZE1,
+];
+
+pub type Handler = fn(&Request) -> Response;
+
+#[derive(Debug)]
+pub struct Request<'a> {
+    pub method: Code,
+    /// The Uri-Path options joined with `/`, e.g. `/sensors/rssi`
+    pub path: &'a str,
+    pub queries: heapless::Vec<&'a str, MAX_QUERIES>,
+    pub content_format: Option<u16>,
+    /// The whole payload, reassembled if sent in blocks
+    pub payload: &'a [u8],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub code: Code,
+    pub content_format: Option<u16>,
+    pub payload: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(code: Code) -> Self {
+        Self {
+            code,
+            content_format: None,
+            payload: Vec::new(),
+        }
+    }
+
+    /// 2.05 Content
+    pub fn content(content_format: u16, payload: impl Into<Vec<u8>>) -> Self {
+        Self {
+            code: Code::CONTENT,
+            content_format: Some(content_format),
+            payload: payload.into(),
+        }
+    }
+}
+
+pub struct Resource {
+    pub path: &'static str,
+    /// Link-format attributes listed in `/.well-known/core`, e.g.
+    /// `rt="temperature";ct=0`
+    pub attributes: &'static str,
+    pub observable: bool,
+    pub get: Option<Handler>,
+    pub put: Option<Handler>,
+}
+
+impl Resource {
+    pub const fn new(path: &'static str, attributes: &'static str) -> Self {
+        Self {
+            path,
+            attributes,
+            observable: false,
+            get: None,
+            put: None,
+        }
+    }
+
+    pub const fn get(self, handler: Handler) -> Self {
+        Self {
+            get: Some(handler),
+            ..self
+        }
+    }
+
+    pub const fn put(self, handler: Handler) -> Self {
+        Self {
+            put: Some(handler),
+            ..self
+        }
+    }
+
+    pub const fn observable(self) -> Self {
+        Self {
+            observable: true,
+            ..self
+        }
+    }
+}
+
+struct Observer<A> {
+    endpoint: A,
+    token: heapless::Vec<u8, { message::MAX_TOKEN_LEN }>,
+    resource: usize,
+    notifications: u32,
+    /// Of the last notification, a reset for it ends the observation
+    message_id: u16,
+}
+
+struct Pending<A> {
+    endpoint: A,
+    token: heapless::Vec<u8, { message::MAX_TOKEN_LEN }>,
+    message_id: u16,
+    datagram: Vec<u8>,
+    retransmissions: u8,
+    timeout_ms: u64,
+    deadline_ms: u64,
+}
+
+struct Upload<A> {
+    endpoint: A,
+    resource: usize,
+    body: Vec<u8>,
+}
+
+/// A response with the options added by the server.
+struct Reply {
+    response: Response,
+    observe: Option<u32>,
+    block1: Option<Block>,
+    block2: Option<Block>,
+    size1: Option<u32>,
+    size2: Option<u32>,
+}
+
+impl Reply {
+    fn new(response: Response) -> Self {
+        Self {
+            response,
+            observe: None,
+            block1: None,
+            block2: None,
+            size1: None,
+            size2: None,
+        }
+    }
+
+    fn error(code: Code) -> Self {
+        Self::new(Response::new(code))
+    }
+
+    fn encode(
+        &self,
+        ty: Type,
+        message_id: u16,
+        token: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, message::Error> {
+        let mut message = Message::new(ty, self.response.code, message_id, token);
+        let options = [
+            (option::OBSERVE, self.observe),
+            (
+                option::CONTENT_FORMAT,
+                self.response.content_format.map(u32::from),
+            ),
+            (option::BLOCK2, self.block2.map(Block::encode)),
+            (option::BLOCK1, self.block1.map(Block::encode)),
+            (option::SIZE2, self.size2),
+            (option::SIZE1, self.size1),
+        ];
+        for (number, value) in options {
+            if let Some(value) = value {
+                message.add_option(number, Value::Uint(value))?;
+            }
+        }
+        message.payload = &self.response.payload;
+        message.encode(out)
+    }
+
+    /// Cut the payload down to the block asked for, or to the first block
+    /// if it is too large for one datagram.
+    fn split(mut self, requested: Option<Block>) -> Self {
+        if self.response.code.class() != 2 {
+            return self;
+        }
+        let (num, szx) = match requested {
+            // Smaller blocks than asked for, with the number scaled to match
+            // (RFC 7959, 2.4).
+            Some(block) if block.szx > BLOCK_SZX => {
+                (block.num << (block.szx - BLOCK_SZX), BLOCK_SZX)
+            }
+            Some(block) => (block.num, block.szx),
+            None if self.response.payload.len() > 16 << BLOCK_SZX => (0, BLOCK_SZX),
+            None => return self,
+        };
+        let total = self.response.payload.len();
+        match block::slice(&self.response.payload, num, szx) {
+            Some((data, block)) => {
+                self.response.payload = data.to_vec();
+                self.block2 = Some(block);
+                if num == 0 {
+                    self.size2 = Some(total as u32);
+                }
+                self
+            }
+            None => Self::error(Code::BAD_OPTION),
+        }
+    }
+}
+
+pub struct Server<A> {
+    resources: &'static [Resource],
+    observers: heapless::Vec<Observer<A>, MAX_OBSERVERS>,
+    pending: heapless::Vec<Pending<A>, MAX_PENDING>,
+    queue: VecDeque<(A, Vec<u8>)>,
+    upload: Option<Upload<A>>,
+    message_id: u16,
+    /// Observe option value last sent, 24 bits
+    sequence: u32,
+}
+
+impl<A: Copy + PartialEq> Server<A> {
+    /// `message_id` should be random, so message IDs aren't reused after a
+    /// restart.
+    pub fn new(resources: &'static [Resource], message_id: u16) -> Self {
+        Self {
+            resources,
+            observers: heapless::Vec::new(),
+            pending: heapless::Vec::new(),
+            queue: VecDeque::new(),
+            upload: None,
+            message_id,
+            sequence: 0,
+        }
+    }
+
+    pub fn observers(&self) -> usize {
+        self.observers.len()
+    }
+
+    /// Handle a datagram from `from`, returns the length of the reply
+    /// written to `out`, if any.
+    pub fn handle(&mut self, from: A, packet: &[u8], out: &mut [u8]) -> Option<usize> {
+        let message = match Message::parse(packet) {
+            Ok(message) => message,
+            // Reject malformed confirmable messages, silently ignore the
+            // others (RFC 7252, 4.2 and 4.3).
+            Err(_) => {
+                return match message::parse_header(packet) {
+                    Ok((Type::Confirmable, _, _, message_id)) => reset(message_id, out),
+                    _ => None,
+                };
+            }
+        };
+
+        match message.ty {
+            Type::Acknowledgement | Type::Reset => {
+                self.acknowledged(from, message.message_id, message.ty == Type::Reset);
+                return None;
+            }
+            // An empty confirmable message is a ping, requests are the only
+            // other messages a server expects.
+            _ if !message.code.is_request() => {
+                return match message.ty {
+                    Type::Confirmable => reset(message.message_id, out),
+                    _ => None,
+                };
+            }
+            _ => {}
+        }
+
+        let reply = self.respond(from, &message);
+        // Piggybacked on the acknowledgement of a confirmable request.
+        let (ty, message_id) = match message.ty {
+            Type::Confirmable => (Type::Acknowledgement, message.message_id),
+            _ => (Type::NonConfirmable, self.next_message_id()),
+        };
+        reply.encode(ty, message_id, message.token, out).ok()
+    }
+
+    /// Send the current representation of the resource at `path` to its
+    /// observers.
+    pub fn notify(&mut self, path: &str, now_ms: u64) {
+        let Some(index) = self.resources.iter().position(|r| r.path == path) else {
+            return;
+        };
+        let Some(get) = self.resources[index].get else {
+            return;
+        };
+        if !self.observers.iter().any(|o| o.resource == index) {
+            return;
+        }
+        let response = get(&Request {
+            method: Code::GET,
+            path,
+            queries: heapless::Vec::new(),
+            content_format: None,
+            payload: &[],
+        });
+
+        let mut out = [0; MAX_DATAGRAM_LEN];
+        for i in 0..self.observers.len() {
+            if self.observers[i].resource != index {
+                continue;
+            }
+            let (endpoint, token) = (self.observers[i].endpoint, self.observers[i].token.clone());
+
+            // A newer notification replaces one still being retransmitted,
+            // and is confirmable in its place (RFC 7641, 4.5.2).
+            let superseded = match self
+                .pending
+                .iter()
+                .position(|p| p.endpoint == endpoint && p.token == token)
+            {
+                Some(i) => {
+                    self.pending.swap_remove(i);
+                    true
+                }
+                None => false,
+            };
+
+            let observer = &mut self.observers[i];
+            observer.notifications = observer.notifications.wrapping_add(1);
+            let confirmable = (superseded
+                || observer.notifications.is_multiple_of(CONFIRMABLE_EVERY))
+                && !self.pending.is_full();
+
+            let mut reply = Reply::new(response.clone()).split(None);
+            reply.observe = Some(self.next_sequence());
+            let message_id = self.next_message_id();
+            self.observers[i].message_id = message_id;
+            let ty = if confirmable {
+                Type::Confirmable
+            } else {
+                Type::NonConfirmable
+            };
+            let Ok(len) = reply.encode(ty, message_id, &token, &mut out) else {
+                continue;
+            };
+            let datagram = out[..len].to_vec();
+
+            if confirmable {
+                let timeout_ms = initial_timeout(message_id);
+                self.pending
+                    .push(Pending {
+                        endpoint,
+                        token,
+                        message_id,
+                        datagram: datagram.clone(),
+                        retransmissions: 0,
+                        timeout_ms,
+                        deadline_ms: now_ms + timeout_ms,
+                    })
+                    .ok();
+            }
+            self.enqueue(endpoint, datagram);
+        }
+
+        // An error response ends the observation (RFC 7641, 3.2).
+        if response.code.class() != 2 {
+            self.observers.retain(|o| o.resource != index);
+        }
+    }
+
+    /// The next datagram to send: queued notifications, then due
+    /// retransmissions.
+    pub fn poll_transmit(&mut self, now_ms: u64) -> Option<(A, Vec<u8>)> {
+        if let Some(datagram) = self.queue.pop_front() {
+            return Some(datagram);
+        }
+        while let Some(i) = self.pending.iter().position(|p| p.deadline_ms <= now_ms) {
+            let pending = &mut self.pending[i];
+            if pending.retransmissions == MAX_RETRANSMIT {
+                // The observer is gone (RFC 7641, 4.5).
+                let pending = self.pending.swap_remove(i);
+                self.deregister(pending.endpoint, &pending.token);
+                continue;
+            }
+            pending.retransmissions += 1;
+            pending.timeout_ms *= 2;
+            pending.deadline_ms = now_ms + pending.timeout_ms;
+            return Some((pending.endpoint, pending.datagram.clone()));
+        }
+        None
+    }
+
+    /// When [`Self::poll_transmit`] has to be called next for a
+    /// retransmission.
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.pending.iter().map(|p| p.deadline_ms).min()
+    }
+
+    fn respond(&mut self, from: A, message: &Message) -> Reply {
+        for (number, _) in &message.options {
+            if option::is_critical(*number) && !KNOWN_OPTIONS.contains(number) {
+                return Reply::error(Code::BAD_OPTION);
+            }
+        }
+
+        let mut path = heapless::String::<MAX_PATH_LEN>::new();
+        let mut queries = heapless::Vec::<&str, MAX_QUERIES>::new();
+        for segment in message.options(option::URI_PATH) {
+            let Some(segment) = segment
+                .as_bytes()
+                .and_then(|s| core::str::from_utf8(s).ok())
+            else {
+                return Reply::error(Code::BAD_REQUEST);
+            };
+            if path.push('/').is_err() || path.push_str(segment).is_err() {
+                return Reply::error(Code::NOT_FOUND);
+            }
+        }
+        if path.is_empty() {
+            path.push('/').ok();
+        }
+        for query in message.options(option::URI_QUERY) {
+            let Some(query) = query.as_bytes().and_then(|s| core::str::from_utf8(s).ok()) else {
+                return Reply::error(Code::BAD_REQUEST);
+            };
+            if queries.push(query).is_err() {
+                return Reply::error(Code::BAD_REQUEST);
+            }
+        }
+
+        let uint = |number| message.option(number).map(|value| value.as_uint());
+        let (block1, block2) = match (
+            uint(option::BLOCK1).map(|v| v.and_then(Block::decode)),
+            uint(option::BLOCK2).map(|v| v.and_then(Block::decode)),
+        ) {
+            (Some(None), _) | (_, Some(None)) => return Reply::error(Code::BAD_OPTION),
+            (block1, block2) => (block1.flatten(), block2.flatten()),
+        };
+
+        if path == WELL_KNOWN_CORE {
+            if message.code != Code::GET {
+                return Reply::error(Code::METHOD_NOT_ALLOWED);
+            }
+            let links = self.links(&queries);
+            return Reply::new(Response::content(content_format::LINK_FORMAT, links)).split(block2);
+        }
+
+        let Some(index) = self.resources.iter().position(|r| r.path == path) else {
+            return Reply::error(Code::NOT_FOUND);
+        };
+        let resource = &self.resources[index];
+        let handler = match message.code {
+            Code::GET => resource.get,
+            Code::PUT => resource.put,
+            _ => None,
+        };
+        let Some(handler) = handler else {
+            return Reply::error(Code::METHOD_NOT_ALLOWED);
+        };
+
+        let body;
+        let mut request = Request {
+            method: message.code,
+            path: &path,
+            queries,
+            content_format: uint(option::CONTENT_FORMAT).flatten().map(|v| v as u16),
+            payload: message.payload,
+        };
+        if let Some(block) = block1 {
+            match self.reassemble(from, index, block, message.payload) {
+                Ok(Some(whole)) => {
+                    body = whole;
+                    request.payload = &body;
+                }
+                Ok(None) => {
+                    let mut reply = Reply::error(Code::CONTINUE);
+                    reply.block1 = Some(block);
+                    return reply;
+                }
+                Err(code) => {
+                    let mut reply = Reply::error(code);
+                    if code == Code::REQUEST_ENTITY_TOO_LARGE {
+                        reply.size1 = Some(MAX_BODY_LEN as u32);
+                    }
+                    return reply;
+                }
+            }
+        }
+
+        let response = handler(&request);
+        if let Some(accept) = uint(option::ACCEPT).flatten()
+            && response
+                .content_format
+                .is_some_and(|format| format as u32 != accept)
+        {
+            return Reply::error(Code::NOT_ACCEPTABLE);
+        }
+        let registered = response.code.class() == 2;
+        let mut reply = Reply::new(response);
+        reply.block1 = block1;
+
+        // Later blocks of an observed resource are plain requests.
+        if message.code == Code::GET && block2.is_none_or(|block| block.num == 0) {
+            match uint(option::OBSERVE).flatten() {
+                Some(0) if registered && resource.observable => {
+                    reply.observe = self.register(from, message.token, index);
+                }
+                Some(1) => self.deregister(from, message.token),
+                _ => {}
+            }
+        }
+        reply.split(block2)
+    }
+
+    /// Add a Block1 block to the body, returns the body after the last
+    /// block.
+    fn reassemble(
+        &mut self,
+        from: A,
+        resource: usize,
+        block: Block,
+        data: &[u8],
+    ) -> Result<Option<Vec<u8>>, Code> {
+        // All but the last block have the full size.
+        if block.more && data.len() != block.size() {
+            return Err(Code::BAD_REQUEST);
+        }
+        if block.num == 0 {
+            self.upload = Some(Upload {
+                endpoint: from,
+                resource,
+                body: Vec::new(),
+            });
+        }
+        let Some(upload) = self.upload.as_mut().filter(|upload| {
+            upload.endpoint == from
+                && upload.resource == resource
+                && upload.body.len() == block.offset()
+        }) else {
+            return Err(Code::REQUEST_ENTITY_INCOMPLETE);
+        };
+        if upload.body.len() + data.len() > MAX_BODY_LEN {
+            self.upload = None;
+            return Err(Code::REQUEST_ENTITY_TOO_LARGE);
+        }
+        upload.body.extend_from_slice(data);
+        if block.more {
+            Ok(None)
+        } else {
+            Ok(self.upload.take().map(|upload| upload.body))
+        }
+    }
+
+    /// Returns the Observe value for the response, `None` if there is no
+    /// room for another observer.
+    fn register(&mut self, endpoint: A, token: &[u8], resource: usize) -> Option<u32> {
+        match self
+            .observers
+            .iter_mut()
+            .find(|o| o.endpoint == endpoint && o.token == token)
+        {
+            Some(observer) => observer.resource = resource,
+            None => self
+                .observers
+                .push(Observer {
+                    endpoint,
+                    token: heapless::Vec::from_slice(token).ok()?,
+                    resource,
+                    notifications: 0,
+                    message_id: 0,
+                })
+                .ok()?,
+        }
+        Some(self.next_sequence())
+    }
+
+    fn deregister(&mut self, endpoint: A, token: &[u8]) {
+        self.observers
+            .retain(|o| !(o.endpoint == endpoint && o.token == token));
+        self.pending
+            .retain(|p| !(p.endpoint == endpoint && p.token == token));
+    }
+
+    fn acknowledged(&mut self, from: A, message_id: u16, reset: bool) {
+        if let Some(i) = self
+            .pending
+            .iter()
+            .position(|p| p.endpoint == from && p.message_id == message_id)
+        {
+            let pending = self.pending.swap_remove(i);
+            if reset {
+                self.deregister(from, &pending.token);
+            }
+        } else if reset {
+            // A reset for a non-confirmable notification.
+            self.observers
+                .retain(|o| !(o.endpoint == from && o.message_id == message_id));
+        }
+    }
+
+    /// The CoRE link format listing of the resources (RFC 6690), filtered by
+    /// `name=value` queries, where a trailing `*` matches a prefix.
+    fn links(&self, queries: &[&str]) -> String {
+        let mut links = String::new();
+        for resource in self
+            .resources
+            .iter()
+            .filter(|resource| queries.iter().all(|query| matches(resource, query)))
+        {
+            if !links.is_empty() {
+                links.push(',');
+            }
+            write!(links, "<{}>", resource.path).ok();
+            if !resource.attributes.is_empty() {
+                write!(links, ";{}", resource.attributes).ok();
+            }
+            if resource.observable {
+                links.push_str(";obs");
+            }
+        }
+        links
+    }
+
+    fn enqueue(&mut self, endpoint: A, datagram: Vec<u8>) {
+        if self.queue.len() == MAX_QUEUED {
+            self.queue.pop_front();
+        }
+        self.queue.push_back((endpoint, datagram));
+    }
+
+    fn next_message_id(&mut self) -> u16 {
+        self.message_id = self.message_id.wrapping_add(1);
+        self.message_id
+    }
+
+    fn next_sequence(&mut self) -> u32 {
+        self.sequence = (self.sequence + 1) & 0xff_ffff;
+        self.sequence
+    }
+}
+
+fn reset(message_id: u16, out: &mut [u8]) -> Option<usize> {
+    Message::new(Type::Reset, Code::EMPTY, message_id, &[])
+        .encode(out)
+        .ok()
+}
+
+/// Between `ACK_TIMEOUT_MS` and 1.5 times that, spread by message ID rather
+/// than a random number.
+fn initial_timeout(message_id: u16) -> u64 {
+    ACK_TIMEOUT_MS + message_id as u64 * 7919 % (ACK_TIMEOUT_MS / 2)
+}
+
+fn matches(resource: &Resource, query: &str) -> bool {
+    let Some((name, value)) = query.split_once('=') else {
+        return true;
+    };
+    let (value, prefix) = match value.strip_suffix('*') {
+        Some(value) => (value, true),
+        None => (value, false),
+    };
+    let matches = |candidate: &str| {
+        if prefix {
+            candidate.starts_with(value)
+        } else {
+            candidate == value
+        }
+    };
+    if name == "href" {
+        return matches(resource.path);
+    }
+    resource
+        .attributes
+        .split(';')
+        .filter_map(|attribute| attribute.split_once('='))
+        .any(|(n, v)| n == name && v.trim_matches('"').split(' ').any(matches))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{format, vec};
+
+    use super::*;
+
+    static RESOURCES: &[Resource] = &[
+        Resource::new("/hello", "rt=\"greeting\";ct=0")
+            .get(|_| Response::content(content_format::TEXT, b"world".as_slice())),
+        Resource::new("/big", "").get(|_| {
+            Response::content(
+                content_format::OCTET_STREAM,
+                (0..1300).map(|i| i as u8).collect::<Vec<_>>(),
+            )
+        }),
+        Resource::new("/value", "rt=\"sensor\"")
+            .get(|_| Response::content(content_format::TEXT, b"42".as_slice()))
+            .observable(),
+        Resource::new("/echo", "").put(|request| {
+            let mut response = Response::new(Code::CHANGED);
+            response.payload = format!("{}", request.payload.len()).into_bytes();
+            response
+        }),
+    ];
+
+    fn request(
+        ty: Type,
+        message_id: u16,
+        code: Code,
+        path: &str,
+        options: &[(u16, u32)],
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let mut message = Message::new(ty, code, message_id, &[0xab, 0xcd]);
+        for segment in path.split('/').filter(|s| !s.is_empty()) {
+            message
+                .add_option(option::URI_PATH, Value::Bytes(segment.as_bytes()))
+                .unwrap();
+        }
+        for (number, value) in options {
+            message.add_option(*number, Value::Uint(*value)).unwrap();
+        }
+        message.payload = payload;
+        let mut buf = [0; MAX_DATAGRAM_LEN];
+        let n = message.encode(&mut buf).unwrap();
+        buf[..n].to_vec()
+    }
+
+    fn get(server: &mut Server<u8>, path: &str, options: &[(u16, u32)]) -> Vec<u8> {
+        let packet = request(Type::Confirmable, 7, Code::GET, path, options, &[]);
+        let mut out = [0; MAX_DATAGRAM_LEN];
+        let n = server.handle(1, &packet, &mut out).unwrap();
+        out[..n].to_vec()
+    }
+
+    fn uint(message: &Message, number: u16) -> Option<u32> {
+        message.option(number).and_then(|value| value.as_uint())
+    }
+
+    #[test]
+    fn test_request() {
+        let mut server = Server::new(RESOURCES, 100);
+        let mut out = [0; MAX_DATAGRAM_LEN];
+
+        let reply = get(&mut server, "/hello", &[]);
+        let reply = Message::parse(&reply).unwrap();
+        assert_eq!(reply.ty, Type::Acknowledgement);
+        assert_eq!(reply.message_id, 7);
+        assert_eq!(reply.token, [0xab, 0xcd]);
+        assert_eq!(reply.code, Code::CONTENT);
+        assert_eq!(uint(&reply, option::CONTENT_FORMAT), Some(0));
+        assert_eq!(reply.payload, b"world");
+
+        let packet = request(Type::NonConfirmable, 8, Code::GET, "/hello", &[], &[]);
+        let n = server.handle(1, &packet, &mut out).unwrap();
+        assert_eq!(
+            Message::parse(&out[..n]).map(|reply| (reply.ty, reply.message_id)),
+            Ok((Type::NonConfirmable, 101))
+        );
+
+        let code = |reply: Vec<u8>| Message::parse(&reply).unwrap().code;
+        assert_eq!(code(get(&mut server, "/missing", &[])), Code::NOT_FOUND);
+        assert_eq!(
+            code(get(&mut server, "/hello", &[(9, 1)])),
+            Code::BAD_OPTION
+        );
+        assert_eq!(
+            code(get(&mut server, "/hello", &[(option::ACCEPT, 50)])),
+            Code::NOT_ACCEPTABLE
+        );
+        let packet = request(Type::Confirmable, 9, Code::POST, "/hello", &[], &[]);
+        let n = server.handle(1, &packet, &mut out).unwrap();
+        assert_eq!(
+            Message::parse(&out[..n]).unwrap().code,
+            Code::METHOD_NOT_ALLOWED
+        );
+
+        // Pings and malformed confirmable messages are reset.
+        let n = server
+            .handle(1, &[0x40, 0x00, 0x12, 0x34], &mut out)
+            .unwrap();
+        assert_eq!(out[..n], [0x70, 0x00, 0x12, 0x34]);
+        let n = server
+            .handle(1, &[0x40, 0x01, 0x12, 0x35, 0xf0], &mut out)
+            .unwrap();
+        assert_eq!(out[..n], [0x70, 0x00, 0x12, 0x35]);
+        assert_eq!(
+            server.handle(1, &[0x50, 0x01, 0x12, 0x36, 0xf0], &mut out),
+            None
+        );
+        assert_eq!(server.handle(1, &[0x60, 0x00, 0x12, 0x37], &mut out), None);
+
+        let reply = get(&mut server, WELL_KNOWN_CORE, &[]);
+        let reply = Message::parse(&reply).unwrap();
+        assert_eq!(uint(&reply, option::CONTENT_FORMAT), Some(40));
+        assert_eq!(
+            reply.payload,
+            b"</hello>;rt=\"greeting\";ct=0,</big>,</value>;rt=\"sensor\";obs,</echo>"
+        );
+
+        let mut message = Message::new(Type::Confirmable, Code::GET, 10, &[]);
+        for segment in [".well-known", "core"] {
+            message
+                .add_option(option::URI_PATH, Value::Bytes(segment.as_bytes()))
+                .unwrap();
+        }
+        message
+            .add_option(option::URI_QUERY, Value::Bytes(b"rt=sens*"))
+            .unwrap();
+        let mut packet = [0; 64];
+        let len = message.encode(&mut packet).unwrap();
+        let n = server.handle(1, &packet[..len], &mut out).unwrap();
+        assert_eq!(
+            Message::parse(&out[..n]).unwrap().payload,
+            b"</value>;rt=\"sensor\";obs"
+        );
+    }
+
+    #[test]
+    fn test_block2() {
+        let mut server = Server::new(RESOURCES, 0);
+
+        let reply = get(&mut server, "/big", &[]);
+        let reply = Message::parse(&reply).unwrap();
+        assert_eq!(reply.payload.len(), 512);
+        assert_eq!(
+            uint(&reply, option::BLOCK2),
+            Some(Block::new(0, true, 5).encode())
+        );
+        assert_eq!(uint(&reply, option::SIZE2), Some(1300));
+
+        let reply = get(
+            &mut server,
+            "/big",
+            &[(option::BLOCK2, Block::new(2, false, 5).encode())],
+        );
+        let reply = Message::parse(&reply).unwrap();
+        assert_eq!(
+            reply.payload,
+            &(1024..1300).map(|i| i as u8).collect::<Vec<_>>()[..]
+        );
+        assert_eq!(
+            uint(&reply, option::BLOCK2),
+            Some(Block::new(2, false, 5).encode())
+        );
+        assert_eq!(uint(&reply, option::SIZE2), None);
+
+        // 1024 byte blocks are answered with 512 byte ones.
+        let reply = get(
+            &mut server,
+            "/big",
+            &[(option::BLOCK2, Block::new(1, false, 6).encode())],
+        );
+        let reply = Message::parse(&reply).unwrap();
+        assert_eq!(reply.payload.len(), 276);
+        assert_eq!(
+            uint(&reply, option::BLOCK2),
+            Some(Block::new(2, false, 5).encode())
+        );
+
+        let reply = get(
+            &mut server,
+            "/big",
+            &[(option::BLOCK2, Block::new(3, false, 5).encode())],
+        );
+        assert_eq!(Message::parse(&reply).unwrap().code, Code::BAD_OPTION);
+    }
+
+    #[test]
+    fn test_block1() {
+        let mut server = Server::new(RESOURCES, 0);
+        let mut out = [0; MAX_DATAGRAM_LEN];
+        let mut put = |server: &mut Server<u8>, block: Block, payload: &[u8]| {
+            let packet = request(
+                Type::Confirmable,
+                block.num as u16,
+                Code::PUT,
+                "/echo",
+                &[(option::BLOCK1, block.encode())],
+                payload,
+            );
+            let n = server.handle(1, &packet, &mut out).unwrap();
+            let reply = Message::parse(&out[..n]).unwrap();
+            (
+                reply.code,
+                uint(&reply, option::BLOCK1).and_then(Block::decode),
+                reply.payload.to_vec(),
+            )
+        };
+
+        let block = Block::new(0, true, 0);
+        assert_eq!(
+            put(&mut server, block, &[0; 16]),
+            (Code::CONTINUE, Some(block), vec![])
+        );
+        let block = Block::new(1, true, 0);
+        assert_eq!(
+            put(&mut server, block, &[0; 16]),
+            (Code::CONTINUE, Some(block), vec![])
+        );
+        let block = Block::new(2, false, 0);
+        assert_eq!(
+            put(&mut server, block, &[0; 5]),
+            (Code::CHANGED, Some(block), b"37".to_vec())
+        );
+
+        // Out of order, then too large.
+        let (code, ..) = put(&mut server, Block::new(5, false, 0), &[0; 5]);
+        assert_eq!(code, Code::REQUEST_ENTITY_INCOMPLETE);
+        put(&mut server, Block::new(0, true, 6), &[0; 1024]);
+        let (code, ..) = put(&mut server, Block::new(1, true, 6), &[0; 1024]);
+        assert_eq!(code, Code::REQUEST_ENTITY_TOO_LARGE);
+    }
+
+    #[test]
+    fn test_observe() {
+        let mut server = Server::new(RESOURCES, 0);
+        let mut out = [0; MAX_DATAGRAM_LEN];
+
+        let reply = get(&mut server, "/value", &[(option::OBSERVE, 0)]);
+        let reply = Message::parse(&reply).unwrap();
+        assert_eq!(uint(&reply, option::OBSERVE), Some(1));
+        assert_eq!(server.observers(), 1);
+        // Not observable
+        let reply = get(&mut server, "/hello", &[(option::OBSERVE, 0)]);
+        assert_eq!(
+            uint(&Message::parse(&reply).unwrap(), option::OBSERVE),
+            None
+        );
+
+        // Four non-confirmable notifications, then a confirmable one.
+        for i in 1..=CONFIRMABLE_EVERY {
+            server.notify("/value", 0);
+            let (to, datagram) = server.poll_transmit(0).unwrap();
+            let notification = Message::parse(&datagram).unwrap();
+            assert_eq!(to, 1);
+            assert_eq!(notification.token, [0xab, 0xcd]);
+            assert_eq!(notification.payload, b"42");
+            assert_eq!(uint(&notification, option::OBSERVE), Some(i + 1));
+            let ty = if i == CONFIRMABLE_EVERY {
+                Type::Confirmable
+            } else {
+                Type::NonConfirmable
+            };
+            assert_eq!(notification.ty, ty);
+        }
+        assert_eq!(server.poll_transmit(0), None);
+
+        // Retransmitted until acknowledged.
+        let deadline = server.next_deadline().unwrap();
+        assert!((ACK_TIMEOUT_MS..ACK_TIMEOUT_MS * 3 / 2).contains(&deadline));
+        let (_, datagram) = server.poll_transmit(deadline).unwrap();
+        let notification = Message::parse(&datagram).unwrap();
+        let ack = [
+            0x60,
+            0x00,
+            (notification.message_id >> 8) as u8,
+            notification.message_id as u8,
+        ];
+        assert_eq!(server.handle(1, &ack, &mut out), None);
+        assert_eq!(server.next_deadline(), None);
+
+        // Given up after MAX_RETRANSMIT retransmissions.
+        for _ in 0..CONFIRMABLE_EVERY {
+            server.notify("/value", 0);
+            server.poll_transmit(0).unwrap();
+        }
+        let mut retransmissions = 0;
+        while let Some(deadline) = server.next_deadline() {
+            if server.poll_transmit(deadline).is_some() {
+                retransmissions += 1;
+            }
+        }
+        assert_eq!(retransmissions, MAX_RETRANSMIT);
+        assert_eq!(server.observers(), 0);
+        server.notify("/value", 0);
+        assert_eq!(server.poll_transmit(0), None);
+
+        // A reset for a notification or Observe 1 ends the observation.
+        get(&mut server, "/value", &[(option::OBSERVE, 0)]);
+        server.notify("/value", 0);
+        let (_, datagram) = server.poll_transmit(0).unwrap();
+        let id = Message::parse(&datagram).unwrap().message_id;
+        server.handle(1, &[0x70, 0x00, (id >> 8) as u8, id as u8], &mut out);
+        assert_eq!(server.observers(), 0);
+        get(&mut server, "/value", &[(option::OBSERVE, 0)]);
+        assert_eq!(server.observers(), 1);
+        get(&mut server, "/value", &[(option::OBSERVE, 1)]);
+        assert_eq!(server.observers(), 0);
+    }
+}
@@ -0,0 +1,159 @@
+//! The datagram format CSI frames are streamed in
+//!
+//! Every UDP datagram carries one frame, all integers are little-endian:
+//!
+//! | Offset | Size | Field                                                  |
+//! |--------|------|--------------------------------------------------------|
+//! | 0      | 2    | Magic, `CS`                                            |
+//! | 2      | 1    | Version, [`VERSION`]                                   |
+//! | 3      | 1    | Flags, [`FLAG_FIRST_WORD_INVALID`], [`FLAG_TRUNCATED`] |
+//! | 4      | 4    | Sequence number, counts sent datagrams                 |
+//! | 8      | 4    | Frames dropped since boot because the queue was full   |
+//! | 12     | 8    | Unix time in µs (i64), 0 before SNTP synchronized      |
+//! | 20     | 4    | Radio timestamp in µs, wraps around                    |
+//! | 24     | 1    | RSSI in dBm (i8)                                       |
+//! | 25     | 1    | Primary channel                                        |
+//! | 26     | 6    | MAC address of the transmitter                         |
+//! | 32     | 2    | Length of the CSI data in bytes                        |
+//! | 34     | n    | CSI data                                               |
+//!
+//! The CSI data is as reported by esp-wifi: two signed bytes per
+//! subcarrier, the imaginary part first. [`subcarriers`] splits it.
+//!
+//! Only depends on `core`. The host decoder (`csi_decode`) has a copy of
+//! this file, which `host_tests` checks against the one of `embassy_wifi`.
+
+pub const MAGIC: [u8; 2] = *b"CS";
+pub const VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 34;
+
+/// The first four bytes of the CSI data are invalid, a hardware limitation
+/// of some chips.
+pub const FLAG_FIRST_WORD_INVALID: u8 = 0x01;
+/// The CSI data was longer than the device buffers and got cut off.
+pub const FLAG_TRUNCATED: u8 = 0x02;
+
+/// Errors
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    BufferTooSmall,
+    BadMagic,
+    UnsupportedVersion(u8),
+    /// Shorter than the header or the announced data length
+    Truncated,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Header {
+    pub flags: u8,
+    pub seq: u32,
+    pub dropped: u32,
+    /// Unix time in µs, 0 if unknown
+    pub time: i64,
+    /// Radio timestamp in µs
+    pub timestamp: u32,
+    pub rssi: i8,
+    pub channel: u8,
+    pub mac: [u8; 6],
+}
+
+/// Write a datagram, returns its length.
+pub fn encode(header: &Header, data: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    let len = u16::try_from(data.len()).map_err(|_| Error::BufferTooSmall)?;
+    let total = HEADER_LEN + data.len();
+    let out = out.get_mut(..total).ok_or(Error::BufferTooSmall)?;
+
+    out[0..2].copy_from_slice(&MAGIC);
+    out[2] = VERSION;
+    out[3] = header.flags;
+    out[4..8].copy_from_slice(&header.seq.to_le_bytes());
+    out[8..12].copy_from_slice(&header.dropped.to_le_bytes());
+    out[12..20].copy_from_slice(&header.time.to_le_bytes());
+    out[20..24].copy_from_slice(&header.timestamp.to_le_bytes());
+    out[24] = header.rssi as u8;
+    out[25] = header.channel;
+    out[26..32].copy_from_slice(&header.mac);
+    out[32..34].copy_from_slice(&len.to_le_bytes());
+    out[HEADER_LEN..].copy_from_slice(data);
+    Ok(total)
+}
+
+/// Read a datagram, returns the header and the CSI data.
+pub fn decode(buf: &[u8]) -> Result<(Header, &[u8]), Error> {
+    if buf.len() < HEADER_LEN {
+        return Err(Error::Truncated);
+    }
+    if buf[0..2] != MAGIC {
+        return Err(Error::BadMagic);
+    }
+    if buf[2] != VERSION {
+        return Err(Error::UnsupportedVersion(buf[2]));
+    }
+
+    let u32_at = |i: usize| u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+    let mut time = [0; 8];
+    time.copy_from_slice(&buf[12..20]);
+    let mut mac = [0; 6];
+    mac.copy_from_slice(&buf[26..32]);
+
+    let header = Header {
+        flags: buf[3],
+        seq: u32_at(4),
+        dropped: u32_at(8),
+        time: i64::from_le_bytes(time),
+        timestamp: u32_at(20),
+        rssi: buf[24] as i8,
+        channel: buf[25],
+        mac,
+    };
+    let len = u16::from_le_bytes([buf[32], buf[33]]) as usize;
+    let data = buf
+        .get(HEADER_LEN..HEADER_LEN + len)
+        .ok_or(Error::Truncated)?;
+    Ok((header, data))
+}
+
+/// The subcarriers in `data` as `(real, imaginary)` pairs.
+pub fn subcarriers(data: &[u8]) -> impl Iterator<Item = (i8, i8)> + '_ {
+    data.as_chunks::<2>()
+        .0
+        .iter()
+        .map(|[imaginary, real]| (*real as i8, *imaginary as i8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let header = Header {
+            flags: FLAG_FIRST_WORD_INVALID,
+            seq: 7,
+            dropped: 2,
+            time: 1_700_000_000_123_456,
+            timestamp: 0xdead_beef,
+            rssi: -57,
+            channel: 6,
+            mac: [0x02, 0x11, 0x22, 0x33, 0x44, 0x55],
+        };
+        let data = [0xfe, 0x03, 0x05, 0x80];
+
+        let mut buf = [0; 64];
+        let len = encode(&header, &data, &mut buf).unwrap();
+        assert_eq!(len, HEADER_LEN + data.len());
+        assert_eq!(&buf[..4], b"CS\x01\x01");
+        assert_eq!(decode(&buf[..len]), Ok((header, &data[..])));
+
+        let pairs: [(i8, i8); 2] = [(3, -2), (-128, 5)];
+        assert!(subcarriers(&data).eq(pairs));
+
+        assert_eq!(decode(&buf[..len - 1]), Err(Error::Truncated));
+        assert_eq!(
+            encode(&header, &data, &mut buf[..HEADER_LEN]),
+            Err(Error::BufferTooSmall)
+        );
+        buf[2] = 2;
+        assert_eq!(decode(&buf[..len]), Err(Error::UnsupportedVersion(2)));
+    }
+}
@@ -0,0 +1,181 @@
+//! Wi-Fi channel state information capture
+//!
+//! [`enable`] registers the esp-wifi CSI callback, which copies every frame
+//! into a lock-free single-producer queue. The `csi` task drains it and
+//! streams the frames to [`COLLECTOR`] over UDP, one datagram per frame in
+//! the format documented in `format`. When the queue is full, frames are
+//! dropped and counted instead of blocking the Wi-Fi driver.
+//!
+//! `format` only depends on `core`, the `csi_decode` host tool has a copy
+//! of it.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_net::{
+    Ipv4Address, Stack,
+    udp::{PacketMetadata, UdpSocket},
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use esp_println::println;
+use esp_wifi::wifi::{CsiConfig, WifiController, WifiError, wifi_csi_info_t};
+use heapless::spsc::{Consumer, Producer, Queue};
+use static_cell::{ConstStaticCell, StaticCell};
+
+use crate::clock;
+
+pub mod format;
+
+pub use format::Header;
+
+/// Where the frames are sent to, e.g. `csi_decode` on a laptop.
+pub const COLLECTOR: (Ipv4Address, u16) = (Ipv4Address::new(192, 168, 1, 100), 5500);
+
+/// Longest CSI data kept per frame, longer data is truncated.
+pub const MAX_DATA_LEN: usize = 384;
+
+/// Queue slots, one of them is always free.
+const QUEUE_LEN: usize = 8;
+
+/// A captured frame, waiting to be sent.
+pub struct Frame {
+    /// `seq` and `dropped` are filled in when sending
+    pub header: Header,
+    pub len: usize,
+    pub data: [u8; MAX_DATA_LEN],
+}
+
+impl Frame {
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+static QUEUE: StaticCell<Queue<Frame, QUEUE_LEN>> = StaticCell::new();
+
+/// Wakes the `csi` task after a frame was queued.
+static QUEUED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// Only written by the CSI callback, so plain loads and stores suffice, which
+// also works on the esp32c3 without atomic read-modify-write instructions.
+static CAPTURED: AtomicU32 = AtomicU32::new(0);
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+fn increment(counter: &AtomicU32) {
+    let value = counter.load(Ordering::Relaxed);
+    counter.store(value.wrapping_add(1), Ordering::Relaxed);
+}
+
+/// Frames captured and dropped since boot.
+pub fn stats() -> (u32, u32) {
+    (
+        CAPTURED.load(Ordering::Relaxed),
+        DROPPED.load(Ordering::Relaxed),
+    )
+}
+
+/// Start capturing, pass the returned consumer to the `csi` task.
+///
+/// Can only be called once.
+pub fn enable(
+    controller: &mut WifiController<'static>,
+) -> Result<Consumer<'static, Frame, QUEUE_LEN>, WifiError> {
+    let (mut producer, consumer) = QUEUE.init(Queue::new()).split();
+
+    controller.set_csi(CsiConfig::default(), move |info: wifi_csi_info_t| {
+        capture(&mut producer, &info)
+    })?;
+    Ok(consumer)
+}
+
+/// Called by the Wi-Fi driver for every frame with CSI, `info.buf` is only
+/// valid until we return.
+fn capture(producer: &mut Producer<'static, Frame, QUEUE_LEN>, info: &wifi_csi_info_t) {
+    increment(&CAPTURED);
+    if !producer.ready() {
+        increment(&DROPPED);
+        return;
+    }
+
+    let data = if info.buf.is_null() {
+        &[][..]
+    } else {
+        unsafe { core::slice::from_raw_parts(info.buf as *const u8, info.len as usize) }
+    };
+    let len = data.len().min(MAX_DATA_LEN);
+
+    let mut flags = 0;
+    if info.first_word_invalid {
+        flags |= format::FLAG_FIRST_WORD_INVALID;
+    }
+    if len < data.len() {
+        flags |= format::FLAG_TRUNCATED;
+    }
+
+    let mut frame = Frame {
+        header: Header {
+            flags,
+            time: clock::now().unix_micros().unwrap_or(0),
+            timestamp: info.rx_ctrl.timestamp() as u32,
+            rssi: info.rx_ctrl.rssi() as i8,
+            channel: info.rx_ctrl.channel() as u8,
+            mac: info.mac,
+            ..Default::default()
+        },
+        len,
+        data: [0; MAX_DATA_LEN],
+    };
+    frame.data[..len].copy_from_slice(&data[..len]);
+
+    // Checked with `ready` above, and we are the only producer.
+    producer.enqueue(frame).ok();
+    QUEUED.signal(());
+}
+
+#[embassy_executor::task]
+pub async fn csi(stack: Stack<'static>, mut queue: Consumer<'static, Frame, QUEUE_LEN>) {
+    const DATAGRAM_LEN: usize = format::HEADER_LEN + MAX_DATA_LEN;
+    // Static, too large for the task arena.
+    static TX_BUFFER: ConstStaticCell<[u8; 4 * DATAGRAM_LEN]> =
+        ConstStaticCell::new([0; 4 * DATAGRAM_LEN]);
+    static OUT: ConstStaticCell<[u8; DATAGRAM_LEN]> = ConstStaticCell::new([0; DATAGRAM_LEN]);
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 64];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let out = OUT.take();
+
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        TX_BUFFER.take(),
+    );
+    socket.bind(0).unwrap();
+
+    let mut seq = 0u32;
+    loop {
+        stack.wait_config_up().await;
+        QUEUED.wait().await;
+
+        while let Some(frame) = queue.dequeue() {
+            let header = Header {
+                seq,
+                dropped: DROPPED.load(Ordering::Relaxed),
+                ..frame.header
+            };
+            let len = match format::encode(&header, frame.data(), &mut out[..]) {
+                Ok(len) => len,
+                Err(e) => {
+                    println!("csi: encode error: {:?}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = socket.send_to(&out[..len], COLLECTOR).await {
+                println!("csi: send error: {:?}", e);
+                break;
+            }
+            seq = seq.wrapping_add(1);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Duplicate detection for retransmitted messages
+//!
+//! A sender retries until it gets an acknowledgement, so a receiver whose
+//! acknowledgement got lost sees the same message again. [`Dedup`]
+//! remembers the last [`WINDOW`] sequence numbers of each peer.
+
+/// How many sequence numbers before the newest one are remembered, older
+/// ones count as a restarted peer.
+pub const WINDOW: u16 = 32;
+
+#[derive(Debug, Copy, Clone)]
+struct Peer {
+    mac: [u8; 6],
+    /// Newest sequence number
+    last: u16,
+    /// Bit `i` is set if `last - i` was seen
+    seen: u32,
+    /// Value of `Dedup::tick` when last heard from
+    used: u32,
+}
+
+/// Sequence numbers seen from up to `N` peers, the least recently heard
+/// from is forgotten first.
+pub struct Dedup<const N: usize> {
+    peers: heapless::Vec<Peer, N>,
+    tick: u32,
+}
+
+impl<const N: usize> Default for Dedup<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Dedup<N> {
+    pub const fn new() -> Self {
+        Self {
+            peers: heapless::Vec::new(),
+            tick: 0,
+        }
+    }
+
+    /// Record `seq` from `mac`, returns whether it is new.
+    pub fn check(&mut self, mac: [u8; 6], seq: u16) -> bool {
+        self.tick = self.tick.wrapping_add(1);
+        let tick = self.tick;
+
+        let Some(peer) = self.peers.iter_mut().find(|peer| peer.mac == mac) else {
+            let peer = Peer {
+                mac,
+                last: seq,
+                seen: 1,
+                used: tick,
+            };
+            if let Err(peer) = self.peers.push(peer) {
+                let oldest = self
+                    .peers
+                    .iter_mut()
+                    .max_by_key(|peer| tick.wrapping_sub(peer.used))
+                    .unwrap();
+                *oldest = peer;
+            }
+            return true;
+        };
+        peer.used = tick;
+
+        let ahead = seq.wrapping_sub(peer.last);
+        let behind = peer.last.wrapping_sub(seq);
+        if ahead == 0 {
+            false
+        } else if ahead < 0x8000 {
+            peer.seen = if ahead >= WINDOW {
+                1
+            } else {
+                peer.seen << ahead | 1
+            };
+            peer.last = seq;
+            true
+        } else if behind < WINDOW {
+            let bit = 1 << behind;
+            let new = peer.seen & bit == 0;
+            peer.seen |= bit;
+            new
+        } else {
+            // Far behind, the peer restarted its sequence numbers.
+            peer.last = seq;
+            peer.seen = 1;
+            true
+        }
+    }
+
+    /// Forget `mac`, e.g. after it was removed as a peer.
+    pub fn forget(&mut self, mac: &[u8; 6]) {
+        self.peers.retain(|peer| peer.mac != *mac);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: [u8; 6] = [2, 0, 0, 0, 0, 1];
+    const B: [u8; 6] = [2, 0, 0, 0, 0, 2];
+    const C: [u8; 6] = [2, 0, 0, 0, 0, 3];
+
+    #[test]
+    fn test_dedup() {
+        let mut dedup = Dedup::<2>::new();
+        assert!(dedup.check(A, 10));
+        assert!(!dedup.check(A, 10));
+        assert!(dedup.check(A, 12));
+        // Reordered, but not seen yet.
+        assert!(dedup.check(A, 11));
+        assert!(!dedup.check(A, 11));
+        assert!(!dedup.check(A, 12));
+
+        // Other peers have their own numbers.
+        assert!(dedup.check(B, 10));
+
+        // Wrapping around.
+        assert!(dedup.check(B, 0xffff));
+        assert!(dedup.check(B, 0));
+        assert!(!dedup.check(B, 0xffff));
+
+        // A restarted peer.
+        assert!(dedup.check(A, 12u16.wrapping_sub(WINDOW)));
+
+        // C replaces B, heard from less recently than A.
+        dedup.check(A, 100);
+        assert!(dedup.check(C, 1));
+        assert!(!dedup.check(A, 100));
+        assert!(dedup.check(B, 0));
+
+        dedup.forget(&A);
+        assert!(dedup.check(A, 100));
+    }
+}
@@ -0,0 +1,200 @@
+//! The envelope around every ESP-NOW message
+//!
+//! | Offset | Size | Field                                  |
+//! |--------|------|----------------------------------------|
+//! | 0      | 1    | Version, [`VERSION`]                   |
+//! | 1      | 1    | [`MessageType`]                        |
+//! | 2      | 1    | Flags, [`FLAG_ACK_REQUESTED`]          |
+//! | 3      | 2    | Sequence number, little-endian         |
+//! | 5      | 1    | Payload length                         |
+//! | 6      | n    | Payload                                |
+//! | 6 + n  | 2    | CRC-16/CCITT-FALSE of all of the above |
+
+pub const VERSION: u8 = 1;
+
+/// Longest ESP-NOW frame (`ESP_NOW_MAX_DATA_LEN`).
+pub const MAX_FRAME_LEN: usize = 250;
+pub const HEADER_LEN: usize = 6;
+const CRC_LEN: usize = 2;
+pub const MAX_PAYLOAD_LEN: usize = MAX_FRAME_LEN - HEADER_LEN - CRC_LEN;
+
+/// The receiver answers with an [`MessageType::Ack`] with the same sequence
+/// number.
+pub const FLAG_ACK_REQUESTED: u8 = 0x01;
+
+/// Errors
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    BufferTooSmall,
+    PayloadTooLarge,
+    UnsupportedVersion(u8),
+    UnknownType(u8),
+    /// The frame is shorter or longer than the payload length says
+    BadLength,
+    BadCrc,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MessageType {
+    /// Broadcast to find peers, the payload is the sender's [`Role`] and
+    /// name
+    Discover = 1,
+    /// Answer to a `Discover`, with the same payload
+    Announce = 2,
+    Ack = 3,
+    /// A sensor reading, relayed by the gateway
+    Reading = 4,
+}
+
+impl MessageType {
+    fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            1 => MessageType::Discover,
+            2 => MessageType::Announce,
+            3 => MessageType::Ack,
+            4 => MessageType::Reading,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Role {
+    /// Connected to the access point, relays readings
+    Gateway = 1,
+    /// Out of range of the access point, sends readings to a gateway
+    Node = 2,
+}
+
+impl Role {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Role::Gateway),
+            2 => Some(Role::Node),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Envelope<'a> {
+    pub kind: MessageType,
+    pub flags: u8,
+    pub seq: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Envelope<'a> {
+    pub fn new(kind: MessageType, seq: u16, payload: &'a [u8]) -> Self {
+        Self {
+            kind,
+            flags: 0,
+            seq,
+            payload,
+        }
+    }
+
+    pub fn ack_requested(&self) -> bool {
+        self.flags & FLAG_ACK_REQUESTED != 0
+    }
+
+    /// The acknowledgement for this message.
+    pub fn ack(&self) -> Envelope<'static> {
+        Envelope::new(MessageType::Ack, self.seq, &[])
+    }
+
+    /// Write the frame, returns its length.
+    pub fn encode(&self, out: &mut [u8]) -> Result<usize, Error> {
+        if self.payload.len() > MAX_PAYLOAD_LEN {
+            return Err(Error::PayloadTooLarge);
+        }
+        let len = HEADER_LEN + self.payload.len();
+        let out = out.get_mut(..len + CRC_LEN).ok_or(Error::BufferTooSmall)?;
+
+        out[0] = VERSION;
+        out[1] = self.kind as u8;
+        out[2] = self.flags;
+        out[3..5].copy_from_slice(&self.seq.to_le_bytes());
+        out[5] = self.payload.len() as u8;
+        out[HEADER_LEN..len].copy_from_slice(self.payload);
+        let crc = crc16(&out[..len]);
+        out[len..].copy_from_slice(&crc.to_le_bytes());
+        Ok(len + CRC_LEN)
+    }
+
+    pub fn decode(frame: &'a [u8]) -> Result<Self, Error> {
+        if frame.len() < HEADER_LEN + CRC_LEN {
+            return Err(Error::BadLength);
+        }
+        if frame[0] != VERSION {
+            return Err(Error::UnsupportedVersion(frame[0]));
+        }
+        let len = HEADER_LEN + frame[5] as usize;
+        if frame.len() != len + CRC_LEN {
+            return Err(Error::BadLength);
+        }
+        if crc16(&frame[..len]).to_le_bytes() != frame[len..] {
+            return Err(Error::BadCrc);
+        }
+
+        Ok(Self {
+            kind: MessageType::from_u8(frame[1]).ok_or(Error::UnknownType(frame[1]))?,
+            flags: frame[2],
+            seq: u16::from_le_bytes([frame[3], frame[4]]),
+            payload: &frame[HEADER_LEN..len],
+        })
+    }
+}
+
+/// CRC-16/CCITT-FALSE
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffffu16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+
+        let mut reading = Envelope::new(MessageType::Reading, 0x1234, b"{\"t\":21.5}");
+        reading.flags = FLAG_ACK_REQUESTED;
+        let mut buf = [0; MAX_FRAME_LEN];
+        let len = reading.encode(&mut buf).unwrap();
+        assert_eq!(len, HEADER_LEN + 10 + 2);
+        assert_eq!(&buf[..6], &[1, 4, 1, 0x34, 0x12, 10]);
+
+        let decoded = Envelope::decode(&buf[..len]).unwrap();
+        assert_eq!(decoded, reading);
+        assert!(decoded.ack_requested());
+        assert_eq!(decoded.ack().seq, 0x1234);
+
+        let mut corrupted = buf;
+        corrupted[8] ^= 0x01;
+        assert_eq!(Envelope::decode(&corrupted[..len]), Err(Error::BadCrc));
+        assert_eq!(Envelope::decode(&buf[..len - 1]), Err(Error::BadLength));
+
+        let large = [0; MAX_PAYLOAD_LEN + 1];
+        assert_eq!(
+            Envelope::new(MessageType::Reading, 0, &large).encode(&mut buf),
+            Err(Error::PayloadTooLarge)
+        );
+        let ack = Envelope::new(MessageType::Ack, 1, &[]);
+        assert_eq!(ack.encode(&mut buf[..7]), Err(Error::BufferTooSmall));
+    }
+}
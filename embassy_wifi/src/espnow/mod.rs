@@ -0,0 +1,387 @@
+//! Peer-to-peer messaging with ESP-NOW
+//!
+//! Uses the ESP-NOW interface of the same `EspWifiController` as the
+//! station. Every message is wrapped in an `envelope` with a type, a
+//! sequence number and a CRC. Peers find each other by broadcasting
+//! `Discover` and answering with `Announce`, both carrying the [`Role`] and
+//! hostname. [`send_reliable`] waits for an `Ack` and retries, and receivers
+//! drop retransmissions they already handled with `dedup`.
+//!
+//! The [`ROLE`] decides what a device does: a gateway relays the readings of
+//! nodes to MQTT, a node sends its readings to the gateway it discovered.
+//! Nodes don't connect to an access point, the [`radio`] task only starts
+//! Wi-Fi so they stay on [`CHANNEL`], which has to be the channel of the
+//! gateway's access point.
+//!
+//! `envelope` and `dedup` don't depend on esp-wifi and are tested on the
+//! host.
+
+use core::cell::{Cell, RefCell};
+use core::fmt::Write as _;
+
+use embassy_futures::select::{Either, select};
+use embassy_sync::{
+    blocking_mutex::{Mutex as BlockingMutex, raw::CriticalSectionRawMutex},
+    channel::Channel,
+    mutex::Mutex,
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Ticker, with_timeout};
+use esp_hal::rng::Rng;
+use esp_println::println;
+use esp_wifi::esp_now::{
+    BROADCAST_ADDRESS, EspNow, EspNowError, EspNowManager, EspNowSender, EspNowWifiInterface,
+    PeerInfo, ReceivedData,
+};
+use esp_wifi::wifi::{ClientConfiguration, Configuration, WifiController};
+
+use crate::{mqtt, net};
+
+pub mod dedup;
+pub mod envelope;
+
+pub use dedup::Dedup;
+pub use envelope::{Envelope, MessageType, Role};
+
+pub const ROLE: Role = Role::Gateway;
+
+/// Channel for nodes, which aren't connected to an access point. Gateways
+/// stay on the channel of their access point.
+pub const CHANNEL: u8 = 1;
+
+/// Readings of node `aabbccddeeff` are published to `esp/relay/aabbccddeeff`.
+pub const RELAY_TOPIC_PREFIX: &str = "esp/relay/";
+
+pub const MAX_PEERS: usize = 8;
+pub const MAX_RETRIES: u8 = 3;
+const ACK_TIMEOUT: Duration = Duration::from_millis(200);
+const DISCOVER_INTERVAL: Duration = Duration::from_secs(30);
+const READING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Errors
+#[derive(Debug)]
+pub enum Error {
+    PayloadTooLarge,
+    /// The `espnow` task isn't running
+    NotStarted,
+    Send(EspNowError),
+    /// No acknowledgement after [`MAX_RETRIES`] retries
+    NoAck,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    pub mac: [u8; 6],
+    pub role: Role,
+    pub name: heapless::String<32>,
+    /// When we last heard from the peer
+    pub seen: Instant,
+}
+
+static SENDER: Mutex<CriticalSectionRawMutex, Option<EspNowSender<'static>>> = Mutex::new(None);
+
+/// Held during [`send_reliable`], so acknowledgements can't get mixed up.
+static RELIABLE: Mutex<CriticalSectionRawMutex, ()> = Mutex::new(());
+
+/// Acknowledgements received, with the sender and sequence number.
+static ACKS: Channel<CriticalSectionRawMutex, ([u8; 6], u16), 4> = Channel::new();
+
+static SEQ: BlockingMutex<CriticalSectionRawMutex, Cell<u16>> = BlockingMutex::new(Cell::new(0));
+
+/// Signaled by [`radio`] once Wi-Fi is started, on nodes.
+static STARTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+static PEERS: BlockingMutex<CriticalSectionRawMutex, RefCell<heapless::Vec<Peer, MAX_PEERS>>> =
+    BlockingMutex::new(RefCell::new(heapless::Vec::new()));
+
+/// Peers that announced themselves.
+pub fn peers() -> heapless::Vec<Peer, MAX_PEERS> {
+    PEERS.lock(|peers| peers.borrow().clone())
+}
+
+/// The most recently seen gateway.
+pub fn gateway() -> Option<[u8; 6]> {
+    PEERS.lock(|peers| {
+        peers
+            .borrow()
+            .iter()
+            .filter(|peer| peer.role == Role::Gateway)
+            .max_by_key(|peer| peer.seen)
+            .map(|peer| peer.mac)
+    })
+}
+
+fn next_seq() -> u16 {
+    SEQ.lock(|seq| {
+        let next = seq.get().wrapping_add(1);
+        seq.set(next);
+        next
+    })
+}
+
+/// Send a message once, without waiting for an acknowledgement.
+pub async fn send(mac: &[u8; 6], kind: MessageType, payload: &[u8]) -> Result<(), Error> {
+    let envelope = Envelope::new(kind, next_seq(), payload);
+    transmit(mac, &envelope).await
+}
+
+/// Send a message and wait for its acknowledgement, retrying up to
+/// [`MAX_RETRIES`] times.
+pub async fn send_reliable(mac: &[u8; 6], kind: MessageType, payload: &[u8]) -> Result<(), Error> {
+    let _guard = RELIABLE.lock().await;
+
+    let mut envelope = Envelope::new(kind, next_seq(), payload);
+    envelope.flags = envelope::FLAG_ACK_REQUESTED;
+
+    // Late acknowledgements of earlier messages.
+    ACKS.clear();
+    for attempt in 0..=MAX_RETRIES {
+        if attempt > 0 {
+            println!("espnow: retry {} of seq {}", attempt, envelope.seq);
+        }
+        match transmit(mac, &envelope).await {
+            Ok(()) => {}
+            // Not acknowledged on the MAC layer either, try again.
+            Err(Error::Send(_)) => continue,
+            Err(e) => return Err(e),
+        }
+
+        let acked = with_timeout(ACK_TIMEOUT, async {
+            loop {
+                if ACKS.receive().await == (*mac, envelope.seq) {
+                    break;
+                }
+            }
+        })
+        .await;
+        if acked.is_ok() {
+            return Ok(());
+        }
+    }
+    Err(Error::NoAck)
+}
+
+async fn transmit(mac: &[u8; 6], envelope: &Envelope<'_>) -> Result<(), Error> {
+    let mut buf = [0; envelope::MAX_FRAME_LEN];
+    let len = envelope
+        .encode(&mut buf)
+        .map_err(|_| Error::PayloadTooLarge)?;
+
+    let mut sender = SENDER.lock().await;
+    let sender = sender.as_mut().ok_or(Error::NotStarted)?;
+    sender
+        .send_async(mac, &buf[..len])
+        .await
+        .map_err(Error::Send)
+}
+
+#[embassy_executor::task]
+pub async fn espnow(esp_now: EspNow<'static>, mut rng: Rng) {
+    println!(
+        "espnow: version {}, {:?}",
+        esp_now.version().unwrap_or(0),
+        ROLE
+    );
+    let (manager, sender, mut receiver) = esp_now.split();
+    if ROLE == Role::Node {
+        // The channel can only be set on a started interface.
+        STARTED.wait().await;
+        if let Err(e) = manager.set_channel(CHANNEL) {
+            println!("espnow: set channel error: {:?}", e);
+        }
+    }
+    *SENDER.lock().await = Some(sender);
+    // Peers remember our sequence numbers across our restarts, don't start
+    // where the last run left off.
+    SEQ.lock(|seq| seq.set(rng.random() as u16));
+
+    let hostname = net::hostname();
+    let mut hello = heapless::Vec::<u8, 33>::new();
+    hello.push(ROLE as u8).ok();
+    hello.extend_from_slice(hostname.as_bytes()).ok();
+
+    let mut dedup = Dedup::<MAX_PEERS>::new();
+    let mut discover = Ticker::every(DISCOVER_INTERVAL);
+    if let Err(e) = send(&BROADCAST_ADDRESS, MessageType::Discover, &hello).await {
+        println!("espnow: discover error: {:?}", e);
+    }
+    loop {
+        match select(receiver.receive_async(), discover.next()).await {
+            Either::First(received) => {
+                if let Err(e) = handle(&manager, &mut dedup, &hello, &received).await {
+                    println!(
+                        "espnow: error answering {:02x?}: {:?}",
+                        received.info.src_address, e
+                    );
+                }
+            }
+            Either::Second(()) => {
+                if let Err(e) = send(&BROADCAST_ADDRESS, MessageType::Discover, &hello).await {
+                    println!("espnow: discover error: {:?}", e);
+                }
+            }
+        }
+    }
+}
+
+/// Start Wi-Fi without connecting, on nodes, instead of the `connection`
+/// task: scanning and connecting would move the radio off [`CHANNEL`].
+#[embassy_executor::task]
+pub async fn radio(mut controller: WifiController<'static>) {
+    let configuration = Configuration::Client(ClientConfiguration::default());
+    if let Err(e) = controller.set_configuration(&configuration) {
+        println!("espnow: configuration error: {:?}", e);
+        return;
+    }
+    match controller.start_async().await {
+        Ok(()) => STARTED.signal(()),
+        Err(e) => println!("espnow: start error: {:?}", e),
+    }
+    // Dropping the controller would stop Wi-Fi.
+    core::future::pending::<()>().await
+}
+
+async fn handle(
+    manager: &EspNowManager<'static>,
+    dedup: &mut Dedup<MAX_PEERS>,
+    hello: &[u8],
+    received: &ReceivedData,
+) -> Result<(), Error> {
+    let from = received.info.src_address;
+    let envelope = match Envelope::decode(received.data()) {
+        Ok(envelope) => envelope,
+        Err(e) => {
+            println!("espnow: bad frame from {:02x?}: {:?}", from, e);
+            return Ok(());
+        }
+    };
+
+    // Unicast needs the sender to be a peer, also to acknowledge.
+    if !manager.peer_exists(&from) {
+        if let Err(e) = manager.add_peer(peer_info(from)) {
+            // The peer table is full, forget the peer we heard from least
+            // recently.
+            println!("espnow: add peer error: {:?}", e);
+            if let Some(oldest) = forget_oldest() {
+                manager.remove_peer(&oldest).ok();
+                dedup.forget(&oldest);
+                manager.add_peer(peer_info(from)).ok();
+            }
+        }
+    }
+
+    if envelope.ack_requested() {
+        transmit(&from, &envelope.ack()).await?;
+    }
+
+    match envelope.kind {
+        MessageType::Discover | MessageType::Announce => {
+            remember(from, envelope.payload);
+            if envelope.kind == MessageType::Discover {
+                send(&from, MessageType::Announce, hello).await?;
+            }
+        }
+        MessageType::Ack => {
+            ACKS.try_send((from, envelope.seq)).ok();
+        }
+        MessageType::Reading => {
+            if dedup.check(from, envelope.seq) {
+                relay(&from, envelope.payload);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn peer_info(mac: [u8; 6]) -> PeerInfo {
+    PeerInfo {
+        interface: EspNowWifiInterface::Sta,
+        peer_address: mac,
+        lmk: None,
+        channel: None,
+        encrypt: false,
+    }
+}
+
+/// Add or update a peer from a `Discover` or `Announce` payload.
+fn remember(mac: [u8; 6], payload: &[u8]) {
+    let Some((&role, name)) = payload.split_first() else {
+        return;
+    };
+    let Some(role) = Role::from_u8(role) else {
+        return;
+    };
+    let mut peer = Peer {
+        mac,
+        role,
+        name: heapless::String::new(),
+        seen: Instant::now(),
+    };
+    if let Ok(name) = core::str::from_utf8(name) {
+        peer.name.push_str(name).ok();
+    }
+
+    PEERS.lock(|peers| {
+        let mut peers = peers.borrow_mut();
+        match peers.iter_mut().find(|known| known.mac == mac) {
+            Some(known) => *known = peer,
+            None => {
+                println!("espnow: found {:?} {} ({:02x?})", role, peer.name, mac);
+                if let Err(peer) = peers.push(peer) {
+                    let oldest = peers.iter_mut().min_by_key(|peer| peer.seen).unwrap();
+                    *oldest = peer;
+                }
+            }
+        }
+    });
+}
+
+fn forget_oldest() -> Option<[u8; 6]> {
+    PEERS.lock(|peers| {
+        let mut peers = peers.borrow_mut();
+        let (i, _) = peers.iter().enumerate().min_by_key(|(_, peer)| peer.seen)?;
+        Some(peers.swap_remove(i).mac)
+    })
+}
+
+/// Publish a node's reading to MQTT.
+fn relay(from: &[u8; 6], payload: &[u8]) {
+    if ROLE != Role::Gateway {
+        return;
+    }
+    let mut topic = heapless::String::<{ mqtt::MAX_TOPIC_LEN }>::new();
+    topic.push_str(RELAY_TOPIC_PREFIX).ok();
+    for b in from {
+        write!(topic, "{:02x}", b).ok();
+    }
+
+    match mqtt::Message::new(&topic, payload, mqtt::QoS::AtLeastOnce) {
+        Some(message) => {
+            if mqtt::OUTBOX.try_send(message).is_err() {
+                println!("espnow: mqtt outbox full, dropping reading");
+            }
+        }
+        None => println!("espnow: reading from {:02x?} too large", from),
+    }
+}
+
+/// Send readings to the gateway, on nodes.
+#[embassy_executor::task]
+pub async fn readings() {
+    let mut ticker = Ticker::every(READING_INTERVAL);
+    loop {
+        ticker.next().await;
+        let Some(gateway) = gateway() else {
+            println!("espnow: no gateway yet");
+            continue;
+        };
+
+        let mut payload = heapless::String::<64>::new();
+        write!(payload, "{{\"uptime\":{}}}", Instant::now().as_secs()).ok();
+        if let Err(e) = send_reliable(&gateway, MessageType::Reading, payload.as_bytes()).await {
+            println!("espnow: reading not delivered: {:?}", e);
+            // Look for another gateway with the next discovery round.
+            PEERS.lock(|peers| peers.borrow_mut().retain(|peer| peer.mac != gateway));
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! ICMP and ICMPv6 echo request and reply messages (RFC 792, RFC 4443)
+
+pub const HEADER_LEN: usize = 8;
+
+/// The echo messages of both only differ in their types.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Version {
+    V4,
+    V6,
+}
+
+impl Version {
+    fn echo_request(self) -> u8 {
+        match self {
+            Version::V4 => 8,
+            Version::V6 => 128,
+        }
+    }
+
+    fn echo_reply(self) -> u8 {
+        match self {
+            Version::V4 => 0,
+            Version::V6 => 129,
+        }
+    }
+}
+
+/// Write an echo request into `buf`, returns its length or `None` if `buf`
+/// is too short.
+///
+/// The ICMPv6 checksum covers the IP addresses too, the stack fills it in.
+pub fn echo_request(
+    version: Version,
+    ident: u16,
+    seq: u16,
+    payload: &[u8],
+    buf: &mut [u8],
+) -> Option<usize> {
+    let len = HEADER_LEN + payload.len();
+    let packet = buf.get_mut(..len)?;
+    packet[0] = version.echo_request();
+    packet[1] = 0;
+    packet[2..4].fill(0);
+    packet[4..6].copy_from_slice(&ident.to_be_bytes());
+    packet[6..8].copy_from_slice(&seq.to_be_bytes());
+    packet[HEADER_LEN..].copy_from_slice(payload);
+    let checksum = checksum(packet);
+    packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    Some(len)
+}
+
+/// Identifier and sequence number of an echo reply with a valid checksum.
+///
+/// ICMPv6 checksums were already checked by the stack.
+pub fn parse_echo_reply(version: Version, packet: &[u8]) -> Option<(u16, u16)> {
+    if packet.len() < HEADER_LEN || packet[0] != version.echo_reply() || packet[1] != 0 {
+        return None;
+    }
+    // Summing over the checksum field too gives zero.
+    if version == Version::V4 && checksum(packet) != 0 {
+        return None;
+    }
+    let ident = u16::from_be_bytes([packet[4], packet[5]]);
+    let seq = u16::from_be_bytes([packet[6], packet[7]]);
+    Some((ident, seq))
+}
+
+/// The internet checksum (RFC 1071).
+pub fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|chunk| match chunk {
+            [a, b] => u16::from_be_bytes([*a, *b]) as u32,
+            [a] => (*a as u32) << 8,
+            _ => unreachable!(),
+        })
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_echo() {
+        let mut buf = [0; 16];
+        assert_eq!(
+            echo_request(Version::V4, 0x1234, 7, b"abc", &mut buf[..10]),
+            None
+        );
+        let len = echo_request(Version::V4, 0x1234, 7, b"abcd", &mut buf).unwrap();
+        assert_eq!(len, 12);
+        assert_eq!(
+            buf[..len],
+            [8, 0, 0x20, 0xfe, 0x12, 0x34, 0, 7, b'a', b'b', b'c', b'd']
+        );
+
+        // The reply only differs in type and checksum.
+        let mut reply = buf;
+        reply[0] = 0;
+        reply[2..4].copy_from_slice(&0x28feu16.to_be_bytes());
+        assert_eq!(parse_echo_reply(Version::V4, &reply[..len]), Some((0x1234, 7)));
+        assert_eq!(parse_echo_reply(Version::V6, &reply[..len]), None);
+        reply[9] ^= 1;
+        assert_eq!(parse_echo_reply(Version::V4, &reply[..len]), None);
+        assert_eq!(parse_echo_reply(Version::V4, &buf[..len]), None);
+    }
+
+    #[test]
+    fn test_echo_v6() {
+        let mut buf = [0; 16];
+        let len = echo_request(Version::V6, 0x1234, 7, b"abcd", &mut buf).unwrap();
+        assert_eq!(buf[0], 128);
+
+        let mut reply = buf;
+        reply[0] = 129;
+        reply[2..4].copy_from_slice(&0xbeefu16.to_be_bytes());
+        assert_eq!(parse_echo_reply(Version::V6, &reply[..len]), Some((0x1234, 7)));
+        assert_eq!(parse_echo_reply(Version::V4, &reply[..len]), None);
+    }
+}
@@ -0,0 +1,317 @@
+//! Connectivity health checks with ICMP echo
+//!
+//! The `health` task pings the gateway, IPv4 if there is one and IPv6
+//! otherwise, and an upstream host every [`INTERVAL`] and keeps latency and
+//! loss [`Stats`] for both. When the gateway stops answering while Wi-Fi
+//! still reports the link as up, the association is likely stale and the
+//! task asks the `connection` task to reconnect.
+//!
+//! `icmp` and `stats` don't depend on esp-hal or embassy-net and are tested
+//! on the host.
+
+use alloc::{boxed::Box, string::String};
+use core::cell::RefCell;
+use core::fmt::Write as _;
+use core::net::IpAddr;
+
+use embassy_net::{
+    IpAddress, Ipv4Address, Ipv6Address, Stack,
+    icmp::{IcmpEndpoint, IcmpSocket, PacketMetadata},
+};
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use esp_println::println;
+use serde::Serialize;
+
+use crate::metrics::{Histogram, Metric};
+use crate::shell::{self, Command, Context, Reply};
+use crate::status::{self, Wifi};
+use crate::{settings, wifi};
+
+pub mod icmp;
+pub mod stats;
+
+use icmp::Version;
+pub use stats::Stats;
+
+/// Pinged when the settings have no `upstream`.
+pub const DEFAULT_UPSTREAM: Ipv4Address = Ipv4Address::new(1, 1, 1, 1);
+/// The same without an IPv4 address.
+pub const DEFAULT_UPSTREAM_V6: Ipv6Address =
+    Ipv6Address::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111);
+
+pub const INTERVAL: Duration = Duration::from_secs(10);
+pub const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Gateway pings lost in a row before reconnecting.
+pub const MAX_GATEWAY_LOSSES: u32 = 3;
+
+/// ICMP identifiers, so replies get to the right socket.
+const HEALTH_IDENT: u16 = 0x4548;
+const SHELL_IDENT: u16 = 0x4549;
+
+const PAYLOAD: &[u8] = b"embassy_wifi ping";
+
+static REPORT: Mutex<CriticalSectionRawMutex, RefCell<Report>> =
+    Mutex::new(RefCell::new(Report::new()));
+
+/// Buckets of the round-trip time histograms, in seconds.
+const RTT_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.0];
+
+static GATEWAY_RTT: Histogram = Histogram::new(
+    "health_ping_rtt_seconds",
+    "Round-trip time of answered pings",
+    RTT_BUCKETS,
+)
+.with_labels("target=\"gateway\"");
+static UPSTREAM_RTT: Histogram = Histogram::new(
+    "health_ping_rtt_seconds",
+    "Round-trip time of answered pings",
+    RTT_BUCKETS,
+)
+.with_labels("target=\"upstream\"");
+
+pub static METRICS: &[Metric] = &[
+    Metric::Histogram(&GATEWAY_RTT),
+    Metric::Histogram(&UPSTREAM_RTT),
+];
+
+pub static COMMANDS: &[Command<Context>] = &[Command::new(
+    "ping",
+    "[address]",
+    "health check statistics, or ping an address",
+    ping_command,
+)];
+
+#[derive(Debug)]
+pub enum Error {
+    Send,
+    Receive,
+    /// No reply within [`TIMEOUT`]
+    Timeout,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct Report {
+    pub gateway: Option<IpAddr>,
+    pub gateway_stats: Stats,
+    pub upstream: IpAddr,
+    pub upstream_stats: Stats,
+    /// Reconnects triggered by an unreachable gateway
+    pub reconnects: u32,
+}
+
+impl Report {
+    const fn new() -> Self {
+        Self {
+            gateway: None,
+            gateway_stats: Stats::new(),
+            upstream: IpAddr::V4(DEFAULT_UPSTREAM),
+            upstream_stats: Stats::new(),
+            reconnects: 0,
+        }
+    }
+}
+
+pub fn report() -> Report {
+    REPORT.lock(|report| *report.borrow())
+}
+
+fn update_report(f: impl FnOnce(&mut Report)) {
+    REPORT.lock(|report| f(&mut report.borrow_mut()));
+}
+
+/// Send an echo request to `address` and wait for the reply, returns the
+/// round trip time.
+pub async fn ping(
+    socket: &IcmpSocket<'_>,
+    address: IpAddr,
+    ident: u16,
+    seq: u16,
+) -> Result<Duration, Error> {
+    let (address, version) = match address {
+        IpAddr::V4(address) => (IpAddress::Ipv4(address), Version::V4),
+        IpAddr::V6(address) => (IpAddress::Ipv6(address), Version::V6),
+    };
+    let mut request = [0; icmp::HEADER_LEN + PAYLOAD.len()];
+    let len = icmp::echo_request(version, ident, seq, PAYLOAD, &mut request).unwrap();
+
+    let start = Instant::now();
+    socket
+        .send_to(&request[..len], address)
+        .await
+        .map_err(|_| Error::Send)?;
+
+    let mut reply = [0; 64];
+    with_timeout(TIMEOUT, async {
+        loop {
+            let (n, from) = socket
+                .recv_from(&mut reply)
+                .await
+                .map_err(|_| Error::Receive)?;
+            // Late replies to earlier requests are skipped.
+            if from == address
+                && icmp::parse_echo_reply(version, &reply[..n]) == Some((ident, seq))
+            {
+                return Ok(start.elapsed());
+            }
+        }
+    })
+    .await
+    .map_err(|_| Error::Timeout)?
+}
+
+/// Ping with the identifier of the `health` task, `None` if lost.
+async fn check(socket: &IcmpSocket<'_>, address: IpAddr, seq: u16) -> Option<u32> {
+    match ping(socket, address, HEALTH_IDENT, seq).await {
+        Ok(rtt) => Some(rtt.as_millis() as u32),
+        Err(Error::Timeout) => None,
+        Err(e) => {
+            println!("health: ping {} error: {:?}", address, e);
+            None
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn health(stack: Stack<'static>) {
+    let upstream = settings::get().upstream;
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 256];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 128];
+    let mut socket = IcmpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(IcmpEndpoint::Ident(HEALTH_IDENT)).unwrap();
+
+    let mut seq: u16 = 0;
+    loop {
+        Timer::after(INTERVAL).await;
+        stack.wait_config_up().await;
+
+        let gateway_v4 = stack.config_v4().and_then(|config| config.gateway);
+        let gateway_v6 = stack.config_v6().and_then(|config| config.gateway);
+        let gateway = gateway_v4.map(IpAddr::V4).or(gateway_v6.map(IpAddr::V6));
+        let upstream = upstream.unwrap_or(match stack.config_v4() {
+            Some(_) => IpAddr::V4(DEFAULT_UPSTREAM),
+            None => IpAddr::V6(DEFAULT_UPSTREAM_V6),
+        });
+        update_report(|report| {
+            report.gateway = gateway;
+            report.upstream = upstream;
+        });
+
+        if let Some(gateway) = gateway {
+            seq = seq.wrapping_add(1);
+            let rtt = check(&socket, gateway, seq).await;
+            if let Some(rtt) = rtt {
+                GATEWAY_RTT.observe(rtt as f64 / 1000.0);
+            }
+            update_report(|report| report.gateway_stats.record(rtt));
+        }
+
+        seq = seq.wrapping_add(1);
+        let rtt = check(&socket, upstream, seq).await;
+        if let Some(rtt) = rtt {
+            UPSTREAM_RTT.observe(rtt as f64 / 1000.0);
+        }
+        update_report(|report| report.upstream_stats.record(rtt));
+
+        let losses = report().gateway_stats.consecutive_losses;
+        if losses >= MAX_GATEWAY_LOSSES
+            && stack.is_link_up()
+            && status::get().wifi == Wifi::Connected
+        {
+            println!(
+                "health: gateway lost {} pings in a row, reconnecting",
+                losses
+            );
+            update_report(|report| {
+                report.reconnects += 1;
+                report.gateway_stats.consecutive_losses = 0;
+            });
+            wifi::reconnect();
+        }
+    }
+}
+
+fn ping_command<'a>(context: &'a Context, args: &'a [&'a str], out: &'a mut String) -> Reply<'a> {
+    Box::pin(async move {
+        match args {
+            [] => {
+                let report = report();
+                if let Some(gateway) = report.gateway {
+                    write_stats(out, "gateway", gateway, &report.gateway_stats);
+                }
+                write_stats(out, "upstream", report.upstream, &report.upstream_stats);
+                writeln!(out, "reconnects {}", report.reconnects).ok();
+                Ok(())
+            }
+            [address] => {
+                let address = address
+                    .parse::<IpAddr>()
+                    .map_err(|_| shell::Error::Usage)?;
+                ping_address(context.stack, address, out).await;
+                Ok(())
+            }
+            _ => Err(shell::Error::Usage),
+        }
+    })
+}
+
+/// Ping `address` four times, like `ping -c 4`.
+async fn ping_address(stack: Stack<'static>, address: IpAddr, out: &mut String) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 128];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; 64];
+    let mut socket = IcmpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if socket.bind(IcmpEndpoint::Ident(SHELL_IDENT)).is_err() {
+        out.push_str("ping in progress\n");
+        return;
+    }
+
+    let mut stats = Stats::new();
+    for seq in 1..=4 {
+        match ping(&socket, address, SHELL_IDENT, seq).await {
+            Ok(rtt) => {
+                let rtt = rtt.as_millis() as u32;
+                writeln!(out, "reply from {}: seq={} time={} ms", address, seq, rtt).ok();
+                stats.record(Some(rtt));
+            }
+            Err(e) => {
+                writeln!(out, "seq={}: {:?}", seq, e).ok();
+                stats.record(None);
+            }
+        }
+        if seq < 4 {
+            Timer::after(Duration::from_secs(1)).await;
+        }
+    }
+    write_stats(out, "total", address, &stats);
+}
+
+fn write_stats(out: &mut String, name: &str, address: IpAddr, stats: &Stats) {
+    write!(
+        out,
+        "{} {}: {} sent, {} received, {}% loss",
+        name, address, stats.sent, stats.received, stats.loss
+    )
+    .ok();
+    if let (Some(min), Some(avg), Some(max)) = (stats.min_rtt, stats.avg_rtt, stats.max_rtt) {
+        write!(out, ", rtt min/avg/max {}/{}/{} ms", min, avg, max).ok();
+    }
+    out.push('\n');
+}
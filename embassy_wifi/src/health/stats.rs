@@ -0,0 +1,107 @@
+//! Latency and loss statistics of the pings to one target
+
+use serde::Serialize;
+
+/// Pings the loss is calculated over.
+pub const WINDOW: u32 = 32;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct Stats {
+    pub sent: u32,
+    pub received: u32,
+    /// Lost in a row, 0 after a reply
+    pub consecutive_losses: u32,
+    /// Percent lost of the last [`WINDOW`] pings
+    pub loss: u8,
+    /// Round trip times in milliseconds
+    pub last_rtt: Option<u32>,
+    pub min_rtt: Option<u32>,
+    pub max_rtt: Option<u32>,
+    pub avg_rtt: Option<u32>,
+    /// Bit `i` is set if the `i`th latest ping was lost
+    #[serde(skip)]
+    losses: u32,
+    #[serde(skip)]
+    rtt_sum: u64,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+    pub const fn new() -> Self {
+        Self {
+            sent: 0,
+            received: 0,
+            consecutive_losses: 0,
+            loss: 0,
+            last_rtt: None,
+            min_rtt: None,
+            max_rtt: None,
+            avg_rtt: None,
+            losses: 0,
+            rtt_sum: 0,
+        }
+    }
+
+    /// Record a ping, with its round trip time unless it was lost.
+    pub fn record(&mut self, rtt: Option<u32>) {
+        self.sent = self.sent.wrapping_add(1);
+        self.losses <<= 1;
+        match rtt {
+            Some(rtt) => {
+                self.received = self.received.wrapping_add(1);
+                self.consecutive_losses = 0;
+                self.rtt_sum += rtt as u64;
+                self.last_rtt = Some(rtt);
+                self.min_rtt = Some(self.min_rtt.map_or(rtt, |min| min.min(rtt)));
+                self.max_rtt = Some(self.max_rtt.map_or(rtt, |max| max.max(rtt)));
+                self.avg_rtt = Some((self.rtt_sum / self.received as u64) as u32);
+            }
+            None => {
+                self.consecutive_losses += 1;
+                self.last_rtt = None;
+                self.losses |= 1;
+            }
+        }
+
+        let window = self.sent.min(WINDOW);
+        self.loss = (self.losses.count_ones() * 100 / window) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let mut stats = Stats::default();
+        stats.record(Some(10));
+        stats.record(None);
+        stats.record(None);
+        stats.record(Some(20));
+        assert_eq!(stats.sent, 4);
+        assert_eq!(stats.received, 2);
+        assert_eq!(stats.consecutive_losses, 0);
+        assert_eq!(stats.loss, 50);
+        assert_eq!(stats.last_rtt, Some(20));
+        assert_eq!(stats.min_rtt, Some(10));
+        assert_eq!(stats.max_rtt, Some(20));
+        assert_eq!(stats.avg_rtt, Some(15));
+
+        stats.record(None);
+        assert_eq!(stats.consecutive_losses, 1);
+        assert_eq!(stats.last_rtt, None);
+
+        // The losses drop out of the window.
+        for _ in 0..WINDOW {
+            stats.record(Some(15));
+        }
+        assert_eq!(stats.loss, 0);
+        assert_eq!(stats.avg_rtt, Some(15));
+    }
+}
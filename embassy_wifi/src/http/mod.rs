@@ -0,0 +1,338 @@
+//! A small HTTP server with a JSON API
+//!
+//! [`WORKERS`] instances of the `http` task listen on [`PORT`], each with its
+//! own socket, so that many requests can be served concurrently. Routes
+//! that change the firmware or the settings need [`TOKEN`].
+
+use alloc::string::{String, ToString};
+use alloc::{vec, vec::Vec};
+
+use embassy_net::{Stack, tcp::TcpSocket};
+use embassy_time::{Duration, Instant};
+use esp_println::println;
+use serde::{Deserialize, Serialize};
+use static_cell::ConstStaticCell;
+
+use crate::{clock, io, led, metrics, ota, settings, shell};
+
+pub mod request;
+pub mod response;
+pub mod router;
+
+pub use request::{Method, Request};
+pub use response::Response;
+pub use router::{Route, Router};
+
+pub const PORT: u16 = 80;
+pub const WORKERS: usize = 3;
+
+/// Shared secret that `POST /ota` and `PUT /settings` require as
+/// `Authorization: Bearer <token>`, `None` turns these routes off.
+pub const TOKEN: Option<&str> = None;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Requests that don't fit the buffer of a worker are read into the heap up
+/// to this length, e.g. settings with certificates.
+pub const MAX_REQUEST_LEN: usize = 16 * 1024;
+
+/// What handlers get to see besides the request.
+pub struct Context {
+    pub stack: Stack<'static>,
+}
+
+static ROUTER: Router<Context> = Router::new(&[
+    Route::new(Method::Get, "/status", status),
+    Route::new(Method::Get, "/led", get_led),
+    Route::new(Method::Post, "/led", post_led),
+    Route::new(Method::Get, "/ota", get_ota),
+    Route::new(Method::Post, "/ota", post_ota),
+    Route::new(Method::Get, "/settings", get_settings),
+    Route::new(Method::Put, "/settings", put_settings),
+    Route::new(Method::Get, "/metrics", get_metrics),
+]);
+
+/// The buffers of a worker, static because they are too large for the task
+/// arena.
+struct Buffers {
+    rx: [u8; 1024],
+    tx: [u8; 1024],
+    buf: [u8; 1024],
+}
+
+static BUFFERS: [ConstStaticCell<Buffers>; WORKERS] = [const {
+    ConstStaticCell::new(Buffers {
+        rx: [0; 1024],
+        tx: [0; 1024],
+        buf: [0; 1024],
+    })
+}; WORKERS];
+
+#[embassy_executor::task(pool_size = WORKERS)]
+pub async fn http(stack: Stack<'static>, id: usize) {
+    let Buffers { rx, tx, buf } = BUFFERS[id].take();
+
+    let context = Context { stack };
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx[..], &mut tx[..]);
+        socket.set_timeout(Some(TIMEOUT));
+
+        if let Err(e) = socket.accept(PORT).await {
+            println!("http[{}]: accept error: {:?}", id, e);
+            continue;
+        }
+
+        if let Err(e) = serve(&context, &mut socket, &mut buf[..]).await {
+            println!("http[{}]: {:?}", id, e);
+        }
+
+        socket.close();
+        socket.flush().await.ok();
+        socket.abort();
+    }
+}
+
+/// Read one request and write the response.
+async fn serve(
+    context: &Context,
+    socket: &mut TcpSocket<'_>,
+    buf: &mut [u8],
+) -> Result<(), io::Error<embassy_net::tcp::Error>> {
+    let mut len = 0;
+    let mut large = Vec::new();
+
+    // Read until the header is complete and we know how long the body is.
+    let total = loop {
+        match Request::parse(&buf[..len]) {
+            Ok(Some((request, header_len))) => match request.total_len(header_len) {
+                Ok(Some(total)) if total <= buf.len() => break Ok(total),
+                Ok(Some(total)) if total <= MAX_REQUEST_LEN => {
+                    large = vec![0; total];
+                    large[..len].copy_from_slice(&buf[..len]);
+                    break Ok(total);
+                }
+                // Larger than we accept, or than a `usize`.
+                Ok(_) => break Err(413),
+                Err(_) => break Err(400),
+            },
+            Ok(None) if len == buf.len() => break Err(431),
+            Ok(None) => {}
+            Err(_) => break Err(400),
+        }
+
+        let n = socket.read(&mut buf[len..]).await.map_err(io::Error::Io)?;
+        if n == 0 {
+            return Ok(());
+        }
+        len += n;
+    };
+    let buf: &mut [u8] = if large.is_empty() { buf } else { &mut large };
+
+    let (response, head_only) = match total {
+        Ok(total) => {
+            while len < total {
+                let n = socket
+                    .read(&mut buf[len..total])
+                    .await
+                    .map_err(io::Error::Io)?;
+                if n == 0 {
+                    return Ok(());
+                }
+                len += n;
+            }
+
+            match Request::parse(&buf[..total]) {
+                Ok(Some((mut request, header_len))) => {
+                    request.body = &buf[header_len..total];
+                    let head_only = request.method == Method::Head;
+                    (ROUTER.handle(context, &request), head_only)
+                }
+                _ => (Response::error(400), false),
+            }
+        }
+        Err(status) => (Response::error(status), false),
+    };
+
+    // A client that stops reading doesn't hold the worker.
+    let mut io = io::Timeout::new(socket, TIMEOUT);
+    response
+        .write_to(&mut io, head_only)
+        .await
+        .map_err(io::Error::flatten)
+}
+
+#[derive(Serialize)]
+struct Status {
+    /// Seconds since boot
+    uptime: u64,
+    /// RFC 3339, once SNTP synchronized
+    time: Option<String>,
+    ip: Option<String>,
+    ipv6: Option<String>,
+    rssi: Option<i32>,
+    network: crate::status::Status,
+    health: crate::health::Report,
+    /// Why the last connection attempt failed or the link dropped
+    wifi_disconnect: Option<crate::wifi::Disconnect>,
+    #[cfg(feature = "log")]
+    syslog: crate::syslog::Stats,
+    heap: Heap,
+}
+
+#[derive(Serialize)]
+struct Heap {
+    used: usize,
+    free: usize,
+}
+
+fn status(context: &Context, _: &Request) -> Response {
+    Response::json(&Status {
+        uptime: Instant::now().as_secs(),
+        time: clock::now().date_time().map(|dt| dt.to_string()),
+        ip: context
+            .stack
+            .config_v4()
+            .map(|config| config.address.address().to_string()),
+        ipv6: context
+            .stack
+            .config_v6()
+            .map(|config| config.address.address().to_string()),
+        rssi: crate::rssi(),
+        network: crate::status::get(),
+        health: crate::health::report(),
+        wifi_disconnect: crate::wifi::last_disconnect(),
+        #[cfg(feature = "log")]
+        syslog: crate::syslog::stats(),
+        heap: Heap {
+            used: esp_alloc::HEAP.used(),
+            free: esp_alloc::HEAP.free(),
+        },
+    })
+}
+
+#[derive(Serialize)]
+struct LedState {
+    on: bool,
+    /// Whether the LED is controlled through the API instead of blinking
+    manual: bool,
+}
+
+#[derive(Deserialize)]
+struct LedCommand {
+    on: Option<bool>,
+    manual: Option<bool>,
+}
+
+fn led_state() -> LedState {
+    LedState {
+        on: led::is_on(),
+        manual: led::is_manual(),
+    }
+}
+
+fn get_led(_: &Context, _: &Request) -> Response {
+    Response::json(&led_state())
+}
+
+/// An empty body toggles the LED, `{"on": true}` sets it and
+/// `{"manual": false}` hands it back to the `indicator` task.
+fn post_led(_: &Context, request: &Request) -> Response {
+    if request.body.is_empty() {
+        led::set_manual(true);
+        led::toggle();
+    } else {
+        let Ok(command) = serde_json::from_slice::<LedCommand>(request.body) else {
+            return Response::text(400, "invalid JSON");
+        };
+        if let Some(manual) = command.manual {
+            led::set_manual(manual);
+        }
+        if let Some(on) = command.on {
+            led::set_manual(true);
+            led::set(on);
+        }
+    }
+
+    Response::json(&led_state())
+}
+
+#[derive(Deserialize)]
+struct OtaCommand {
+    url: String,
+    /// Hex, as printed by `sha256sum`
+    sha256: String,
+}
+
+fn get_ota(_: &Context, _: &Request) -> Response {
+    Response::json(&ota::status())
+}
+
+/// `{"url": "http://...", "sha256": "..."}` starts an update, follow its
+/// progress with `GET /ota`.
+fn post_ota(_: &Context, request: &Request) -> Response {
+    if let Err(response) = authorize(request) {
+        return response;
+    }
+    let Ok(command) = serde_json::from_slice::<OtaCommand>(request.body) else {
+        return Response::text(400, "invalid JSON");
+    };
+    let Some(sha256) = ota::download::parse_sha256(&command.sha256) else {
+        return Response::text(400, "invalid sha256");
+    };
+
+    match ota::start(&command.url, sha256) {
+        Ok(()) => {
+            let mut response = Response::json(&ota::status());
+            response.status = 202;
+            response
+        }
+        Err(ota::Error::Busy) => Response::text(409, "update in progress"),
+        Err(ota::Error::Unavailable) => Response::error(503),
+        Err(_) => Response::text(400, "invalid url"),
+    }
+}
+
+/// Check the bearer token against [`TOKEN`].
+fn authorize(request: &Request) -> Result<(), Response> {
+    let Some(token) = TOKEN else {
+        return Err(Response::text(403, "set http::TOKEN to enable"));
+    };
+    match request.bearer_token() {
+        Some(bearer) if shell::token_eq(token, bearer) => Ok(()),
+        _ => Err(Response::error(401)),
+    }
+}
+
+/// Without the Wi-Fi secrets, so they have to be sent again with `PUT`.
+fn get_settings(_: &Context, _: &Request) -> Response {
+    Response::json(&settings::get().redacted())
+}
+
+/// Replace the stored settings, they are written to flash in the
+/// background and take effect after a restart.
+fn put_settings(_: &Context, request: &Request) -> Response {
+    if let Err(response) = authorize(request) {
+        return response;
+    }
+    let Ok(new) = serde_json::from_slice::<settings::Settings>(request.body) else {
+        return Response::text(400, "invalid JSON");
+    };
+
+    match settings::submit(new) {
+        Ok(()) => {
+            let mut response = Response::json(&settings::get().redacted());
+            response.status = 202;
+            response
+        }
+        Err(reason) => Response::text(400, reason),
+    }
+}
+
+/// In the Prometheus text format.
+fn get_metrics(_: &Context, _: &Request) -> Response {
+    let mut body = String::new();
+    // Writing to a `String` doesn't fail.
+    metrics::REGISTRY.encode(&mut body).ok();
+    Response::new(200, metrics::CONTENT_TYPE, body.into_bytes())
+}
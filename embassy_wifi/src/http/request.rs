@@ -0,0 +1,174 @@
+//! HTTP/1.x request parsing.
+
+pub const MAX_HEADERS: usize = 16;
+
+/// Errors
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Not a valid HTTP/1.x request
+    BadRequest,
+    /// More than [`MAX_HEADERS`] headers
+    TooManyHeaders,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Options,
+}
+
+impl Method {
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "OPTIONS" => Method::Options,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Request<'a> {
+    pub method: Method,
+    pub path: &'a str,
+    pub query: Option<&'a str>,
+    pub headers: heapless::Vec<(&'a str, &'a str), MAX_HEADERS>,
+    pub body: &'a [u8],
+}
+
+impl<'a> Request<'a> {
+    /// Parse the request line and headers at the start of `buf`.
+    ///
+    /// Returns `None` if the header isn't complete yet, otherwise the
+    /// request with an empty body and the length of the header.
+    pub fn parse(buf: &'a [u8]) -> Result<Option<(Self, usize)>, Error> {
+        let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+            return Ok(None);
+        };
+        let head = core::str::from_utf8(&buf[..end]).map_err(|_| Error::BadRequest)?;
+        let mut lines = head.split("\r\n");
+
+        let mut parts = lines.next().unwrap_or("").split(' ');
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(Error::BadRequest);
+        };
+        let method = Method::parse(method).ok_or(Error::BadRequest)?;
+        if !version.starts_with("HTTP/1.") || !target.starts_with('/') {
+            return Err(Error::BadRequest);
+        }
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (target, None),
+        };
+
+        let mut headers = heapless::Vec::new();
+        for line in lines {
+            let (name, value) = line.split_once(':').ok_or(Error::BadRequest)?;
+            headers
+                .push((name.trim(), value.trim()))
+                .map_err(|_| Error::TooManyHeaders)?;
+        }
+
+        let request = Request {
+            method,
+            path,
+            query,
+            headers,
+            body: &[],
+        };
+        Ok(Some((request, end + 4)))
+    }
+
+    /// Value of the first header named `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| *v)
+    }
+
+    pub fn content_length(&self) -> Result<usize, Error> {
+        match self.header("Content-Length") {
+            Some(len) => len.parse().map_err(|_| Error::BadRequest),
+            None => Ok(0),
+        }
+    }
+
+    /// Length of the request with a header of `header_len` bytes, `None` if
+    /// the client sent a Content-Length too large for a `usize`.
+    pub fn total_len(&self, header_len: usize) -> Result<Option<usize>, Error> {
+        Ok(header_len.checked_add(self.content_length()?))
+    }
+
+    /// The token of an `Authorization: Bearer <token>` header.
+    pub fn bearer_token(&self) -> Option<&'a str> {
+        let (scheme, token) = self.header("Authorization")?.split_once(' ')?;
+        scheme.eq_ignore_ascii_case("Bearer").then(|| token.trim())
+    }
+
+    /// Value of a query parameter, without percent decoding.
+    pub fn query_param(&self, name: &str) -> Option<&'a str> {
+        self.query?.split('&').find_map(|pair| match pair.split_once('=') {
+            Some((n, v)) if n == name => Some(v),
+            None if pair == name => Some(""),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let buf = b"POST /led?on=1 HTTP/1.1\r\nHost: esp\r\ncontent-length: 2\r\n\r\n{}";
+        assert!(Request::parse(&buf[..20]).unwrap().is_none());
+
+        let (request, len) = Request::parse(buf).unwrap().unwrap();
+        assert_eq!(request.method, Method::Post);
+        assert_eq!(request.path, "/led");
+        assert_eq!(request.query_param("on"), Some("1"));
+        assert_eq!(request.header("Host"), Some("esp"));
+        assert_eq!(request.content_length(), Ok(2));
+        assert_eq!(request.total_len(len), Ok(Some(buf.len())));
+        assert_eq!(request.bearer_token(), None);
+        assert_eq!(&buf[len..], b"{}");
+
+        assert_eq!(
+            Request::parse(b"GET status HTTP/1.1\r\n\r\n").unwrap_err(),
+            Error::BadRequest
+        );
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let buf = b"POST /ota HTTP/1.1\r\nauthorization: bearer s3cret\r\n\r\n";
+        let (request, _) = Request::parse(buf).unwrap().unwrap();
+        assert_eq!(request.bearer_token(), Some("s3cret"));
+
+        let buf = b"POST /ota HTTP/1.1\r\nAuthorization: Basic dXNlcjpwYXNz\r\n\r\n";
+        let (request, _) = Request::parse(buf).unwrap().unwrap();
+        assert_eq!(request.bearer_token(), None);
+    }
+
+    #[test]
+    fn test_huge_content_length() {
+        let buf = format!("PUT /ota HTTP/1.1\r\nContent-Length: {}\r\n\r\n", usize::MAX);
+        let (request, len) = Request::parse(buf.as_bytes()).unwrap().unwrap();
+        assert_eq!(request.content_length(), Ok(usize::MAX));
+        assert_eq!(request.total_len(len), Ok(None));
+    }
+}
@@ -0,0 +1,128 @@
+//! HTTP responses.
+
+use alloc::vec::Vec;
+use core::fmt::Write as _;
+
+use embedded_io_async::Write;
+use serde::Serialize;
+
+use crate::io;
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    /// `200 OK` with `value` serialized as JSON.
+    pub fn json<T: Serialize>(value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => Self::new(200, "application/json", body),
+            Err(_) => Self::error(500),
+        }
+    }
+
+    pub fn text(status: u16, text: &str) -> Self {
+        Self::new(status, "text/plain; charset=utf-8", text.as_bytes().into())
+    }
+
+    /// An empty status page, e.g. for 404.
+    pub fn error(status: u16) -> Self {
+        Self::text(status, reason(status))
+    }
+
+    /// Write status line, headers and body. The connection is closed after
+    /// every response, so there's no need to support keep-alive.
+    pub async fn write_to<W: Write>(
+        &self,
+        io: &mut W,
+        head_only: bool,
+    ) -> Result<(), io::Error<W::Error>> {
+        // The parts are written one by one, the content type has no limit.
+        let mut status = heapless::String::<5>::new();
+        write!(status, "{}", self.status).map_err(|_| io::Error::BufferFull)?;
+        let mut len = heapless::String::<20>::new();
+        write!(len, "{}", self.body.len()).map_err(|_| io::Error::BufferFull)?;
+        let head = [
+            "HTTP/1.1 ",
+            &status,
+            " ",
+            reason(self.status),
+            "\r\nContent-Type: ",
+            self.content_type,
+            "\r\nContent-Length: ",
+            &len,
+            "\r\nConnection: close\r\n\r\n",
+        ];
+        for part in head {
+            io::write_all(io, part.as_bytes()).await?;
+        }
+        if !head_only {
+            io::write_all(io, &self.body).await?;
+        }
+        io.flush().await.map_err(io::Error::Io)
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    fn write(response: &Response, head_only: bool) -> heapless::String<512> {
+        let mut buf = [0; 512];
+        let mut out = &mut buf[..];
+        block_on(response.write_to(&mut out, head_only)).unwrap();
+        let len = 512 - out.len();
+        core::str::from_utf8(&buf[..len]).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn test_write_to() {
+        // The head is longer than 128 bytes.
+        let response = Response::error(431);
+        assert_eq!(
+            write(&response, false),
+            "HTTP/1.1 431 Request Header Fields Too Large\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             Content-Length: 31\r\n\
+             Connection: close\r\n\r\n\
+             Request Header Fields Too Large"
+        );
+        assert!(write(&response, true).ends_with("Connection: close\r\n\r\n"));
+
+        let content_type = "application/vnd.example.firmware-status+json; charset=utf-8";
+        let long = Response::new(200, content_type, b"{}".into());
+        assert!(write(&long, false).contains("\r\nContent-Length: 2\r\n"));
+    }
+}
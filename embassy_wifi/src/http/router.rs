@@ -0,0 +1,55 @@
+//! Map request paths to handlers.
+
+use super::request::{Method, Request};
+use super::response::Response;
+
+pub type Handler<C> = fn(&C, &Request) -> Response;
+
+pub struct Route<C: 'static> {
+    pub method: Method,
+    pub path: &'static str,
+    pub handler: Handler<C>,
+}
+
+impl<C> Route<C> {
+    pub const fn new(method: Method, path: &'static str, handler: Handler<C>) -> Self {
+        Self {
+            method,
+            path,
+            handler,
+        }
+    }
+}
+
+/// A static routing table, `C` is the context passed to every handler.
+pub struct Router<C: 'static> {
+    routes: &'static [Route<C>],
+}
+
+impl<C> Router<C> {
+    pub const fn new(routes: &'static [Route<C>]) -> Self {
+        Self { routes }
+    }
+
+    /// Dispatch `request` to the first route matching path and method.
+    ///
+    /// `HEAD` falls back to the `GET` handler, the caller drops the body.
+    pub fn handle(&self, context: &C, request: &Request) -> Response {
+        let mut path_found = false;
+
+        for route in self.routes.iter().filter(|r| r.path == request.path) {
+            path_found = true;
+            if route.method == request.method
+                || (request.method == Method::Head && route.method == Method::Get)
+            {
+                return (route.handler)(context, request);
+            }
+        }
+
+        if path_found {
+            Response::error(405)
+        } else {
+            Response::error(404)
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! Error-propagating I/O helpers on top of `embedded-io-async`
+//!
+//! Unlike the provided `Write::write_all`, nothing here panics on a peer
+//! that misbehaves: a write of zero bytes, an early end of stream, a
+//! stalled connection or more data than fits all come back as [`Error`].
+
+use embassy_time::{Duration, with_timeout};
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+
+/// Errors
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error<E> {
+    Io(E),
+    /// The operation didn't complete in time
+    Timeout,
+    /// The stream accepted no more data
+    WriteZero,
+    /// The stream ended before the expected data
+    UnexpectedEof,
+    /// The delimiter wasn't found before the buffer was full
+    BufferFull,
+}
+
+impl<E: embedded_io_async::Error> embedded_io_async::Error for Error<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Io(e) => e.kind(),
+            Error::Timeout => ErrorKind::TimedOut,
+            Error::WriteZero => ErrorKind::WriteZero,
+            Error::UnexpectedEof => ErrorKind::BrokenPipe,
+            Error::BufferFull => ErrorKind::OutOfMemory,
+        }
+    }
+}
+
+impl<E> Error<Error<E>> {
+    /// Merge the errors of a helper used on top of [`Timeout`] or
+    /// [`BufReader`].
+    pub fn flatten(self) -> Error<E> {
+        match self {
+            Error::Io(e) => e,
+            Error::Timeout => Error::Timeout,
+            Error::WriteZero => Error::WriteZero,
+            Error::UnexpectedEof => Error::UnexpectedEof,
+            Error::BufferFull => Error::BufferFull,
+        }
+    }
+}
+
+/// Write all of `buf`.
+pub async fn write_all<T: Write>(io: &mut T, mut buf: &[u8]) -> Result<(), Error<T::Error>> {
+    while !buf.is_empty() {
+        match io.write(buf).await.map_err(Error::Io)? {
+            0 => return Err(Error::WriteZero),
+            n => buf = &buf[n..],
+        }
+    }
+    Ok(())
+}
+
+/// Fill all of `buf`.
+pub async fn read_exact<T: Read>(io: &mut T, mut buf: &mut [u8]) -> Result<(), Error<T::Error>> {
+    while !buf.is_empty() {
+        match io.read(buf).await.map_err(Error::Io)? {
+            0 => return Err(Error::UnexpectedEof),
+            n => buf = &mut buf[n..],
+        }
+    }
+    Ok(())
+}
+
+/// Gives every read, write and flush at most `timeout` to complete.
+pub struct Timeout<T> {
+    inner: T,
+    timeout: Duration,
+}
+
+impl<T> Timeout<T> {
+    pub fn new(inner: T, timeout: Duration) -> Self {
+        Self { inner, timeout }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: ErrorType> ErrorType for Timeout<T> {
+    type Error = Error<T::Error>;
+}
+
+impl<T: Read> Read for Timeout<T> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        with_timeout(self.timeout, self.inner.read(buf))
+            .await
+            .map_err(|_| Error::Timeout)?
+            .map_err(Error::Io)
+    }
+}
+
+impl<T: Write> Write for Timeout<T> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        with_timeout(self.timeout, self.inner.write(buf))
+            .await
+            .map_err(|_| Error::Timeout)?
+            .map_err(Error::Io)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        with_timeout(self.timeout, self.inner.flush())
+            .await
+            .map_err(|_| Error::Timeout)?
+            .map_err(Error::Io)
+    }
+}
+
+/// Reads ahead into `buf`, for protocols that look for delimiters.
+pub struct BufReader<'b, T> {
+    inner: T,
+    buf: &'b mut [u8],
+    start: usize,
+    end: usize,
+}
+
+impl<'b, T: Read> BufReader<'b, T> {
+    pub fn new(inner: T, buf: &'b mut [u8]) -> Self {
+        Self {
+            inner,
+            buf,
+            start: 0,
+            end: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Data read ahead and not consumed yet.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buf[self.start..self.end]
+    }
+
+    /// Read up to and including `delimiter`, which must not be empty.
+    ///
+    /// Fails with [`Error::BufferFull`] if the delimiter doesn't show up
+    /// within the buffer, and [`Error::UnexpectedEof`] if the stream ends
+    /// first.
+    pub async fn read_until(&mut self, delimiter: &[u8]) -> Result<&[u8], Error<T::Error>> {
+        let mut searched = 0;
+        loop {
+            let buffered = &self.buf[self.start..self.end];
+            if let Some(i) = buffered[searched..]
+                .windows(delimiter.len())
+                .position(|window| window == delimiter)
+            {
+                let start = self.start;
+                self.start += searched + i + delimiter.len();
+                return Ok(&self.buf[start..self.start]);
+            }
+            // The delimiter may start in what was searched already.
+            searched = buffered.len().saturating_sub(delimiter.len() - 1);
+
+            if self.fill().await? == 0 {
+                return Err(Error::UnexpectedEof);
+            }
+        }
+    }
+
+    /// Read more data, makes room by moving the buffered data to the front.
+    /// Returns how much was read.
+    async fn fill(&mut self) -> Result<usize, Error<T::Error>> {
+        if self.end == self.buf.len() {
+            if self.start == 0 {
+                return Err(Error::BufferFull);
+            }
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+        let n = self
+            .inner
+            .read(&mut self.buf[self.end..])
+            .await
+            .map_err(Error::Io)?;
+        self.end += n;
+        Ok(n)
+    }
+}
+
+impl<T: ErrorType> ErrorType for BufReader<'_, T> {
+    type Error = Error<T::Error>;
+}
+
+impl<T: Read> Read for BufReader<'_, T> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.start == self.end {
+            return self.inner.read(buf).await.map_err(Error::Io);
+        }
+        let n = buf.len().min(self.end - self.start);
+        buf[..n].copy_from_slice(&self.buf[self.start..self.start + n]);
+        self.start += n;
+        Ok(n)
+    }
+}
+
+/// Pass `data` to `f` as text, with invalid UTF-8 replaced by U+FFFD.
+///
+/// Returns the length of an incomplete character at the end, which should
+/// be kept and completed by the next read.
+pub fn lossy_utf8(mut data: &[u8], mut f: impl FnMut(&str)) -> usize {
+    loop {
+        match core::str::from_utf8(data) {
+            Ok(text) => {
+                f(text);
+                return 0;
+            }
+            Err(e) => {
+                let (valid, rest) = data.split_at(e.valid_up_to());
+                // Checked by `from_utf8`.
+                f(unsafe { core::str::from_utf8_unchecked(valid) });
+                match e.error_len() {
+                    Some(len) => {
+                        f("\u{fffd}");
+                        data = &rest[len..];
+                    }
+                    None => return rest.len(),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::String, vec::Vec};
+    use core::convert::Infallible;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    /// Returns the chunks one per read, then the end of the stream. Reads
+    /// must be large enough for every chunk.
+    struct Chunks<'a>(&'a [&'a [u8]]);
+
+    impl ErrorType for Chunks<'_> {
+        type Error = Infallible;
+    }
+
+    impl Read for Chunks<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+            let Some((chunk, rest)) = self.0.split_first() else {
+                return Ok(0);
+            };
+            let n = buf.len().min(chunk.len());
+            buf[..n].copy_from_slice(&chunk[..n]);
+            self.0 = rest;
+            Ok(n)
+        }
+    }
+
+    /// Accepts `capacity` bytes, then nothing.
+    struct Full {
+        written: Vec<u8>,
+        capacity: usize,
+    }
+
+    impl ErrorType for Full {
+        type Error = Infallible;
+    }
+
+    impl Write for Full {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+            let n = buf.len().min(self.capacity - self.written.len()).min(3);
+            self.written.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+    }
+
+    struct Stalled;
+
+    impl ErrorType for Stalled {
+        type Error = Infallible;
+    }
+
+    impl Read for Stalled {
+        async fn read(&mut self, _: &mut [u8]) -> Result<usize, Infallible> {
+            core::future::pending().await
+        }
+    }
+
+    #[test]
+    fn test_write_read() {
+        let mut full = Full {
+            written: Vec::new(),
+            capacity: 8,
+        };
+        assert_eq!(block_on(write_all(&mut full, b"hello")), Ok(()));
+        assert_eq!(
+            block_on(write_all(&mut full, b"world")),
+            Err(Error::WriteZero)
+        );
+        assert_eq!(full.written, b"hellowor");
+
+        let mut buf = [0; 5];
+        let mut chunks = Chunks(&[b"he", b"llo", b"!"]);
+        assert_eq!(block_on(read_exact(&mut chunks, &mut buf)), Ok(()));
+        assert_eq!(&buf, b"hello");
+        assert_eq!(
+            block_on(read_exact(&mut chunks, &mut buf)),
+            Err(Error::UnexpectedEof)
+        );
+
+        let mut stalled = Timeout::new(Stalled, Duration::from_millis(10));
+        assert_eq!(block_on(stalled.read(&mut buf)), Err(Error::Timeout));
+    }
+
+    #[test]
+    fn test_read_until() {
+        let mut buf = [0; 32];
+        let chunks = Chunks(&[b"HTTP/1.0 200 OK\r", b"\nA: b\r\n\r", b"\nbody"]);
+        let mut reader = BufReader::new(chunks, &mut buf);
+        assert_eq!(
+            block_on(reader.read_until(b"\r\n")),
+            Ok(&b"HTTP/1.0 200 OK\r\n"[..])
+        );
+        assert_eq!(
+            block_on(reader.read_until(b"\r\n\r\n")),
+            Ok(&b"A: b\r\n\r\n"[..])
+        );
+        assert_eq!(reader.buffered(), b"body");
+
+        let mut rest = [0; 8];
+        assert_eq!(block_on(reader.read(&mut rest)), Ok(4));
+        assert_eq!(
+            block_on(reader.read_until(b"\n")),
+            Err(Error::UnexpectedEof)
+        );
+
+        let mut buf = [0; 4];
+        let mut reader = BufReader::new(Chunks(&[b"no newline"]), &mut buf);
+        assert_eq!(block_on(reader.read_until(b"\n")), Err(Error::BufferFull));
+    }
+
+    #[test]
+    fn test_lossy_utf8() {
+        let mut text = String::new();
+        // "é" split over two reads, and a stray continuation byte.
+        assert_eq!(lossy_utf8(b"caf\xc3", |s| text.push_str(s)), 1);
+        assert_eq!(lossy_utf8(b"\xc3\xa9 \x80!", |s| text.push_str(s)), 0);
+        assert_eq!(text, "café \u{fffd}!");
+    }
+}
@@ -0,0 +1,128 @@
+//! The LED on GPIO8, shared between the `indicator` task, the HTTP API and
+//! the shell.
+//!
+//! The `indicator` task shows the network status with a blink pattern:
+//!
+//! | Status                          | Pattern                   |
+//! |---------------------------------|---------------------------|
+//! | Wi-Fi disconnected              | short flash every 2 s     |
+//! | Connecting to the access point  | fast blinking             |
+//! | Associated, waiting for address | slow blinking             |
+//! | Address, broker not connected   | double flash every 2 s    |
+//! | Everything up                   | on, briefly off every 2 s |
+
+use alloc::string::String;
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_futures::select::{Either, select};
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::Timer;
+use esp_hal::gpio::Output;
+
+use crate::shell::{self, Command, Context, Reply, done};
+use crate::status::{self, Status, Wifi};
+
+// Alternating on and off times in milliseconds.
+const DISCONNECTED: &[u64] = &[100, 1900];
+const CONNECTING: &[u64] = &[100, 100];
+const NO_ADDRESS: &[u64] = &[500, 500];
+const NO_BACKEND: &[u64] = &[100, 200, 100, 1600];
+const ONLINE: &[u64] = &[1900, 100];
+
+pub static COMMANDS: &[Command<Context>] = &[Command::new(
+    "led",
+    "on|off|auto",
+    "switch the LED, auto shows the network status",
+    led_command,
+)];
+
+static LED: Mutex<CriticalSectionRawMutex, RefCell<Option<Output<'static>>>> =
+    Mutex::new(RefCell::new(None));
+
+/// When set, the LED is controlled remotely and the `indicator` task leaves
+/// it alone.
+static MANUAL: AtomicBool = AtomicBool::new(false);
+
+pub fn init(led: Output<'static>) {
+    LED.lock(|cell| cell.replace(Some(led)));
+}
+
+pub fn toggle() {
+    LED.lock(|cell| {
+        if let Some(led) = cell.borrow_mut().as_mut() {
+            led.toggle();
+        }
+    })
+}
+
+pub fn set(on: bool) {
+    LED.lock(|cell| {
+        if let Some(led) = cell.borrow_mut().as_mut() {
+            led.set_level(on.into());
+        }
+    })
+}
+
+pub fn is_on() -> bool {
+    LED.lock(|cell| {
+        cell.borrow()
+            .as_ref()
+            .map(|led| led.is_set_high())
+            .unwrap_or(false)
+    })
+}
+
+pub fn set_manual(manual: bool) {
+    MANUAL.store(manual, Ordering::Relaxed);
+}
+
+pub fn is_manual() -> bool {
+    MANUAL.load(Ordering::Relaxed)
+}
+
+/// The blink pattern for `status`.
+pub fn pattern(status: &Status) -> &'static [u64] {
+    match status.wifi {
+        Wifi::Disconnected => DISCONNECTED,
+        Wifi::Connecting => CONNECTING,
+        Wifi::Connected if !status.address => NO_ADDRESS,
+        Wifi::Connected if !status.backend => NO_BACKEND,
+        Wifi::Connected => ONLINE,
+    }
+}
+
+fn led_command<'a>(_: &'a Context, args: &'a [&'a str], _: &'a mut String) -> Reply<'a> {
+    match args {
+        ["on"] | ["off"] => {
+            set_manual(true);
+            set(args[0] == "on");
+        }
+        ["auto"] => set_manual(false),
+        _ => return done(Err(shell::Error::Usage)),
+    }
+    done(Ok(()))
+}
+
+#[embassy_executor::task]
+pub async fn indicator() {
+    let mut receiver = status::STATUS.receiver().unwrap();
+    let mut status = status::get();
+    loop {
+        // Start over with the new pattern as soon as the status changes.
+        if let Either::Second(new) = select(play(pattern(&status)), receiver.changed()).await {
+            status = new;
+        }
+    }
+}
+
+async fn play(pattern: &[u64]) {
+    loop {
+        for (i, millis) in pattern.iter().enumerate() {
+            if !is_manual() {
+                set(i % 2 == 0);
+            }
+            Timer::after_millis(*millis).await;
+        }
+    }
+}
@@ -9,33 +9,114 @@
 
 extern crate alloc;
 
+use alloc::vec;
 use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicI32, Ordering};
 
 use esp_hal::{
     clock::CpuClock,
     gpio::{Level, Output, OutputConfig},
     rng::Rng,
+    rtc_cntl::Rtc,
     timer::timg::TimerGroup,
 };
 
 use esp_backtrace as _;
-use esp_println::println;
+use esp_println::{print, println};
+use esp_storage::FlashStorage;
 
 use embassy_executor::Spawner;
+use embassy_futures::select::{Either4, select4};
 use embassy_time::{Duration, Timer};
+use embedded_io_async::{Read, Write};
 
 use esp_wifi::{
     EspWifiController, init,
-    wifi::{ClientConfiguration, Configuration, WifiController, WifiDevice, WifiEvent, WifiState},
+    wifi::{WifiController, WifiDevice, WifiEvent, WifiState},
 };
 
-use embassy_net::{Config, Ipv4Address, Runner, Stack, StackResources, tcp::TcpSocket};
-
+use embassy_net::{Ipv4Address, Runner, Stack, StackResources, tcp::TcpSocket};
+use static_cell::ConstStaticCell;
+
+use metrics::{Counter, Metric};
+
+pub mod clock;
+pub mod coap;
+pub mod csi;
+pub mod espnow;
+pub mod health;
+pub mod http;
+pub mod io;
+pub mod led;
+#[cfg(test)]
+pub mod mem_flash;
+pub mod mdns;
+pub mod metrics;
+pub mod modbus;
+pub mod mqtt;
+pub mod net;
+pub mod ota;
+pub mod power;
+pub mod settings;
+pub mod shell;
+pub mod sntp;
+pub mod status;
+#[cfg(feature = "log")]
+pub mod syslog;
+pub mod tls;
+pub mod websocket;
+pub mod wifi;
+
+/// WPA2-Personal access point, unless the settings have `wifi`.
 // const SSID: &str = env!("SSID");
 // const PASSWORD: &str = env!("PASSWORD");
 const SSID: &str = "HOME_2";
 const PASSWORD: &str = "lalala123456";
 
+/// Fetched by the `tcp` task, over TLS with `tls::CA` pinned when `TLS` is
+/// set.
+const TLS: bool = false;
+const HOST: &str = "www.mobile-j.de";
+const REMOTE: (Ipv4Address, u16) = (
+    Ipv4Address::new(142, 250, 185, 115),
+    if TLS { 443 } else { 80 },
+);
+/// For every read and write of the `tcp` task.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `git describe` of the checkout the firmware was built from, see
+/// `build.rs`.
+pub const FIRMWARE: &str = match option_env!("FIRMWARE_VERSION") {
+    Some(version) => version,
+    None => "unknown",
+};
+
+/// Signal strength of the access point in dBm, 0 while not connected.
+static WIFI_RSSI: AtomicI32 = AtomicI32::new(0);
+
+const RSSI_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Signal strength of the access point, if connected.
+pub fn rssi() -> Option<i32> {
+    match WIFI_RSSI.load(Ordering::Relaxed) {
+        0 => None,
+        rssi => Some(rssi),
+    }
+}
+
+pub static METRICS: &[Metric] = &[
+    Metric::Counter(&MAIN_LOOPS),
+    Metric::Counter(&TASK_LOOPS),
+    Metric::Counter(&TCP_ERRORS),
+];
+
+static MAIN_LOOPS: Counter =
+    Counter::new("task_loops_total", "Iterations of task loops").with_labels("task=\"main\"");
+static TASK_LOOPS: Counter =
+    Counter::new("task_loops_total", "Iterations of task loops").with_labels("task=\"run\"");
+static TCP_ERRORS: Counter =
+    Counter::new("tcp_errors_total", "Failed connections and requests").with_labels("task=\"tcp\"");
+
 macro_rules! mk_static {
     ($t:ty,$val:expr) => {{
         static STATIC_CELL: static_cell::StaticCell<$t> = static_cell::StaticCell::new();
@@ -46,7 +127,7 @@ macro_rules! mk_static {
 }
 
 fn init_heap() {
-    const HEAP_SIZE: usize = 64 * 1024;
+    const HEAP_SIZE: usize = 96 * 1024;
     static mut HEAP: core::mem::MaybeUninit<[u8; HEAP_SIZE]> = core::mem::MaybeUninit::uninit();
 
     unsafe {
@@ -65,8 +146,8 @@ async fn main(spawner: Spawner) {
     #[cfg(feature = "log")]
     {
         // The default log level can be specified here.
-        // You can see the esp-println documentation： https://docs.rs/esp-println
-        esp_println::logger::init_logger(log::LevelFilter::Info);
+        // Printed like esp-println's logger does, and also sent with syslog.
+        syslog::init(log::LevelFilter::Info);
     }
 
     println!("Init!");
@@ -85,19 +166,25 @@ async fn main(spawner: Spawner) {
     // let init = &*singleton!(:EspWifiController<'static> = init(timer1.timer0, rng, peripherals.RADIO_CLK).unwrap()).unwrap();
 
     // set wifi mode
-    let (controller, interfaces) = esp_wifi::wifi::new(esp_wifi_ctrl, peripherals.WIFI).unwrap();
+    let (mut controller, interfaces) = esp_wifi::wifi::new(esp_wifi_ctrl, peripherals.WIFI).unwrap();
 
     let wifi_interface = interfaces.sta;
 
-    let config = Config::dhcpv4(Default::default());
+    let settings = settings::init(&mut FlashStorage::new()).unwrap_or_else(|e| {
+        println!("settings: read error: {:?}", e);
+        settings::Settings::default()
+    });
+    println!("Network settings: {:?}", settings.redacted());
+    let config = net::config(&settings);
 
     let seed = 1234; // very random, very secure seed
 
-    // Init network stack
+    // Init network stack, with room for the sockets of every task plus
+    // DHCP and DNS
     let (stack, runner) = embassy_net::new(
         wifi_interface,
         config,
-        mk_static!(StackResources<8>, StackResources::<8>::new()),
+        mk_static!(StackResources<20>, StackResources::<20>::new()),
         seed,
     );
 
@@ -116,6 +203,7 @@ async fn main(spawner: Spawner) {
 
     // Set GPIO0 as an output, and set its state high initially.
     let led = Output::new(peripherals.GPIO8, Level::High, OutputConfig::default());
+    led::init(led);
 
     let timg0 = TimerGroup::new(peripherals.TIMG0);
     esp_hal_embassy::init(timg0.timer0);
@@ -123,14 +211,53 @@ async fn main(spawner: Spawner) {
     println!("embassy init!");
 
     spawner.spawn(run()).ok();
-    spawner.spawn(toggle(led)).ok();
+    spawner.spawn(store_settings()).ok();
+    spawner.spawn(led::indicator()).ok();
 
-    spawner.spawn(connection(controller)).ok();
+    match csi::enable(&mut controller) {
+        Ok(queue) => {
+            spawner.spawn(csi::csi(stack, queue)).ok();
+        }
+        Err(e) => println!("CSI capture unavailable: {:?}", e),
+    }
+    // Nodes stay on the ESP-NOW channel instead of following an access
+    // point.
+    if espnow::ROLE == espnow::Role::Node {
+        spawner.spawn(espnow::radio(controller)).ok();
+    } else {
+        spawner.spawn(connection(controller)).ok();
+    }
+    if let Some(sleep_secs) = settings.power.sleep_secs {
+        let rtc = Rtc::new(peripherals.LPWR);
+        spawner.spawn(power::duty_cycle(rtc, sleep_secs)).ok();
+    }
     spawner.spawn(net_task(runner)).ok();
-    spawner.spawn(tcp(stack)).ok();
+    spawner.spawn(status::network(stack)).ok();
+    spawner.spawn(health::health(stack)).ok();
+    #[cfg(feature = "log")]
+    spawner.spawn(syslog::syslog(stack)).ok();
+    spawner.spawn(tcp(stack, rng)).ok();
+    spawner.spawn(sntp::sntp(stack)).ok();
+    spawner.spawn(mqtt::mqtt(stack, rng)).ok();
+    spawner.spawn(mqtt::commands()).ok();
+    spawner.spawn(websocket::websocket(stack, rng)).ok();
+    spawner.spawn(websocket::commands()).ok();
+    spawner.spawn(ota::ota(stack)).ok();
+    spawner.spawn(mdns::mdns(stack)).ok();
+    spawner.spawn(coap::coap(stack, rng)).ok();
+    spawner.spawn(shell::shell(stack)).ok();
+    spawner.spawn(modbus::modbus(stack)).ok();
+    spawner.spawn(espnow::espnow(interfaces.esp_now, rng)).ok();
+    if espnow::ROLE == espnow::Role::Node {
+        spawner.spawn(espnow::readings()).ok();
+    }
+    for id in 0..http::WORKERS {
+        spawner.spawn(http::http(stack, id)).ok();
+    }
 
     loop {
         println!("main loop!");
+        MAIN_LOOPS.inc();
         Timer::after(Duration::from_millis(5_000)).await;
     }
 }
@@ -139,18 +266,19 @@ async fn main(spawner: Spawner) {
 async fn run() {
     loop {
         println!("task loop!");
+        TASK_LOOPS.inc();
         Timer::after(Duration::from_millis(1_000)).await;
     }
 }
 
+/// Write the settings submitted by the HTTP and Modbus servers.
 #[embassy_executor::task]
-async fn toggle(mut led: Output<'static>) {
+async fn store_settings() {
     loop {
-        println!("toggle loop!");
-        led.toggle();
-        Timer::after_secs(1).await;
-        led.toggle();
-        Timer::after_secs(2).await;
+        let new = settings::pending().await;
+        if let Err(e) = new.write(&mut FlashStorage::new()) {
+            println!("settings: write error: {:?}", e);
+        }
     }
 }
 
@@ -158,30 +286,87 @@ async fn toggle(mut led: Output<'static>) {
 async fn connection(mut controller: WifiController<'static>) {
     println!("start connection task");
     println!("Device capabilities: {:?}", controller.capabilities());
+    wifi::init();
+    let access_point = settings::get().wifi.unwrap_or_else(|| settings::Wifi {
+        ssid: SSID.into(),
+        auth: settings::Auth::Wpa2Personal {
+            password: PASSWORD.into(),
+        },
+    });
+    println!(
+        "Access point {}, {}",
+        access_point.ssid,
+        access_point.auth.name()
+    );
+    let configuration = wifi::configuration(&access_point);
+    // The access point configured from RTC memory, if any.
+    let mut hint = None;
     loop {
         if esp_wifi::wifi::wifi_state() == WifiState::StaConnected {
-            // wait until we're no longer connected
-            controller.wait_for_event(WifiEvent::StaDisconnected).await;
-            Timer::after(Duration::from_millis(5000)).await
+            // wait until we're no longer connected, sampling the signal
+            // strength and serving scans and reconnects meanwhile
+            while esp_wifi::wifi::wifi_state() == WifiState::StaConnected {
+                match select4(
+                    controller.wait_for_event(WifiEvent::StaDisconnected),
+                    Timer::after(RSSI_INTERVAL),
+                    wifi::requested(),
+                    wifi::reconnect_requested(),
+                )
+                .await
+                {
+                    Either4::First(_) => break,
+                    Either4::Second(_) => {
+                        if let Ok(rssi) = controller.rssi() {
+                            WIFI_RSSI.store(rssi, Ordering::Relaxed);
+                        }
+                    }
+                    Either4::Third(_) => wifi::serve(&mut controller).await,
+                    Either4::Fourth(_) => {
+                        println!("Reconnecting");
+                        controller.disconnect_async().await.ok();
+                        break;
+                    }
+                }
+            }
+            WIFI_RSSI.store(0, Ordering::Relaxed);
+            wifi::RECONNECTS.inc();
+            wifi::lost();
+            status::set_wifi(status::Wifi::Disconnected);
+            Timer::after(wifi::RECONNECT_DELAY).await
         }
+        let power_settings = settings::get().power;
         if !matches!(controller.is_started(), Ok(true)) {
-            let client_config = Configuration::Client(ClientConfiguration {
-                ssid: SSID.into(),
-                password: PASSWORD.into(),
-                ..Default::default()
-            });
+            let mut client_config = configuration.clone();
+            // Skip the scan with the access point from before deep sleep.
+            hint = power_settings
+                .sleep_secs
+                .and_then(|_| power::last_access_point());
+            if let Some((bssid, channel)) = hint {
+                wifi::skip_scan(&mut client_config, bssid, channel);
+            }
             controller.set_configuration(&client_config).unwrap();
+            power::set_listen_interval(power_settings.listen_interval);
             println!("Starting wifi");
             controller.start_async().await.unwrap();
             println!("Wifi started!");
+            power::set_power_save(&mut controller, power_settings.save);
         }
         println!("About to connect...");
+        status::set_wifi(status::Wifi::Connecting);
 
-        match controller.connect_async().await {
-            Ok(_) => println!("Wifi connected!"),
-            Err(e) => {
-                println!("Failed to connect to wifi: {e:?}");
-                Timer::after(Duration::from_millis(5000)).await
+        match wifi::connect(&mut controller).await {
+            Ok(()) => {
+                status::set_wifi(status::Wifi::Connected);
+                power::remember_access_point();
+            }
+            Err(_) => {
+                status::set_wifi(status::Wifi::Disconnected);
+                if hint.take().is_some() {
+                    // Configure again without it.
+                    power::forget_access_point();
+                    controller.stop_async().await.ok();
+                }
+                Timer::after(wifi::RECONNECT_DELAY).await
             }
         }
     }
@@ -193,78 +378,96 @@ async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
 }
 
 #[embassy_executor::task]
-async fn tcp(stack: Stack<'static>) {
-    let mut rx_buffer = [0; 4096];
-    let mut tx_buffer = [0; 4096];
-
-    loop {
-        if stack.is_link_up() {
-            break;
-        }
-        Timer::after(Duration::from_millis(500)).await;
-    }
+async fn tcp(stack: Stack<'static>, rng: Rng) {
+    // Static, too large for the task arena.
+    static RX_BUFFER: ConstStaticCell<[u8; 4096]> = ConstStaticCell::new([0; 4096]);
+    static TX_BUFFER: ConstStaticCell<[u8; 4096]> = ConstStaticCell::new([0; 4096]);
+    let rx_buffer = RX_BUFFER.take();
+    let tx_buffer = TX_BUFFER.take();
 
-    println!("Waiting to get IP address...");
-    loop {
-        if let Some(config) = stack.config_v4() {
-            println!("Got IP: {}", config.address);
-            break;
-        }
-        Timer::after(Duration::from_millis(500)).await;
-    }
+    net::wait_for_address(stack).await;
 
     loop {
         Timer::after(Duration::from_millis(1_000)).await;
 
-        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer[..], &mut tx_buffer[..]);
 
         socket.set_timeout(Some(embassy_time::Duration::from_secs(10)));
 
-        let remote_endpoint = (Ipv4Address::new(142, 250, 185, 115), 80);
         println!("connecting...");
-        let r = socket.connect(remote_endpoint).await;
+        let r = socket.connect(REMOTE).await;
         if let Err(e) = r {
             println!("connect error: {:?}", e);
+            TCP_ERRORS.inc();
             continue;
         }
         println!("connected!");
-        let mut buf = [0; 1024];
-        loop {
-            let r = write_all(
+
+        if TLS {
+            let mut read_buffer = vec![0; tls::READ_BUFFER_LEN];
+            let mut write_buffer = vec![0; tls::WRITE_BUFFER_LEN];
+            match tls::connect(
                 &mut socket,
-                b"GET / HTTP/1.0\r\nHost: www.mobile-j.de\r\n\r\n",
+                HOST,
+                tls::CA,
+                rng,
+                &mut read_buffer,
+                &mut write_buffer,
             )
-            .await;
-            if let Err(e) = r {
-                println!("write error: {:?}", e);
-                break;
-            }
-
-            let n = match socket.read(&mut buf).await {
-                Ok(0) => {
-                    println!("read EOF");
-                    break;
+            .await
+            {
+                Ok(connection) => {
+                    if let Err(e) = fetch(connection).await {
+                        println!("fetch error: {:?}", e);
+                        TCP_ERRORS.inc();
+                    }
                 }
-                Ok(n) => n,
                 Err(e) => {
-                    println!("read error: {:?}", e);
-                    break;
+                    println!("TLS handshake error: {:?}", e);
+                    TCP_ERRORS.inc();
                 }
-            };
-            println!("{}", core::str::from_utf8(&buf[..n]).unwrap());
+            }
+        } else if let Err(e) = fetch(&mut socket).await {
+            println!("fetch error: {:?}", e);
+            TCP_ERRORS.inc();
         }
         Timer::after(Duration::from_millis(3000)).await;
     }
 }
 
-async fn write_all(socket: &mut TcpSocket<'_>, buf: &[u8]) -> Result<(), embassy_net::tcp::Error> {
-    let mut buf = buf;
-    while !buf.is_empty() {
-        match socket.write(buf).await {
-            Ok(0) => panic!("write() returned Ok(0)"),
-            Ok(n) => buf = &buf[n..],
-            Err(e) => return Err(e),
+/// Request `/` and print the response until the server closes the
+/// connection. The response may be binary or end in the middle of a
+/// character, invalid UTF-8 is printed as U+FFFD.
+async fn fetch<T: Read + Write>(io: T) -> Result<(), io::Error<T::Error>> {
+    let mut io = io::Timeout::new(io, FETCH_TIMEOUT);
+    exchange(&mut io).await.map_err(io::Error::flatten)
+}
+
+async fn exchange<T: Read + Write>(io: &mut T) -> Result<(), io::Error<T::Error>> {
+    let request = alloc::format!("GET / HTTP/1.0\r\nHost: {}\r\n\r\n", HOST);
+    io::write_all(io, request.as_bytes()).await?;
+    // TLS connections only send once flushed.
+    io.flush().await.map_err(io::Error::Io)?;
+
+    let mut head_buffer = [0; 1024];
+    let mut reader = io::BufReader::new(io, &mut head_buffer);
+    let head = reader.read_until(b"\r\n\r\n").await?;
+    io::lossy_utf8(head, |text| print!("{}", text));
+
+    // Bytes of an incomplete character are kept for the next read.
+    let mut buf = [0; 512];
+    let mut pending = 0;
+    loop {
+        let n = reader.read(&mut buf[pending..]).await?;
+        if n == 0 {
+            if pending > 0 {
+                print!("\u{fffd}");
+            }
+            println!();
+            return Ok(());
         }
+        let end = pending + n;
+        pending = io::lossy_utf8(&buf[..end], |text| print!("{}", text));
+        buf.copy_within(end - pending..end, 0);
     }
-    Ok(())
 }
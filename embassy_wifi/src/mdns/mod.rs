@@ -0,0 +1,170 @@
+//! mDNS responder and DNS-SD service advertisement
+//!
+//! The `mdns` task answers queries for `<hostname>.local` and advertises
+//! [`SERVICES`] on both the IPv4 and the IPv6 group, so the device can be
+//! found without knowing its addresses. `packet` and `responder` don't
+//! depend on esp-hal or embassy-net and are tested on the host.
+
+use core::fmt::Write as _;
+
+use embassy_futures::select::{Either, select};
+use embassy_net::{
+    IpAddress, IpEndpoint, Ipv4Address, Ipv6Address, Stack,
+    udp::{PacketMetadata, UdpSocket},
+};
+use embassy_time::Timer;
+use esp_hal::efuse::Efuse;
+use esp_println::println;
+use static_cell::ConstStaticCell;
+
+use crate::{coap, http, mqtt, net};
+
+pub mod packet;
+pub mod responder;
+
+pub use responder::{Responder, Service};
+
+pub const PORT: u16 = 5353;
+pub const GROUP: Ipv4Address = Ipv4Address::new(224, 0, 0, 251);
+pub const GROUP_V6: Ipv6Address = Ipv6Address::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+
+/// Services announced with DNS-SD, besides the TXT entries in `mdns`.
+pub static SERVICES: &[Service] = &[
+    Service {
+        service: "_http._tcp",
+        port: http::PORT,
+        txt: &[("path", "/status")],
+    },
+    Service {
+        service: "_esp-telemetry._tcp",
+        port: http::PORT,
+        txt: &[("path", "/status"), ("topic", mqtt::TELEMETRY_TOPIC)],
+    },
+    Service {
+        service: "_coap._udp",
+        port: coap::PORT,
+        txt: &[],
+    },
+];
+
+#[embassy_executor::task]
+pub async fn mdns(stack: Stack<'static>) {
+    let hostname = net::hostname();
+
+    let mac = Efuse::read_base_mac_address();
+    let mut mac_text = heapless::String::<17>::new();
+    for (i, b) in mac.iter().enumerate() {
+        let separator = if i == 0 { "" } else { ":" };
+        write!(mac_text, "{}{:02x}", separator, b).ok();
+    }
+    let txt = [
+        ("chip", esp_hal::chip!()),
+        ("fw", crate::FIRMWARE),
+        ("mac", mac_text.as_str()),
+    ];
+
+    // Static, too large for the task arena.
+    static RX_BUFFER: ConstStaticCell<[u8; 1024]> = ConstStaticCell::new([0; 1024]);
+    static TX_BUFFER: ConstStaticCell<[u8; 1024]> = ConstStaticCell::new([0; 1024]);
+    static BUF: ConstStaticCell<[u8; 1024]> = ConstStaticCell::new([0; 1024]);
+    static OUT: ConstStaticCell<[u8; 1024]> = ConstStaticCell::new([0; 1024]);
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let buf = BUF.take();
+    let out = OUT.take();
+
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        RX_BUFFER.take(),
+        &mut tx_meta,
+        TX_BUFFER.take(),
+    );
+    socket.bind(PORT).unwrap();
+    // RFC 6762, 11
+    socket.set_hop_limit(Some(255));
+
+    // Either family may be disabled in the settings.
+    let mut joined = false;
+    for group in [IpAddress::Ipv4(GROUP), IpAddress::Ipv6(GROUP_V6)] {
+        match stack.join_multicast_group(group) {
+            Ok(()) => joined = true,
+            Err(e) => println!("mdns: join {} error: {:?}", group, e),
+        }
+    }
+    if !joined {
+        return;
+    }
+
+    loop {
+        stack.wait_config_up().await;
+
+        let responder = Responder {
+            hostname: &hostname,
+            ipv4: stack.config_v4().map(|config| config.address.address()),
+            ipv6: stack.config_v6().map(|config| config.address.address()),
+            services: SERVICES,
+            txt: &txt,
+        };
+        println!("mdns: answering for {}.local", hostname);
+
+        // Announce twice, one second apart (RFC 6762, 8.3).
+        for _ in 0..2 {
+            match responder.announce(&mut out[..]) {
+                Ok(len) => {
+                    let groups = [
+                        responder.ipv4.map(|_| IpAddress::Ipv4(GROUP)),
+                        responder.ipv6.map(|_| IpAddress::Ipv6(GROUP_V6)),
+                    ];
+                    for group in groups.into_iter().flatten() {
+                        if let Err(e) = socket.send_to(&out[..len], (group, PORT)).await {
+                            println!("mdns: send error: {:?}", e);
+                        }
+                    }
+                }
+                Err(e) => println!("mdns: announce error: {:?}", e),
+            }
+            Timer::after_secs(1).await;
+        }
+
+        // Start over with the new address once the current one is gone.
+        loop {
+            let (len, meta) =
+                match select(socket.recv_from(&mut buf[..]), stack.wait_config_down()).await {
+                    Either::First(Ok(received)) => received,
+                    Either::First(Err(e)) => {
+                        println!("mdns: receive error: {:?}", e);
+                        continue;
+                    }
+                    Either::Second(()) => break,
+                };
+
+            // Queries from other ports come from plain DNS resolvers, which
+            // expect a unicast response.
+            let legacy = meta.endpoint.port != PORT;
+            match responder.respond(&buf[..len], legacy, &mut out[..]) {
+                Ok(Some(n)) => {
+                    let r = if legacy {
+                        socket.send_to(&out[..n], meta.endpoint).await
+                    } else {
+                        socket.send_to(&out[..n], group(meta.endpoint)).await
+                    };
+                    if let Err(e) = r {
+                        println!("mdns: send error: {:?}", e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => println!("mdns: bad query from {}: {:?}", meta.endpoint, e),
+            }
+        }
+    }
+}
+
+/// The group of the same family as `from`, for multicast responses.
+fn group(from: IpEndpoint) -> IpEndpoint {
+    match from.addr {
+        IpAddress::Ipv4(_) => IpEndpoint::new(IpAddress::Ipv4(GROUP), PORT),
+        IpAddress::Ipv6(_) => IpEndpoint::new(IpAddress::Ipv6(GROUP_V6), PORT),
+    }
+}
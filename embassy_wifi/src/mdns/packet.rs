@@ -0,0 +1,309 @@
+//! The parts of the DNS wire format (RFC 1035) mDNS needs.
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_SRV: u16 = 33;
+pub const TYPE_ANY: u16 = 255;
+
+pub const CLASS_IN: u16 = 1;
+/// In questions, the querier accepts a unicast response (RFC 6762, 5.4)
+pub const UNICAST_RESPONSE: u16 = 0x8000;
+/// In records, replaces cached records with the same name (RFC 6762, 10.2)
+pub const CACHE_FLUSH: u16 = 0x8000;
+
+/// Response, authoritative answer
+pub const FLAGS_RESPONSE: u16 = 0x8400;
+const FLAGS_QR: u16 = 0x8000;
+
+pub const MAX_NAME_LEN: usize = 255;
+
+/// Errors
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    BufferTooSmall,
+    MalformedPacket,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub id: u16,
+    pub flags: u16,
+    pub questions: u16,
+    pub answers: u16,
+    pub authorities: u16,
+    pub additionals: u16,
+}
+
+impl Header {
+    pub fn is_response(&self) -> bool {
+        self.flags & FLAGS_QR != 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Question {
+    /// Dotted, without the trailing dot
+    pub name: heapless::String<MAX_NAME_LEN>,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+/// Reads a message, following compression pointers in names.
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        let b = *self.buf.get(self.pos).ok_or(Error::MalformedPacket)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes([self.u8()?, self.u8()?]))
+    }
+
+    pub fn header(&mut self) -> Result<Header, Error> {
+        Ok(Header {
+            id: self.u16()?,
+            flags: self.u16()?,
+            questions: self.u16()?,
+            answers: self.u16()?,
+            authorities: self.u16()?,
+            additionals: self.u16()?,
+        })
+    }
+
+    pub fn question(&mut self) -> Result<Question, Error> {
+        Ok(Question {
+            name: self.name()?,
+            qtype: self.u16()?,
+            qclass: self.u16()?,
+        })
+    }
+
+    fn name(&mut self) -> Result<heapless::String<MAX_NAME_LEN>, Error> {
+        let mut name = heapless::String::new();
+        let mut pos = self.pos;
+        // Where to continue after the first pointer
+        let mut end = None;
+        // Pointers must go backwards, which also rules out loops.
+        let mut limit = self.pos;
+
+        loop {
+            let len = *self.buf.get(pos).ok_or(Error::MalformedPacket)? as usize;
+            match len {
+                0 => {
+                    self.pos = end.unwrap_or(pos + 1);
+                    return Ok(name);
+                }
+                0xc0.. => {
+                    let low = *self.buf.get(pos + 1).ok_or(Error::MalformedPacket)? as usize;
+                    let target = (len & 0x3f) << 8 | low;
+                    if target >= limit {
+                        return Err(Error::MalformedPacket);
+                    }
+                    end.get_or_insert(pos + 2);
+                    limit = target;
+                    pos = target;
+                }
+                1..=63 => {
+                    let label = self
+                        .buf
+                        .get(pos + 1..pos + 1 + len)
+                        .ok_or(Error::MalformedPacket)?;
+                    let label = core::str::from_utf8(label).map_err(|_| Error::MalformedPacket)?;
+                    if !name.is_empty() {
+                        name.push('.').map_err(|_| Error::MalformedPacket)?;
+                    }
+                    name.push_str(label).map_err(|_| Error::MalformedPacket)?;
+                    pos += 1 + len;
+                }
+                _ => return Err(Error::MalformedPacket),
+            }
+        }
+    }
+}
+
+/// Writes a message, names are not compressed.
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == 0
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+        let end = self.pos + data.len();
+        self.buf
+            .get_mut(self.pos..end)
+            .ok_or(Error::BufferTooSmall)?
+            .copy_from_slice(data);
+        self.pos = end;
+        Ok(())
+    }
+
+    pub fn u16(&mut self, value: u16) -> Result<(), Error> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn u32(&mut self, value: u32) -> Result<(), Error> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn header(&mut self, header: &Header) -> Result<(), Error> {
+        for value in [
+            header.id,
+            header.flags,
+            header.questions,
+            header.answers,
+            header.authorities,
+            header.additionals,
+        ] {
+            self.u16(value)?;
+        }
+        Ok(())
+    }
+
+    /// Update the header counts once all records are written.
+    pub fn set_counts(&mut self, questions: u16, answers: u16, additionals: u16) {
+        self.buf[4..6].copy_from_slice(&questions.to_be_bytes());
+        self.buf[6..8].copy_from_slice(&answers.to_be_bytes());
+        self.buf[10..12].copy_from_slice(&additionals.to_be_bytes());
+    }
+
+    /// Write a name made of the labels of each part, e.g.
+    /// `["esp", "_http._tcp", "local"]`.
+    pub fn name(&mut self, parts: &[&str]) -> Result<(), Error> {
+        for label in parts.iter().flat_map(|part| part.split('.')) {
+            if label.is_empty() || label.len() > 63 {
+                return Err(Error::MalformedPacket);
+            }
+            self.bytes(&[label.len() as u8])?;
+            self.bytes(label.as_bytes())?;
+        }
+        self.bytes(&[0])
+    }
+
+    pub fn question(&mut self, question: &Question) -> Result<(), Error> {
+        self.name(&[&question.name])?;
+        self.u16(question.qtype)?;
+        self.u16(question.qclass)
+    }
+
+    /// Write a resource record, `rdata` writes the data.
+    pub fn record(
+        &mut self,
+        name: &[&str],
+        rtype: u16,
+        class: u16,
+        ttl: u32,
+        rdata: impl FnOnce(&mut Self) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.name(name)?;
+        self.u16(rtype)?;
+        self.u16(class)?;
+        self.u32(ttl)?;
+
+        let len_pos = self.pos;
+        self.u16(0)?;
+        rdata(self)?;
+        let len = (self.pos - len_pos - 2) as u16;
+        self.buf[len_pos..len_pos + 2].copy_from_slice(&len.to_be_bytes());
+        Ok(())
+    }
+}
+
+/// Compare dotted names, ignoring ASCII case like DNS does.
+pub fn name_eq(name: &str, parts: &[&str]) -> bool {
+    let mut rest = name;
+    for (i, part) in parts.iter().enumerate() {
+        let Some(tail) = strip_prefix_ignore_case(rest, part) else {
+            return false;
+        };
+        rest = match (i + 1 == parts.len(), tail.strip_prefix('.')) {
+            (true, _) => tail,
+            (false, Some(tail)) => tail,
+            (false, None) => return false,
+        };
+    }
+    rest.is_empty()
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = s.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix)
+        .then(|| &s[prefix.len()..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_question() {
+        // Two questions, the second one compressed against the first.
+        let query = [
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+            0x05, b'_', b'h', b't', b't', b'p', 0x04, b'_', b't', b'c', b'p', //
+            0x05, b'l', b'o', b'c', b'a', b'l', 0x00, 0x00, 0x0c, 0x80, 0x01, //
+            0x03, b'e', b's', b'p', 0xc0, 0x0c, 0x00, 0x21, 0x00, 0x01,
+        ];
+        let mut reader = Reader::new(&query);
+        let header = reader.header().unwrap();
+        assert_eq!(header.questions, 2);
+        assert!(!header.is_response());
+
+        let question = reader.question().unwrap();
+        assert_eq!(question.name, "_http._tcp.local");
+        assert_eq!(question.qtype, TYPE_PTR);
+        assert_eq!(question.qclass, UNICAST_RESPONSE | CLASS_IN);
+
+        let question = reader.question().unwrap();
+        assert_eq!(question.name, "esp._http._tcp.local");
+        assert_eq!(question.qtype, TYPE_SRV);
+
+        // A pointer to itself.
+        let mut looped = query;
+        looped[12..14].copy_from_slice(&[0xc0, 0x0c]);
+        let mut reader = Reader::new(&looped);
+        reader.header().unwrap();
+        assert_eq!(reader.question(), Err(Error::MalformedPacket));
+    }
+
+    #[test]
+    fn test_name() {
+        let mut buf = [0; 32];
+        let mut writer = Writer::new(&mut buf);
+        writer.name(&["esp", "_http._tcp", "local"]).unwrap();
+        let len = writer.len();
+        assert_eq!(&buf[..len], b"\x03esp\x05_http\x04_tcp\x05local\x00");
+
+        assert!(name_eq("ESP.local", &["esp", "local"]));
+        assert!(name_eq(
+            "esp._http._tcp.local",
+            &["esp", "_http._tcp", "local"]
+        ));
+        assert!(!name_eq("esp.local", &["esp"]));
+        assert!(!name_eq("espx.local", &["esp", "local"]));
+    }
+}
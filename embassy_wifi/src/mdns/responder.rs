@@ -0,0 +1,455 @@
+//! Answering mDNS (RFC 6762) and DNS-SD (RFC 6763) queries for one host.
+//!
+//! The responder doesn't probe for conflicts or suppress known answers,
+//! which is fine for a handful of devices with unique hostnames.
+
+use core::net::{Ipv4Addr, Ipv6Addr};
+
+use super::packet::{
+    CACHE_FLUSH, CLASS_IN, Error, FLAGS_RESPONSE, Header, Question, Reader, TYPE_A, TYPE_AAAA,
+    TYPE_ANY, TYPE_PTR, TYPE_SRV, TYPE_TXT, Writer, name_eq,
+};
+
+/// TTL of records containing the hostname (RFC 6762, 10)
+pub const HOST_TTL: u32 = 120;
+/// TTL of the other records
+pub const TTL: u32 = 4500;
+/// Legacy unicast responses must not be cached for longer (RFC 6762, 6.7)
+const LEGACY_TTL: u32 = 10;
+
+const SERVICES: &str = "_services._dns-sd._udp";
+const LOCAL: &str = "local";
+
+const MAX_RECORDS: usize = 32;
+const MAX_QUESTIONS: usize = 4;
+
+/// A DNS-SD service, e.g. `_http._tcp` on port 80.
+#[derive(Debug, Copy, Clone)]
+pub struct Service<'a> {
+    pub service: &'a str,
+    pub port: u16,
+    pub txt: &'a [(&'a str, &'a str)],
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Record {
+    /// `<hostname>.local`
+    A,
+    Aaaa,
+    /// `_services._dns-sd._udp.local` pointing to a service type
+    Services(usize),
+    /// `<service>.local` pointing to our instance
+    Ptr(usize),
+    Srv(usize),
+    Txt(usize),
+}
+
+type Records = heapless::Vec<Record, MAX_RECORDS>;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Responder<'a> {
+    /// Without `.local`, also used as the service instance name
+    pub hostname: &'a str,
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
+    pub services: &'a [Service<'a>],
+    /// TXT entries added to every service
+    pub txt: &'a [(&'a str, &'a str)],
+}
+
+impl Responder<'_> {
+    /// Answer `query`, returns the length of the response written to `out`
+    /// or `None` if none of the questions are about us.
+    ///
+    /// `legacy` is for queries that didn't come from port 5353, they get a
+    /// conventional unicast DNS response (RFC 6762, 6.7).
+    pub fn respond(
+        &self,
+        query: &[u8],
+        legacy: bool,
+        out: &mut [u8],
+    ) -> Result<Option<usize>, Error> {
+        let mut reader = Reader::new(query);
+        let header = reader.header()?;
+        // Only standard queries
+        if header.is_response() || header.flags & 0x7800 != 0 {
+            return Ok(None);
+        }
+
+        let mut questions = heapless::Vec::<Question, MAX_QUESTIONS>::new();
+        let mut answers = Records::new();
+        for _ in 0..header.questions {
+            let question = reader.question()?;
+            self.answer(&question, &mut answers);
+            if legacy {
+                questions.push(question).ok();
+            }
+        }
+        if answers.is_empty() {
+            return Ok(None);
+        }
+
+        let id = if legacy { header.id } else { 0 };
+        let additionals = self.additionals(&answers);
+        self.write(out, id, &questions, &answers, &additionals, legacy)
+            .map(Some)
+    }
+
+    /// An unsolicited response with all our records, sent when we get an
+    /// address (RFC 6762, 8.3).
+    pub fn announce(&self, out: &mut [u8]) -> Result<usize, Error> {
+        let mut answers = Records::new();
+        if self.ipv4.is_some() {
+            answers.push(Record::A).ok();
+        }
+        if self.ipv6.is_some() {
+            answers.push(Record::Aaaa).ok();
+        }
+        for i in 0..self.services.len() {
+            for record in [
+                Record::Services(i),
+                Record::Ptr(i),
+                Record::Srv(i),
+                Record::Txt(i),
+            ] {
+                answers.push(record).map_err(|_| Error::BufferTooSmall)?;
+            }
+        }
+        self.write(out, 0, &[], &answers, &[], false)
+    }
+
+    fn answer(&self, question: &Question, answers: &mut Records) {
+        let name = question.name.as_str();
+        let wants = |rtype| question.qtype == TYPE_ANY || question.qtype == rtype;
+        let mut add = |record| {
+            if !answers.contains(&record) {
+                answers.push(record).ok();
+            }
+        };
+
+        if name_eq(name, &[self.hostname, LOCAL]) {
+            if self.ipv4.is_some() && wants(TYPE_A) {
+                add(Record::A);
+            }
+            if self.ipv6.is_some() && wants(TYPE_AAAA) {
+                add(Record::Aaaa);
+            }
+        }
+        if wants(TYPE_PTR) && name_eq(name, &[SERVICES, LOCAL]) {
+            (0..self.services.len()).for_each(|i| add(Record::Services(i)));
+        }
+        for (i, service) in self.services.iter().enumerate() {
+            if wants(TYPE_PTR) && name_eq(name, &[service.service, LOCAL]) {
+                add(Record::Ptr(i));
+            }
+            if name_eq(name, &[self.hostname, service.service, LOCAL]) {
+                if wants(TYPE_SRV) {
+                    add(Record::Srv(i));
+                }
+                if wants(TYPE_TXT) {
+                    add(Record::Txt(i));
+                }
+            }
+        }
+    }
+
+    /// Records the querier will need next (RFC 6763, 12).
+    fn additionals(&self, answers: &Records) -> Records {
+        let mut additionals = Records::new();
+        for answer in answers {
+            let needed: &[Record] = match *answer {
+                Record::Ptr(i) => &[Record::Srv(i), Record::Txt(i), Record::A, Record::Aaaa],
+                Record::Srv(_) => &[Record::A, Record::Aaaa],
+                _ => &[],
+            };
+            for record in needed {
+                if self.has(*record)
+                    && !answers.contains(record)
+                    && !additionals.contains(record)
+                {
+                    additionals.push(*record).ok();
+                }
+            }
+        }
+        additionals
+    }
+
+    /// Whether we have the address for `record`.
+    fn has(&self, record: Record) -> bool {
+        match record {
+            Record::A => self.ipv4.is_some(),
+            Record::Aaaa => self.ipv6.is_some(),
+            _ => true,
+        }
+    }
+
+    fn write(
+        &self,
+        out: &mut [u8],
+        id: u16,
+        questions: &[Question],
+        answers: &[Record],
+        additionals: &[Record],
+        legacy: bool,
+    ) -> Result<usize, Error> {
+        let mut writer = Writer::new(out);
+        writer.header(&Header {
+            id,
+            flags: FLAGS_RESPONSE,
+            questions: questions.len() as u16,
+            answers: answers.len() as u16,
+            authorities: 0,
+            additionals: additionals.len() as u16,
+        })?;
+        for question in questions {
+            writer.question(question)?;
+        }
+        for record in answers.iter().chain(additionals) {
+            self.write_record(&mut writer, *record, legacy)?;
+        }
+        Ok(writer.len())
+    }
+
+    fn write_record(&self, writer: &mut Writer, record: Record, legacy: bool) -> Result<(), Error> {
+        let ttl = |ttl| if legacy { LEGACY_TTL } else { ttl };
+        // Legacy resolvers don't know about the cache flush bit.
+        let unique = if legacy {
+            CLASS_IN
+        } else {
+            CLASS_IN | CACHE_FLUSH
+        };
+        let host = self.hostname;
+
+        match record {
+            Record::A => {
+                let ip = self.ipv4.ok_or(Error::MalformedPacket)?;
+                writer.record(&[host, LOCAL], TYPE_A, unique, ttl(HOST_TTL), |w| {
+                    w.bytes(&ip.octets())
+                })
+            }
+            Record::Aaaa => {
+                let ip = self.ipv6.ok_or(Error::MalformedPacket)?;
+                writer.record(&[host, LOCAL], TYPE_AAAA, unique, ttl(HOST_TTL), |w| {
+                    w.bytes(&ip.octets())
+                })
+            }
+            Record::Services(i) => {
+                let service = self.services[i].service;
+                writer.record(&[SERVICES, LOCAL], TYPE_PTR, CLASS_IN, ttl(TTL), |w| {
+                    w.name(&[service, LOCAL])
+                })
+            }
+            Record::Ptr(i) => {
+                let service = self.services[i].service;
+                writer.record(&[service, LOCAL], TYPE_PTR, CLASS_IN, ttl(TTL), |w| {
+                    w.name(&[host, service, LOCAL])
+                })
+            }
+            Record::Srv(i) => {
+                let service = &self.services[i];
+                let name = [host, service.service, LOCAL];
+                writer.record(&name, TYPE_SRV, unique, ttl(HOST_TTL), |w| {
+                    // priority, weight
+                    w.u16(0)?;
+                    w.u16(0)?;
+                    w.u16(service.port)?;
+                    w.name(&[host, LOCAL])
+                })
+            }
+            Record::Txt(i) => {
+                let service = &self.services[i];
+                let name = [host, service.service, LOCAL];
+                writer.record(&name, TYPE_TXT, unique, ttl(TTL), |w| {
+                    let mut empty = true;
+                    for (key, value) in service.txt.iter().chain(self.txt) {
+                        let len = key.len() + 1 + value.len();
+                        let len = u8::try_from(len).map_err(|_| Error::MalformedPacket)?;
+                        w.bytes(&[len])?;
+                        w.bytes(key.as_bytes())?;
+                        w.bytes(b"=")?;
+                        w.bytes(value.as_bytes())?;
+                        empty = false;
+                    }
+                    // At least one string, even if empty (RFC 6763, 6.1)
+                    if empty { w.bytes(&[0]) } else { Ok(()) }
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::packet::{CLASS_IN, TYPE_ANY, UNICAST_RESPONSE};
+    use super::*;
+
+    const SERVICES: &[Service] = &[
+        Service {
+            service: "_http._tcp",
+            port: 80,
+            txt: &[("path", "/status")],
+        },
+        Service {
+            service: "_esp-telemetry._tcp",
+            port: 80,
+            txt: &[],
+        },
+    ];
+
+    fn responder() -> Responder<'static> {
+        Responder {
+            hostname: "esp-a1b2c3",
+            ipv4: Some(Ipv4Addr::new(192, 168, 1, 23)),
+            ipv6: None,
+            services: SERVICES,
+            txt: &[("chip", "esp32c6")],
+        }
+    }
+
+    fn make_query(id: u16, name: &str, qtype: u16) -> ([u8; 128], usize) {
+        let mut buf = [0; 128];
+        let mut writer = Writer::new(&mut buf);
+        writer
+            .header(&Header {
+                id,
+                flags: 0,
+                questions: 1,
+                answers: 0,
+                authorities: 0,
+                additionals: 0,
+            })
+            .unwrap();
+        writer
+            .question(&Question {
+                name: name.try_into().unwrap(),
+                qtype,
+                qclass: UNICAST_RESPONSE | CLASS_IN,
+            })
+            .unwrap();
+        let len = writer.len();
+        (buf, len)
+    }
+
+    /// Header and the name, type and class of the first record.
+    fn parse(response: &[u8]) -> (Header, Question) {
+        let mut reader = Reader::new(response);
+        let header = reader.header().unwrap();
+        for _ in 0..header.questions {
+            reader.question().unwrap();
+        }
+        (header, reader.question().unwrap())
+    }
+
+    #[test]
+    fn test_host() {
+        let responder = responder();
+        let mut out = [0; 1024];
+
+        let (query, len) = make_query(7, "ESP-A1B2C3.local", TYPE_A);
+        let n = responder
+            .respond(&query[..len], false, &mut out)
+            .unwrap()
+            .unwrap();
+        let (header, record) = parse(&out[..n]);
+        assert_eq!((header.id, header.answers, header.additionals), (0, 1, 0));
+        assert_eq!(record.name, "esp-a1b2c3.local");
+        assert_eq!(record.qclass, CACHE_FLUSH | CLASS_IN);
+        assert_eq!(&out[n - 4..n], &[192, 168, 1, 23]);
+
+        // Legacy queries get their ID and question back.
+        let n = responder
+            .respond(&query[..len], true, &mut out)
+            .unwrap()
+            .unwrap();
+        let (header, record) = parse(&out[..n]);
+        assert_eq!((header.id, header.questions, header.answers), (7, 1, 1));
+        assert_eq!(record.qclass, CLASS_IN);
+
+        let (query, len) = make_query(0, "other.local", TYPE_A);
+        assert_eq!(responder.respond(&query[..len], false, &mut out), Ok(None));
+        let (query, len) = make_query(0, "esp-a1b2c3.local", TYPE_AAAA);
+        assert_eq!(responder.respond(&query[..len], false, &mut out), Ok(None));
+    }
+
+    #[test]
+    fn test_host_ipv6() {
+        let responder = Responder {
+            ipv4: None,
+            ipv6: Some("fd00::23".parse().unwrap()),
+            ..responder()
+        };
+        let mut out = [0; 1024];
+
+        let (query, len) = make_query(0, "esp-a1b2c3.local", TYPE_AAAA);
+        let n = responder
+            .respond(&query[..len], false, &mut out)
+            .unwrap()
+            .unwrap();
+        let (header, record) = parse(&out[..n]);
+        assert_eq!((header.answers, header.additionals), (1, 0));
+        assert_eq!(record.qtype, TYPE_AAAA);
+        assert_eq!(&out[n - 16..n], &Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 0x23).octets());
+
+        let (query, len) = make_query(0, "esp-a1b2c3.local", TYPE_A);
+        assert_eq!(responder.respond(&query[..len], false, &mut out), Ok(None));
+
+        // Both addresses for ANY, and with services
+        let responder = Responder {
+            ipv4: Some(Ipv4Addr::new(192, 168, 1, 23)),
+            ..responder
+        };
+        let (query, len) = make_query(0, "esp-a1b2c3.local", TYPE_ANY);
+        let n = responder
+            .respond(&query[..len], false, &mut out)
+            .unwrap()
+            .unwrap();
+        assert_eq!(parse(&out[..n]).0.answers, 2);
+
+        let (query, len) = make_query(0, "_http._tcp.local", TYPE_PTR);
+        let n = responder
+            .respond(&query[..len], false, &mut out)
+            .unwrap()
+            .unwrap();
+        assert_eq!(parse(&out[..n]).0.additionals, 4);
+
+        let n = responder.announce(&mut out).unwrap();
+        assert_eq!(parse(&out[..n]).0.answers, 10);
+    }
+
+    #[test]
+    fn test_service() {
+        let responder = responder();
+        let mut out = [0; 1024];
+
+        let (query, len) = make_query(0, "_http._tcp.local", TYPE_PTR);
+        let n = responder
+            .respond(&query[..len], false, &mut out)
+            .unwrap()
+            .unwrap();
+        let (header, record) = parse(&out[..n]);
+        // SRV, TXT and A as additional records
+        assert_eq!((header.answers, header.additionals), (1, 3));
+        assert_eq!(record.name, "_http._tcp.local");
+        assert_eq!(record.qtype, TYPE_PTR);
+
+        let (query, len) = make_query(0, "_services._dns-sd._udp.local", TYPE_PTR);
+        let n = responder
+            .respond(&query[..len], false, &mut out)
+            .unwrap()
+            .unwrap();
+        assert_eq!(parse(&out[..n]).0.answers, 2);
+
+        let (query, len) = make_query(0, "esp-a1b2c3._esp-telemetry._tcp.local", TYPE_ANY);
+        let n = responder
+            .respond(&query[..len], false, &mut out)
+            .unwrap()
+            .unwrap();
+        let (header, record) = parse(&out[..n]);
+        assert_eq!((header.answers, header.additionals), (2, 1));
+        assert_eq!(record.qtype, TYPE_SRV);
+        assert!(out[..n].windows(13).any(|w| w == b"\x0cchip=esp32c6"));
+
+        let n = responder.announce(&mut out).unwrap();
+        assert_eq!(parse(&out[..n]).0.answers, 9);
+    }
+}
@@ -0,0 +1,79 @@
+//! NOR flash in memory, for the tests of the modules that use flash
+//!
+//! Erasing sets bits and writing can only clear them, with the alignment of
+//! the ESP32 flash, so the tests catch writes a real chip would reject.
+
+use alloc::{vec, vec::Vec};
+
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+use crate::ota::partition::{TABLE_OFFSET, tests::table};
+
+pub struct MemFlash(pub Vec<u8>);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct MemError(NorFlashErrorKind);
+
+impl NorFlashError for MemError {
+    fn kind(&self) -> NorFlashErrorKind {
+        self.0
+    }
+}
+
+impl ErrorType for MemFlash {
+    type Error = MemError;
+}
+
+impl ReadNorFlash for MemFlash {
+    const READ_SIZE: usize = 4;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), MemError> {
+        let offset = offset as usize;
+        if !offset.is_multiple_of(Self::READ_SIZE) || !bytes.len().is_multiple_of(Self::READ_SIZE) {
+            return Err(MemError(NorFlashErrorKind::NotAligned));
+        }
+        bytes.copy_from_slice(&self.0[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl NorFlash for MemFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = 4096;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), MemError> {
+        if !(from as usize).is_multiple_of(Self::ERASE_SIZE)
+            || !(to as usize).is_multiple_of(Self::ERASE_SIZE)
+        {
+            return Err(MemError(NorFlashErrorKind::NotAligned));
+        }
+        self.0[from as usize..to as usize].fill(0xff);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), MemError> {
+        let offset = offset as usize;
+        if !offset.is_multiple_of(Self::WRITE_SIZE) || !bytes.len().is_multiple_of(Self::WRITE_SIZE)
+        {
+            return Err(MemError(NorFlashErrorKind::NotAligned));
+        }
+        for (cell, byte) in self.0[offset..offset + bytes.len()].iter_mut().zip(bytes) {
+            *cell &= byte;
+        }
+        Ok(())
+    }
+}
+
+/// Flash with the partition table of `partitions.csv`.
+pub fn flash() -> MemFlash {
+    let mut flash = MemFlash(vec![0xff; 0x3a0000]);
+    let table = table();
+    flash.0[TABLE_OFFSET as usize..][..table.len()].copy_from_slice(&table);
+    flash
+}
@@ -0,0 +1,234 @@
+//! Counters, gauges and histograms, updated from any task
+//!
+//! Metrics are statics with their name, help text and labels. Labels are
+//! preformatted, e.g. `task="mqtt"`, metrics with the same name and
+//! different labels form one family.
+
+use core::cell::{Cell, RefCell};
+
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+
+/// Buckets a histogram can have besides `+Inf`.
+pub const MAX_BUCKETS: usize = 12;
+
+/// Only ever goes up, e.g. reconnects.
+pub struct Counter {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub labels: &'static str,
+    value: Mutex<CriticalSectionRawMutex, Cell<u64>>,
+}
+
+impl Counter {
+    pub const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            labels: "",
+            value: Mutex::new(Cell::new(0)),
+        }
+    }
+
+    pub const fn with_labels(self, labels: &'static str) -> Self {
+        Self { labels, ..self }
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.value
+            .lock(|value| value.set(value.get().wrapping_add(n)));
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.lock(Cell::get)
+    }
+}
+
+/// A value that goes up and down, set by its subsystem or read when
+/// exported.
+pub struct Gauge {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub labels: &'static str,
+    value: Mutex<CriticalSectionRawMutex, Cell<Option<i64>>>,
+    read: Option<fn() -> Option<i64>>,
+}
+
+impl Gauge {
+    /// Without a sample until it is set.
+    pub const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            labels: "",
+            value: Mutex::new(Cell::new(None)),
+            read: None,
+        }
+    }
+
+    /// Calls `read` for the value, `None` leaves out the sample.
+    pub const fn computed(
+        name: &'static str,
+        help: &'static str,
+        read: fn() -> Option<i64>,
+    ) -> Self {
+        Self {
+            read: Some(read),
+            ..Self::new(name, help)
+        }
+    }
+
+    pub const fn with_labels(self, labels: &'static str) -> Self {
+        Self { labels, ..self }
+    }
+
+    pub fn set(&self, value: i64) {
+        self.value.lock(|cell| cell.set(Some(value)));
+    }
+
+    /// Leave out the sample, e.g. while the value is unknown.
+    pub fn clear(&self) {
+        self.value.lock(|cell| cell.set(None));
+    }
+
+    pub fn get(&self) -> Option<i64> {
+        match self.read {
+            Some(read) => read(),
+            None => self.value.lock(Cell::get),
+        }
+    }
+}
+
+/// Observations counted into fixed buckets, e.g. round-trip times.
+pub struct Histogram {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub labels: &'static str,
+    /// Upper bounds of the buckets, increasing
+    pub bounds: &'static [f64],
+    state: Mutex<CriticalSectionRawMutex, RefCell<Snapshot>>,
+}
+
+/// Counts per bucket, not cumulative, the last one for `+Inf`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub buckets: [u64; MAX_BUCKETS + 1],
+    pub count: u64,
+    pub sum: f64,
+}
+
+impl Histogram {
+    pub const fn new(name: &'static str, help: &'static str, bounds: &'static [f64]) -> Self {
+        assert!(bounds.len() <= MAX_BUCKETS);
+        Self {
+            name,
+            help,
+            labels: "",
+            bounds,
+            state: Mutex::new(RefCell::new(Snapshot {
+                buckets: [0; MAX_BUCKETS + 1],
+                count: 0,
+                sum: 0.0,
+            })),
+        }
+    }
+
+    pub const fn with_labels(self, labels: &'static str) -> Self {
+        Self { labels, ..self }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            state.buckets[bucket] += 1;
+            state.count += 1;
+            state.sum += value;
+        });
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        self.state.lock(|state| state.borrow().clone())
+    }
+}
+
+/// An entry of a registry table.
+#[derive(Copy, Clone)]
+pub enum Metric {
+    Counter(&'static Counter),
+    Gauge(&'static Gauge),
+    Histogram(&'static Histogram),
+}
+
+impl Metric {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::Counter(counter) => counter.name,
+            Metric::Gauge(gauge) => gauge.name,
+            Metric::Histogram(histogram) => histogram.name,
+        }
+    }
+
+    pub fn help(&self) -> &'static str {
+        match self {
+            Metric::Counter(counter) => counter.help,
+            Metric::Gauge(gauge) => gauge.help,
+            Metric::Histogram(histogram) => histogram.help,
+        }
+    }
+
+    pub fn labels(&self) -> &'static str {
+        match self {
+            Metric::Counter(counter) => counter.labels,
+            Metric::Gauge(gauge) => gauge.labels,
+            Metric::Histogram(histogram) => histogram.labels,
+        }
+    }
+
+    /// The `TYPE` in the text format.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Metric::Counter(_) => "counter",
+            Metric::Gauge(_) => "gauge",
+            Metric::Histogram(_) => "histogram",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metric() {
+        let counter = Counter::new("c_total", "").with_labels("task=\"a\"");
+        counter.inc();
+        counter.add(2);
+        assert_eq!(counter.get(), 3);
+        assert_eq!(counter.labels, "task=\"a\"");
+
+        let gauge = Gauge::new("g", "");
+        assert_eq!(gauge.get(), None);
+        gauge.set(-52);
+        assert_eq!(gauge.get(), Some(-52));
+        gauge.clear();
+        assert_eq!(gauge.get(), None);
+        assert_eq!(Gauge::computed("g", "", || Some(7)).get(), Some(7));
+
+        let histogram = Histogram::new("h", "", &[0.01, 0.1, 1.0]);
+        for value in [0.005, 0.01, 0.05, 2.0] {
+            histogram.observe(value);
+        }
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.buckets[..4], [2, 1, 0, 1]);
+        assert_eq!(snapshot.count, 4);
+        assert!((snapshot.sum - 2.065).abs() < 1e-9);
+    }
+}
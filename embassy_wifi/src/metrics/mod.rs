@@ -0,0 +1,43 @@
+//! Metrics in the Prometheus text format
+//!
+//! Subsystems keep their metrics in statics and export them in a `METRICS`
+//! table, which is listed in [`REGISTRY`]. The HTTP server serves the
+//! registry at `/metrics`.
+//!
+//! `metric` and `registry` only depend on `embassy-sync` and are tested on
+//! the host.
+
+use embassy_time::Instant;
+
+use crate::{health, mqtt, websocket, wifi};
+
+pub mod metric;
+pub mod registry;
+
+pub use metric::{Counter, Gauge, Histogram, Metric};
+pub use registry::{CONTENT_TYPE, Registry};
+
+pub static REGISTRY: Registry = Registry::new(&[
+    METRICS,
+    crate::METRICS,
+    wifi::METRICS,
+    mqtt::METRICS,
+    websocket::METRICS,
+    health::METRICS,
+]);
+
+pub static METRICS: &[Metric] = &[
+    Metric::Gauge(&UPTIME),
+    Metric::Gauge(&HEAP_USED),
+    Metric::Gauge(&HEAP_FREE),
+];
+
+static UPTIME: Gauge = Gauge::computed("uptime_seconds", "Seconds since boot", || {
+    Some(Instant::now().as_secs() as i64)
+});
+static HEAP_USED: Gauge = Gauge::computed("heap_used_bytes", "Allocated heap", || {
+    Some(esp_alloc::HEAP.used() as i64)
+});
+static HEAP_FREE: Gauge = Gauge::computed("heap_free_bytes", "Free heap", || {
+    Some(esp_alloc::HEAP.free() as i64)
+});
@@ -0,0 +1,143 @@
+//! The tables of metrics and their Prometheus text exposition format
+//! (version 0.0.4)
+
+use core::fmt::{self, Display, Write};
+
+use super::metric::Metric;
+
+/// Content type of [`Registry::encode`] output.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Tables of metrics, one per subsystem.
+pub struct Registry {
+    tables: &'static [&'static [Metric]],
+}
+
+impl Registry {
+    pub const fn new(tables: &'static [&'static [Metric]]) -> Self {
+        Self { tables }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Metric> + '_ {
+        self.tables.iter().flat_map(|table| table.iter().copied())
+    }
+
+    /// Write every metric family, where its first metric is, with the help
+    /// and type of that metric.
+    pub fn encode(&self, out: &mut impl Write) -> fmt::Result {
+        for (i, first) in self.iter().enumerate() {
+            let name = first.name();
+            if self.iter().take(i).any(|metric| metric.name() == name) {
+                continue;
+            }
+            write!(out, "# HELP {} ", name)?;
+            for c in first.help().chars() {
+                match c {
+                    '\\' => out.write_str("\\\\")?,
+                    '\n' => out.write_str("\\n")?,
+                    c => out.write_char(c)?,
+                }
+            }
+            writeln!(out, "\n# TYPE {} {}", name, first.kind())?;
+            for metric in self.iter().skip(i).filter(|metric| metric.name() == name) {
+                write_samples(out, metric)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_samples(out: &mut impl Write, metric: Metric) -> fmt::Result {
+    let (name, labels) = (metric.name(), metric.labels());
+    match metric {
+        Metric::Counter(counter) => sample(out, name, "", labels, None, counter.get()),
+        Metric::Gauge(gauge) => match gauge.get() {
+            Some(value) => sample(out, name, "", labels, None, value),
+            None => Ok(()),
+        },
+        Metric::Histogram(histogram) => {
+            let snapshot = histogram.snapshot();
+            let mut cumulative = 0;
+            for (bound, count) in histogram.bounds.iter().zip(snapshot.buckets) {
+                cumulative += count;
+                sample(out, name, "_bucket", labels, Some(bound), cumulative)?;
+            }
+            sample(out, name, "_bucket", labels, Some(&"+Inf"), snapshot.count)?;
+            sample(out, name, "_sum", labels, None, snapshot.sum)?;
+            sample(out, name, "_count", labels, None, snapshot.count)
+        }
+    }
+}
+
+fn sample(
+    out: &mut impl Write,
+    name: &str,
+    suffix: &str,
+    labels: &str,
+    le: Option<&dyn Display>,
+    value: impl Display,
+) -> fmt::Result {
+    write!(out, "{}{}", name, suffix)?;
+    match (labels.is_empty(), le) {
+        (true, None) => {}
+        (false, None) => write!(out, "{{{}}}", labels)?,
+        (true, Some(le)) => write!(out, "{{le=\"{}\"}}", le)?,
+        (false, Some(le)) => write!(out, "{{{},le=\"{}\"}}", labels, le)?,
+    }
+    writeln!(out, " {}", value)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+
+    use super::*;
+    use crate::metrics::metric::{Counter, Gauge, Histogram};
+
+    static A_ERRORS: Counter =
+        Counter::new("errors_total", "Errors\\failures").with_labels("task=\"a\"");
+    static B_ERRORS: Counter = Counter::new("errors_total", "").with_labels("task=\"b\"");
+    static RSSI: Gauge = Gauge::new("rssi_dbm", "Signal strength\nof the AP");
+    static HEAP: Gauge = Gauge::computed("heap_free_bytes", "Free heap", || Some(1024));
+    static RTT: Histogram = Histogram::new("rtt_seconds", "Round-trip time", &[0.01, 0.1]);
+
+    static REGISTRY: Registry = Registry::new(&[
+        &[Metric::Counter(&A_ERRORS), Metric::Gauge(&RSSI)],
+        &[Metric::Gauge(&HEAP), Metric::Counter(&B_ERRORS)],
+        &[Metric::Histogram(&RTT)],
+    ]);
+
+    #[test]
+    fn test_encode() {
+        A_ERRORS.add(3);
+        RTT.observe(0.005);
+        RTT.observe(0.5);
+
+        let mut out = String::new();
+        REGISTRY.encode(&mut out).unwrap();
+        assert_eq!(
+            out,
+            "# HELP errors_total Errors\\\\failures\n\
+             # TYPE errors_total counter\n\
+             errors_total{task=\"a\"} 3\n\
+             errors_total{task=\"b\"} 0\n\
+             # HELP rssi_dbm Signal strength\\nof the AP\n\
+             # TYPE rssi_dbm gauge\n\
+             # HELP heap_free_bytes Free heap\n\
+             # TYPE heap_free_bytes gauge\n\
+             heap_free_bytes 1024\n\
+             # HELP rtt_seconds Round-trip time\n\
+             # TYPE rtt_seconds histogram\n\
+             rtt_seconds_bucket{le=\"0.01\"} 1\n\
+             rtt_seconds_bucket{le=\"0.1\"} 1\n\
+             rtt_seconds_bucket{le=\"+Inf\"} 2\n\
+             rtt_seconds_sum 0.505\n\
+             rtt_seconds_count 2\n"
+        );
+
+        RSSI.set(-61);
+        out.clear();
+        REGISTRY.encode(&mut out).unwrap();
+        assert!(out.contains("# TYPE rssi_dbm gauge\nrssi_dbm -61\n"));
+    }
+}
@@ -0,0 +1,337 @@
+//! Modbus TCP frames: the MBAP header and request PDUs (Modbus Application
+//! Protocol V1.1b3, Modbus Messaging on TCP/IP V1.0b)
+
+/// Transaction ID, protocol ID, length and unit ID.
+pub const HEADER_LEN: usize = 7;
+/// Function code and data, the longest is a write of 123 registers.
+pub const MAX_PDU_LEN: usize = 253;
+
+/// Set in the function code of an exception response.
+pub const EXCEPTION: u8 = 0x80;
+
+const MAX_READ_BITS: u16 = 2000;
+const MAX_READ_REGISTERS: u16 = 125;
+const MAX_WRITE_BITS: u16 = 1968;
+const MAX_WRITE_REGISTERS: u16 = 123;
+
+/// Function codes
+pub mod function {
+    pub const READ_COILS: u8 = 0x01;
+    pub const READ_HOLDING_REGISTERS: u8 = 0x03;
+    pub const READ_INPUT_REGISTERS: u8 = 0x04;
+    pub const WRITE_SINGLE_COIL: u8 = 0x05;
+    pub const WRITE_SINGLE_REGISTER: u8 = 0x06;
+    pub const WRITE_MULTIPLE_COILS: u8 = 0x0f;
+    pub const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+}
+
+/// Exception codes sent back instead of a response.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Exception {
+    IllegalFunction = 0x01,
+    IllegalDataAddress = 0x02,
+    IllegalDataValue = 0x03,
+    ServerDeviceFailure = 0x04,
+}
+
+/// Errors of the MBAP header, the connection is closed on them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Not 0, which is Modbus
+    ProtocolId(u16),
+    /// No function code or more than [`MAX_PDU_LEN`]
+    Length(u16),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub transaction_id: u16,
+    pub protocol_id: u16,
+    /// Of the unit ID and the PDU
+    pub length: u16,
+    pub unit_id: u8,
+}
+
+impl Header {
+    pub fn parse(buf: &[u8; HEADER_LEN]) -> Result<Self, Error> {
+        let header = Self {
+            transaction_id: u16::from_be_bytes([buf[0], buf[1]]),
+            protocol_id: u16::from_be_bytes([buf[2], buf[3]]),
+            length: u16::from_be_bytes([buf[4], buf[5]]),
+            unit_id: buf[6],
+        };
+        if header.protocol_id != 0 {
+            return Err(Error::ProtocolId(header.protocol_id));
+        }
+        if !(2..=MAX_PDU_LEN as u16 + 1).contains(&header.length) {
+            return Err(Error::Length(header.length));
+        }
+        Ok(header)
+    }
+
+    /// Length of the PDU that follows.
+    pub fn pdu_len(&self) -> usize {
+        self.length as usize - 1
+    }
+
+    /// The header of the response with a PDU of `pdu_len` bytes.
+    pub fn encode_response(&self, pdu_len: usize) -> [u8; HEADER_LEN] {
+        let [t0, t1] = self.transaction_id.to_be_bytes();
+        let [l0, l1] = (pdu_len as u16 + 1).to_be_bytes();
+        [t0, t1, 0, 0, l0, l1, self.unit_id]
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Request<'a> {
+    ReadCoils {
+        address: u16,
+        quantity: u16,
+    },
+    ReadHoldingRegisters {
+        address: u16,
+        quantity: u16,
+    },
+    ReadInputRegisters {
+        address: u16,
+        quantity: u16,
+    },
+    WriteSingleCoil {
+        address: u16,
+        value: bool,
+    },
+    WriteSingleRegister {
+        address: u16,
+        value: u16,
+    },
+    /// `values` has one bit per coil, the lowest bit first
+    WriteMultipleCoils {
+        address: u16,
+        quantity: u16,
+        values: &'a [u8],
+    },
+    /// `values` has two big-endian bytes per register
+    WriteMultipleRegisters {
+        address: u16,
+        quantity: u16,
+        values: &'a [u8],
+    },
+}
+
+impl<'a> Request<'a> {
+    /// Parse a request PDU, errors are the exception to answer with.
+    pub fn parse(pdu: &'a [u8]) -> Result<Self, Exception> {
+        let (&function, data) = pdu.split_first().ok_or(Exception::IllegalFunction)?;
+        let word = |i: usize| {
+            data.get(i..i + 2)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+                .ok_or(Exception::IllegalDataValue)
+        };
+        let fixed = |len: usize| {
+            if data.len() == len {
+                Ok(())
+            } else {
+                Err(Exception::IllegalDataValue)
+            }
+        };
+
+        let request = match function {
+            function::READ_COILS
+            | function::READ_HOLDING_REGISTERS
+            | function::READ_INPUT_REGISTERS => {
+                fixed(4)?;
+                let (address, quantity) = (word(0)?, word(2)?);
+                let max = if function == function::READ_COILS {
+                    MAX_READ_BITS
+                } else {
+                    MAX_READ_REGISTERS
+                };
+                check_quantity(quantity, max)?;
+                check_range(address, quantity)?;
+                match function {
+                    function::READ_COILS => Request::ReadCoils { address, quantity },
+                    function::READ_HOLDING_REGISTERS => {
+                        Request::ReadHoldingRegisters { address, quantity }
+                    }
+                    _ => Request::ReadInputRegisters { address, quantity },
+                }
+            }
+            function::WRITE_SINGLE_COIL => {
+                fixed(4)?;
+                let value = match word(2)? {
+                    0xff00 => true,
+                    0x0000 => false,
+                    _ => return Err(Exception::IllegalDataValue),
+                };
+                Request::WriteSingleCoil {
+                    address: word(0)?,
+                    value,
+                }
+            }
+            function::WRITE_SINGLE_REGISTER => {
+                fixed(4)?;
+                Request::WriteSingleRegister {
+                    address: word(0)?,
+                    value: word(2)?,
+                }
+            }
+            function::WRITE_MULTIPLE_COILS | function::WRITE_MULTIPLE_REGISTERS => {
+                let (address, quantity) = (word(0)?, word(2)?);
+                let count = *data.get(4).ok_or(Exception::IllegalDataValue)? as usize;
+                let (max, expected) = if function == function::WRITE_MULTIPLE_COILS {
+                    (MAX_WRITE_BITS, quantity.div_ceil(8) as usize)
+                } else {
+                    (MAX_WRITE_REGISTERS, quantity as usize * 2)
+                };
+                check_quantity(quantity, max)?;
+                if count != expected {
+                    return Err(Exception::IllegalDataValue);
+                }
+                fixed(5 + count)?;
+                check_range(address, quantity)?;
+                let values = &data[5..];
+                if function == function::WRITE_MULTIPLE_COILS {
+                    Request::WriteMultipleCoils {
+                        address,
+                        quantity,
+                        values,
+                    }
+                } else {
+                    Request::WriteMultipleRegisters {
+                        address,
+                        quantity,
+                        values,
+                    }
+                }
+            }
+            _ => return Err(Exception::IllegalFunction),
+        };
+        Ok(request)
+    }
+}
+
+fn check_quantity(quantity: u16, max: u16) -> Result<(), Exception> {
+    if (1..=max).contains(&quantity) {
+        Ok(())
+    } else {
+        Err(Exception::IllegalDataValue)
+    }
+}
+
+/// The addresses must not wrap around.
+fn check_range(address: u16, quantity: u16) -> Result<(), Exception> {
+    if address as u32 + quantity as u32 > 0x10000 {
+        Err(Exception::IllegalDataAddress)
+    } else {
+        Ok(())
+    }
+}
+
+/// Write the exception response to a request with `function`, returns its
+/// length.
+pub fn encode_exception(function: u8, exception: Exception, out: &mut [u8]) -> usize {
+    out[0] = function | EXCEPTION;
+    out[1] = exception as u8;
+    2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let header = Header::parse(&[0x00, 0x2a, 0x00, 0x00, 0x00, 0x06, 0x01]).unwrap();
+        assert_eq!(header.transaction_id, 42);
+        assert_eq!(header.unit_id, 1);
+        assert_eq!(header.pdu_len(), 5);
+        assert_eq!(
+            header.encode_response(3),
+            [0x00, 0x2a, 0x00, 0x00, 0x00, 0x04, 0x01]
+        );
+
+        assert_eq!(
+            Header::parse(&[0, 1, 0, 1, 0, 6, 1]),
+            Err(Error::ProtocolId(1))
+        );
+        assert_eq!(Header::parse(&[0, 1, 0, 0, 0, 1, 1]), Err(Error::Length(1)));
+        assert_eq!(
+            Header::parse(&[0, 1, 0, 0, 1, 0, 1]),
+            Err(Error::Length(256))
+        );
+    }
+
+    #[test]
+    fn test_request() {
+        assert_eq!(
+            Request::parse(&[0x01, 0x00, 0x13, 0x00, 0x13]),
+            Ok(Request::ReadCoils {
+                address: 0x13,
+                quantity: 0x13
+            })
+        );
+        assert_eq!(
+            Request::parse(&[0x04, 0x00, 0x08, 0x00, 0x01]),
+            Ok(Request::ReadInputRegisters {
+                address: 8,
+                quantity: 1
+            })
+        );
+        assert_eq!(
+            Request::parse(&[0x05, 0x00, 0xac, 0xff, 0x00]),
+            Ok(Request::WriteSingleCoil {
+                address: 0xac,
+                value: true
+            })
+        );
+        assert_eq!(
+            Request::parse(&[0x0f, 0x00, 0x13, 0x00, 0x0a, 0x02, 0xcd, 0x01]),
+            Ok(Request::WriteMultipleCoils {
+                address: 0x13,
+                quantity: 10,
+                values: &[0xcd, 0x01]
+            })
+        );
+        assert_eq!(
+            Request::parse(&[0x10, 0x00, 0x01, 0x00, 0x02, 0x04, 0x00, 0x0a, 0x01, 0x02]),
+            Ok(Request::WriteMultipleRegisters {
+                address: 1,
+                quantity: 2,
+                values: &[0x00, 0x0a, 0x01, 0x02]
+            })
+        );
+
+        // Unknown function, bad quantities and lengths, wrapping addresses.
+        assert_eq!(
+            Request::parse(&[0x2b, 0x0e]),
+            Err(Exception::IllegalFunction)
+        );
+        assert_eq!(
+            Request::parse(&[0x03, 0x00, 0x00, 0x00, 0x7e]),
+            Err(Exception::IllegalDataValue)
+        );
+        assert_eq!(
+            Request::parse(&[0x03, 0x00, 0x00, 0x00]),
+            Err(Exception::IllegalDataValue)
+        );
+        assert_eq!(
+            Request::parse(&[0x05, 0x00, 0x00, 0x12, 0x34]),
+            Err(Exception::IllegalDataValue)
+        );
+        assert_eq!(
+            Request::parse(&[0x10, 0x00, 0x01, 0x00, 0x02, 0x03, 0x00, 0x0a, 0x01]),
+            Err(Exception::IllegalDataValue)
+        );
+        assert_eq!(
+            Request::parse(&[0x03, 0xff, 0xff, 0x00, 0x02]),
+            Err(Exception::IllegalDataAddress)
+        );
+
+        let mut out = [0; 2];
+        assert_eq!(
+            encode_exception(0x03, Exception::IllegalDataAddress, &mut out),
+            2
+        );
+        assert_eq!(out, [0x83, 0x02]);
+    }
+}
@@ -0,0 +1,315 @@
+//! The register map: one table of coils, input registers and holding
+//! registers, each with the functions that read and write it
+
+use super::frame::{self, Exception, Request};
+
+/// The Modbus data table a register is in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Table {
+    /// Single bits, read and written
+    Coil,
+    /// Read-only 16-bit values
+    InputRegister,
+    /// 16-bit values, read and written
+    HoldingRegister,
+}
+
+pub type Read<C> = fn(&C) -> u16;
+/// Returns [`Exception::IllegalDataValue`] for values out of range.
+pub type Write<C> = fn(&mut C, u16) -> Result<(), Exception>;
+
+/// A register at `address` in `table`, `C` is the context passed to the
+/// read and write functions. Coils read and write 0 or 1.
+pub struct Register<C: 'static> {
+    pub table: Table,
+    pub address: u16,
+    pub read: Read<C>,
+    /// `None` for read-only
+    pub write: Option<Write<C>>,
+}
+
+impl<C> Register<C> {
+    pub const fn coil(address: u16, read: Read<C>, write: Write<C>) -> Self {
+        Self {
+            table: Table::Coil,
+            address,
+            read,
+            write: Some(write),
+        }
+    }
+
+    pub const fn input(address: u16, read: Read<C>) -> Self {
+        Self {
+            table: Table::InputRegister,
+            address,
+            read,
+            write: None,
+        }
+    }
+
+    pub const fn holding(address: u16, read: Read<C>, write: Write<C>) -> Self {
+        Self {
+            table: Table::HoldingRegister,
+            address,
+            read,
+            write: Some(write),
+        }
+    }
+}
+
+/// A static register map.
+pub struct Map<C: 'static> {
+    registers: &'static [Register<C>],
+}
+
+impl<C> Map<C> {
+    pub const fn new(registers: &'static [Register<C>]) -> Self {
+        Self { registers }
+    }
+
+    /// Execute the request `pdu` and write the response PDU, or the
+    /// exception response, to `out`. Returns its length.
+    ///
+    /// Every address of a request must be in the map, writes check all of
+    /// them before writing any. `out` must hold [`frame::MAX_PDU_LEN`].
+    pub fn respond(&self, context: &mut C, pdu: &[u8], out: &mut [u8]) -> usize {
+        let function = pdu.first().copied().unwrap_or(0);
+        match self.execute(context, pdu, out) {
+            Ok(len) => len,
+            Err(exception) => frame::encode_exception(function, exception, out),
+        }
+    }
+
+    fn execute(&self, context: &mut C, pdu: &[u8], out: &mut [u8]) -> Result<usize, Exception> {
+        let request = Request::parse(pdu)?;
+        out[0] = pdu[0];
+        match request {
+            Request::ReadCoils { address, quantity } => {
+                let registers = self.range(Table::Coil, address, quantity, false)?;
+                let count = quantity.div_ceil(8) as usize;
+                out[1] = count as u8;
+                out[2..2 + count].fill(0);
+                for (i, register) in registers.enumerate() {
+                    if (register.read)(context) != 0 {
+                        out[2 + i / 8] |= 1 << (i % 8);
+                    }
+                }
+                Ok(2 + count)
+            }
+            Request::ReadHoldingRegisters { address, quantity }
+            | Request::ReadInputRegisters { address, quantity } => {
+                let table = match request {
+                    Request::ReadHoldingRegisters { .. } => Table::HoldingRegister,
+                    _ => Table::InputRegister,
+                };
+                let registers = self.range(table, address, quantity, false)?;
+                out[1] = quantity as u8 * 2;
+                for (i, register) in registers.enumerate() {
+                    let value = (register.read)(context);
+                    out[2 + i * 2..4 + i * 2].copy_from_slice(&value.to_be_bytes());
+                }
+                Ok(2 + quantity as usize * 2)
+            }
+            Request::WriteSingleCoil { address, value } => {
+                self.write(context, Table::Coil, address, 1, |_| value as u16)?;
+                out[1..5].copy_from_slice(&pdu[1..5]);
+                Ok(5)
+            }
+            Request::WriteSingleRegister { address, value } => {
+                self.write(context, Table::HoldingRegister, address, 1, |_| value)?;
+                out[1..5].copy_from_slice(&pdu[1..5]);
+                Ok(5)
+            }
+            Request::WriteMultipleCoils {
+                address,
+                quantity,
+                values,
+            } => {
+                self.write(context, Table::Coil, address, quantity, |i| {
+                    (values[i / 8] >> (i % 8)) as u16 & 1
+                })?;
+                out[1..5].copy_from_slice(&pdu[1..5]);
+                Ok(5)
+            }
+            Request::WriteMultipleRegisters {
+                address,
+                quantity,
+                values,
+            } => {
+                self.write(context, Table::HoldingRegister, address, quantity, |i| {
+                    u16::from_be_bytes([values[i * 2], values[i * 2 + 1]])
+                })?;
+                out[1..5].copy_from_slice(&pdu[1..5]);
+                Ok(5)
+            }
+        }
+    }
+
+    fn find(&self, table: Table, address: u16) -> Option<&Register<C>> {
+        self.registers
+            .iter()
+            .find(|r| r.table == table && r.address == address)
+    }
+
+    /// The registers at `address` and the `quantity - 1` after it, all of
+    /// which must exist, and be writable if `write` is set.
+    fn range(
+        &self,
+        table: Table,
+        address: u16,
+        quantity: u16,
+        write: bool,
+    ) -> Result<impl Iterator<Item = &Register<C>>, Exception> {
+        let exists = |i| {
+            self.find(table, address + i)
+                .is_some_and(|r| !write || r.write.is_some())
+        };
+        if !(0..quantity).all(exists) {
+            return Err(Exception::IllegalDataAddress);
+        }
+        Ok((0..quantity).filter_map(move |i| self.find(table, address + i)))
+    }
+
+    fn write(
+        &self,
+        context: &mut C,
+        table: Table,
+        address: u16,
+        quantity: u16,
+        value: impl Fn(usize) -> u16,
+    ) -> Result<(), Exception> {
+        for (i, register) in self.range(table, address, quantity, true)?.enumerate() {
+            if let Some(write) = register.write {
+                write(context, value(i))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Device {
+        led: bool,
+        interval: u16,
+        mode: u16,
+    }
+
+    static MAP: Map<Device> = Map::new(&[
+        Register::coil(
+            0,
+            |d| d.led as u16,
+            |d, v| {
+                d.led = v != 0;
+                Ok(())
+            },
+        ),
+        Register::input(0, |_| 0x1234),
+        Register::input(1, |_| 0xfffe),
+        Register::holding(
+            0,
+            |d| d.interval,
+            |d, v| {
+                d.interval = v;
+                Ok(())
+            },
+        ),
+        Register::holding(
+            1,
+            |d| d.mode,
+            |d, v| {
+                if v > 2 {
+                    return Err(Exception::IllegalDataValue);
+                }
+                d.mode = v;
+                Ok(())
+            },
+        ),
+        Register::holding(2, |_| 7, |_, _| Ok(())),
+    ]);
+
+    fn respond(device: &mut Device, pdu: &[u8]) -> heapless::Vec<u8, { frame::MAX_PDU_LEN }> {
+        let mut out = [0; frame::MAX_PDU_LEN];
+        let n = MAP.respond(device, pdu, &mut out);
+        heapless::Vec::from_slice(&out[..n]).unwrap()
+    }
+
+    #[test]
+    fn test_map() {
+        let mut device = Device::default();
+
+        assert_eq!(
+            respond(&mut device, &[0x04, 0x00, 0x00, 0x00, 0x02]),
+            [0x04, 0x04, 0x12, 0x34, 0xff, 0xfe]
+        );
+        assert_eq!(
+            respond(&mut device, &[0x04, 0x00, 0x01, 0x00, 0x02]),
+            [0x84, 0x02]
+        );
+
+        assert_eq!(
+            respond(&mut device, &[0x05, 0x00, 0x00, 0xff, 0x00]),
+            [0x05, 0x00, 0x00, 0xff, 0x00]
+        );
+        assert!(device.led);
+        assert_eq!(
+            respond(&mut device, &[0x01, 0x00, 0x00, 0x00, 0x01]),
+            [0x01, 0x01, 0x01]
+        );
+        assert_eq!(
+            respond(&mut device, &[0x0f, 0x00, 0x00, 0x00, 0x01, 0x01, 0x00]),
+            [0x0f, 0x00, 0x00, 0x00, 0x01]
+        );
+        assert!(!device.led);
+        assert_eq!(
+            respond(&mut device, &[0x05, 0x00, 0x01, 0xff, 0x00]),
+            [0x85, 0x02]
+        );
+
+        assert_eq!(
+            respond(
+                &mut device,
+                &[0x10, 0x00, 0x00, 0x00, 0x02, 0x04, 0x00, 0x0a, 0x00, 0x02]
+            ),
+            [0x10, 0x00, 0x00, 0x00, 0x02]
+        );
+        assert_eq!((device.interval, device.mode), (10, 2));
+        assert_eq!(
+            respond(&mut device, &[0x03, 0x00, 0x00, 0x00, 0x03]),
+            [0x03, 0x06, 0x00, 0x0a, 0x00, 0x02, 0x00, 0x07]
+        );
+        assert_eq!(
+            respond(&mut device, &[0x06, 0x00, 0x01, 0x00, 0x03]),
+            [0x86, 0x03]
+        );
+
+        // Nothing is written when an address is missing.
+        assert_eq!(
+            respond(
+                &mut device,
+                &[0x10, 0x00, 0x02, 0x00, 0x02, 0x04, 0x00, 0x01, 0x00, 0x01]
+            ),
+            [0x90, 0x02]
+        );
+        assert_eq!(
+            respond(&mut device, &[0x06, 0x00, 0x00, 0x00, 0x05]),
+            [0x06, 0x00, 0x00, 0x00, 0x05]
+        );
+        assert_eq!(
+            respond(
+                &mut device,
+                &[0x10, 0x00, 0x00, 0x00, 0x04, 0x08, 0, 1, 0, 1, 0, 1, 0, 1]
+            ),
+            [0x90, 0x02]
+        );
+        assert_eq!(device.interval, 5);
+
+        assert_eq!(
+            respond(&mut device, &[0x02, 0x00, 0x00, 0x00, 0x01]),
+            [0x82, 0x01]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Modbus TCP server
+//!
+//! The `modbus` task serves one connection at a time on [`PORT`], with the
+//! registers of [`MAP`]:
+//!
+//! | Table            | Address | Value                                            |
+//! |------------------|---------|--------------------------------------------------|
+//! | Coil             | 0       | LED, writing takes it over                       |
+//! | Coil             | 1       | LED controlled remotely, 0 shows the status      |
+//! | Input register   | 0       | Wi-Fi: 0 disconnected, 1 connecting, 2 connected |
+//! | Input register   | 1       | RSSI in dBm, signed, 0 while not connected       |
+//! | Input register   | 2, 3    | Uptime in seconds, high word first               |
+//! | Input register   | 4, 5    | Free heap in bytes, high word first              |
+//! | Holding register | 0       | Power save: 0 none, 1 minimum, 2 maximum         |
+//! | Holding register | 1       | Listen interval in beacons                       |
+//! | Holding register | 2, 3    | Deep sleep in seconds, high word first, 0 off    |
+//!
+//! Addresses not in the table are answered with an exception. Holding
+//! registers are saved to the settings after each request that changed
+//! them and take effect after a restart. Modbus has no authentication, so
+//! when [`http::TOKEN`] protects the settings they are read-only here. Any
+//! unit ID is accepted.
+//!
+//! `frame` and `map` don't depend on esp-hal or embassy-net and are tested
+//! on the host.
+
+use embassy_net::{Stack, tcp::TcpSocket};
+use embassy_time::{Duration, Instant};
+use esp_println::println;
+
+use crate::settings::{self, PowerSave, Settings};
+use crate::status::{self, Wifi};
+use crate::{http, io, led};
+
+pub mod frame;
+pub mod map;
+
+pub use frame::{Exception, Header};
+pub use map::{Map, Register};
+
+pub const PORT: u16 = 502;
+
+/// Closed after this long without a request.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub static MAP: Map<Device> = Map::new(&[
+    Register::coil(0, |_| led::is_on() as u16, write_led),
+    Register::coil(1, |_| led::is_manual() as u16, write_manual),
+    Register::input(0, |_| wifi_status()),
+    Register::input(1, |_| crate::rssi().unwrap_or(0) as i16 as u16),
+    Register::input(2, |_| (uptime() >> 16) as u16),
+    Register::input(3, |_| uptime() as u16),
+    Register::input(4, |_| (esp_alloc::HEAP.free() >> 16) as u16),
+    Register::input(5, |_| esp_alloc::HEAP.free() as u16),
+    Register::holding(0, |d| d.settings.power.save as u16, write_power_save),
+    Register::holding(
+        1,
+        |d| d.settings.power.listen_interval,
+        |d, value| {
+            d.settings.power.listen_interval = value;
+            Ok(())
+        },
+    ),
+    Register::holding(
+        2,
+        |d| (sleep_secs(d) >> 16) as u16,
+        |d, value| {
+            set_sleep_secs(d, sleep_secs(d) & 0xffff | (value as u32) << 16);
+            Ok(())
+        },
+    ),
+    Register::holding(
+        3,
+        |d| sleep_secs(d) as u16,
+        |d, value| {
+            set_sleep_secs(d, sleep_secs(d) & 0xffff_0000 | value as u32);
+            Ok(())
+        },
+    ),
+]);
+
+/// What the registers read and write, one per request.
+pub struct Device {
+    /// Changed by holding register writes, saved if the request succeeded
+    pub settings: Settings,
+}
+
+fn write_led(_: &mut Device, value: u16) -> Result<(), Exception> {
+    led::set_manual(true);
+    led::set(value != 0);
+    Ok(())
+}
+
+fn write_manual(_: &mut Device, value: u16) -> Result<(), Exception> {
+    led::set_manual(value != 0);
+    Ok(())
+}
+
+fn wifi_status() -> u16 {
+    match status::get().wifi {
+        Wifi::Disconnected => 0,
+        Wifi::Connecting => 1,
+        Wifi::Connected => 2,
+    }
+}
+
+fn uptime() -> u32 {
+    Instant::now().as_secs() as u32
+}
+
+fn write_power_save(device: &mut Device, value: u16) -> Result<(), Exception> {
+    device.settings.power.save = match value {
+        0 => PowerSave::None,
+        1 => PowerSave::Minimum,
+        2 => PowerSave::Maximum,
+        _ => return Err(Exception::IllegalDataValue),
+    };
+    Ok(())
+}
+
+fn sleep_secs(device: &Device) -> u32 {
+    device.settings.power.sleep_secs.unwrap_or(0)
+}
+
+fn set_sleep_secs(device: &mut Device, secs: u32) {
+    device.settings.power.sleep_secs = (secs != 0).then_some(secs);
+}
+
+#[embassy_executor::task]
+pub async fn modbus(stack: Stack<'static>) {
+    let mut rx_buffer = [0; 512];
+    let mut tx_buffer = [0; 512];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(IDLE_TIMEOUT));
+
+        if let Err(e) = socket.accept(PORT).await {
+            println!("modbus: accept error: {:?}", e);
+            continue;
+        }
+        println!("modbus: connection from {:?}", socket.remote_endpoint());
+
+        if let Err(e) = session(&mut socket).await {
+            println!("modbus: {:?}", e);
+        }
+        socket.close();
+        socket.flush().await.ok();
+        socket.abort();
+    }
+}
+
+/// Answer requests until the client closes the connection or sends an
+/// invalid header.
+async fn session(socket: &mut TcpSocket<'_>) -> Result<(), io::Error<embassy_net::tcp::Error>> {
+    let mut head = [0; frame::HEADER_LEN];
+    let mut pdu = [0; frame::MAX_PDU_LEN];
+    let mut out = [0; frame::HEADER_LEN + frame::MAX_PDU_LEN];
+
+    loop {
+        match io::read_exact(socket, &mut head).await {
+            Err(io::Error::UnexpectedEof) => return Ok(()),
+            result => result?,
+        }
+        let header = match Header::parse(&head) {
+            Ok(header) => header,
+            Err(e) => {
+                println!("modbus: bad header: {:?}", e);
+                return Ok(());
+            }
+        };
+        let pdu = &mut pdu[..header.pdu_len()];
+        io::read_exact(socket, pdu).await?;
+
+        let n = respond(pdu, &mut out[frame::HEADER_LEN..]);
+        out[..frame::HEADER_LEN].copy_from_slice(&header.encode_response(n));
+        io::write_all(socket, &out[..frame::HEADER_LEN + n]).await?;
+    }
+}
+
+/// Execute the request, saving the settings if it changed them.
+fn respond(pdu: &[u8], out: &mut [u8]) -> usize {
+    if http::TOKEN.is_some()
+        && matches!(
+            pdu[0],
+            frame::function::WRITE_SINGLE_REGISTER | frame::function::WRITE_MULTIPLE_REGISTERS
+        )
+    {
+        return frame::encode_exception(pdu[0], Exception::IllegalDataAddress, out);
+    }
+    let before = settings::get();
+    let mut device = Device {
+        settings: before.clone(),
+    };
+    let n = MAP.respond(&mut device, pdu, out);
+    if out[0] & frame::EXCEPTION == 0
+        && device.settings != before
+        && let Err(reason) = settings::submit(device.settings)
+    {
+        println!("modbus: can't save settings: {}", reason);
+        return frame::encode_exception(pdu[0], Exception::ServerDeviceFailure, out);
+    }
+    n
+}
@@ -0,0 +1,609 @@
+//! A minimal async MQTT client.
+//!
+//! The client is generic over `embedded_io_async`, so it runs on top of an
+//! embassy-net `TcpSocket` on the device and on top of a scripted broker in
+//! the tests below.
+
+use embassy_time::{Duration, Instant, with_deadline};
+use embedded_io_async::{Read, Write};
+
+use super::packet::{self, Connect, Packet, ProtocolVersion, Publish, QoS};
+use crate::io;
+
+/// How long to wait for CONNACK, PUBACK, SUBACK and PINGRESP.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many times a QoS 1 publish is retransmitted before giving up.
+const PUBLISH_RETRIES: usize = 3;
+
+/// PUBACKs queued by [`Client::poll`] until [`Client::flush`] sends them.
+const MAX_PENDING_ACKS: usize = 4;
+
+/// Errors
+#[derive(Debug)]
+pub enum Error<E> {
+    /// Transport error
+    Io(E),
+    /// Encoding or decoding error
+    Packet(packet::Error),
+    /// The broker closed the connection
+    Closed,
+    /// The broker didn't answer in time
+    Timeout,
+    /// CONNACK with a non zero return code
+    ConnectionRefused(u8),
+    /// SUBACK with a failure return code
+    SubscribeRejected,
+    /// QoS 1 publish without PUBACK after all retries
+    NotAcknowledged,
+    /// An incoming packet doesn't fit into the receive buffer
+    PacketTooLarge,
+}
+
+impl<E> From<io::Error<E>> for Error<E> {
+    fn from(e: io::Error<E>) -> Self {
+        match e {
+            io::Error::Io(e) => Error::Io(e),
+            io::Error::Timeout => Error::Timeout,
+            io::Error::WriteZero | io::Error::UnexpectedEof => Error::Closed,
+            io::Error::BufferFull => Error::PacketTooLarge,
+        }
+    }
+}
+
+impl<E> From<packet::Error> for Error<E> {
+    fn from(e: packet::Error) -> Self {
+        Error::Packet(e)
+    }
+}
+
+/// What we learned from a received packet, without borrowing the buffer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Incoming {
+    ConnAck { code: u8 },
+    Publish { ack: Option<u16> },
+    PubAck(u16),
+    SubAck { packet_id: u16, ok: bool },
+    UnsubAck(u16),
+    PingResp,
+}
+
+pub struct Client<'b, T, H> {
+    io: T,
+    version: ProtocolVersion,
+    keep_alive: Duration,
+    response_timeout: Duration,
+    tx: &'b mut [u8],
+    rx: &'b mut [u8],
+    rx_len: usize,
+    next_packet_id: u16,
+    last_tx: Instant,
+    ping_sent: Option<Instant>,
+    /// Packet identifiers of received QoS 1 messages not acknowledged yet
+    acks: heapless::Vec<u16, MAX_PENDING_ACKS>,
+    /// The keep alive interval passed without sending anything
+    ping_due: bool,
+    handler: H,
+}
+
+impl<'b, T, H> Client<'b, T, H>
+where
+    T: Read + Write,
+    H: FnMut(&Publish<'_>),
+{
+    /// Creates a new client.
+    ///
+    /// `handler` is called for every message received on a subscribed topic.
+    pub fn new(
+        io: T,
+        version: ProtocolVersion,
+        tx: &'b mut [u8],
+        rx: &'b mut [u8],
+        handler: H,
+    ) -> Self {
+        Self {
+            io,
+            version,
+            keep_alive: Duration::from_secs(0),
+            response_timeout: RESPONSE_TIMEOUT,
+            tx,
+            rx,
+            rx_len: 0,
+            next_packet_id: 1,
+            last_tx: Instant::now(),
+            ping_sent: None,
+            acks: heapless::Vec::new(),
+            ping_due: false,
+            handler,
+        }
+    }
+
+    /// How long to wait for CONNACK, PUBACK, SUBACK and PINGRESP, 5 seconds
+    /// by default.
+    pub fn with_response_timeout(mut self, timeout: Duration) -> Self {
+        self.response_timeout = timeout;
+        self
+    }
+
+    /// Send CONNECT and wait for CONNACK.
+    pub async fn connect(&mut self, connect: &Connect<'_>) -> Result<(), Error<T::Error>> {
+        self.keep_alive = Duration::from_secs(connect.keep_alive.into());
+
+        let n = packet::encode_connect(self.tx, self.version, connect)?;
+        self.send(n).await?;
+
+        match self
+            .wait_for(|i| matches!(i, Incoming::ConnAck { .. }))
+            .await?
+        {
+            Some(Incoming::ConnAck { code: 0 }) => Ok(()),
+            Some(Incoming::ConnAck { code }) => Err(Error::ConnectionRefused(code)),
+            _ => Err(Error::Timeout),
+        }
+    }
+
+    /// Publish a message.
+    ///
+    /// With QoS 1 this waits for PUBACK and retransmits with the DUP flag set
+    /// if it doesn't arrive in time.
+    pub async fn publish(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+    ) -> Result<(), Error<T::Error>> {
+        let packet_id = match qos {
+            QoS::AtMostOnce => None,
+            QoS::AtLeastOnce => Some(self.packet_id()),
+        };
+
+        let mut publish = Publish {
+            topic,
+            payload,
+            qos,
+            retain,
+            dup: false,
+            packet_id,
+        };
+
+        for _ in 0..=PUBLISH_RETRIES {
+            let n = packet::encode_publish(self.tx, self.version, &publish)?;
+            self.send(n).await?;
+
+            let Some(id) = packet_id else {
+                return Ok(());
+            };
+
+            if self.wait_for(|i| *i == Incoming::PubAck(id)).await?.is_some() {
+                return Ok(());
+            }
+
+            publish.dup = true;
+        }
+
+        Err(Error::NotAcknowledged)
+    }
+
+    /// Subscribe to a list of topic filters and wait for SUBACK.
+    pub async fn subscribe(&mut self, topics: &[(&str, QoS)]) -> Result<(), Error<T::Error>> {
+        let id = self.packet_id();
+        let n = packet::encode_subscribe(self.tx, self.version, id, topics)?;
+        self.send(n).await?;
+
+        match self
+            .wait_for(|i| matches!(i, Incoming::SubAck { packet_id, .. } if *packet_id == id))
+            .await?
+        {
+            Some(Incoming::SubAck { ok: true, .. }) => Ok(()),
+            Some(_) => Err(Error::SubscribeRejected),
+            None => Err(Error::Timeout),
+        }
+    }
+
+    /// Unsubscribe from a list of topic filters and wait for UNSUBACK.
+    pub async fn unsubscribe(&mut self, topics: &[&str]) -> Result<(), Error<T::Error>> {
+        let id = self.packet_id();
+        let n = packet::encode_unsubscribe(self.tx, self.version, id, topics)?;
+        self.send(n).await?;
+
+        match self.wait_for(|i| *i == Incoming::UnsubAck(id)).await? {
+            Some(_) => Ok(()),
+            None => Err(Error::Timeout),
+        }
+    }
+
+    /// Handle incoming packets and watch the keep alive interval.
+    ///
+    /// Returns after one packet was processed or a keep alive deadline
+    /// passed. It only reads, PUBACKs and PINGREQ are queued for
+    /// [`flush`](Self::flush), so it can be raced against other work with
+    /// `select` without cutting a packet short.
+    pub async fn poll(&mut self) -> Result<(), Error<T::Error>> {
+        let now = Instant::now();
+
+        if let Some(sent) = self.ping_sent {
+            if now >= sent + self.response_timeout {
+                return Err(Error::Timeout);
+            }
+        } else if self.keep_alive.as_ticks() > 0 && now >= self.last_tx + self.keep_alive {
+            self.ping_due = true;
+        }
+
+        // Nothing more is read until the queued packets went out.
+        if self.ping_due || self.acks.is_full() {
+            return Ok(());
+        }
+
+        let deadline = match (self.ping_sent, self.keep_alive.as_ticks()) {
+            (Some(sent), _) => sent + self.response_timeout,
+            (None, 0) => Instant::MAX,
+            (None, _) => self.last_tx + self.keep_alive,
+        };
+
+        match with_deadline(deadline, self.receive()).await {
+            Ok(incoming) => self.dispatch(incoming?),
+            Err(_) if self.ping_sent.is_some() => return Err(Error::Timeout),
+            Err(_) => self.ping_due = true,
+        }
+
+        Ok(())
+    }
+
+    /// Send what [`poll`](Self::poll) queued, call it before every `poll`
+    /// outside of the `select`.
+    pub async fn flush(&mut self) -> Result<(), Error<T::Error>> {
+        while let Some(&id) = self.acks.first() {
+            let n = packet::encode_puback(self.tx, id)?;
+            self.send(n).await?;
+            self.acks.remove(0);
+        }
+
+        if self.ping_due {
+            let n = packet::encode_pingreq(self.tx)?;
+            self.send(n).await?;
+            self.ping_sent = Some(Instant::now());
+            self.ping_due = false;
+        }
+
+        Ok(())
+    }
+
+    /// Send DISCONNECT, the broker discards the last will.
+    pub async fn disconnect(&mut self) -> Result<(), Error<T::Error>> {
+        let n = packet::encode_disconnect(self.tx)?;
+        self.send(n).await
+    }
+
+    fn packet_id(&mut self) -> u16 {
+        let id = self.next_packet_id;
+        // Packet identifiers must be non zero.
+        self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+        id
+    }
+
+    async fn send(&mut self, len: usize) -> Result<(), Error<T::Error>> {
+        // A broker that stops reading gets the response timeout too.
+        let mut io = io::Timeout::new(&mut self.io, self.response_timeout);
+        io::write_all(&mut io, &self.tx[..len])
+            .await
+            .map_err(io::Error::flatten)?;
+        io.flush().await?;
+        self.last_tx = Instant::now();
+        Ok(())
+    }
+
+    /// Receive packets until `matcher` accepts one or the response timeout
+    /// expires. Everything else is dispatched as usual.
+    async fn wait_for(
+        &mut self,
+        matcher: impl Fn(&Incoming) -> bool,
+    ) -> Result<Option<Incoming>, Error<T::Error>> {
+        let deadline = Instant::now() + self.response_timeout;
+
+        loop {
+            let Ok(incoming) = with_deadline(deadline, self.receive()).await else {
+                return Ok(None);
+            };
+            let incoming = incoming?;
+
+            if matcher(&incoming) {
+                return Ok(Some(incoming));
+            }
+
+            self.dispatch(incoming);
+            self.flush().await?;
+        }
+    }
+
+    /// Side effects of packets nobody is waiting for.
+    fn dispatch(&mut self, incoming: Incoming) {
+        match incoming {
+            Incoming::Publish { ack: Some(id) } => {
+                // `poll` stops reading while the queue is full.
+                self.acks.push(id).ok();
+            }
+            Incoming::PingResp => self.ping_sent = None,
+            // Late acknowledgements of something we already gave up on.
+            _ => (),
+        }
+    }
+
+    /// Read one packet.
+    ///
+    /// Partial data stays in the receive buffer, so dropping this future
+    /// doesn't lose bytes.
+    async fn receive(&mut self) -> Result<Incoming, Error<T::Error>> {
+        loop {
+            if let Some(len) = packet::packet_len(&self.rx[..self.rx_len])? {
+                if len > self.rx.len() {
+                    return Err(Error::PacketTooLarge);
+                }
+                if self.rx_len >= len {
+                    let incoming = self.decode(len)?;
+                    self.rx.copy_within(len..self.rx_len, 0);
+                    self.rx_len -= len;
+                    return incoming.ok_or(Error::Closed);
+                }
+            }
+
+            let n = self
+                .io
+                .read(&mut self.rx[self.rx_len..])
+                .await
+                .map_err(Error::Io)?;
+            if n == 0 {
+                return Err(Error::Closed);
+            }
+            self.rx_len += n;
+        }
+    }
+
+    /// Decode the first `len` bytes of the receive buffer.
+    ///
+    /// Returns `None` when the broker sent DISCONNECT.
+    fn decode(&mut self, len: usize) -> Result<Option<Incoming>, Error<T::Error>> {
+        let incoming = match packet::decode(&self.rx[..len], self.version)? {
+            Packet::ConnAck { code, .. } => Incoming::ConnAck { code },
+            Packet::Publish(publish) => {
+                (self.handler)(&publish);
+                Incoming::Publish {
+                    ack: publish.packet_id,
+                }
+            }
+            Packet::PubAck { packet_id } => Incoming::PubAck(packet_id),
+            Packet::SubAck { packet_id, granted } => Incoming::SubAck {
+                packet_id,
+                ok: granted.iter().all(|code| *code < 0x80),
+            },
+            Packet::UnsubAck { packet_id } => Incoming::UnsubAck(packet_id),
+            Packet::PingResp => Incoming::PingResp,
+            Packet::Disconnect { .. } => return Ok(None),
+        };
+        Ok(Some(incoming))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+    use alloc::vec::Vec;
+    use core::cell::RefCell;
+    use core::convert::Infallible;
+    use core::future::poll_fn;
+    use core::task::Poll;
+
+    use embassy_futures::block_on;
+    use embedded_io_async::ErrorType;
+
+    use super::*;
+
+    /// Answers the n-th packet the client sends with `replies[n]`, an empty
+    /// reply is a packet the broker ignores. Reads wait while there is
+    /// nothing to read, like a quiet connection.
+    struct Broker<'a> {
+        replies: &'a [&'a [u8]],
+        input: Vec<u8>,
+        output: Vec<u8>,
+        sent: usize,
+    }
+
+    impl ErrorType for Broker<'_> {
+        type Error = Infallible;
+    }
+
+    impl Read for Broker<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+            poll_fn(|_| match self.input.is_empty() {
+                true => Poll::Pending,
+                false => {
+                    let n = buf.len().min(self.input.len()).min(5);
+                    buf[..n].copy_from_slice(&self.input[..n]);
+                    self.input.drain(..n);
+                    Poll::Ready(Ok(n))
+                }
+            })
+            .await
+        }
+    }
+
+    impl Write for Broker<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        /// Every packet is flushed once.
+        async fn flush(&mut self) -> Result<(), Infallible> {
+            if let Some(reply) = self.replies.get(self.sent) {
+                self.input.extend_from_slice(reply);
+            }
+            self.sent += 1;
+            Ok(())
+        }
+    }
+
+    fn encoded(encode: impl FnOnce(&mut [u8]) -> Result<usize, packet::Error>) -> Vec<u8> {
+        let mut buf = [0; 64];
+        let n = encode(&mut buf).unwrap();
+        buf[..n].to_vec()
+    }
+
+    #[test]
+    fn test_session() {
+        let connack: &[u8] = &[0x20, 2, 0, 0];
+        let suback: &[u8] = &[0x90, 3, 0, 1, 1];
+        // PUBACK of the retransmission, then a QoS 1 command with id 7.
+        let puback_and_publish: &[u8] = &[
+            0x40, 2, 0, 2, 0x32, 17, 0, 11, b'e', b's', b'p', b'/', b'c', b'o', b'm', b'm', b'a',
+            b'n', b'd', 0, 7, b'o', b'n',
+        ];
+        let pingresp: &[u8] = &[0xd0, 0];
+        let replies = [connack, suback, &[], puback_and_publish, &[], pingresp];
+
+        let io = Broker {
+            replies: &replies,
+            input: Vec::new(),
+            output: Vec::new(),
+            sent: 0,
+        };
+        let received = RefCell::new(Vec::new());
+        let mut tx = [0; 128];
+        let mut rx = [0; 128];
+        let mut client = Client::new(io, ProtocolVersion::V311, &mut tx, &mut rx, |publish| {
+            received.borrow_mut().push((String::from(publish.topic), publish.payload.to_vec()))
+        })
+        .with_response_timeout(Duration::from_millis(50));
+
+        let connect = Connect {
+            client_id: "test",
+            keep_alive: 1,
+            clean_session: true,
+            username: None,
+            password: None,
+            will: None,
+        };
+        block_on(client.connect(&connect)).unwrap();
+        block_on(client.subscribe(&[("esp/command", QoS::AtLeastOnce)])).unwrap();
+
+        // The first PUBLISH is lost, the retransmission is acknowledged.
+        block_on(client.publish("esp/telemetry", b"42", QoS::AtLeastOnce, false)).unwrap();
+
+        // The command is handled, but poll doesn't write the PUBACK.
+        let len = client.io.output.len();
+        block_on(client.poll()).unwrap();
+        assert_eq!(*received.borrow(), [("esp/command".into(), b"on".to_vec())]);
+        assert_eq!(client.io.output.len(), len);
+        block_on(client.flush()).unwrap();
+
+        // A second later poll returns to let flush send PINGREQ.
+        let start = Instant::now();
+        block_on(client.poll()).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(900));
+        block_on(client.flush()).unwrap();
+        block_on(client.poll()).unwrap();
+        assert_eq!(client.ping_sent, None);
+
+        let mut publish = Publish {
+            topic: "esp/telemetry",
+            payload: b"42",
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            dup: false,
+            packet_id: Some(2),
+        };
+        let mut expected = Vec::new();
+        expected.extend(encoded(|buf| {
+            packet::encode_connect(buf, ProtocolVersion::V311, &connect)
+        }));
+        expected.extend(encoded(|buf| {
+            packet::encode_subscribe(buf, ProtocolVersion::V311, 1, &[("esp/command", QoS::AtLeastOnce)])
+        }));
+        expected.extend(encoded(|buf| {
+            packet::encode_publish(buf, ProtocolVersion::V311, &publish)
+        }));
+        publish.dup = true;
+        expected.extend(encoded(|buf| {
+            packet::encode_publish(buf, ProtocolVersion::V311, &publish)
+        }));
+        expected.extend(encoded(|buf| packet::encode_puback(buf, 7)));
+        expected.extend(encoded(packet::encode_pingreq));
+        assert_eq!(client.io.output, expected);
+    }
+
+    #[test]
+    fn test_ping_timeout() {
+        let connack: &[u8] = &[0x20, 2, 0, 0];
+        let io = Broker {
+            replies: &[connack],
+            input: Vec::new(),
+            output: Vec::new(),
+            sent: 0,
+        };
+        let mut tx = [0; 64];
+        let mut rx = [0; 64];
+        let mut client = Client::new(io, ProtocolVersion::V311, &mut tx, &mut rx, |_| {})
+            .with_response_timeout(Duration::from_millis(50));
+
+        let connect = Connect {
+            client_id: "test",
+            keep_alive: 1,
+            clean_session: true,
+            username: None,
+            password: None,
+            will: None,
+        };
+        block_on(client.connect(&connect)).unwrap();
+
+        // Without PINGRESP the connection is given up.
+        block_on(client.poll()).unwrap();
+        block_on(client.flush()).unwrap();
+        assert!(matches!(block_on(client.poll()), Err(Error::Timeout)));
+    }
+
+    /// Accepts `capacity` bytes, then writes return 0 or never complete.
+    struct Stuck {
+        capacity: usize,
+        stall: bool,
+    }
+
+    impl ErrorType for Stuck {
+        type Error = Infallible;
+    }
+
+    impl Read for Stuck {
+        async fn read(&mut self, _: &mut [u8]) -> Result<usize, Infallible> {
+            core::future::pending().await
+        }
+    }
+
+    impl Write for Stuck {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+            if self.capacity == 0 && self.stall {
+                core::future::pending::<()>().await;
+            }
+            let n = buf.len().min(self.capacity);
+            self.capacity -= n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_stuck_writes() {
+        for stall in [false, true] {
+            let io = Stuck {
+                capacity: 4,
+                stall,
+            };
+            let mut tx = [0; 64];
+            let mut rx = [0; 64];
+            let mut client = Client::new(io, ProtocolVersion::V311, &mut tx, &mut rx, |_| {})
+                .with_response_timeout(Duration::from_millis(50));
+            let result = block_on(client.publish("esp/telemetry", b"42", QoS::AtMostOnce, false));
+            match stall {
+                false => assert!(matches!(result, Err(Error::Closed))),
+                true => assert!(matches!(result, Err(Error::Timeout))),
+            }
+        }
+    }
+}
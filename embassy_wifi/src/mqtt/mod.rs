@@ -0,0 +1,241 @@
+//! Publish telemetry to an MQTT broker
+//!
+//! `packet` and `client` don't depend on esp-hal or embassy-net, only on
+//! `embedded-io-async` and `embassy-time`, so their tests run on the host,
+//! see `host_tests`.
+//!
+//! With [`TLS`] set, the connection is wrapped with `crate::tls` and the
+//! broker certificate must be issued by `tls::CA` for [`SERVER_NAME`].
+
+use alloc::vec;
+use core::fmt::Write as _;
+
+use embassy_futures::select::{Either, Either3, select, select3};
+use embassy_net::{Ipv4Address, Stack, tcp::TcpSocket};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, signal::Signal};
+use embassy_time::{Duration, Instant, Ticker, Timer};
+use embedded_io_async::{Read, Write};
+use esp_hal::rng::Rng;
+use esp_println::println;
+use static_cell::ConstStaticCell;
+
+use crate::metrics::{Counter, Metric};
+use crate::status::{self, Wifi};
+use crate::{clock, tls};
+
+pub mod client;
+pub mod packet;
+
+pub use client::{Client, Error};
+pub use packet::{Connect, ProtocolVersion, Publish, QoS, Will};
+
+pub const TLS: bool = false;
+pub const BROKER: (Ipv4Address, u16) = (
+    Ipv4Address::new(192, 168, 1, 100),
+    if TLS { 8883 } else { 1883 },
+);
+/// Name the broker certificate is checked against, also sent as SNI.
+pub const SERVER_NAME: &str = "esp-test.local";
+pub const CLIENT_ID: &str = "esp32-embassy";
+pub const VERSION: ProtocolVersion = ProtocolVersion::V311;
+pub const KEEP_ALIVE_SECS: u16 = 30;
+
+pub const STATUS_TOPIC: &str = "esp/status";
+pub const TELEMETRY_TOPIC: &str = "esp/telemetry";
+pub const COMMAND_TOPIC: &str = "esp/command";
+
+const TELEMETRY_INTERVAL: Duration = Duration::from_secs(10);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+pub const MAX_TOPIC_LEN: usize = 64;
+pub const MAX_PAYLOAD_LEN: usize = 256;
+
+/// A message received from, or to be published to, the broker.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub topic: heapless::String<MAX_TOPIC_LEN>,
+    pub payload: heapless::Vec<u8, MAX_PAYLOAD_LEN>,
+    pub qos: QoS,
+}
+
+impl Message {
+    /// Returns `None` if the topic or payload is too long.
+    pub fn new(topic: &str, payload: &[u8], qos: QoS) -> Option<Self> {
+        Some(Self {
+            topic: heapless::String::try_from(topic).ok()?,
+            payload: heapless::Vec::from_slice(payload).ok()?,
+            qos,
+        })
+    }
+}
+
+/// Connections that failed or were lost.
+static TCP_ERRORS: Counter = Counter::new("tcp_errors_total", "Failed connections and requests")
+    .with_labels("task=\"mqtt\"");
+
+pub static METRICS: &[Metric] = &[Metric::Counter(&TCP_ERRORS)];
+
+/// Messages received on subscribed topics.
+pub static INBOX: Channel<CriticalSectionRawMutex, Message, 4> = Channel::new();
+
+/// Messages other tasks want to publish.
+pub static OUTBOX: Channel<CriticalSectionRawMutex, Message, 4> = Channel::new();
+
+/// Signaled after a message from [`OUTBOX`] was published, with QoS 1 once
+/// the broker acknowledged it.
+pub static PUBLISHED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// The telemetry payload, e.g. `{"uptime":42,"time":1714566600000}`.
+pub fn telemetry() -> heapless::String<MAX_PAYLOAD_LEN> {
+    let mut payload = heapless::String::new();
+    write!(payload, "{{\"uptime\":{}", Instant::now().as_secs()).ok();
+    // Milliseconds since the Unix epoch, null until SNTP synced.
+    match clock::now().unix_micros() {
+        Some(micros) => write!(payload, ",\"time\":{}}}", micros / 1000).ok(),
+        None => write!(payload, ",\"time\":null}}").ok(),
+    };
+    payload
+}
+
+#[embassy_executor::task]
+pub async fn mqtt(stack: Stack<'static>, rng: Rng) {
+    // Static, too large for the task arena.
+    static RX_BUFFER: ConstStaticCell<[u8; 1536]> = ConstStaticCell::new([0; 1536]);
+    static TX_BUFFER: ConstStaticCell<[u8; 1536]> = ConstStaticCell::new([0; 1536]);
+    let rx_buffer = RX_BUFFER.take();
+    let tx_buffer = TX_BUFFER.take();
+    let mut network = status::STATUS.receiver().unwrap();
+
+    loop {
+        stack.wait_config_up().await;
+
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer[..], &mut tx_buffer[..]);
+        socket.set_timeout(Some(Duration::from_secs(KEEP_ALIVE_SECS as u64 * 2)));
+
+        println!("mqtt: connecting to {:?}", BROKER);
+        match socket.connect(BROKER).await {
+            Ok(()) => {
+                // Stop the session as soon as the connection task reports
+                // that the station lost the access point, the socket would
+                // otherwise only notice after the keep alive timeout.
+                if let Either::Second(_) = select(
+                    run(&mut socket, rng),
+                    network.changed_and(|status| status.wifi != Wifi::Connected),
+                )
+                .await
+                {
+                    println!("mqtt: wifi link lost");
+                }
+            }
+            Err(e) => {
+                println!("mqtt: connect error: {:?}", e);
+                TCP_ERRORS.inc();
+            }
+        }
+
+        status::set_backend(false);
+        socket.abort();
+        Timer::after(RECONNECT_DELAY).await;
+    }
+}
+
+/// Run a session over the connected socket, through TLS if enabled.
+async fn run(socket: &mut TcpSocket<'_>, rng: Rng) {
+    if !TLS {
+        println!("mqtt: session ended: {:?}", session(socket).await);
+        return;
+    }
+
+    // Too large for the task arena, the buffers only live while connected.
+    let mut read_buffer = vec![0; tls::READ_BUFFER_LEN];
+    let mut write_buffer = vec![0; tls::WRITE_BUFFER_LEN];
+    match tls::connect(
+        socket,
+        SERVER_NAME,
+        tls::CA,
+        rng,
+        &mut read_buffer,
+        &mut write_buffer,
+    )
+    .await
+    {
+        Ok(mut connection) => {
+            println!("mqtt: session ended: {:?}", session(&mut connection).await)
+        }
+        Err(e) => println!("mqtt: TLS handshake failed: {:?}", e),
+    }
+}
+
+/// Run one broker connection until it fails.
+async fn session<T: Read + Write>(io: T) -> Result<(), Error<T::Error>> {
+    let mut tx = [0; 512];
+    let mut rx = [0; 512];
+
+    let mut client = Client::new(io, VERSION, &mut tx, &mut rx, |publish| {
+        match Message::new(publish.topic, publish.payload, publish.qos) {
+            Some(message) => {
+                if INBOX.try_send(message).is_err() {
+                    println!("mqtt: inbox full, dropping message");
+                }
+            }
+            None => println!("mqtt: message on {} too large", publish.topic),
+        }
+    });
+
+    let connect = Connect {
+        client_id: CLIENT_ID,
+        keep_alive: KEEP_ALIVE_SECS,
+        clean_session: true,
+        username: None,
+        password: None,
+        will: Some(Will {
+            topic: STATUS_TOPIC,
+            payload: b"offline",
+            qos: QoS::AtLeastOnce,
+            retain: true,
+        }),
+    };
+
+    client.connect(&connect).await?;
+    println!("mqtt: connected");
+    status::set_backend(true);
+
+    client
+        .publish(STATUS_TOPIC, b"online", QoS::AtLeastOnce, true)
+        .await?;
+    client.subscribe(&[(COMMAND_TOPIC, QoS::AtLeastOnce)]).await?;
+
+    let mut ticker = Ticker::every(TELEMETRY_INTERVAL);
+    loop {
+        // Not raced, a PUBACK or PINGREQ must not be cut short by a publish.
+        client.flush().await?;
+
+        match select3(client.poll(), OUTBOX.receive(), ticker.next()).await {
+            Either3::First(r) => r?,
+            Either3::Second(message) => {
+                client
+                    .publish(&message.topic, &message.payload, message.qos, false)
+                    .await?;
+                PUBLISHED.signal(());
+            }
+            Either3::Third(()) => {
+                let payload = telemetry();
+                client
+                    .publish(TELEMETRY_TOPIC, payload.as_bytes(), QoS::AtLeastOnce, false)
+                    .await?
+            }
+        }
+    }
+}
+
+/// Print commands received on [`COMMAND_TOPIC`].
+#[embassy_executor::task]
+pub async fn commands() {
+    loop {
+        let message = INBOX.receive().await;
+        match core::str::from_utf8(&message.payload) {
+            Ok(text) => println!("mqtt: {} <- {}", message.topic, text),
+            Err(_) => println!("mqtt: {} <- {:?}", message.topic, message.payload),
+        }
+    }
+}
@@ -0,0 +1,557 @@
+//! MQTT 3.1.1 / 5.0 packet encoding and decoding.
+//!
+//! Only the subset needed by a device client is implemented: QoS 0 and 1,
+//! no QoS 2 flows. MQTT 5 properties are written empty and skipped on read.
+
+/// Protocol level sent in CONNECT.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    V311,
+    V5,
+}
+
+impl ProtocolVersion {
+    const fn level(self) -> u8 {
+        match self {
+            ProtocolVersion::V311 => 4,
+            ProtocolVersion::V5 => 5,
+        }
+    }
+
+    const fn has_properties(self) -> bool {
+        matches!(self, ProtocolVersion::V5)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum QoS {
+    AtMostOnce = 0,
+    AtLeastOnce = 1,
+}
+
+impl QoS {
+    fn from_bits(bits: u8) -> Result<Self, Error> {
+        match bits {
+            0 => Ok(QoS::AtMostOnce),
+            1 => Ok(QoS::AtLeastOnce),
+            _ => Err(Error::UnsupportedQoS),
+        }
+    }
+}
+
+/// Errors
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The output buffer can't hold the packet
+    BufferTooSmall,
+    /// The input doesn't follow the specification
+    MalformedPacket,
+    /// QoS 2 is not supported
+    UnsupportedQoS,
+    /// Packet type we never expect to receive as a client
+    UnexpectedPacket(u8),
+}
+
+/// Last-will message published by the broker when the client disappears.
+#[derive(Debug, Clone)]
+pub struct Will<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub qos: QoS,
+    pub retain: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Connect<'a> {
+    pub client_id: &'a str,
+    /// Keep alive interval in seconds, 0 disables it.
+    pub keep_alive: u16,
+    pub clean_session: bool,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a [u8]>,
+    pub will: Option<Will<'a>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Publish<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub qos: QoS,
+    pub retain: bool,
+    pub dup: bool,
+    /// Only present for QoS 1.
+    pub packet_id: Option<u16>,
+}
+
+/// Packets a client receives from the broker.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Packet<'a> {
+    ConnAck { session_present: bool, code: u8 },
+    Publish(Publish<'a>),
+    PubAck { packet_id: u16 },
+    SubAck { packet_id: u16, granted: &'a [u8] },
+    UnsubAck { packet_id: u16 },
+    PingResp,
+    Disconnect { code: u8 },
+}
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const UNSUBSCRIBE: u8 = 10;
+const UNSUBACK: u8 = 11;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+/// Largest value the variable byte integer can represent.
+const MAX_REMAINING_LENGTH: usize = 268_435_455;
+
+/// Cursor over an output buffer.
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn u8(&mut self, value: u8) -> Result<(), Error> {
+        self.bytes(&[value])
+    }
+
+    fn u16(&mut self, value: u16) -> Result<(), Error> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    fn bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+        let end = self.pos + data.len();
+        if end > self.buf.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        self.buf[self.pos..end].copy_from_slice(data);
+        self.pos = end;
+        Ok(())
+    }
+
+    /// Length prefixed binary data or UTF-8 string.
+    fn binary(&mut self, data: &[u8]) -> Result<(), Error> {
+        let len = u16::try_from(data.len()).map_err(|_| Error::BufferTooSmall)?;
+        self.u16(len)?;
+        self.bytes(data)
+    }
+
+    fn varint(&mut self, mut value: usize) -> Result<(), Error> {
+        if value > MAX_REMAINING_LENGTH {
+            return Err(Error::BufferTooSmall);
+        }
+        loop {
+            let mut byte = (value % 128) as u8;
+            value /= 128;
+            if value > 0 {
+                byte |= 0x80;
+            }
+            self.u8(byte)?;
+            if value == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Empty MQTT 5 property list.
+    fn properties(&mut self, version: ProtocolVersion) -> Result<(), Error> {
+        if version.has_properties() {
+            self.u8(0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of bytes `value` takes as a variable byte integer.
+const fn varint_len(value: usize) -> usize {
+    match value {
+        0..=127 => 1,
+        128..=16_383 => 2,
+        16_384..=2_097_151 => 3,
+        _ => 4,
+    }
+}
+
+/// Write a fixed header followed by the body produced by `body`.
+///
+/// The body is first written at the largest possible offset and then moved
+/// down, so we don't need to compute its length twice.
+fn packet(
+    buf: &mut [u8],
+    header: u8,
+    body: impl FnOnce(&mut Writer) -> Result<(), Error>,
+) -> Result<usize, Error> {
+    const MAX_HEADER: usize = 5;
+    if buf.len() < MAX_HEADER {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let body_len = {
+        let mut w = Writer::new(&mut buf[MAX_HEADER..]);
+        body(&mut w)?;
+        w.pos
+    };
+
+    let header_len = 1 + varint_len(body_len);
+    buf.copy_within(MAX_HEADER..MAX_HEADER + body_len, header_len);
+
+    let mut w = Writer::new(&mut buf[..header_len]);
+    w.u8(header)?;
+    w.varint(body_len)?;
+
+    Ok(header_len + body_len)
+}
+
+/// Encode a CONNECT packet into `buf`, returning the number of bytes used.
+pub fn encode_connect(
+    buf: &mut [u8],
+    version: ProtocolVersion,
+    connect: &Connect,
+) -> Result<usize, Error> {
+    packet(buf, CONNECT << 4, |w| {
+        w.binary(b"MQTT")?;
+        w.u8(version.level())?;
+
+        let mut flags = 0;
+        if connect.username.is_some() {
+            flags |= 0x80;
+        }
+        if connect.password.is_some() {
+            flags |= 0x40;
+        }
+        if let Some(will) = &connect.will {
+            if will.retain {
+                flags |= 0x20;
+            }
+            flags |= (will.qos as u8) << 3;
+            flags |= 0x04;
+        }
+        if connect.clean_session {
+            flags |= 0x02;
+        }
+        w.u8(flags)?;
+        w.u16(connect.keep_alive)?;
+        w.properties(version)?;
+
+        w.binary(connect.client_id.as_bytes())?;
+        if let Some(will) = &connect.will {
+            w.properties(version)?;
+            w.binary(will.topic.as_bytes())?;
+            w.binary(will.payload)?;
+        }
+        if let Some(username) = connect.username {
+            w.binary(username.as_bytes())?;
+        }
+        if let Some(password) = connect.password {
+            w.binary(password)?;
+        }
+        Ok(())
+    })
+}
+
+pub fn encode_publish(
+    buf: &mut [u8],
+    version: ProtocolVersion,
+    publish: &Publish,
+) -> Result<usize, Error> {
+    let mut header = PUBLISH << 4 | (publish.qos as u8) << 1;
+    if publish.dup {
+        header |= 0x08;
+    }
+    if publish.retain {
+        header |= 0x01;
+    }
+
+    packet(buf, header, |w| {
+        w.binary(publish.topic.as_bytes())?;
+        match (publish.qos, publish.packet_id) {
+            (QoS::AtMostOnce, _) => {}
+            (QoS::AtLeastOnce, Some(id)) => w.u16(id)?,
+            (QoS::AtLeastOnce, None) => return Err(Error::MalformedPacket),
+        }
+        w.properties(version)?;
+        w.bytes(publish.payload)
+    })
+}
+
+pub fn encode_puback(buf: &mut [u8], packet_id: u16) -> Result<usize, Error> {
+    // A PUBACK without reason code means "success" in MQTT 5 as well.
+    packet(buf, PUBACK << 4, |w| w.u16(packet_id))
+}
+
+pub fn encode_subscribe(
+    buf: &mut [u8],
+    version: ProtocolVersion,
+    packet_id: u16,
+    topics: &[(&str, QoS)],
+) -> Result<usize, Error> {
+    if topics.is_empty() {
+        return Err(Error::MalformedPacket);
+    }
+
+    packet(buf, SUBSCRIBE << 4 | 0x02, |w| {
+        w.u16(packet_id)?;
+        w.properties(version)?;
+        for (topic, qos) in topics {
+            w.binary(topic.as_bytes())?;
+            w.u8(*qos as u8)?;
+        }
+        Ok(())
+    })
+}
+
+pub fn encode_unsubscribe(
+    buf: &mut [u8],
+    version: ProtocolVersion,
+    packet_id: u16,
+    topics: &[&str],
+) -> Result<usize, Error> {
+    if topics.is_empty() {
+        return Err(Error::MalformedPacket);
+    }
+
+    packet(buf, UNSUBSCRIBE << 4 | 0x02, |w| {
+        w.u16(packet_id)?;
+        w.properties(version)?;
+        for topic in topics {
+            w.binary(topic.as_bytes())?;
+        }
+        Ok(())
+    })
+}
+
+pub fn encode_pingreq(buf: &mut [u8]) -> Result<usize, Error> {
+    packet(buf, PINGREQ << 4, |_| Ok(()))
+}
+
+pub fn encode_disconnect(buf: &mut [u8]) -> Result<usize, Error> {
+    packet(buf, DISCONNECT << 4, |_| Ok(()))
+}
+
+/// Cursor over an input buffer.
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let end = self.pos + len;
+        if end > self.buf.len() {
+            return Err(Error::MalformedPacket);
+        }
+        let data = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(data)
+    }
+
+    fn str(&mut self) -> Result<&'a str, Error> {
+        let len = self.u16()? as usize;
+        core::str::from_utf8(self.bytes(len)?).map_err(|_| Error::MalformedPacket)
+    }
+
+    fn varint(&mut self) -> Result<usize, Error> {
+        let mut value = 0;
+        for i in 0..4 {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as usize) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::MalformedPacket)
+    }
+
+    fn skip_properties(&mut self, version: ProtocolVersion) -> Result<(), Error> {
+        if version.has_properties() {
+            let len = self.varint()?;
+            self.bytes(len)?;
+        }
+        Ok(())
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let data = &self.buf[self.pos..];
+        self.pos = self.buf.len();
+        data
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+}
+
+/// Parse the fixed header at the start of `buf`.
+///
+/// Returns the total packet length, or `None` if more bytes are needed.
+pub fn packet_len(buf: &[u8]) -> Result<Option<usize>, Error> {
+    let mut remaining = 0;
+    for i in 0..4 {
+        let Some(&byte) = buf.get(1 + i) else {
+            return Ok(None);
+        };
+        remaining |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some(2 + i + remaining));
+        }
+    }
+    Err(Error::MalformedPacket)
+}
+
+/// Decode one complete packet, as delimited by [`packet_len`].
+pub fn decode(buf: &[u8], version: ProtocolVersion) -> Result<Packet<'_>, Error> {
+    let mut r = Reader::new(buf);
+    let header = r.u8()?;
+    let len = r.varint()?;
+    let mut r = Reader::new(r.bytes(len)?);
+
+    let packet = match header >> 4 {
+        CONNACK => {
+            let flags = r.u8()?;
+            let code = r.u8()?;
+            r.skip_properties(version)?;
+            Packet::ConnAck {
+                session_present: flags & 0x01 != 0,
+                code,
+            }
+        }
+        PUBLISH => {
+            let qos = QoS::from_bits((header >> 1) & 0x03)?;
+            let topic = r.str()?;
+            let packet_id = match qos {
+                QoS::AtMostOnce => None,
+                QoS::AtLeastOnce => Some(r.u16()?),
+            };
+            r.skip_properties(version)?;
+            Packet::Publish(Publish {
+                topic,
+                payload: r.rest(),
+                qos,
+                retain: header & 0x01 != 0,
+                dup: header & 0x08 != 0,
+                packet_id,
+            })
+        }
+        PUBACK => {
+            let packet_id = r.u16()?;
+            // MQTT 5 may append a reason code and properties, a failure code
+            // is still an acknowledgement as far as retransmission goes.
+            r.rest();
+            Packet::PubAck { packet_id }
+        }
+        SUBACK => {
+            let packet_id = r.u16()?;
+            r.skip_properties(version)?;
+            Packet::SubAck {
+                packet_id,
+                granted: r.rest(),
+            }
+        }
+        UNSUBACK => {
+            let packet_id = r.u16()?;
+            r.rest();
+            Packet::UnsubAck { packet_id }
+        }
+        PINGRESP => Packet::PingResp,
+        DISCONNECT => {
+            let code = if r.is_empty() { 0 } else { r.u8()? };
+            r.rest();
+            Packet::Disconnect { code }
+        }
+        other => return Err(Error::UnexpectedPacket(other)),
+    };
+
+    if !r.is_empty() {
+        return Err(Error::MalformedPacket);
+    }
+
+    Ok(packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connect() {
+        let mut buf = [0; 64];
+        let connect = Connect {
+            client_id: "esp",
+            keep_alive: 60,
+            clean_session: true,
+            username: None,
+            password: None,
+            will: Some(Will {
+                topic: "t",
+                payload: b"x",
+                qos: QoS::AtLeastOnce,
+                retain: false,
+            }),
+        };
+        let n = encode_connect(&mut buf, ProtocolVersion::V311, &connect).unwrap();
+        assert_eq!(
+            &buf[..n],
+            &[
+                0x10, 21, 0, 4, b'M', b'Q', b'T', b'T', 4, 0x0e, 0, 60, 0, 3, b'e', b's', b'p', 0,
+                1, b't', 0, 1, b'x'
+            ]
+        );
+        assert_eq!(packet_len(&buf[..n]), Ok(Some(n)));
+    }
+
+    #[test]
+    fn test_publish_roundtrip() {
+        let mut buf = [0; 300];
+        let payload = [0xaa; 200];
+        let publish = Publish {
+            topic: "a/b",
+            payload: &payload,
+            qos: QoS::AtLeastOnce,
+            retain: true,
+            dup: false,
+            packet_id: Some(7),
+        };
+
+        for version in [ProtocolVersion::V311, ProtocolVersion::V5] {
+            let n = encode_publish(&mut buf, version, &publish).unwrap();
+            assert_eq!(packet_len(&buf[..2]), Ok(None));
+            assert_eq!(packet_len(&buf[..n]), Ok(Some(n)));
+            assert_eq!(decode(&buf[..n], version), Ok(Packet::Publish(publish.clone())));
+        }
+    }
+
+    #[test]
+    fn test_suback() {
+        let buf = [0x90, 4, 0, 1, 0x01, 0x80];
+        assert_eq!(
+            decode(&buf, ProtocolVersion::V311),
+            Ok(Packet::SubAck {
+                packet_id: 1,
+                granted: &[0x01, 0x80]
+            })
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! Network configuration from the stored [`Settings`]
+//!
+//! IPv4 comes from DHCP (announcing [`hostname`]) or a static address, IPv6
+//! from SLAAC or a static address. Either family can be turned off, so
+//! tasks wait with [`wait_for_address`] instead of asking for IPv4.
+
+use core::fmt::Write as _;
+
+use embassy_net::{
+    Config, ConfigV4, ConfigV6, DhcpConfig, Ipv4Cidr, Ipv6Cidr, Stack, StaticConfigV4,
+    StaticConfigV6,
+};
+use esp_hal::efuse::Efuse;
+use esp_println::println;
+
+use crate::settings::{self, Ipv4, Ipv6, Settings};
+
+/// Without a configured hostname, it is the prefix followed by the end of
+/// the MAC address, e.g. `esp-a1b2c3`.
+pub const HOSTNAME_PREFIX: &str = "esp";
+
+/// Hostname sent to the DHCP server and answered for with mDNS.
+pub fn hostname() -> heapless::String<32> {
+    let mut hostname = heapless::String::new();
+    match settings::get().hostname {
+        Some(configured) => {
+            hostname.push_str(&configured).ok();
+        }
+        None => {
+            let mac = Efuse::read_base_mac_address();
+            write!(
+                hostname,
+                "{}-{:02x}{:02x}{:02x}",
+                HOSTNAME_PREFIX, mac[3], mac[4], mac[5]
+            )
+            .ok();
+        }
+    }
+    hostname
+}
+
+/// The embassy-net configuration for `settings`.
+pub fn config(settings: &Settings) -> Config {
+    let ipv4 = match &settings.ipv4 {
+        Ipv4::Dhcp => {
+            let mut dhcp = DhcpConfig::default();
+            dhcp.hostname = Some(hostname());
+            ConfigV4::Dhcp(dhcp)
+        }
+        Ipv4::Static {
+            address,
+            prefix,
+            gateway,
+            dns,
+        } => ConfigV4::Static(StaticConfigV4 {
+            address: Ipv4Cidr::new(*address, *prefix),
+            gateway: *gateway,
+            dns_servers: dns.iter().copied().collect(),
+        }),
+        Ipv4::Disabled => ConfigV4::None,
+    };
+
+    let ipv6 = match &settings.ipv6 {
+        Ipv6::Disabled => ConfigV6::None,
+        Ipv6::Slaac => ConfigV6::Slaac,
+        Ipv6::Static {
+            address,
+            prefix,
+            gateway,
+            dns,
+        } => ConfigV6::Static(StaticConfigV6 {
+            address: Ipv6Cidr::new(*address, *prefix),
+            gateway: *gateway,
+            dns_servers: dns.iter().copied().collect(),
+        }),
+    };
+
+    let mut config = Config::default();
+    config.ipv4 = ipv4;
+    config.ipv6 = ipv6;
+    config
+}
+
+/// Wait for the link, then for an IPv4 or IPv6 address, whichever is
+/// configured and comes up first.
+pub async fn wait_for_address(stack: Stack<'_>) {
+    stack.wait_link_up().await;
+
+    println!("Waiting to get IP address...");
+    stack.wait_config_up().await;
+
+    if let Some(config) = stack.config_v4() {
+        println!("Got IP: {}", config.address);
+    }
+    if let Some(config) = stack.config_v6() {
+        println!("Got IPv6: {}", config.address);
+    }
+}
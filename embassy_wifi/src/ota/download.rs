@@ -0,0 +1,168 @@
+//! Parsing for image downloads over plain HTTP/1.0.
+
+use core::net::IpAddr;
+
+/// Not a valid HTTP/1.x response
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BadResponse;
+
+/// An `http://host[:port]/path` URL, with IPv6 addresses in brackets.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Url<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    pub path: &'a str,
+}
+
+impl<'a> Url<'a> {
+    pub fn parse(url: &'a str) -> Option<Self> {
+        let rest = url.strip_prefix("http://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            // Not one of the colons in an IPv6 address
+            Some((host, port)) if !port.contains(']') => (host, port.parse().ok()?),
+            _ => (authority, 80),
+        };
+        if host.is_empty() {
+            return None;
+        }
+        Some(Self { host, port, path })
+    }
+
+    /// The host if it's an IPv4 or bracketed IPv6 address.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self.host.strip_prefix('[') {
+            Some(host) => host.strip_suffix(']')?.parse().ok().map(IpAddr::V6),
+            None => self.host.parse().ok().map(IpAddr::V4),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ResponseHead {
+    pub status: u16,
+    pub content_length: Option<u32>,
+}
+
+impl ResponseHead {
+    /// Parse the status line and headers at the start of `buf`.
+    ///
+    /// Returns `None` if the header isn't complete yet, otherwise the
+    /// response head and its length.
+    pub fn parse(buf: &[u8]) -> Result<Option<(Self, usize)>, BadResponse> {
+        let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+            return Ok(None);
+        };
+        let head = core::str::from_utf8(&buf[..end]).map_err(|_| BadResponse)?;
+        let mut lines = head.split("\r\n");
+
+        let mut parts = lines.next().unwrap_or("").splitn(3, ' ');
+        let (Some(version), Some(status)) = (parts.next(), parts.next()) else {
+            return Err(BadResponse);
+        };
+        if !version.starts_with("HTTP/1.") {
+            return Err(BadResponse);
+        }
+        let status = status.parse().map_err(|_| BadResponse)?;
+
+        let mut content_length = None;
+        for line in lines {
+            let (name, value) = line.split_once(':').ok_or(BadResponse)?;
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                content_length = Some(value.trim().parse().map_err(|_| BadResponse)?);
+            }
+        }
+
+        let head = ResponseHead {
+            status,
+            content_length,
+        };
+        Ok(Some((head, end + 4)))
+    }
+}
+
+/// A SHA-256 digest in hex, as printed by `sha256sum`.
+pub fn parse_sha256(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut digest = [0; 32];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url() {
+        assert_eq!(
+            Url::parse("http://192.168.1.100:8000/firmware.bin"),
+            Some(Url {
+                host: "192.168.1.100",
+                port: 8000,
+                path: "/firmware.bin"
+            })
+        );
+        assert_eq!(
+            Url::parse("http://example.com"),
+            Some(Url {
+                host: "example.com",
+                port: 80,
+                path: "/"
+            })
+        );
+        assert_eq!(Url::parse("https://example.com/"), None);
+        assert_eq!(Url::parse("http://:80/"), None);
+
+        let url = Url::parse("http://[fd00::1]:8000/firmware.bin").unwrap();
+        assert_eq!((url.host, url.port), ("[fd00::1]", 8000));
+        assert_eq!(url.ip(), Some("fd00::1".parse().unwrap()));
+        let url = Url::parse("http://[fd00::1]/").unwrap();
+        assert_eq!((url.host, url.port), ("[fd00::1]", 80));
+        assert_eq!(
+            Url::parse("http://192.168.1.100/").unwrap().ip(),
+            Some("192.168.1.100".parse().unwrap())
+        );
+        assert_eq!(Url::parse("http://example.com/").unwrap().ip(), None);
+    }
+
+    #[test]
+    fn test_response_head() {
+        let buf = b"HTTP/1.0 200 OK\r\nContent-Type: application/octet-stream\r\ncontent-length: 1024\r\n\r\n\xe9";
+        assert_eq!(ResponseHead::parse(&buf[..20]), Ok(None));
+
+        let (head, len) = ResponseHead::parse(buf).unwrap().unwrap();
+        assert_eq!(head.status, 200);
+        assert_eq!(head.content_length, Some(1024));
+        assert_eq!(&buf[len..], b"\xe9");
+
+        let (head, _) = ResponseHead::parse(b"HTTP/1.1 404 Not Found\r\n\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(head.status, 404);
+        assert_eq!(head.content_length, None);
+
+        assert_eq!(ResponseHead::parse(b"SSH-2.0\r\n\r\n"), Err(BadResponse));
+    }
+
+    #[test]
+    fn test_sha256() {
+        let digest =
+            parse_sha256("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+                .unwrap();
+        assert_eq!(digest[0], 0xe3);
+        assert_eq!(digest[31], 0x55);
+        assert_eq!(parse_sha256("e3b0"), None);
+        assert_eq!(
+            parse_sha256("g3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+            None
+        );
+    }
+}
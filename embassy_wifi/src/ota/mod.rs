@@ -0,0 +1,328 @@
+//! Over-the-air firmware updates
+//!
+//! [`start`] hands a download to the `ota` task, which writes the image to
+//! the inactive `ota_n` partition, checks its size and SHA-256, selects it in
+//! `otadata` and restarts. A new image has [`MAX_BOOTS`] boots to get an IP
+//! address and confirm itself, otherwise the previous one is restored. A
+//! boot that doesn't confirm within [`CONFIRM_TIMEOUT`] restarts, so it
+//! counts.
+//!
+//! `partition`, `otadata`, `updater` and `download` only depend on
+//! `embedded-storage` and `sha2`, so they can be tested on the host.
+
+use alloc::string::{String, ToString};
+use alloc::vec;
+use core::cell::RefCell;
+use core::fmt::Write as _;
+use core::net::IpAddr;
+
+use embassy_net::{IpAddress, Stack, dns::DnsQueryType, tcp::TcpSocket};
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Timer, with_timeout};
+use esp_println::println;
+use esp_storage::{FlashStorage, FlashStorageError};
+use serde::Serialize;
+
+use crate::io;
+
+pub mod download;
+pub mod otadata;
+pub mod partition;
+pub mod updater;
+
+pub use download::{ResponseHead, Url};
+pub use updater::{BootState, Update, Updater};
+
+/// Unconfirmed boots before rolling back.
+pub const MAX_BOOTS: u32 = 3;
+
+/// How long a new image has to stay up with an IP address before it counts
+/// as working.
+const CONFIRM_DELAY: Duration = Duration::from_secs(10);
+
+/// How long a new image may take to confirm itself before restarting.
+pub const CONFIRM_TIMEOUT: Duration = Duration::from_secs(120);
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `esp_chip_id_t` of the chip we are built for.
+#[cfg(feature = "esp32c3")]
+pub const CHIP_ID: u16 = 0x0005;
+#[cfg(feature = "esp32c6")]
+pub const CHIP_ID: u16 = 0x000d;
+
+pub const MAX_URL_LEN: usize = 128;
+
+/// Errors
+#[derive(Debug)]
+pub enum Error {
+    InvalidUrl,
+    /// Another update is in progress
+    Busy,
+    /// The partition table has no OTA partitions, or the running image
+    /// isn't confirmed yet
+    Unavailable,
+    Dns,
+    Connect(embassy_net::tcp::ConnectError),
+    Io(embassy_net::tcp::Error),
+    /// The server stopped taking the request
+    Timeout,
+    /// The server closed the connection while we sent the request
+    Closed,
+    BadResponse,
+    /// The server answered with another status than 200
+    Status(u16),
+    /// The response has no `Content-Length`
+    UnknownSize,
+    /// The connection closed before the image was complete
+    Incomplete,
+    Update(updater::Error<FlashStorageError>),
+}
+
+impl From<io::Error<embassy_net::tcp::Error>> for Error {
+    fn from(e: io::Error<embassy_net::tcp::Error>) -> Self {
+        match e {
+            io::Error::Io(e) => Error::Io(e),
+            io::Error::Timeout => Error::Timeout,
+            io::Error::WriteZero | io::Error::UnexpectedEof => Error::Closed,
+            io::Error::BufferFull => Error::BadResponse,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Idle,
+    Downloading,
+    /// The new image is selected, about to restart
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    /// Label of the partition we booted from
+    pub running: Option<String>,
+    pub state: State,
+    pub received: u32,
+    pub size: u32,
+    pub error: Option<String>,
+}
+
+static STATUS: Mutex<CriticalSectionRawMutex, RefCell<Status>> = Mutex::new(RefCell::new(Status {
+    running: None,
+    state: State::Idle,
+    received: 0,
+    size: 0,
+    error: None,
+}));
+
+struct Request {
+    url: heapless::String<MAX_URL_LEN>,
+    sha256: [u8; 32],
+}
+
+static REQUEST: Signal<CriticalSectionRawMutex, Request> = Signal::new();
+
+pub fn status() -> Status {
+    STATUS.lock(|status| status.borrow().clone())
+}
+
+fn update_status(f: impl FnOnce(&mut Status)) {
+    STATUS.lock(|status| f(&mut status.borrow_mut()))
+}
+
+/// Download and install the image at `url`, an `http://` URL.
+pub fn start(url: &str, sha256: [u8; 32]) -> Result<(), Error> {
+    if Url::parse(url).is_none() {
+        return Err(Error::InvalidUrl);
+    }
+    let url = heapless::String::try_from(url).map_err(|_| Error::InvalidUrl)?;
+
+    STATUS.lock(|status| {
+        let mut status = status.borrow_mut();
+        if status.running.is_none() {
+            return Err(Error::Unavailable);
+        }
+        if matches!(status.state, State::Downloading | State::Done) {
+            return Err(Error::Busy);
+        }
+        status.state = State::Downloading;
+        status.received = 0;
+        status.size = 0;
+        status.error = None;
+        Ok(())
+    })?;
+
+    REQUEST.signal(Request { url, sha256 });
+    Ok(())
+}
+
+#[embassy_executor::task]
+pub async fn ota(stack: Stack<'static>) {
+    let mut updater = match Updater::new(FlashStorage::new(), CHIP_ID) {
+        Ok(updater) => updater,
+        Err(e) => {
+            println!("ota: disabled: {:?}", e);
+            return;
+        }
+    };
+    println!("ota: running from {}", updater.running().label());
+
+    match updater.boot(MAX_BOOTS) {
+        Ok(BootState::Confirmed) => {}
+        Ok(BootState::Unconfirmed { boots }) => {
+            println!("ota: unconfirmed image, boot {} of {}", boots, MAX_BOOTS);
+            let up = with_timeout(CONFIRM_TIMEOUT, async {
+                stack.wait_config_up().await;
+                Timer::after(CONFIRM_DELAY).await;
+            })
+            .await;
+            if up.is_err() {
+                // Without a restart the boot counter never reaches
+                // MAX_BOOTS and the image is never rolled back.
+                println!("ota: no network within {:?}, restarting", CONFIRM_TIMEOUT);
+                esp_hal::system::software_reset();
+            }
+            match updater.confirm() {
+                Ok(()) => println!("ota: image confirmed"),
+                Err(e) => println!("ota: confirm error: {:?}", e),
+            }
+        }
+        Ok(BootState::RolledBack) => {
+            println!(
+                "ota: image not confirmed after {} boots, rolling back",
+                MAX_BOOTS
+            );
+            esp_hal::system::software_reset();
+        }
+        Err(e) => println!("ota: otadata error: {:?}", e),
+    }
+
+    let running = updater.running().label().to_string();
+    update_status(|status| status.running = Some(running));
+
+    loop {
+        let request = REQUEST.wait().await;
+        println!("ota: downloading {}", request.url);
+
+        match download(stack, &mut updater, &request).await {
+            Ok(()) => {
+                println!("ota: restarting into {}", updater.next().label());
+                update_status(|status| status.state = State::Done);
+                // Give the HTTP server a chance to report it.
+                Timer::after(Duration::from_secs(1)).await;
+                esp_hal::system::software_reset();
+            }
+            Err(e) => {
+                println!("ota: update failed: {:?}", e);
+                let error = alloc::format!("{:?}", e);
+                update_status(|status| {
+                    status.state = State::Failed;
+                    status.error = Some(error);
+                });
+            }
+        }
+    }
+}
+
+async fn download(
+    stack: Stack<'static>,
+    updater: &mut Updater<FlashStorage>,
+    request: &Request,
+) -> Result<(), Error> {
+    let url = Url::parse(&request.url).ok_or(Error::InvalidUrl)?;
+    let address = match url.ip() {
+        Some(IpAddr::V4(address)) => IpAddress::Ipv4(address),
+        Some(IpAddr::V6(address)) => IpAddress::Ipv6(address),
+        None => {
+            // Without an IPv4 address, e.g. on an IPv6-only network, try AAAA.
+            let addrs = match stack.dns_query(url.host, DnsQueryType::A).await {
+                Ok(addrs) if !addrs.is_empty() => addrs,
+                _ => stack
+                    .dns_query(url.host, DnsQueryType::Aaaa)
+                    .await
+                    .map_err(|_| Error::Dns)?,
+            };
+            *addrs.first().ok_or(Error::Dns)?
+        }
+    };
+
+    // Too large for the task arena, the buffers only live while downloading.
+    let mut rx_buffer = vec![0; 2048];
+    let mut tx_buffer = vec![0; 512];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    socket.set_timeout(Some(TIMEOUT));
+    socket
+        .connect((address, url.port))
+        .await
+        .map_err(Error::Connect)?;
+
+    let result = receive(&mut socket, updater, &url, &request.sha256).await;
+    socket.abort();
+    result
+}
+
+/// Request the image and write it to flash.
+async fn receive(
+    socket: &mut TcpSocket<'_>,
+    updater: &mut Updater<FlashStorage>,
+    url: &Url<'_>,
+    sha256: &[u8; 32],
+) -> Result<(), Error> {
+    let mut head = heapless::String::<{ MAX_URL_LEN + 64 }>::new();
+    write!(
+        head,
+        "GET {} HTTP/1.0\r\nHost: {}\r\n\r\n",
+        url.path, url.host
+    )
+    .map_err(|_| Error::InvalidUrl)?;
+    let mut io = io::Timeout::new(&mut *socket, TIMEOUT);
+    io::write_all(&mut io, head.as_bytes())
+        .await
+        .map_err(io::Error::flatten)?;
+
+    let mut buf = [0; 1024];
+    let mut len = 0;
+    let (response, header_len) = loop {
+        if let Some(response) = ResponseHead::parse(&buf[..len]).map_err(|_| Error::BadResponse)? {
+            break response;
+        }
+        if len == buf.len() {
+            return Err(Error::BadResponse);
+        }
+        match socket.read(&mut buf[len..]).await.map_err(Error::Io)? {
+            0 => return Err(Error::BadResponse),
+            n => len += n,
+        }
+    };
+
+    if response.status != 200 {
+        return Err(Error::Status(response.status));
+    }
+    let size = response.content_length.ok_or(Error::UnknownSize)?;
+    update_status(|status| status.size = size);
+
+    let mut update = updater.begin(size).map_err(Error::Update)?;
+    println!(
+        "ota: writing {} bytes to {}",
+        size,
+        update.partition().label()
+    );
+
+    update.write(&buf[header_len..len]).map_err(Error::Update)?;
+    while update.received() < size {
+        match socket.read(&mut buf).await.map_err(Error::Io)? {
+            0 => return Err(Error::Incomplete),
+            n => update.write(&buf[..n]).map_err(Error::Update)?,
+        }
+        let received = update.received();
+        update_status(|status| status.received = received);
+    }
+
+    update.finish(sha256).map_err(Error::Update)
+}
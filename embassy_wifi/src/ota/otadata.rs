@@ -0,0 +1,262 @@
+//! The `otadata` partition, which tells the bootloader which `ota_n`
+//! partition to boot.
+//!
+//! It has two sectors with one entry each. The bootloader picks the valid
+//! entry with the highest sequence number and boots `ota_{(seq - 1) % n}`.
+//! Updates always rewrite the other sector, so a power loss leaves the
+//! previous entry intact.
+
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+pub const SECTOR_LEN: u32 = 0x1000;
+pub const ENTRY_LEN: usize = 32;
+
+/// `esp_ota_img_states_t`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum State {
+    /// Never booted
+    New,
+    /// Booted, waiting to be confirmed
+    PendingVerify,
+    Valid,
+    Invalid,
+    Aborted,
+    Undefined,
+}
+
+impl State {
+    fn from_u32(value: u32) -> Self {
+        match value {
+            0 => State::New,
+            1 => State::PendingVerify,
+            2 => State::Valid,
+            3 => State::Invalid,
+            4 => State::Aborted,
+            _ => State::Undefined,
+        }
+    }
+
+    fn to_u32(self) -> u32 {
+        match self {
+            State::New => 0,
+            State::PendingVerify => 1,
+            State::Valid => 2,
+            State::Invalid => 3,
+            State::Aborted => 4,
+            State::Undefined => u32::MAX,
+        }
+    }
+}
+
+/// `esp_ota_select_entry_t`
+///
+/// The bootloader ignores `label`. We count unconfirmed boots in it by
+/// clearing one bit per boot, which flash allows without erasing.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub seq: u32,
+    pub label: [u8; 20],
+    pub state: State,
+    pub crc: u32,
+}
+
+impl Entry {
+    pub fn new(seq: u32) -> Self {
+        Self {
+            seq,
+            label: [0xff; 20],
+            state: State::New,
+            crc: crc32(&seq.to_le_bytes()),
+        }
+    }
+
+    pub fn parse(buf: &[u8; ENTRY_LEN]) -> Self {
+        let word = |i: usize| u32::from_le_bytes(buf[i..i + 4].try_into().unwrap());
+        Self {
+            seq: word(0),
+            label: buf[4..24].try_into().unwrap(),
+            state: State::from_u32(word(24)),
+            crc: word(28),
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; ENTRY_LEN] {
+        let mut buf = [0; ENTRY_LEN];
+        buf[0..4].copy_from_slice(&self.seq.to_le_bytes());
+        buf[4..24].copy_from_slice(&self.label);
+        buf[24..28].copy_from_slice(&self.state.to_u32().to_le_bytes());
+        buf[28..32].copy_from_slice(&self.crc.to_le_bytes());
+        buf
+    }
+
+    /// Whether the bootloader would consider this entry. Sequence numbers
+    /// start at 1, an entry with 0 doesn't select any app.
+    pub fn is_valid(&self) -> bool {
+        self.seq != 0
+            && self.seq != u32::MAX
+            && self.crc == crc32(&self.seq.to_le_bytes())
+            && !matches!(self.state, State::Invalid | State::Aborted)
+    }
+
+    /// Whether the application still has to confirm itself.
+    pub fn is_pending(&self) -> bool {
+        matches!(self.state, State::New | State::PendingVerify)
+    }
+
+    /// Unconfirmed boots counted so far.
+    pub fn boots(&self) -> u32 {
+        self.label.iter().map(|b| b.count_zeros()).sum()
+    }
+
+    /// Count one more boot, returns `false` once the label is used up.
+    pub fn count_boot(&mut self) -> bool {
+        match self.label.iter_mut().find(|b| **b != 0) {
+            Some(b) => {
+                // Clear the lowest set bit.
+                *b &= *b - 1;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Otadata {
+    /// Offset of the partition
+    pub offset: u32,
+    pub entries: [Entry; 2],
+}
+
+impl Otadata {
+    pub fn read<F: ReadNorFlash>(flash: &mut F, offset: u32) -> Result<Self, F::Error> {
+        let mut entries = [Entry::new(u32::MAX); 2];
+        for (i, entry) in entries.iter_mut().enumerate() {
+            let mut buf = [0; ENTRY_LEN];
+            flash.read(offset + i as u32 * SECTOR_LEN, &mut buf)?;
+            *entry = Entry::parse(&buf);
+        }
+        Ok(Self { offset, entries })
+    }
+
+    /// Index of the entry the bootloader uses.
+    pub fn active(&self) -> Option<usize> {
+        (0..2)
+            .filter(|&i| self.entries[i].is_valid())
+            .max_by_key(|&i| self.entries[i].seq)
+    }
+
+    /// The `ota_n` partition the bootloader boots, `None` if it falls back
+    /// to the factory app (or `ota_0` if there is none).
+    pub fn boot_index(&self, ota_count: u8) -> Option<u8> {
+        let entry = &self.entries[self.active()?];
+        Some(((entry.seq - 1) % ota_count as u32) as u8)
+    }
+
+    /// Select `ota_{index}` for the next boot.
+    pub fn set_boot<F: NorFlash>(
+        &mut self,
+        flash: &mut F,
+        index: u8,
+        ota_count: u8,
+    ) -> Result<(), F::Error> {
+        let (slot, seq) = match self.active() {
+            Some(active) => (1 - active, self.entries[active].seq),
+            None => (0, 0),
+        };
+        // The next sequence number that maps to `index`.
+        let n = ota_count as u32;
+        let seq = seq + 1 + (index as u32 + n - seq % n) % n;
+        self.write(flash, slot, Entry::new(seq))
+    }
+
+    /// Erase the sector of entry `slot` and write `entry` to it.
+    pub fn write<F: NorFlash>(
+        &mut self,
+        flash: &mut F,
+        slot: usize,
+        entry: Entry,
+    ) -> Result<(), F::Error> {
+        let from = self.offset + slot as u32 * SECTOR_LEN;
+        flash.erase(from, from + SECTOR_LEN)?;
+        flash.write(from, &entry.to_bytes())?;
+        self.entries[slot] = entry;
+        Ok(())
+    }
+
+    /// Write `entry` over entry `slot` without erasing, only valid if it
+    /// just clears bits.
+    pub fn overwrite<F: NorFlash>(
+        &mut self,
+        flash: &mut F,
+        slot: usize,
+        entry: Entry,
+    ) -> Result<(), F::Error> {
+        flash.write(self.offset + slot as u32 * SECTOR_LEN, &entry.to_bytes())?;
+        self.entries[slot] = entry;
+        Ok(())
+    }
+}
+
+/// `esp_rom_crc32_le(UINT32_MAX, ..)`, which starts from 0 rather than the
+/// usual `0xffffffff`.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry() {
+        // Written by `esp_ota_set_boot_partition` for ota_0.
+        let mut buf = [0xff; ENTRY_LEN];
+        buf[0..4].copy_from_slice(&[0x01, 0x00, 0x00, 0x00]);
+        buf[24..28].copy_from_slice(&[0x00; 4]);
+        buf[28..32].copy_from_slice(&[0x9a, 0x98, 0x43, 0x47]);
+
+        let entry = Entry::parse(&buf);
+        assert_eq!(entry, Entry::new(1));
+        assert!(entry.is_valid());
+        assert_eq!(entry.to_bytes(), buf);
+
+        let mut entry = Entry::new(2);
+        assert_eq!(entry.boots(), 0);
+        assert!(entry.count_boot());
+        assert!(entry.count_boot());
+        assert_eq!(entry.boots(), 2);
+        assert_eq!(entry.label[0], 0b1111_1100);
+
+        entry.crc ^= 1;
+        assert!(!entry.is_valid());
+        assert!(!Entry::parse(&[0xff; ENTRY_LEN]).is_valid());
+    }
+
+    #[test]
+    fn test_boot_index() {
+        let mut otadata = Otadata {
+            offset: 0,
+            entries: [Entry::new(0), Entry::new(u32::MAX)],
+        };
+        // A correct CRC doesn't make sequence number 0 valid.
+        assert!(!otadata.entries[0].is_valid());
+        assert_eq!(otadata.active(), None);
+        assert_eq!(otadata.boot_index(2), None);
+
+        otadata.entries[1] = Entry::new(4);
+        assert_eq!(otadata.active(), Some(1));
+        assert_eq!(otadata.boot_index(2), Some(1));
+    }
+}
@@ -0,0 +1,407 @@
+//! Writing images to the inactive `ota_n` partition and switching to them.
+//!
+//! Only depends on `embedded-storage` and `sha2`, so it can be tested on
+//! the host against an in-memory flash.
+
+use embedded_storage::nor_flash::NorFlash;
+use sha2::{Digest, Sha256};
+
+use super::otadata::{Otadata, State};
+use super::partition::{Partition, PartitionTable};
+
+/// First byte of an application image, `ESP_IMAGE_HEADER_MAGIC`.
+const IMAGE_MAGIC: u8 = 0xe9;
+/// Offset of the chip ID in `esp_image_header_t`.
+const CHIP_ID_OFFSET: usize = 12;
+
+/// Data is written in chunks of this size, a multiple of the flash
+/// `WRITE_SIZE`.
+const CHUNK_LEN: usize = 256;
+
+/// Errors
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error<E> {
+    Flash(E),
+    /// The partition table needs `otadata` and at least two `ota_n`
+    /// partitions
+    NoOtaPartitions,
+    /// The image doesn't fit into the partition
+    TooLarge,
+    /// More or less data than announced
+    SizeMismatch,
+    /// Not an application image for this chip
+    InvalidImage,
+    ChecksumMismatch,
+}
+
+/// What [`Updater::boot`] found out about the running image.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BootState {
+    /// Confirmed, or flashed over USB
+    Confirmed,
+    /// Not confirmed yet, this is boot number `boots`
+    Unconfirmed { boots: u32 },
+    /// Not confirmed within the allowed number of boots. The previous image
+    /// is selected, the device should restart.
+    RolledBack,
+}
+
+pub struct Updater<F> {
+    flash: F,
+    table: PartitionTable,
+    otadata: Otadata,
+    ota_count: u8,
+    /// Doesn't change until the device restarts
+    running: Partition,
+    chip_id: u16,
+}
+
+impl<F: NorFlash> Updater<F> {
+    /// Read the partition table and `otadata`. Images must be built for
+    /// `chip_id`, see `esp_chip_id_t`.
+    pub fn new(mut flash: F, chip_id: u16) -> Result<Self, Error<F::Error>> {
+        let table = PartitionTable::read(&mut flash).map_err(Error::Flash)?;
+        let ota_count = table.ota_count();
+        let otadata = match table.otadata() {
+            Some(otadata) if ota_count >= 2 => {
+                Otadata::read(&mut flash, otadata.offset).map_err(Error::Flash)?
+            }
+            _ => return Err(Error::NoOtaPartitions),
+        };
+
+        let running = match otadata.boot_index(ota_count) {
+            Some(index) => table.ota(index),
+            None => table.factory().or(table.ota(0)),
+        };
+
+        Ok(Self {
+            running: *running.unwrap(),
+            flash,
+            table,
+            otadata,
+            ota_count,
+            chip_id,
+        })
+    }
+
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// The partition the bootloader started.
+    pub fn running(&self) -> &Partition {
+        &self.running
+    }
+
+    /// The partition the next update goes to.
+    pub fn next(&self) -> &Partition {
+        let index = match self.running().ota_index() {
+            Some(index) => (index + 1) % self.ota_count,
+            None => 0,
+        };
+        self.table.ota(index).unwrap()
+    }
+
+    /// Count this boot if the running image isn't confirmed yet, and roll
+    /// back once it was started more than `max_boots` times.
+    pub fn boot(&mut self, max_boots: u32) -> Result<BootState, Error<F::Error>> {
+        let Some(slot) = self.otadata.active() else {
+            return Ok(BootState::Confirmed);
+        };
+        let mut entry = self.otadata.entries[slot];
+        if !entry.is_pending() {
+            return Ok(BootState::Confirmed);
+        }
+
+        if entry.boots() >= max_boots || !entry.count_boot() {
+            // The bootloader skips invalid entries and takes the other one,
+            // or the factory app if there is none.
+            entry.state = State::Invalid;
+            self.otadata
+                .write(&mut self.flash, slot, entry)
+                .map_err(Error::Flash)?;
+            return Ok(BootState::RolledBack);
+        }
+
+        self.otadata
+            .overwrite(&mut self.flash, slot, entry)
+            .map_err(Error::Flash)?;
+        Ok(BootState::Unconfirmed {
+            boots: entry.boots(),
+        })
+    }
+
+    /// Mark the running image as good, it won't be rolled back anymore.
+    pub fn confirm(&mut self) -> Result<(), Error<F::Error>> {
+        if let Some(slot) = self.otadata.active() {
+            let mut entry = self.otadata.entries[slot];
+            if entry.is_pending() {
+                entry.state = State::Valid;
+                self.otadata
+                    .write(&mut self.flash, slot, entry)
+                    .map_err(Error::Flash)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Start writing an image of `size` bytes to [`Self::next`].
+    pub fn begin(&mut self, size: u32) -> Result<Update<'_, F>, Error<F::Error>> {
+        let partition = *self.next();
+        if size > partition.size {
+            return Err(Error::TooLarge);
+        }
+        debug_assert_eq!(CHUNK_LEN % F::WRITE_SIZE, 0);
+
+        Ok(Update {
+            updater: self,
+            partition,
+            size,
+            written: 0,
+            erased: 0,
+            chunk: [0; CHUNK_LEN],
+            chunk_len: 0,
+        })
+    }
+}
+
+/// An image being written, see [`Updater::begin`].
+pub struct Update<'a, F> {
+    updater: &'a mut Updater<F>,
+    partition: Partition,
+    size: u32,
+    /// Bytes written to flash, not counting `chunk`
+    written: u32,
+    /// Bytes erased at the start of the partition
+    erased: u32,
+    chunk: [u8; CHUNK_LEN],
+    chunk_len: usize,
+}
+
+impl<F: NorFlash> Update<'_, F> {
+    pub fn partition(&self) -> &Partition {
+        &self.partition
+    }
+
+    /// Bytes received so far.
+    pub fn received(&self) -> u32 {
+        self.written + self.chunk_len as u32
+    }
+
+    pub fn write(&mut self, mut data: &[u8]) -> Result<(), Error<F::Error>> {
+        if self.received() as usize + data.len() > self.size as usize {
+            return Err(Error::SizeMismatch);
+        }
+
+        while !data.is_empty() {
+            let n = data.len().min(CHUNK_LEN - self.chunk_len);
+            self.chunk[self.chunk_len..self.chunk_len + n].copy_from_slice(&data[..n]);
+            self.chunk_len += n;
+            data = &data[n..];
+
+            if self.chunk_len == CHUNK_LEN {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the buffered chunk, erasing sectors as we go rather than all
+    /// at once, which would block for seconds.
+    fn flush(&mut self) -> Result<(), Error<F::Error>> {
+        let len = self.chunk_len.next_multiple_of(F::WRITE_SIZE);
+        self.chunk[self.chunk_len..len].fill(0xff);
+
+        let flash = &mut self.updater.flash;
+        let offset = self.partition.offset;
+        while self.erased < self.written + len as u32 {
+            let sector = F::ERASE_SIZE as u32;
+            flash
+                .erase(offset + self.erased, offset + self.erased + sector)
+                .map_err(Error::Flash)?;
+            self.erased += sector;
+        }
+        flash
+            .write(offset + self.written, &self.chunk[..len])
+            .map_err(Error::Flash)?;
+
+        self.written += self.chunk_len as u32;
+        self.chunk_len = 0;
+        Ok(())
+    }
+
+    /// Check the image as it ended up in flash against `sha256` and select
+    /// it for the next boot.
+    pub fn finish(mut self, sha256: &[u8; 32]) -> Result<(), Error<F::Error>> {
+        self.flush()?;
+        if self.written != self.size {
+            return Err(Error::SizeMismatch);
+        }
+
+        let flash = &mut self.updater.flash;
+        let mut hasher = Sha256::new();
+        let mut header = [0; 16];
+        let mut buf = [0; CHUNK_LEN];
+        let mut offset = 0;
+        while offset < self.size {
+            let n = (self.size - offset).min(CHUNK_LEN as u32) as usize;
+            // Reads must be aligned like writes.
+            let aligned = n.next_multiple_of(F::READ_SIZE);
+            flash
+                .read(self.partition.offset + offset, &mut buf[..aligned])
+                .map_err(Error::Flash)?;
+            if offset == 0 {
+                let len = header.len().min(n);
+                header[..len].copy_from_slice(&buf[..len]);
+            }
+            hasher.update(&buf[..n]);
+            offset += n as u32;
+        }
+
+        let chip_id = u16::from_le_bytes([header[CHIP_ID_OFFSET], header[CHIP_ID_OFFSET + 1]]);
+        if header[0] != IMAGE_MAGIC || chip_id != self.updater.chip_id {
+            return Err(Error::InvalidImage);
+        }
+        if hasher.finalize()[..] != sha256[..] {
+            return Err(Error::ChecksumMismatch);
+        }
+
+        let updater = self.updater;
+        updater
+            .otadata
+            .set_boot(
+                &mut updater.flash,
+                self.partition.ota_index().unwrap(),
+                updater.ota_count,
+            )
+            .map_err(Error::Flash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::super::partition::TABLE_OFFSET;
+    use super::*;
+    use crate::mem_flash::{MemError, MemFlash, flash};
+
+    const CHIP_ID: u16 = 13;
+
+    fn make_image(len: usize, seed: u8) -> (Vec<u8>, [u8; 32]) {
+        let mut image: Vec<u8> = (0..len).map(|i| (i as u8).wrapping_mul(seed)).collect();
+        image[0] = IMAGE_MAGIC;
+        image[CHIP_ID_OFFSET..CHIP_ID_OFFSET + 2].copy_from_slice(&CHIP_ID.to_le_bytes());
+        let sha256 = Sha256::digest(&image).into();
+        (image, sha256)
+    }
+
+    /// Write `image` in odd sized pieces, like they come from the network.
+    fn write_image(
+        updater: &mut Updater<MemFlash>,
+        image: &[u8],
+        sha256: &[u8; 32],
+    ) -> Result<(), Error<MemError>> {
+        let mut update = updater.begin(image.len() as u32)?;
+        for piece in image.chunks(1000) {
+            update.write(piece)?;
+        }
+        update.finish(sha256)
+    }
+
+    /// Restart the device.
+    fn reboot(updater: Updater<MemFlash>) -> Updater<MemFlash> {
+        Updater::new(updater.into_inner(), CHIP_ID).unwrap()
+    }
+
+    #[test]
+    fn test_update() {
+        let mut updater = Updater::new(flash(), CHIP_ID).unwrap();
+        assert_eq!(updater.running().label(), "ota_0");
+        assert_eq!(updater.next().label(), "ota_1");
+        assert_eq!(updater.boot(3), Ok(BootState::Confirmed));
+
+        let (image, sha256) = make_image(10_007, 3);
+        write_image(&mut updater, &image, &sha256).unwrap();
+        let offset = updater.next().offset as usize;
+        assert_eq!(&updater.flash.0[offset..offset + image.len()], &image[..]);
+
+        let mut updater = reboot(updater);
+        assert_eq!(updater.running().label(), "ota_1");
+        assert_eq!(updater.boot(3), Ok(BootState::Unconfirmed { boots: 1 }));
+        updater.confirm().unwrap();
+
+        let mut updater = reboot(updater);
+        assert_eq!(updater.boot(3), Ok(BootState::Confirmed));
+        assert_eq!(updater.next().label(), "ota_0");
+
+        // Back and forth.
+        let (image, sha256) = make_image(5_000, 7);
+        write_image(&mut updater, &image, &sha256).unwrap();
+        let updater = reboot(updater);
+        assert_eq!(updater.running().label(), "ota_0");
+        assert_eq!(updater.otadata.entries.map(|e| e.seq), [2, 3]);
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut updater = Updater::new(flash(), CHIP_ID).unwrap();
+        let (image, sha256) = make_image(4096, 5);
+        write_image(&mut updater, &image, &sha256).unwrap();
+
+        for boots in 1..=3 {
+            updater = reboot(updater);
+            assert_eq!(updater.running().label(), "ota_1");
+            assert_eq!(updater.boot(3), Ok(BootState::Unconfirmed { boots }));
+        }
+
+        updater = reboot(updater);
+        assert_eq!(updater.boot(3), Ok(BootState::RolledBack));
+
+        let mut updater = reboot(updater);
+        assert_eq!(updater.running().label(), "ota_0");
+        assert_eq!(updater.boot(3), Ok(BootState::Confirmed));
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut updater = Updater::new(flash(), CHIP_ID).unwrap();
+        let (mut image, sha256) = make_image(3000, 9);
+
+        assert_eq!(updater.begin(0x1c0001).err(), Some(Error::TooLarge));
+
+        let mut update = updater.begin(2000).unwrap();
+        assert_eq!(update.write(&image), Err(Error::SizeMismatch));
+
+        let mut update = updater.begin(4000).unwrap();
+        update.write(&image).unwrap();
+        assert_eq!(update.finish(&sha256), Err(Error::SizeMismatch));
+
+        assert_eq!(
+            write_image(&mut updater, &image, &[0; 32]),
+            Err(Error::ChecksumMismatch)
+        );
+
+        image[CHIP_ID_OFFSET] = 5;
+        let sha256 = Sha256::digest(&image).into();
+        assert_eq!(
+            write_image(&mut updater, &image, &sha256),
+            Err(Error::InvalidImage)
+        );
+
+        // Nothing was switched.
+        let updater = reboot(updater);
+        assert_eq!(updater.running().label(), "ota_0");
+    }
+
+    #[test]
+    fn test_no_ota_partitions() {
+        let mut flash = flash();
+        // Only keep nvs.
+        flash.0[TABLE_OFFSET as usize + 32..][..32].fill(0xff);
+        assert!(matches!(
+            Updater::new(flash, CHIP_ID),
+            Err(Error::NoOtaPartitions)
+        ));
+    }
+}
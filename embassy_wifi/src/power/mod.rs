@@ -0,0 +1,160 @@
+//! Wi-Fi power saving and duty-cycled operation
+//!
+//! The `connection` task applies the modem sleep mode and listen interval
+//! from the `power` settings. With `sleep_secs` set, the `duty_cycle` task
+//! waits until the broker is connected, publishes one telemetry message and
+//! puts the chip into deep sleep with an RTC timer wake-up. Deep sleep ends
+//! in a reset, so the access point of the last association is kept in RTC
+//! memory, which lets the next connection skip the scan.
+//!
+//! `retained` doesn't depend on esp-hal and is tested on the host.
+
+use core::ptr::{addr_of, addr_of_mut};
+
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use esp_hal::rtc_cntl::{Rtc, sleep::TimerWakeupSource};
+use esp_println::println;
+use esp_wifi::config::PowerSaveMode;
+use esp_wifi::wifi::WifiController;
+use esp_wifi_sys::include::{
+    esp_wifi_get_config, esp_wifi_set_config, esp_wifi_sta_get_ap_info, wifi_ap_record_t,
+    wifi_config_t, wifi_interface_t_WIFI_IF_STA,
+};
+
+use crate::mqtt::{self, QoS};
+use crate::settings::PowerSave;
+use crate::status;
+
+pub mod retained;
+
+pub use retained::Retained;
+
+/// Time to connect and publish before going back to sleep anyway.
+pub const AWAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Wake-ups in a row without publishing after which the remembered access
+/// point is no longer used.
+pub const MAX_FAILURES: u8 = 2;
+
+#[esp_hal::ram(rtc_fast, persistent)]
+static mut RETAINED: Retained = Retained::new();
+
+/// Guards [`RETAINED`], which can't hold a mutex itself.
+static LOCK: Mutex<CriticalSectionRawMutex, ()> = Mutex::new(());
+
+// Only plain data, checked with `Retained::is_valid` before use.
+unsafe impl esp_hal::Persistable for Retained {}
+
+pub fn retained() -> Retained {
+    LOCK.lock(|_| unsafe { addr_of!(RETAINED).read() })
+}
+
+/// Change the retained state, starting over if it isn't valid.
+fn update(f: impl FnOnce(&mut Retained)) {
+    LOCK.lock(|_| {
+        let retained = unsafe { &mut *addr_of_mut!(RETAINED) };
+        if !retained.is_valid() {
+            *retained = Retained::new();
+        }
+        f(retained);
+        retained.seal();
+    })
+}
+
+/// BSSID and channel to try first, from before the last deep sleep.
+pub fn last_access_point() -> Option<([u8; 6], u8)> {
+    retained().access_point()
+}
+
+/// Remember the access point we are associated with.
+pub fn remember_access_point() {
+    let mut record: wifi_ap_record_t = unsafe { core::mem::zeroed() };
+    if unsafe { esp_wifi_sta_get_ap_info(&mut record) } == 0 {
+        update(|retained| {
+            retained.bssid = record.bssid;
+            retained.channel = record.primary;
+        });
+    }
+}
+
+/// Scan again on the next connection, e.g. after the remembered access
+/// point didn't answer.
+pub fn forget_access_point() {
+    update(|retained| retained.channel = 0);
+}
+
+pub fn set_power_save(controller: &mut WifiController<'static>, save: PowerSave) {
+    let mode = match save {
+        PowerSave::None => PowerSaveMode::None,
+        PowerSave::Minimum => PowerSaveMode::Minimum,
+        PowerSave::Maximum => PowerSaveMode::Maximum,
+    };
+    if let Err(e) = controller.set_power_saving(mode) {
+        println!("power: can't set power save mode: {:?}", e);
+    }
+}
+
+/// Beacon intervals between wake-ups in maximum power save, takes effect
+/// on the next association. esp-wifi has no setting for it, so it is
+/// patched into the station configuration.
+pub fn set_listen_interval(interval: u16) {
+    if interval == 0 {
+        return;
+    }
+    unsafe {
+        let mut config: wifi_config_t = core::mem::zeroed();
+        if esp_wifi_get_config(wifi_interface_t_WIFI_IF_STA, &mut config) == 0 {
+            config.sta.listen_interval = interval;
+            esp_wifi_set_config(wifi_interface_t_WIFI_IF_STA, &mut config);
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn duty_cycle(mut rtc: Rtc<'static>, sleep_secs: u32) {
+    let mut network = status::STATUS.receiver().unwrap();
+    let start = Instant::now();
+
+    let published = with_timeout(AWAKE_TIMEOUT, async {
+        network.get_and(|status| status.backend).await;
+        let payload = mqtt::telemetry();
+        let message =
+            mqtt::Message::new(mqtt::TELEMETRY_TOPIC, payload.as_bytes(), QoS::AtLeastOnce)
+                .unwrap();
+        mqtt::PUBLISHED.reset();
+        mqtt::OUTBOX.send(message).await;
+        mqtt::PUBLISHED.wait().await;
+    })
+    .await;
+
+    match published {
+        Ok(()) => {
+            println!("power: published after {} ms", start.elapsed().as_millis());
+            update(|retained| retained.failures = 0);
+        }
+        Err(_) => {
+            println!(
+                "power: nothing published within {} s",
+                AWAKE_TIMEOUT.as_secs()
+            );
+            update(|retained| {
+                retained.failures = retained.failures.saturating_add(1);
+                if retained.failures >= MAX_FAILURES {
+                    retained.channel = 0;
+                }
+            });
+        }
+    }
+    update(|retained| retained.sleeps = retained.sleeps.wrapping_add(1));
+
+    println!(
+        "power: deep sleep for {} s, {} since power-on",
+        sleep_secs,
+        retained().sleeps
+    );
+    // Let the output drain.
+    Timer::after(Duration::from_millis(100)).await;
+    let timer = TimerWakeupSource::new(core::time::Duration::from_secs(sleep_secs as u64));
+    rtc.sleep_deep(&[&timer]);
+}
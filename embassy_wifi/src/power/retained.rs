@@ -0,0 +1,90 @@
+//! State kept in RTC memory across deep sleep
+//!
+//! RTC memory survives deep sleep but holds garbage after a power-on, so
+//! the state is only trusted with the right magic and checksum.
+
+const MAGIC: u32 = u32::from_le_bytes(*b"ESPR");
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C)]
+pub struct Retained {
+    magic: u32,
+    /// The access point of the last association, channel 0 if none
+    pub bssid: [u8; 6],
+    pub channel: u8,
+    /// Wake-ups in a row that didn't get to publish
+    pub failures: u8,
+    /// Deep sleeps since power-on
+    pub sleeps: u32,
+    checksum: u32,
+}
+
+impl Default for Retained {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Retained {
+    pub const fn new() -> Self {
+        Self {
+            magic: 0,
+            bssid: [0; 6],
+            channel: 0,
+            failures: 0,
+            sleeps: 0,
+            checksum: 0,
+        }
+    }
+
+    /// Whether this was written by [`seal`](Self::seal), rather than being
+    /// left over from a power-on.
+    pub fn is_valid(&self) -> bool {
+        self.magic == MAGIC && self.checksum == self.compute_checksum()
+    }
+
+    /// Mark as valid after changing it.
+    pub fn seal(&mut self) {
+        self.magic = MAGIC;
+        self.checksum = self.compute_checksum();
+    }
+
+    /// BSSID and channel of the last access point.
+    pub fn access_point(&self) -> Option<([u8; 6], u8)> {
+        (self.is_valid() && self.channel != 0).then_some((self.bssid, self.channel))
+    }
+
+    /// FNV-1a over the fields.
+    fn compute_checksum(&self) -> u32 {
+        self.bssid
+            .iter()
+            .chain(&[self.channel, self.failures])
+            .chain(&self.sleeps.to_le_bytes())
+            .fold(0x811c_9dc5, |hash, byte| {
+                (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retained() {
+        let mut retained = Retained::new();
+        assert!(!retained.is_valid());
+        assert_eq!(retained.access_point(), None);
+
+        retained.bssid = [2, 0, 0, 0, 0, 1];
+        retained.channel = 6;
+        retained.seal();
+        assert!(retained.is_valid());
+        assert_eq!(retained.access_point(), Some(([2, 0, 0, 0, 0, 1], 6)));
+
+        // Changed without sealing, e.g. garbage after a power-on.
+        retained.sleeps = 7;
+        assert!(!retained.is_valid());
+        assert_eq!(retained.access_point(), None);
+    }
+}
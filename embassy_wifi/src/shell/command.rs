@@ -0,0 +1,224 @@
+//! Command tables, looked up by the leading words of a line.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use core::fmt::Write;
+use core::future::Future;
+use core::pin::Pin;
+
+/// Most words on a line, including the command name.
+pub const MAX_WORDS: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    UnknownCommand,
+    TooManyWords,
+    /// Wrong arguments, the usage is printed
+    Usage,
+    /// The command ran and failed, with a reason
+    Failed(&'static str),
+}
+
+/// What a handler returns, for commands that don't need to wait use
+/// [`done`].
+pub type Reply<'a> = Pin<Box<dyn Future<Output = Result<(), Error>> + 'a>>;
+
+/// Gets the context, the arguments after the command name and the output,
+/// where lines end with `\n`.
+pub type Handler<C> = for<'a> fn(&'a C, &'a [&'a str], &'a mut String) -> Reply<'a>;
+
+pub struct Command<C: 'static> {
+    /// One or more words, e.g. `wifi scan`
+    pub name: &'static str,
+    /// Arguments, e.g. `on|off`
+    pub args: &'static str,
+    pub help: &'static str,
+    pub handler: Handler<C>,
+}
+
+impl<C> Command<C> {
+    pub const fn new(
+        name: &'static str,
+        args: &'static str,
+        help: &'static str,
+        handler: Handler<C>,
+    ) -> Self {
+        Self {
+            name,
+            args,
+            help,
+            handler,
+        }
+    }
+
+    /// Name and arguments.
+    pub fn usage(&self) -> heapless::String<48> {
+        let mut usage = heapless::String::new();
+        if self.args.is_empty() {
+            write!(usage, "{}", self.name).ok();
+        } else {
+            write!(usage, "{} {}", self.name, self.args).ok();
+        }
+        usage
+    }
+
+    /// If `words` start with the name, the words after it.
+    fn matches<'w>(&self, words: &'w [&'w str]) -> Option<&'w [&'w str]> {
+        let mut rest = words;
+        for name in self.name.split_whitespace() {
+            let (first, tail) = rest.split_first()?;
+            if *first != name {
+                return None;
+            }
+            rest = tail;
+        }
+        Some(rest)
+    }
+}
+
+/// A reply for a handler that is done already.
+pub fn done<'a>(result: Result<(), Error>) -> Reply<'a> {
+    Box::pin(core::future::ready(result))
+}
+
+/// Compare a token without leaking where the first difference is.
+pub fn token_eq(token: &str, given: &str) -> bool {
+    token.len() == given.len()
+        && token
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Command tables, e.g. one per module, `C` is the context passed to every
+/// handler. `help` is built in.
+pub struct Registry<C: 'static> {
+    tables: &'static [&'static [Command<C>]],
+}
+
+impl<C> Registry<C> {
+    pub const fn new(tables: &'static [&'static [Command<C>]]) -> Self {
+        Self { tables }
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = &'static Command<C>> {
+        self.tables.iter().flat_map(|table| table.iter())
+    }
+
+    /// The command with the longest name matching the start of `words`.
+    pub fn find<'w>(&self, words: &'w [&'w str]) -> Option<(&'static Command<C>, &'w [&'w str])> {
+        self.commands()
+            .filter_map(|command| Some((command, command.matches(words)?)))
+            .min_by_key(|(_, args)| args.len())
+    }
+
+    /// Run the command on `line`. An empty line does nothing.
+    pub async fn run(&self, context: &C, line: &str, out: &mut String) -> Result<(), Error> {
+        let mut words = heapless::Vec::<&str, MAX_WORDS>::new();
+        for word in line.split_whitespace() {
+            words.push(word).map_err(|_| Error::TooManyWords)?;
+        }
+
+        match words.as_slice() {
+            [] => Ok(()),
+            ["help"] => {
+                self.help(out);
+                Ok(())
+            }
+            words => {
+                let (command, args) = self.find(words).ok_or(Error::UnknownCommand)?;
+                (command.handler)(context, args, out).await
+            }
+        }
+    }
+
+    /// Print the usage of the command on `line`, e.g. after [`Error::Usage`].
+    pub fn usage(&self, line: &str, out: &mut String) {
+        let mut words = heapless::Vec::<&str, MAX_WORDS>::new();
+        for word in line.split_whitespace().take(MAX_WORDS) {
+            words.push(word).ok();
+        }
+        if let Some((command, _)) = self.find(&words) {
+            writeln!(out, "usage: {}", command.usage()).ok();
+        }
+    }
+
+    fn help(&self, out: &mut String) {
+        for command in self.commands() {
+            writeln!(out, "{:<24} {}", command.usage(), command.help).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    fn echo<'a>(count: &'a Cell<u32>, args: &'a [&'a str], out: &'a mut String) -> Reply<'a> {
+        count.set(count.get() + 1);
+        writeln!(out, "{:?}", args).ok();
+        done(Ok(()))
+    }
+
+    fn scan<'a>(_: &'a Cell<u32>, args: &'a [&'a str], out: &'a mut String) -> Reply<'a> {
+        Box::pin(async move {
+            if !args.is_empty() {
+                return Err(Error::Usage);
+            }
+            out.push_str("scanning\n");
+            Ok(())
+        })
+    }
+
+    static WIFI: &[Command<Cell<u32>>] = &[
+        Command::new("wifi", "[args]", "echo", echo),
+        Command::new("wifi scan", "", "scan", scan),
+    ];
+    static REGISTRY: Registry<Cell<u32>> = Registry::new(&[WIFI]);
+
+    fn run(count: &Cell<u32>, line: &str, out: &mut String) -> Result<(), Error> {
+        out.clear();
+        block_on(REGISTRY.run(count, line, out))
+    }
+
+    #[test]
+    fn test_registry() {
+        let count = Cell::new(0);
+        let mut out = String::new();
+
+        assert_eq!(run(&count, "  ", &mut out), Ok(()));
+        assert_eq!(run(&count, "wifi  a b", &mut out), Ok(()));
+        assert_eq!(out, "[\"a\", \"b\"]\n");
+        // The longest name wins.
+        assert_eq!(run(&count, "wifi scan", &mut out), Ok(()));
+        assert_eq!(out, "scanning\n");
+        assert_eq!(run(&count, "wifi scan now", &mut out), Err(Error::Usage));
+        assert_eq!(run(&count, "led on", &mut out), Err(Error::UnknownCommand));
+        assert_eq!(
+            run(&count, "wifi 1 2 3 4 5 6 7 8", &mut out),
+            Err(Error::TooManyWords)
+        );
+        assert_eq!(count.get(), 1);
+
+        assert_eq!(run(&count, "help", &mut out), Ok(()));
+        assert!(out.starts_with("wifi [args]"));
+        assert!(out.ends_with("scan\n"));
+
+        out.clear();
+        REGISTRY.usage("wifi scan now", &mut out);
+        assert_eq!(out, "usage: wifi scan\n");
+    }
+
+    #[test]
+    fn test_token_eq() {
+        assert!(token_eq("secret", "secret"));
+        assert!(!token_eq("secret", "secreT"));
+        assert!(!token_eq("secret", "secret "));
+        assert!(!token_eq("secret", ""));
+    }
+}
@@ -0,0 +1,186 @@
+//! Line input from telnet or netcat
+//!
+//! Lines end with CR LF, CR NUL (telnet), a bare LF (netcat) or a bare CR.
+//! Backspace deletes the last character, telnet option negotiation (RFC
+//! 854) is skipped, the shell never agrees to any option. The console of
+//! `embassy_ble` reads the lines of BLE terminal apps with a copy of it.
+
+const IAC: u8 = 255;
+const SB: u8 = 250;
+const SE: u8 = 240;
+/// WILL, WONT, DO and DONT, followed by an option byte
+const NEGOTIATE: core::ops::RangeInclusive<u8> = 251..=254;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    TooLong,
+    InvalidUtf8,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Text,
+    /// After CR, a following LF or NUL is part of the line end
+    Cr,
+    Iac,
+    Negotiate,
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// Collects bytes into lines of up to `N` bytes.
+pub struct LineReader<const N: usize> {
+    line: heapless::Vec<u8, N>,
+    state: State,
+    overflow: bool,
+}
+
+impl<const N: usize> Default for LineReader<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> LineReader<N> {
+    pub const fn new() -> Self {
+        Self {
+            line: heapless::Vec::new(),
+            state: State::Text,
+            overflow: false,
+        }
+    }
+
+    /// Feed a received byte, returns `true` when a line is complete. Get it
+    /// with [`line`](Self::line) before feeding more.
+    pub fn push(&mut self, byte: u8) -> bool {
+        match (self.state, byte) {
+            (State::Iac, IAC) => {
+                // An escaped 255, not valid in UTF-8 anyway.
+                self.state = State::Text;
+                self.put(byte);
+                false
+            }
+            (State::Iac, SB) => {
+                self.state = State::Subnegotiation;
+                false
+            }
+            (State::Iac, byte) if NEGOTIATE.contains(&byte) => {
+                self.state = State::Negotiate;
+                false
+            }
+            (State::Iac | State::Negotiate, _) => {
+                self.state = State::Text;
+                false
+            }
+            (State::Subnegotiation, IAC) => {
+                self.state = State::SubnegotiationIac;
+                false
+            }
+            (State::SubnegotiationIac, SE) => {
+                self.state = State::Text;
+                false
+            }
+            (State::Subnegotiation | State::SubnegotiationIac, _) => {
+                self.state = State::Subnegotiation;
+                false
+            }
+            (State::Cr, b'\n' | 0) => {
+                self.state = State::Text;
+                false
+            }
+            (_, IAC) => {
+                self.state = State::Iac;
+                false
+            }
+            (_, b'\r') => {
+                self.state = State::Cr;
+                true
+            }
+            (_, b'\n') => {
+                self.state = State::Text;
+                true
+            }
+            (_, 0x08 | 0x7f) => {
+                self.state = State::Text;
+                self.line.pop();
+                false
+            }
+            (_, byte) => {
+                self.state = State::Text;
+                self.put(byte);
+                false
+            }
+        }
+    }
+
+    /// The completed line, and start the next one.
+    pub fn line(&mut self) -> Result<heapless::String<N>, Error> {
+        let line = core::mem::take(&mut self.line);
+        if core::mem::take(&mut self.overflow) {
+            return Err(Error::TooLong);
+        }
+        heapless::String::from_utf8(line).map_err(|_| Error::InvalidUtf8)
+    }
+
+    /// Drop a partial line, e.g. after a disconnect.
+    pub fn clear(&mut self) {
+        self.line.clear();
+        self.state = State::Text;
+        self.overflow = false;
+    }
+
+    fn put(&mut self, byte: u8) {
+        if self.line.push(byte).is_err() {
+            self.overflow = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    fn read<const N: usize>(input: &[u8]) -> Vec<Result<heapless::String<N>, Error>> {
+        let mut reader = LineReader::<N>::new();
+        let mut lines = Vec::new();
+        for &byte in input {
+            if reader.push(byte) {
+                lines.push(reader.line());
+            }
+        }
+        lines
+    }
+
+    #[test]
+    fn test_lines() {
+        // Telnet negotiation, CR LF, CR NUL, bare LF and backspace.
+        let input = b"\xff\xfb\x01\xff\xfa\x18\x01\xff\xf0status\r\nheap\r\0ipx\x7f\n";
+        let lines = read::<16>(input);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].as_deref(), Ok("status"));
+        assert_eq!(lines[1].as_deref(), Ok("heap"));
+        assert_eq!(lines[2].as_deref(), Ok("ip"));
+
+        // An empty line after CR NUL, and a bare CR.
+        assert_eq!(read::<16>(b"\r\0\r\n").len(), 2);
+        let lines = read::<16>(b"heap\rip\r");
+        assert_eq!(lines[0].as_deref(), Ok("heap"));
+        assert_eq!(lines[1].as_deref(), Ok("ip"));
+
+        // Without the partial line and the pending IAC.
+        let mut reader = LineReader::<16>::new();
+        for &byte in b"sta\xff" {
+            reader.push(byte);
+        }
+        reader.clear();
+        assert!(!reader.push(b'o') && !reader.push(b'k') && reader.push(b'\n'));
+        assert_eq!(reader.line().as_deref(), Ok("ok"));
+
+        let lines = read::<4>(b"toolong\nok\n\xc3\n");
+        assert_eq!(lines[0], Err(Error::TooLong));
+        assert_eq!(lines[1].as_deref(), Ok("ok"));
+        assert_eq!(lines[2], Err(Error::InvalidUtf8));
+    }
+}
@@ -0,0 +1,268 @@
+//! A line-oriented shell for remote diagnostics
+//!
+//! The `shell` task serves one session at a time on [`PORT`], connect with
+//! `telnet <ip>` or `nc <ip> 23`. When [`TOKEN`] is set, it must be sent as
+//! the first line. Commands come from the tables in [`REGISTRY`]: modules
+//! add their own by exporting a `COMMANDS` table and listing it there.
+//!
+//! `command` and `line` don't depend on esp-hal or embassy-net and are
+//! tested on the host.
+
+use alloc::string::String;
+use core::cell::Cell;
+use core::fmt::Write as _;
+
+use embassy_net::{Stack, tcp::TcpSocket};
+use embassy_time::{Duration, Instant, Timer};
+use esp_println::println;
+
+use crate::{clock, health, io, led, net, status, wifi};
+
+#[cfg(feature = "log")]
+use crate::syslog::COMMANDS as LOG_COMMANDS;
+#[cfg(not(feature = "log"))]
+const LOG_COMMANDS: &[Command<Context>] = &[];
+
+pub mod command;
+pub mod line;
+
+pub use command::{Command, Error, Handler, Registry, Reply, done, token_eq};
+pub use line::LineReader;
+
+pub const PORT: u16 = 23;
+
+/// Shared secret asked for before any command, `None` for no
+/// authentication.
+pub const TOKEN: Option<&str> = None;
+
+const MAX_LINE_LEN: usize = 128;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// After a wrong token, to slow down guessing.
+const DENIED_DELAY: Duration = Duration::from_secs(2);
+const PROMPT: &str = "> ";
+
+pub static REGISTRY: Registry<Context> = Registry::new(&[
+    COMMANDS,
+    led::COMMANDS,
+    wifi::COMMANDS,
+    health::COMMANDS,
+    LOG_COMMANDS,
+]);
+
+pub static COMMANDS: &[Command<Context>] = &[
+    Command::new("status", "", "uptime, time and network status", status),
+    Command::new("heap", "", "heap usage", heap),
+    Command::new("ip", "", "addresses, gateways and DNS servers", ip),
+    Command::new("reboot", "", "restart the device", reboot),
+    Command::new("exit", "", "end the session", exit),
+];
+
+/// What commands get to see, one per session.
+pub struct Context {
+    pub stack: Stack<'static>,
+    close: Cell<bool>,
+    reboot: Cell<bool>,
+}
+
+impl Context {
+    /// End the session after the output was sent.
+    pub fn close(&self) {
+        self.close.set(true);
+    }
+
+    /// Restart the device after the output was sent.
+    pub fn reboot(&self) {
+        self.reboot.set(true);
+    }
+}
+
+#[embassy_executor::task]
+pub async fn shell(stack: Stack<'static>) {
+    let mut rx_buffer = [0; 512];
+    let mut tx_buffer = [0; 1024];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(IDLE_TIMEOUT));
+
+        if let Err(e) = socket.accept(PORT).await {
+            println!("shell: accept error: {:?}", e);
+            continue;
+        }
+        println!("shell: session from {:?}", socket.remote_endpoint());
+
+        let context = Context {
+            stack,
+            close: Cell::new(false),
+            reboot: Cell::new(false),
+        };
+        if let Err(e) = session(&context, &mut socket).await {
+            println!("shell: {:?}", e);
+        }
+
+        socket.close();
+        socket.flush().await.ok();
+        if context.reboot.get() {
+            println!("shell: rebooting");
+            Timer::after(Duration::from_millis(100)).await;
+            esp_hal::system::software_reset();
+        }
+        socket.abort();
+    }
+}
+
+async fn session(
+    context: &Context,
+    socket: &mut TcpSocket<'_>,
+) -> Result<(), io::Error<embassy_net::tcp::Error>> {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{} {} on {}, type help for commands",
+        env!("CARGO_PKG_NAME"),
+        crate::FIRMWARE,
+        net::hostname()
+    )
+    .ok();
+    let mut authorized = TOKEN.is_none();
+    out.push_str(if authorized { PROMPT } else { "token: " });
+    send(socket, &out).await?;
+
+    let mut reader = LineReader::<MAX_LINE_LEN>::new();
+    let mut buf = [0; 64];
+    loop {
+        let n = socket.read(&mut buf).await.map_err(io::Error::Io)?;
+        if n == 0 {
+            return Ok(());
+        }
+
+        for &byte in &buf[..n] {
+            if !reader.push(byte) {
+                continue;
+            }
+            out.clear();
+            match reader.line() {
+                Ok(line) if !authorized => {
+                    if !TOKEN.is_some_and(|token| token_eq(token, &line)) {
+                        send(socket, "denied\n").await?;
+                        Timer::after(DENIED_DELAY).await;
+                        return Ok(());
+                    }
+                    authorized = true;
+                }
+                Ok(line) => match REGISTRY.run(context, &line, &mut out).await {
+                    Ok(()) => {}
+                    Err(Error::UnknownCommand) => out.push_str("unknown command, try help\n"),
+                    Err(Error::TooManyWords) => out.push_str("too many words\n"),
+                    Err(Error::Usage) => REGISTRY.usage(&line, &mut out),
+                    Err(Error::Failed(reason)) => {
+                        writeln!(out, "error: {}", reason).ok();
+                    }
+                },
+                Err(line::Error::TooLong) => out.push_str("line too long\n"),
+                Err(line::Error::InvalidUtf8) => out.push_str("invalid UTF-8\n"),
+            }
+
+            if context.close.get() || context.reboot.get() {
+                return send(socket, &out).await;
+            }
+            out.push_str(PROMPT);
+            send(socket, &out).await?;
+        }
+    }
+}
+
+/// Write `text` with telnet line ends.
+async fn send(
+    socket: &mut TcpSocket<'_>,
+    text: &str,
+) -> Result<(), io::Error<embassy_net::tcp::Error>> {
+    for (i, part) in text.split('\n').enumerate() {
+        if i > 0 {
+            io::write_all(socket, b"\r\n").await?;
+        }
+        io::write_all(socket, part.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+fn status<'a>(_: &'a Context, args: &'a [&'a str], out: &'a mut String) -> Reply<'a> {
+    if !args.is_empty() {
+        return done(Err(Error::Usage));
+    }
+    let network = status::get();
+    writeln!(out, "uptime   {} s", Instant::now().as_secs()).ok();
+    match clock::now().date_time() {
+        Some(dt) => writeln!(out, "time     {}", dt).ok(),
+        None => writeln!(out, "time     not synchronized").ok(),
+    };
+    match crate::rssi() {
+        Some(rssi) => writeln!(out, "wifi     {:?}, {} dBm", network.wifi, rssi).ok(),
+        None => writeln!(out, "wifi     {:?}", network.wifi).ok(),
+    };
+    if let Some(disconnect) = wifi::last_disconnect() {
+        writeln!(
+            out,
+            "last     {} (reason {}, {})",
+            disconnect.message, disconnect.reason, disconnect.method
+        )
+        .ok();
+    }
+    writeln!(out, "address  {}", network.address).ok();
+    writeln!(out, "backend  {}", network.backend).ok();
+    done(Ok(()))
+}
+
+fn heap<'a>(_: &'a Context, args: &'a [&'a str], out: &'a mut String) -> Reply<'a> {
+    if !args.is_empty() {
+        return done(Err(Error::Usage));
+    }
+    writeln!(
+        out,
+        "used {} free {}",
+        esp_alloc::HEAP.used(),
+        esp_alloc::HEAP.free()
+    )
+    .ok();
+    done(Ok(()))
+}
+
+fn ip<'a>(context: &'a Context, args: &'a [&'a str], out: &'a mut String) -> Reply<'a> {
+    if !args.is_empty() {
+        return done(Err(Error::Usage));
+    }
+    writeln!(out, "hostname {}", net::hostname()).ok();
+    if let Some(config) = context.stack.config_v4() {
+        writeln!(out, "ipv4     {}", config.address).ok();
+        if let Some(gateway) = config.gateway {
+            writeln!(out, "gateway  {}", gateway).ok();
+        }
+        for dns in &config.dns_servers {
+            writeln!(out, "dns      {}", dns).ok();
+        }
+    }
+    if let Some(config) = context.stack.config_v6() {
+        writeln!(out, "ipv6     {}", config.address).ok();
+        if let Some(gateway) = config.gateway {
+            writeln!(out, "gateway  {}", gateway).ok();
+        }
+        for dns in &config.dns_servers {
+            writeln!(out, "dns      {}", dns).ok();
+        }
+    }
+    done(Ok(()))
+}
+
+fn reboot<'a>(context: &'a Context, args: &'a [&'a str], out: &'a mut String) -> Reply<'a> {
+    if !args.is_empty() {
+        return done(Err(Error::Usage));
+    }
+    out.push_str("rebooting\n");
+    context.reboot();
+    done(Ok(()))
+}
+
+fn exit<'a>(context: &'a Context, _: &'a [&'a str], _: &'a mut String) -> Reply<'a> {
+    context.close();
+    done(Ok(()))
+}
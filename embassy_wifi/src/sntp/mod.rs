@@ -0,0 +1,112 @@
+//! Synchronize the wall clock with SNTP (RFC 4330)
+//!
+//! `packet` doesn't depend on esp-hal or embassy-net and is tested on the
+//! host.
+
+use embassy_net::{
+    Stack,
+    dns::DnsQueryType,
+    udp::{PacketMetadata, UdpSocket},
+};
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use esp_println::println;
+
+use crate::clock;
+
+pub mod packet;
+
+pub use packet::{Error, PACKET_LEN, Sample, decode_response, encode_request};
+
+pub const SERVER: &str = "pool.ntp.org";
+pub const UTC_OFFSET_MINUTES: i16 = 0;
+
+const NTP_PORT: u16 = 123;
+const SYNC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[embassy_executor::task]
+pub async fn sntp(stack: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 128];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 128];
+
+    clock::set_utc_offset(UTC_OFFSET_MINUTES);
+
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(0).unwrap();
+
+    loop {
+        stack.wait_config_up().await;
+
+        let delay = match sync(stack, &mut socket).await {
+            Ok(sample) => {
+                println!(
+                    "sntp: {} (offset {} us, delay {} us, drift {} ppm)",
+                    clock::now(),
+                    sample.offset,
+                    sample.delay,
+                    clock::drift_ppm()
+                );
+                SYNC_INTERVAL
+            }
+            Err(e) => {
+                println!("sntp: {}", e);
+                RETRY_INTERVAL
+            }
+        };
+
+        Timer::after(delay).await;
+    }
+}
+
+/// Query the server once and adjust the clock.
+async fn sync(stack: Stack<'static>, socket: &mut UdpSocket<'_>) -> Result<Sample, &'static str> {
+    // Without an IPv4 address, e.g. on an IPv6-only network, try AAAA.
+    let addrs = match stack.dns_query(SERVER, DnsQueryType::A).await {
+        Ok(addrs) if !addrs.is_empty() => addrs,
+        _ => stack
+            .dns_query(SERVER, DnsQueryType::Aaaa)
+            .await
+            .map_err(|_| "dns query failed")?,
+    };
+    let server = (*addrs.first().ok_or("no address")?, NTP_PORT);
+
+    let local1 = Instant::now().as_micros();
+    let t1 = clock::estimate(local1);
+    socket
+        .send_to(&encode_request(t1), server)
+        .await
+        .map_err(|_| "send failed")?;
+
+    let mut buf = [0; PACKET_LEN];
+    loop {
+        let (n, meta) = with_timeout(RESPONSE_TIMEOUT, socket.recv_from(&mut buf))
+            .await
+            .map_err(|_| "timeout")?
+            .map_err(|_| "receive failed")?;
+        let local4 = Instant::now().as_micros();
+
+        // Late answers to earlier requests or traffic from other hosts.
+        if meta.endpoint != server.into() {
+            continue;
+        }
+
+        match decode_response(&buf[..n], t1, clock::estimate(local4)) {
+            Ok(sample) => {
+                clock::adjust(local4, sample.offset);
+                return Ok(sample);
+            }
+            Err(Error::OriginMismatch) => continue,
+            Err(Error::Kiss(_)) => return Err("kiss-o'-death"),
+            Err(_) => return Err("bad response"),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! SNTP packets (RFC 4330)
+
+/// Seconds between 1900-01-01 (NTP era 0) and 1970-01-01.
+const NTP_UNIX_OFFSET: i64 = 2_208_988_800;
+
+pub const PACKET_LEN: usize = 48;
+
+/// Errors
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Shorter than an NTP header
+    Truncated,
+    /// Not a server response
+    NotServer,
+    /// Response to a different request
+    OriginMismatch,
+    /// Kiss-o'-Death packet, the server asks us to go away
+    Kiss([u8; 4]),
+    /// The server itself isn't synchronized
+    Unsynchronized,
+}
+
+/// Result of one request/response exchange, in microseconds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Sample {
+    /// Correction to apply to the local clock
+    pub offset: i64,
+    /// Round trip delay
+    pub delay: i64,
+}
+
+/// Microseconds since the Unix epoch to a 32.32 NTP timestamp.
+fn to_ntp(unix_micros: i64) -> u64 {
+    let secs = unix_micros.div_euclid(1_000_000) + NTP_UNIX_OFFSET;
+    let micros = unix_micros.rem_euclid(1_000_000) as u64;
+    ((secs as u64) << 32) | ((micros << 32) / 1_000_000)
+}
+
+/// 32.32 NTP timestamp to microseconds since the Unix epoch.
+fn from_ntp(ntp: u64) -> i64 {
+    let mut secs = (ntp >> 32) as i64;
+    // Timestamps with the top bit clear belong to era 1, starting 2036.
+    if secs < 0x8000_0000 {
+        secs += 1 << 32;
+    }
+    let micros = ((ntp & 0xffff_ffff) * 1_000_000 + (1 << 31)) >> 32;
+    (secs - NTP_UNIX_OFFSET) * 1_000_000 + micros as i64
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&buf[offset..offset + 8]);
+    u64::from_be_bytes(bytes)
+}
+
+/// Build a client request, `transmit` is our current estimate of the time.
+pub fn encode_request(transmit: i64) -> [u8; PACKET_LEN] {
+    let mut buf = [0; PACKET_LEN];
+    // LI = 0, VN = 4, Mode = 3 (client)
+    buf[0] = 0x23;
+    buf[40..48].copy_from_slice(&to_ntp(transmit).to_be_bytes());
+    buf
+}
+
+/// Check a server response and compute offset and delay.
+///
+/// `t1` is the transmit timestamp of the request and `t4` the local time the
+/// response arrived, both on the local clock.
+pub fn decode_response(buf: &[u8], t1: i64, t4: i64) -> Result<Sample, Error> {
+    if buf.len() < PACKET_LEN {
+        return Err(Error::Truncated);
+    }
+
+    let leap = buf[0] >> 6;
+    let mode = buf[0] & 0x07;
+    let stratum = buf[1];
+
+    if mode != 4 {
+        return Err(Error::NotServer);
+    }
+    if read_u64(buf, 24) != to_ntp(t1) {
+        return Err(Error::OriginMismatch);
+    }
+    if stratum == 0 {
+        let mut code = [0; 4];
+        code.copy_from_slice(&buf[12..16]);
+        return Err(Error::Kiss(code));
+    }
+    if leap == 3 {
+        return Err(Error::Unsynchronized);
+    }
+
+    let t2 = from_ntp(read_u64(buf, 32));
+    let t3 = from_ntp(read_u64(buf, 40));
+
+    Ok(Sample {
+        offset: ((t2 - t1) + (t3 - t4)) / 2,
+        delay: (t4 - t1) - (t3 - t2),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamp() {
+        let unix = 1_714_566_600_123_456;
+        assert_eq!(from_ntp(to_ntp(unix)), unix);
+        // 2036-02-07T06:28:16Z wraps to era 1
+        assert_eq!(from_ntp(0), 2_085_978_496_000_000);
+    }
+
+    #[test]
+    fn test_offset() {
+        let t1 = 1_000_000;
+        let request = encode_request(t1);
+
+        // Server is 10 s ahead, 20 ms each way, 1 ms processing.
+        let mut response = [0; PACKET_LEN];
+        response[0] = 0x24;
+        response[1] = 2;
+        response[24..32].copy_from_slice(&request[40..48]);
+        response[32..40].copy_from_slice(&to_ntp(t1 + 10_020_000).to_be_bytes());
+        response[40..48].copy_from_slice(&to_ntp(t1 + 10_021_000).to_be_bytes());
+
+        let sample = decode_response(&response, t1, t1 + 41_000).unwrap();
+        assert_eq!(
+            sample,
+            Sample {
+                offset: 10_000_000,
+                delay: 40_000
+            }
+        );
+
+        assert_eq!(
+            decode_response(&response, t1 + 1, t1 + 41_000),
+            Err(Error::OriginMismatch)
+        );
+    }
+}
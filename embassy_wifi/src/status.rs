@@ -0,0 +1,78 @@
+//! Network status bus
+//!
+//! The tasks that know a part of the status publish it here: the
+//! `connection` task the Wi-Fi state, the `network` task whether the stack
+//! has an address and the `mqtt` task whether the broker is reachable.
+//! Anyone interested, like the LED, watches [`STATUS`] for changes instead
+//! of polling.
+
+use embassy_net::Stack;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, watch::Watch};
+use serde::Serialize;
+
+/// Receivers: `led`, `mqtt`, `power`, `websocket` and one spare.
+pub const MAX_RECEIVERS: usize = 5;
+
+pub static STATUS: Watch<CriticalSectionRawMutex, Status, MAX_RECEIVERS> = Watch::new();
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Wifi {
+    #[default]
+    Disconnected,
+    Connecting,
+    Connected,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Status {
+    pub wifi: Wifi,
+    /// The stack has an IPv4 or IPv6 address
+    pub address: bool,
+    /// Connected to the MQTT broker
+    pub backend: bool,
+}
+
+/// Current status.
+pub fn get() -> Status {
+    STATUS.try_get().unwrap_or_default()
+}
+
+/// Change part of the status, receivers are only woken if it changed.
+pub fn update(f: impl FnOnce(&mut Status)) {
+    STATUS.sender().send_if_modified(|value| {
+        let old = value.unwrap_or_default();
+        let mut new = old;
+        f(&mut new);
+        *value = Some(new);
+        new != old
+    });
+}
+
+pub fn set_wifi(wifi: Wifi) {
+    update(|status| {
+        status.wifi = wifi;
+        // Without the access point nothing else is reachable either.
+        if wifi != Wifi::Connected {
+            status.backend = false;
+        }
+    });
+}
+
+pub fn set_backend(connected: bool) {
+    update(|status| status.backend = connected);
+}
+
+/// Publish whether the stack has an address.
+#[embassy_executor::task]
+pub async fn network(stack: Stack<'static>) {
+    loop {
+        let up = stack.is_config_up();
+        update(|status| status.address = up);
+        if up {
+            stack.wait_config_down().await;
+        } else {
+            stack.wait_config_up().await;
+        }
+    }
+}
@@ -0,0 +1,57 @@
+//! Token bucket rate limiting
+//!
+//! A burst of up to `burst` messages goes out at once, after that they are
+//! limited to `rate` per second on average.
+
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    rate: u32,
+    burst: u32,
+    /// Available tokens, in thousandths
+    tokens: u64,
+    /// Milliseconds of the last refill
+    last: u64,
+}
+
+impl RateLimit {
+    pub const fn new(rate: u32, burst: u32) -> Self {
+        Self {
+            rate,
+            burst,
+            tokens: burst as u64 * 1000,
+            last: 0,
+        }
+    }
+
+    /// Take a token at `now` (milliseconds), returns whether one was left.
+    pub fn allow(&mut self, now: u64) -> bool {
+        let elapsed = now.saturating_sub(self.last);
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate as u64).min(self.burst as u64 * 1000);
+
+        if self.tokens >= 1000 {
+            self.tokens -= 1000;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit() {
+        let mut limit = RateLimit::new(10, 3);
+        assert!((0..3).all(|_| limit.allow(0)));
+        assert!(!limit.allow(0));
+        // One token every 100 ms.
+        assert!(!limit.allow(99));
+        assert!(limit.allow(100));
+        assert!(!limit.allow(100));
+        // Never more than the burst.
+        assert_eq!((0..10).filter(|_| limit.allow(60_000)).count(), 3);
+    }
+}
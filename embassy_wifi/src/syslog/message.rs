@@ -0,0 +1,158 @@
+//! RFC 5424 syslog messages
+//!
+//! ```text
+//! <134>1 2024-05-01T12:30:00.000Z esp-a1b2c3 embassy_wifi - embassy_wifi::mqtt [meta sequenceId="7"] mqtt: connected
+//! ```
+
+use core::fmt::{self, Write};
+
+use crate::clock::Timestamp;
+
+/// Longest text kept per record, longer text is truncated.
+pub const MAX_TEXT_LEN: usize = 192;
+/// Longest MSGID allowed by RFC 5424.
+pub const MAX_MSGID_LEN: usize = 32;
+
+/// `local0`, the first facility reserved for local use.
+pub const FACILITY: u8 = 16;
+
+pub const APP_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// Syslog severities, the most severe first.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Severity {
+    Emergency = 0,
+    Alert = 1,
+    Critical = 2,
+    Error = 3,
+    Warning = 4,
+    Notice = 5,
+    Informational = 6,
+    Debug = 7,
+}
+
+impl From<log::Level> for Severity {
+    fn from(level: log::Level) -> Self {
+        match level {
+            log::Level::Error => Severity::Error,
+            log::Level::Warn => Severity::Warning,
+            log::Level::Info => Severity::Informational,
+            log::Level::Debug | log::Level::Trace => Severity::Debug,
+        }
+    }
+}
+
+/// A log record, captured when it was logged and sent later.
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub severity: Severity,
+    pub timestamp: Timestamp,
+    /// The `log` target, sent as MSGID
+    pub target: heapless::String<MAX_MSGID_LEN>,
+    pub text: heapless::String<MAX_TEXT_LEN>,
+}
+
+impl Record {
+    pub fn new(
+        severity: Severity,
+        timestamp: Timestamp,
+        target: &str,
+        args: fmt::Arguments,
+    ) -> Self {
+        let mut record = Self {
+            severity,
+            timestamp,
+            target: heapless::String::new(),
+            text: heapless::String::new(),
+        };
+        Truncate(&mut record.target).write_str(target).ok();
+        Truncate(&mut record.text).write_fmt(args).ok();
+        record
+    }
+
+    /// Write the message, `seq` numbers the messages sent since boot.
+    pub fn format(&self, out: &mut impl Write, hostname: &str, seq: u32) -> fmt::Result {
+        let priority = FACILITY * 8 + self.severity as u8;
+        write!(out, "<{}>1 ", priority)?;
+        // Until the clock is synchronized there is no time to send.
+        match self.timestamp.date_time() {
+            Some(dt) => write!(out, "{}", dt)?,
+            None => out.write_char('-')?,
+        }
+        write!(out, " {} {} - ", nil_or(hostname), APP_NAME)?;
+        // MSGID and the header fields are printable US-ASCII without spaces.
+        if self.target.is_empty() {
+            out.write_char('-')?;
+        } else {
+            for c in self.target.chars() {
+                out.write_char(if c.is_ascii_graphic() { c } else { '_' })?;
+            }
+        }
+        write!(out, " [meta sequenceId=\"{}\"] {}", seq, self.text)
+    }
+}
+
+fn nil_or(value: &str) -> &str {
+    if value.is_empty() { "-" } else { value }
+}
+
+/// Writes into a `heapless::String` up to its capacity, dropping the rest
+/// instead of failing.
+struct Truncate<'a, const N: usize>(&'a mut heapless::String<N>);
+
+impl<const N: usize> Write for Truncate<'_, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.0.push(c).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{format, string::String};
+
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let timestamp = Timestamp::Utc {
+            unix_micros: 1_714_566_600_000_000,
+            utc_offset: 0,
+        };
+        let record = Record::new(
+            log::Level::Info.into(),
+            timestamp,
+            "embassy_wifi::mqtt",
+            format_args!("mqtt: {}", "connected"),
+        );
+        let mut out = String::new();
+        record.format(&mut out, "esp-a1b2c3", 7).unwrap();
+        assert_eq!(
+            out,
+            format!(
+                "<134>1 2024-05-01T12:30:00.000Z esp-a1b2c3 {} - embassy_wifi::mqtt \
+                 [meta sequenceId=\"7\"] mqtt: connected",
+                APP_NAME
+            )
+        );
+
+        // Not synchronized, and too long.
+        let long = "é".repeat(MAX_TEXT_LEN);
+        let record = Record::new(
+            Severity::Error,
+            Timestamp::Uptime(5),
+            "a b",
+            format_args!("{}", long),
+        );
+        assert_eq!(record.text.len(), MAX_TEXT_LEN);
+        out.clear();
+        record.format(&mut out, "", 1).unwrap();
+        let header = format!("<131>1 - - {} - a_b [meta sequenceId=\"1\"] éé", APP_NAME);
+        assert!(out.starts_with(&header));
+    }
+}
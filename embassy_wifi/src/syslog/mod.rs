@@ -0,0 +1,276 @@
+//! Remote logging with syslog over UDP (RFC 5424, RFC 5426)
+//!
+//! [`init`] installs a `log` logger that prints every record locally like
+//! `esp_println`'s logger did, and also copies it into a ring buffer. The
+//! `syslog` task sends the buffered records to [`COLLECTOR`], so records
+//! logged before the network is up are not lost unless the buffer
+//! overflows. Both outputs have their own level filter, and the remote one
+//! is rate limited. Dropped records are counted and reported to the
+//! collector once it can be reached again.
+//!
+//! `message` and `limit` don't depend on esp-hal or embassy-net and are
+//! tested on the host.
+
+use alloc::string::String;
+use core::cell::RefCell;
+use core::fmt::Write as _;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use embassy_net::{
+    Ipv4Address, Stack,
+    udp::{PacketMetadata, UdpSocket},
+};
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    signal::Signal,
+};
+use embassy_time::Instant;
+use esp_println::println;
+use heapless::Deque;
+use log::{LevelFilter, Log, Metadata};
+use serde::Serialize;
+
+use crate::shell::{self, Command, Context, Reply, done};
+use crate::{clock, net};
+
+pub mod limit;
+pub mod message;
+
+pub use limit::RateLimit;
+pub use message::{Record, Severity};
+
+/// Where the records are sent to, e.g. rsyslog or syslog-ng.
+pub const COLLECTOR: (Ipv4Address, u16) = (Ipv4Address::new(192, 168, 1, 100), 514);
+
+/// Records waiting to be sent, when full the oldest one is dropped.
+pub const BUFFER_LEN: usize = 32;
+
+/// Records per second sent on average, and at most at once.
+pub const RATE: u32 = 10;
+pub const BURST: u32 = 20;
+
+const MAX_MESSAGE_LEN: usize = 480;
+
+const RESET: &str = "\u{001B}[0m";
+
+pub static COMMANDS: &[Command<Context>] = &[Command::new(
+    "log level",
+    "[local|remote] [off|error|warn|info|debug|trace]",
+    "show or change the log levels",
+    level_command,
+)];
+
+static LOGGER: Logger = Logger;
+
+static STATE: Mutex<CriticalSectionRawMutex, RefCell<State>> =
+    Mutex::new(RefCell::new(State::new()));
+
+/// Wakes the `syslog` task after a record was buffered.
+static BUFFERED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// Levels as `LevelFilter as usize`, only written by the setters.
+static LOCAL_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Info as usize);
+static REMOTE_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Info as usize);
+
+struct State {
+    records: Deque<Record, BUFFER_LEN>,
+    stats: Stats,
+}
+
+impl State {
+    const fn new() -> Self {
+        Self {
+            records: Deque::new(),
+            stats: Stats {
+                sent: 0,
+                overflowed: 0,
+                rate_limited: 0,
+                failed: 0,
+            },
+        }
+    }
+}
+
+/// Counters since boot.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct Stats {
+    pub sent: u32,
+    /// Dropped because the buffer was full
+    pub overflowed: u32,
+    /// Dropped by the rate limit
+    pub rate_limited: u32,
+    /// Dropped because sending failed
+    pub failed: u32,
+}
+
+/// Install the logger, with `level` for both outputs.
+pub fn init(level: LevelFilter) {
+    LOCAL_LEVEL.store(level as usize, Ordering::Relaxed);
+    REMOTE_LEVEL.store(level as usize, Ordering::Relaxed);
+    match log::set_logger(&LOGGER) {
+        Ok(()) => log::set_max_level(level),
+        Err(_) => println!("syslog: a logger is already installed"),
+    }
+}
+
+pub fn local_level() -> LevelFilter {
+    level_filter(LOCAL_LEVEL.load(Ordering::Relaxed))
+}
+
+pub fn remote_level() -> LevelFilter {
+    level_filter(REMOTE_LEVEL.load(Ordering::Relaxed))
+}
+
+/// Change the level of the JTAG/UART output.
+pub fn set_local_level(level: LevelFilter) {
+    LOCAL_LEVEL.store(level as usize, Ordering::Relaxed);
+    log::set_max_level(level.max(remote_level()));
+}
+
+/// Change the level of the records sent to the collector.
+pub fn set_remote_level(level: LevelFilter) {
+    REMOTE_LEVEL.store(level as usize, Ordering::Relaxed);
+    log::set_max_level(level.max(local_level()));
+}
+
+pub fn stats() -> Stats {
+    STATE.lock(|state| state.borrow().stats)
+}
+
+fn level_filter(value: usize) -> LevelFilter {
+    LevelFilter::iter()
+        .find(|level| *level as usize == value)
+        .unwrap_or(LevelFilter::Off)
+}
+
+fn pop() -> Option<Record> {
+    STATE.lock(|state| state.borrow_mut().records.pop_front())
+}
+
+fn count(f: impl FnOnce(&mut Stats)) {
+    STATE.lock(|state| f(&mut state.borrow_mut().stats));
+}
+
+/// Without a level prints both, without an output changes both.
+fn level_command<'a>(_: &'a Context, args: &'a [&'a str], out: &'a mut String) -> Reply<'a> {
+    let (local, remote, level) = match args {
+        [] => {
+            writeln!(out, "local  {}", local_level()).ok();
+            writeln!(out, "remote {}", remote_level()).ok();
+            return done(Ok(()));
+        }
+        [level] => (true, true, level),
+        ["local", level] => (true, false, level),
+        ["remote", level] => (false, true, level),
+        _ => return done(Err(shell::Error::Usage)),
+    };
+    let Ok(level) = level.parse::<LevelFilter>() else {
+        return done(Err(shell::Error::Usage));
+    };
+    if local {
+        set_local_level(level);
+    }
+    if remote {
+        set_remote_level(level);
+    }
+    done(Ok(()))
+}
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= local_level().max(remote_level())
+    }
+
+    fn log(&self, record: &log::Record) {
+        let level = record.level();
+        if level <= local_level() {
+            let color = match level {
+                log::Level::Error => "\u{001B}[31m",
+                log::Level::Warn => "\u{001B}[33m",
+                log::Level::Info => "\u{001B}[32m",
+                log::Level::Debug => "\u{001B}[34m",
+                log::Level::Trace => "\u{001B}[36m",
+            };
+            println!("{}{} - {}{}", color, level, record.args(), RESET);
+        }
+
+        if level <= remote_level() {
+            let record = Record::new(level.into(), clock::now(), record.target(), *record.args());
+            STATE.lock(|state| {
+                let mut state = state.borrow_mut();
+                if let Err(record) = state.records.push_back(record) {
+                    state.records.pop_front();
+                    state.records.push_back(record).ok();
+                    state.stats.overflowed += 1;
+                }
+            });
+            BUFFERED.signal(());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[embassy_executor::task]
+pub async fn syslog(stack: Stack<'static>) {
+    let hostname = net::hostname();
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 64];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 2 * MAX_MESSAGE_LEN];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(0).unwrap();
+
+    let mut limit = RateLimit::new(RATE, BURST);
+    let mut seq = 0;
+    // Drops already reported, as overflowed + rate limited + failed.
+    let mut reported = 0;
+
+    loop {
+        // Records are kept in the buffer while there is no address.
+        stack.wait_config_up().await;
+
+        let stats = stats();
+        let dropped = stats.overflowed + stats.rate_limited + stats.failed;
+        let record = if dropped != reported && limit.allow(Instant::now().as_millis()) {
+            reported = dropped;
+            Record::new(
+                Severity::Warning,
+                clock::now(),
+                module_path!(),
+                format_args!(
+                    "syslog: dropped {} records, {} buffer full, {} rate limited, {} failed",
+                    dropped, stats.overflowed, stats.rate_limited, stats.failed
+                ),
+            )
+        } else if let Some(record) = pop() {
+            if !limit.allow(Instant::now().as_millis()) {
+                count(|stats| stats.rate_limited += 1);
+                continue;
+            }
+            record
+        } else {
+            BUFFERED.wait().await;
+            continue;
+        };
+
+        seq = seq % i32::MAX as u32 + 1;
+        let mut message = heapless::String::<MAX_MESSAGE_LEN>::new();
+        // Always fits, the text is limited to `message::MAX_TEXT_LEN`.
+        record.format(&mut message, &hostname, seq).ok();
+
+        match socket.send_to(message.as_bytes(), COLLECTOR).await {
+            Ok(()) => count(|stats| stats.sent += 1),
+            Err(_) => count(|stats| stats.failed += 1),
+        }
+    }
+}
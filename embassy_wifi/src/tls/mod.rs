@@ -0,0 +1,170 @@
+//! TLS 1.3 client connections
+//!
+//! [`connect`] wraps any `embedded_io_async` transport, usually a
+//! `TcpSocket`, in an [embedded-tls](https://github.com/drogue-iot/embedded-tls)
+//! connection. The server certificate must lead to the pinned [`CA`] and
+//! carry the server name, see `verify`.
+
+use embedded_io_async::{Read, Write};
+use embedded_tls::{
+    Aes128GcmSha256, CertificateEntryRef, CertificateRef, CryptoProvider, HandshakeVerifyRef,
+    SignatureScheme, TlsConfig, TlsConnection, TlsContext, TlsError, TlsVerifier,
+};
+use esp_hal::rng::Rng;
+use sha2::{Digest, Sha256};
+
+use crate::clock;
+
+pub mod verify;
+pub mod x509;
+
+/// The CA server certificates must be issued by, in DER format.
+pub const CA: &[u8] = include_bytes!("../../certs/ca.der");
+
+/// Incoming records can be up to 16 KiB plus overhead.
+pub const READ_BUFFER_LEN: usize = 16 * 1024 + 256;
+/// We control the size of outgoing records, small ones are fine.
+pub const WRITE_BUFFER_LEN: usize = 4096;
+
+pub type Connection<'a, T> = TlsConnection<'a, T, Aes128GcmSha256>;
+
+/// Open a TLS connection to `server_name` over `io`.
+pub async fn connect<'a, T: Read + Write + 'a>(
+    io: T,
+    server_name: &'a str,
+    ca: &'a [u8],
+    rng: Rng,
+    read_buffer: &'a mut [u8],
+    write_buffer: &'a mut [u8],
+) -> Result<Connection<'a, T>, TlsError> {
+    let config = TlsConfig::new().with_server_name(server_name);
+    let mut tls = TlsConnection::new(io, read_buffer, write_buffer);
+    tls.open(TlsContext::new(&config, Provider::new(rng, server_name, ca)))
+        .await?;
+    Ok(tls)
+}
+
+/// The hardware RNG as a `rand_core` generator.
+///
+/// It is a true random number generator while the Wi-Fi radio is running,
+/// which is always the case when we open a TLS connection.
+pub struct HwRng(pub Rng);
+
+impl rand_core::RngCore for HwRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.random()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (self.0.random() as u64) << 32 | self.0.random() as u64
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.read(dest);
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.0.read(dest);
+        Ok(())
+    }
+}
+
+impl rand_core::CryptoRng for HwRng {}
+
+struct Provider<'a> {
+    rng: HwRng,
+    verifier: PinnedVerifier<'a>,
+}
+
+impl<'a> Provider<'a> {
+    fn new(rng: Rng, server_name: &'a str, ca: &'a [u8]) -> Self {
+        Self {
+            rng: HwRng(rng),
+            verifier: PinnedVerifier {
+                ca,
+                server_name,
+                public_key: None,
+                transcript_hash: [0; 32],
+            },
+        }
+    }
+}
+
+impl CryptoProvider for Provider<'_> {
+    type CipherSuite = Aes128GcmSha256;
+    type Signature = &'static [u8];
+
+    fn rng(&mut self) -> impl rand_core::CryptoRngCore {
+        &mut self.rng
+    }
+
+    fn verifier(
+        &mut self,
+    ) -> Result<&mut impl TlsVerifier<Self::CipherSuite>, embedded_tls::CryptoError> {
+        Ok(&mut self.verifier)
+    }
+}
+
+/// Glue between embedded-tls and `verify`.
+struct PinnedVerifier<'a> {
+    ca: &'a [u8],
+    server_name: &'a str,
+    /// SEC1 key of the verified server certificate
+    public_key: Option<heapless::Vec<u8, 65>>,
+    transcript_hash: [u8; 32],
+}
+
+impl TlsVerifier<Aes128GcmSha256> for PinnedVerifier<'_> {
+    fn set_hostname_verification(&mut self, _hostname: &str) -> Result<(), TlsError> {
+        // We always check against the server name we were created with.
+        Ok(())
+    }
+
+    fn verify_certificate(
+        &mut self,
+        transcript: &Sha256,
+        _ca: &Option<embedded_tls::Certificate>,
+        cert: CertificateRef,
+    ) -> Result<(), TlsError> {
+        let chain: heapless::Vec<&[u8], 4> = cert
+            .entries
+            .iter()
+            .filter_map(|entry| match entry {
+                CertificateEntryRef::X509(der) => Some(*der),
+                _ => None,
+            })
+            .take(4)
+            .collect();
+
+        // Validity periods can only be checked once SNTP synchronized.
+        let now = clock::now().unix_micros().map(|micros| micros / 1_000_000);
+
+        let leaf = verify::verify_chain(self.ca, &chain, self.server_name, now).map_err(|e| {
+            esp_println::println!("tls: certificate rejected: {:?}", e);
+            TlsError::InvalidCertificate
+        })?;
+
+        self.public_key = Some(
+            heapless::Vec::from_slice(leaf.public_key).map_err(|_| TlsError::InvalidCertificate)?,
+        );
+        self.transcript_hash
+            .copy_from_slice(&transcript.clone().finalize());
+        Ok(())
+    }
+
+    fn verify_signature(&mut self, verify: HandshakeVerifyRef) -> Result<(), TlsError> {
+        let public_key = self.public_key.take().ok_or(TlsError::InvalidCertificate)?;
+
+        let scheme = match verify.signature_scheme {
+            SignatureScheme::EcdsaSecp256r1Sha256 => verify::ECDSA_SECP256R1_SHA256,
+            _ => return Err(TlsError::InvalidSignatureScheme),
+        };
+
+        verify::verify_handshake(&public_key, scheme, &self.transcript_hash, verify.signature).map_err(
+            |e| {
+                esp_println::println!("tls: handshake signature rejected: {:?}", e);
+                TlsError::InvalidSignature
+            },
+        )
+    }
+}
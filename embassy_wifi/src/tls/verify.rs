@@ -0,0 +1,245 @@
+//! Server certificate verification against a pinned CA.
+//!
+//! Only ECDSA P-256 with SHA-256 is supported, which is what a private CA
+//! created with `openssl ecparam -name prime256v1` uses.
+
+use p256::ecdsa::{Signature, VerifyingKey, signature::Verifier};
+
+use super::x509::{self, Certificate};
+
+/// TLS 1.3 `ecdsa_secp256r1_sha256` signature scheme.
+pub const ECDSA_SECP256R1_SHA256: u16 = 0x0403;
+
+/// Errors
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    Certificate(x509::Error),
+    /// The server didn't send a certificate
+    NoCertificate,
+    /// Not ECDSA P-256 with SHA-256
+    UnsupportedAlgorithm,
+    /// The chain doesn't end at the pinned CA
+    UntrustedIssuer,
+    /// An issuer isn't a CA allowed to sign certificates
+    NotCa,
+    BadSignature,
+    /// None of the DNS names matches the server name
+    HostnameMismatch,
+    Expired,
+    NotYetValid,
+}
+
+impl From<x509::Error> for Error {
+    fn from(e: x509::Error) -> Self {
+        Error::Certificate(e)
+    }
+}
+
+/// Verify the certificate chain sent by the server.
+///
+/// `chain` starts with the server certificate, optionally followed by
+/// intermediates. It is accepted if it leads to `ca`, or if the server
+/// certificate itself is `ca`. Every issuer, `ca` included, must be a CA
+/// with the `keyCertSign` key usage. `now` is in seconds since the Unix
+/// epoch, validity periods are not checked without it.
+///
+/// Returns the server certificate, whose P-256 key signs the handshake.
+pub fn verify_chain<'a>(
+    ca: &[u8],
+    chain: &[&'a [u8]],
+    hostname: &str,
+    now: Option<i64>,
+) -> Result<Certificate<'a>, Error> {
+    let ca_cert = Certificate::parse(ca)?;
+    let leaf = Certificate::parse(chain.first().ok_or(Error::NoCertificate)?)?;
+
+    if !leaf.dns_names().any(|name| hostname_matches(name, hostname)) {
+        return Err(Error::HostnameMismatch);
+    }
+    if !is_p256(&leaf) {
+        return Err(Error::UnsupportedAlgorithm);
+    }
+
+    let mut cert = leaf;
+    for (i, der) in chain.iter().enumerate() {
+        check_validity(&cert, now)?;
+
+        if *der == ca {
+            return Ok(leaf);
+        }
+
+        if cert.issuer == ca_cert.subject {
+            check_validity(&ca_cert, now)?;
+            check_issuer(&ca_cert)?;
+            verify_signature(&ca_cert, cert.signature_algorithm, cert.tbs, cert.signature)?;
+            return Ok(leaf);
+        }
+
+        let issuer = Certificate::parse(chain.get(i + 1).ok_or(Error::UntrustedIssuer)?)?;
+        if cert.issuer != issuer.subject {
+            return Err(Error::UntrustedIssuer);
+        }
+        check_issuer(&issuer)?;
+        verify_signature(&issuer, cert.signature_algorithm, cert.tbs, cert.signature)?;
+        cert = issuer;
+    }
+
+    Err(Error::UntrustedIssuer)
+}
+
+/// Verify the server's CertificateVerify message (RFC 8446, 4.4.3).
+///
+/// `public_key` is the key of the server certificate returned by
+/// [`verify_chain`], `transcript_hash` the SHA-256 of the handshake up to
+/// and including the Certificate message.
+pub fn verify_handshake(
+    public_key: &[u8],
+    scheme: u16,
+    transcript_hash: &[u8],
+    signature: &[u8],
+) -> Result<(), Error> {
+    if scheme != ECDSA_SECP256R1_SHA256 {
+        return Err(Error::UnsupportedAlgorithm);
+    }
+
+    let mut message = heapless::Vec::<u8, 130>::new();
+    message.resize(64, 0x20).ok();
+    message
+        .extend_from_slice(b"TLS 1.3, server CertificateVerify\0")
+        .map_err(|_| Error::UnsupportedAlgorithm)?;
+    message
+        .extend_from_slice(transcript_hash)
+        .map_err(|_| Error::UnsupportedAlgorithm)?;
+
+    verify_p256(public_key, &message, signature)
+}
+
+/// Check that `signature` over `data` was made with the key of `signer`.
+fn verify_signature(
+    signer: &Certificate,
+    algorithm: &[u8],
+    data: &[u8],
+    signature: &[u8],
+) -> Result<(), Error> {
+    if algorithm != x509::OID_ECDSA_WITH_SHA256 || !is_p256(signer) {
+        return Err(Error::UnsupportedAlgorithm);
+    }
+
+    verify_p256(signer.public_key, data, signature)
+}
+
+fn is_p256(cert: &Certificate) -> bool {
+    cert.public_key_algorithm == x509::OID_EC_PUBLIC_KEY
+        && cert.public_key_curve == Some(x509::OID_PRIME256V1)
+}
+
+/// ECDSA P-256 with SHA-256, `signature` is DER encoded.
+fn verify_p256(public_key: &[u8], data: &[u8], signature: &[u8]) -> Result<(), Error> {
+    let key = VerifyingKey::from_sec1_bytes(public_key).map_err(|_| Error::BadSignature)?;
+    let signature = Signature::from_der(signature).map_err(|_| Error::BadSignature)?;
+    key.verify(data, &signature).map_err(|_| Error::BadSignature)
+}
+
+fn check_issuer(cert: &Certificate) -> Result<(), Error> {
+    match cert.is_issuer() {
+        true => Ok(()),
+        false => Err(Error::NotCa),
+    }
+}
+
+fn check_validity(cert: &Certificate, now: Option<i64>) -> Result<(), Error> {
+    match now {
+        Some(now) if now < cert.not_before => Err(Error::NotYetValid),
+        Some(now) if now > cert.not_after => Err(Error::Expired),
+        _ => Ok(()),
+    }
+}
+
+/// Compare a certificate DNS name with the server name, `*.example.com`
+/// matches exactly one label.
+fn hostname_matches(pattern: &str, hostname: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => match hostname.split_once('.') {
+            Some((label, rest)) => !label.is_empty() && rest.eq_ignore_ascii_case(suffix),
+            None => false,
+        },
+        None => pattern.eq_ignore_ascii_case(hostname),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CA: &[u8] = include_bytes!("testdata/ca.der");
+    const SERVER: &[u8] = include_bytes!("testdata/server.der");
+    const SERVER_VERIFY: &[u8] = include_bytes!("testdata/server-verify.sig");
+    /// A certificate for example.com signed with the key of `SERVER`
+    const FORGED: &[u8] = include_bytes!("testdata/forged.der");
+
+    #[test]
+    fn test_chain() {
+        let leaf = verify_chain(CA, &[SERVER], "esp-test.local", None).unwrap();
+        assert!(verify_chain(CA, &[SERVER], "a.esp-test.local", None).is_ok());
+        assert!(verify_chain(CA, &[SERVER, CA], "esp-test.local", None).is_ok());
+
+        assert_eq!(
+            verify_chain(CA, &[SERVER], "example.com", None).unwrap_err(),
+            Error::HostnameMismatch
+        );
+        assert_eq!(
+            verify_chain(SERVER, &[CA], "esp-test.local", None).unwrap_err(),
+            Error::HostnameMismatch
+        );
+        assert_eq!(
+            verify_chain(CA, &[SERVER], "esp-test.local", Some(leaf.not_after + 1)).unwrap_err(),
+            Error::Expired
+        );
+
+        // The server certificate with a damaged signature.
+        let mut forged = [0; SERVER.len()];
+        forged.copy_from_slice(SERVER);
+        let last = forged.len() - 1;
+        forged[last] ^= 1;
+        assert_eq!(
+            verify_chain(CA, &[&forged], "esp-test.local", None).unwrap_err(),
+            Error::BadSignature
+        );
+    }
+
+    #[test]
+    fn test_issuer() {
+        // The signatures are fine, but the server certificate is no CA.
+        assert_eq!(
+            verify_chain(CA, &[FORGED, SERVER], "example.com", None).unwrap_err(),
+            Error::NotCa
+        );
+        assert_eq!(
+            verify_chain(CA, &[FORGED, SERVER, CA], "example.com", None).unwrap_err(),
+            Error::NotCa
+        );
+        assert_eq!(
+            verify_chain(SERVER, &[FORGED], "example.com", None).unwrap_err(),
+            Error::NotCa
+        );
+    }
+
+    #[test]
+    fn test_handshake() {
+        let key = Certificate::parse(SERVER).unwrap().public_key;
+        let transcript_hash = [0x42; 32];
+
+        assert_eq!(
+            verify_handshake(key, ECDSA_SECP256R1_SHA256, &transcript_hash, SERVER_VERIFY),
+            Ok(())
+        );
+        assert_eq!(
+            verify_handshake(key, ECDSA_SECP256R1_SHA256, &[0; 32], SERVER_VERIFY),
+            Err(Error::BadSignature)
+        );
+        assert_eq!(
+            verify_handshake(key, 0x0804, &transcript_hash, SERVER_VERIFY),
+            Err(Error::UnsupportedAlgorithm)
+        );
+    }
+}
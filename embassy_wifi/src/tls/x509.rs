@@ -0,0 +1,359 @@
+//! Just enough X.509 (RFC 5280) to verify a pinned certificate chain.
+
+use crate::clock::days_from_civil;
+
+/// Errors
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Invalid DER encoding
+    Der,
+    /// Valid DER, but not the expected structure
+    Structure,
+    /// Unparsable validity time
+    Time,
+    /// A critical extension we don't understand
+    UnknownCriticalExtension,
+}
+
+pub const OID_ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+pub const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+pub const OID_PRIME256V1: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x0f];
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+const OID_BASIC_CONSTRAINTS: &[u8] = &[0x55, 0x1d, 0x13];
+
+/// `keyCertSign` in the first byte of the key usage bits
+const KEY_USAGE_KEY_CERT_SIGN: u8 = 0x04;
+
+const TAG_BOOLEAN: u8 = 0x01;
+const TAG_INTEGER: u8 = 0x02;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_OID: u8 = 0x06;
+const TAG_UTC_TIME: u8 = 0x17;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_VERSION: u8 = 0xa0;
+const TAG_EXTENSIONS: u8 = 0xa3;
+const TAG_DNS_NAME: u8 = 0x82;
+
+/// One DER element.
+#[derive(Debug, Copy, Clone)]
+struct Tlv<'a> {
+    tag: u8,
+    content: &'a [u8],
+    /// Tag, length and content
+    raw: &'a [u8],
+}
+
+/// Reads consecutive DER elements.
+#[derive(Debug, Copy, Clone)]
+struct Der<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Der<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    fn peek_tag(&self) -> Option<u8> {
+        self.buf.first().copied()
+    }
+
+    fn read(&mut self) -> Result<Tlv<'a>, Error> {
+        let (&tag, rest) = self.buf.split_first().ok_or(Error::Der)?;
+        let (&first, mut rest) = rest.split_first().ok_or(Error::Der)?;
+
+        let len = match first {
+            0..=0x7f => first as usize,
+            0x81..=0x84 => {
+                let n = (first & 0x7f) as usize;
+                if rest.len() < n {
+                    return Err(Error::Der);
+                }
+                let len = rest[..n].iter().fold(0, |len, b| len << 8 | *b as usize);
+                rest = &rest[n..];
+                len
+            }
+            _ => return Err(Error::Der),
+        };
+
+        if rest.len() < len {
+            return Err(Error::Der);
+        }
+
+        let header_len = self.buf.len() - rest.len();
+        let raw = &self.buf[..header_len + len];
+        self.buf = &self.buf[header_len + len..];
+
+        Ok(Tlv {
+            tag,
+            content: &rest[..len],
+            raw,
+        })
+    }
+
+    fn expect(&mut self, tag: u8) -> Result<Tlv<'a>, Error> {
+        let tlv = self.read()?;
+        if tlv.tag != tag {
+            return Err(Error::Structure);
+        }
+        Ok(tlv)
+    }
+
+    fn sequence(&mut self) -> Result<Der<'a>, Error> {
+        Ok(Der::new(self.expect(TAG_SEQUENCE)?.content))
+    }
+
+    /// BIT STRING content without the unused bits count.
+    fn bit_string(&mut self) -> Result<&'a [u8], Error> {
+        match self.expect(TAG_BIT_STRING)?.content {
+            [0, bits @ ..] => Ok(bits),
+            _ => Err(Error::Structure),
+        }
+    }
+}
+
+/// A parsed certificate, borrowing from its DER encoding.
+#[derive(Debug, Copy, Clone)]
+pub struct Certificate<'a> {
+    /// DER of `tbsCertificate`, the signed part
+    pub tbs: &'a [u8],
+    pub signature_algorithm: &'a [u8],
+    pub signature: &'a [u8],
+    /// DER of the issuer name
+    pub issuer: &'a [u8],
+    /// DER of the subject name
+    pub subject: &'a [u8],
+    /// Seconds since the Unix epoch
+    pub not_before: i64,
+    /// Seconds since the Unix epoch
+    pub not_after: i64,
+    pub public_key_algorithm: &'a [u8],
+    pub public_key_curve: Option<&'a [u8]>,
+    pub public_key: &'a [u8],
+    /// `cA` of the basic constraints extension
+    pub ca: bool,
+    /// First byte of the key usage extension, `None` without it
+    key_usage: Option<u8>,
+    /// Content of the subject alternative name extension
+    subject_alt_names: Option<&'a [u8]>,
+}
+
+impl<'a> Certificate<'a> {
+    pub fn parse(der: &'a [u8]) -> Result<Self, Error> {
+        let mut outer = Der::new(der);
+        let mut cert = outer.sequence()?;
+        if !outer.is_empty() {
+            return Err(Error::Der);
+        }
+
+        let tbs = cert.expect(TAG_SEQUENCE)?;
+        let signature_algorithm = cert.sequence()?.expect(TAG_OID)?.content;
+        let signature = cert.bit_string()?;
+
+        let mut fields = Der::new(tbs.content);
+        if fields.peek_tag() == Some(TAG_VERSION) {
+            fields.read()?;
+        }
+        fields.expect(TAG_INTEGER)?;
+        fields.sequence()?;
+        let issuer = fields.expect(TAG_SEQUENCE)?.raw;
+
+        let mut validity = fields.sequence()?;
+        let not_before = parse_time(validity.read()?)?;
+        let not_after = parse_time(validity.read()?)?;
+
+        let subject = fields.expect(TAG_SEQUENCE)?.raw;
+
+        let mut spki = fields.sequence()?;
+        let mut algorithm = spki.sequence()?;
+        let public_key_algorithm = algorithm.expect(TAG_OID)?.content;
+        let public_key_curve = match algorithm.peek_tag() {
+            Some(TAG_OID) => Some(algorithm.read()?.content),
+            _ => None,
+        };
+        let public_key = spki.bit_string()?;
+
+        let mut ca = false;
+        let mut key_usage = None;
+        let mut subject_alt_names = None;
+        while !fields.is_empty() {
+            let field = fields.read()?;
+            if field.tag != TAG_EXTENSIONS {
+                continue;
+            }
+
+            let mut extensions = Der::new(field.content).sequence()?;
+            while !extensions.is_empty() {
+                let mut extension = extensions.sequence()?;
+                let id = extension.expect(TAG_OID)?.content;
+                let critical = match extension.peek_tag() {
+                    Some(TAG_BOOLEAN) => parse_bool(extension.read()?)?,
+                    _ => false,
+                };
+                let value = extension.expect(TAG_OCTET_STRING)?.content;
+                match id {
+                    OID_SUBJECT_ALT_NAME => {
+                        subject_alt_names = Some(Der::new(value).sequence()?.buf);
+                    }
+                    OID_BASIC_CONSTRAINTS => {
+                        // cA is DEFAULT FALSE, the path length is ignored
+                        let mut constraints = Der::new(value).sequence()?;
+                        if constraints.peek_tag() == Some(TAG_BOOLEAN) {
+                            ca = parse_bool(constraints.read()?)?;
+                        }
+                    }
+                    OID_KEY_USAGE => {
+                        // The unused bits count is usually not 0 here
+                        key_usage = match Der::new(value).expect(TAG_BIT_STRING)?.content {
+                            [_unused] => Some(0),
+                            [_unused, bits, ..] => Some(*bits),
+                            [] => return Err(Error::Structure),
+                        };
+                    }
+                    _ if critical => return Err(Error::UnknownCriticalExtension),
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(Self {
+            tbs: tbs.raw,
+            signature_algorithm,
+            signature,
+            issuer,
+            subject,
+            not_before,
+            not_after,
+            public_key_algorithm,
+            public_key_curve,
+            public_key,
+            ca,
+            key_usage,
+            subject_alt_names,
+        })
+    }
+
+    /// Whether the certificate may sign other certificates, RFC 5280
+    /// 4.2.1.3 and 4.2.1.9. Both extensions are required.
+    pub fn is_issuer(&self) -> bool {
+        self.ca && self.key_usage.is_some_and(|bits| bits & KEY_USAGE_KEY_CERT_SIGN != 0)
+    }
+
+    /// DNS names from the subject alternative name extension.
+    pub fn dns_names(&self) -> impl Iterator<Item = &'a str> {
+        let mut names = Der::new(self.subject_alt_names.unwrap_or(&[]));
+        core::iter::from_fn(move || {
+            while !names.is_empty() {
+                let name = names.read().ok()?;
+                if name.tag == TAG_DNS_NAME {
+                    return core::str::from_utf8(name.content).ok();
+                }
+            }
+            None
+        })
+    }
+}
+
+/// DER BOOLEAN, which only allows 0x00 and 0xff.
+fn parse_bool(tlv: Tlv) -> Result<bool, Error> {
+    match (tlv.tag, tlv.content) {
+        (TAG_BOOLEAN, [0x00]) => Ok(false),
+        (TAG_BOOLEAN, [0xff]) => Ok(true),
+        _ => Err(Error::Der),
+    }
+}
+
+/// UTCTime or GeneralizedTime in seconds since the Unix epoch.
+fn parse_time(tlv: Tlv) -> Result<i64, Error> {
+    let digits = |s: &[u8]| -> Result<u32, Error> {
+        s.iter().try_fold(0, |n, c| match c {
+            b'0'..=b'9' => Ok(n * 10 + (c - b'0') as u32),
+            _ => Err(Error::Time),
+        })
+    };
+
+    // Both must be in UTC with seconds, RFC 5280 4.1.2.5
+    let (year, rest) = match (tlv.tag, tlv.content) {
+        (TAG_UTC_TIME, [y @ .., b'Z']) if y.len() == 12 => {
+            let year = digits(&y[..2])? as i32;
+            (if year < 50 { 2000 + year } else { 1900 + year }, &y[2..])
+        }
+        (TAG_GENERALIZED_TIME, [y @ .., b'Z']) if y.len() == 14 => {
+            (digits(&y[..4])? as i32, &y[4..])
+        }
+        _ => return Err(Error::Time),
+    };
+
+    let month = digits(&rest[0..2])? as u8;
+    let day = digits(&rest[2..4])? as u8;
+    let hour = digits(&rest[4..6])? as i64;
+    let minute = digits(&rest[6..8])? as i64;
+    let second = digits(&rest[8..10])? as i64;
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(Error::Time);
+    }
+
+    let days = days_from_civil(year, month, day);
+    Ok(days * 86_400 + hour * 3600 + minute * 60 + second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CA: &[u8] = include_bytes!("testdata/ca.der");
+    const SERVER: &[u8] = include_bytes!("testdata/server.der");
+    const CRITICAL: &[u8] = include_bytes!("testdata/critical.der");
+
+    #[test]
+    fn test_parse() {
+        let cert = Certificate::parse(SERVER).unwrap();
+        assert_eq!(cert.signature_algorithm, OID_ECDSA_WITH_SHA256);
+        assert_eq!(cert.public_key_algorithm, OID_EC_PUBLIC_KEY);
+        assert_eq!(cert.public_key_curve, Some(OID_PRIME256V1));
+        assert_eq!(cert.public_key.len(), 65);
+        assert!(cert.dns_names().eq(["esp-test.local", "*.esp-test.local"]));
+        assert!(cert.not_before < cert.not_after);
+        assert!(!cert.is_issuer());
+
+        assert_eq!(Certificate::parse(&SERVER[..100]).unwrap_err(), Error::Der);
+    }
+
+    #[test]
+    fn test_extensions() {
+        let ca = Certificate::parse(CA).unwrap();
+        assert!(ca.ca);
+        assert!(ca.is_issuer());
+        assert_eq!(ca.dns_names().count(), 0);
+
+        assert_eq!(
+            Certificate::parse(CRITICAL).unwrap_err(),
+            Error::UnknownCriticalExtension
+        );
+    }
+
+    #[test]
+    fn test_time() {
+        let utc = Tlv {
+            tag: TAG_UTC_TIME,
+            content: b"240501123000Z",
+            raw: &[],
+        };
+        assert_eq!(parse_time(utc), Ok(1_714_566_600));
+
+        let generalized = Tlv {
+            tag: TAG_GENERALIZED_TIME,
+            content: b"20240501123000Z",
+            raw: &[],
+        };
+        assert_eq!(parse_time(generalized), Ok(1_714_566_600));
+    }
+}
@@ -0,0 +1,590 @@
+//! A minimal async WebSocket client.
+//!
+//! Like the MQTT client it is generic over `embedded_io_async`, so it runs
+//! on top of an embassy-net `TcpSocket` or a TLS connection on the device
+//! and on top of a scripted server in the tests below.
+
+use embassy_time::{Duration, Instant, with_deadline, with_timeout};
+use embedded_io_async::{Read, Write};
+use rand_core::RngCore;
+
+use super::frame::{self, Header, MAX_CONTROL_LEN, Opcode};
+use super::handshake;
+use crate::io;
+
+/// How long to wait for the handshake response, a pong and the close frame
+/// of the server.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sent when closing without a reason.
+pub const NORMAL_CLOSURE: u16 = 1000;
+
+/// Errors
+#[derive(Debug)]
+pub enum Error<E> {
+    Io(io::Error<E>),
+    Handshake(handshake::Error),
+    Frame(frame::Error),
+    /// The connection was closed, with the status code of the close frame
+    /// of the server if it sent one
+    Closed(Option<u16>),
+    /// The server didn't answer in time
+    Timeout,
+    /// A frame doesn't fit into the receive buffer, or a message into the
+    /// message buffer
+    MessageTooLarge,
+    /// A text message that isn't UTF-8
+    InvalidUtf8,
+    /// A continuation frame outside of a fragmented message, or a new
+    /// message before the last one ended
+    UnexpectedFragment,
+}
+
+impl<E> From<io::Error<E>> for Error<E> {
+    fn from(e: io::Error<E>) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl<E> From<frame::Error> for Error<E> {
+    fn from(e: frame::Error) -> Self {
+        Error::Frame(e)
+    }
+}
+
+impl<E> From<handshake::Error> for Error<E> {
+    fn from(e: handshake::Error) -> Self {
+        Error::Handshake(e)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kind {
+    Text,
+    Binary,
+}
+
+/// A complete message, reassembled from its fragments.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Message<'a> {
+    Text(&'a str),
+    Binary(&'a [u8]),
+}
+
+pub struct Client<'b, T, R> {
+    io: T,
+    rng: R,
+    ping_interval: Duration,
+    tx: &'b mut [u8],
+    rx: &'b mut [u8],
+    rx_len: usize,
+    /// Payload of the last message
+    message: &'b mut [u8],
+    message_len: usize,
+    /// Kind of the message whose fragments are being received
+    fragmented: Option<Kind>,
+    /// A close frame was sent
+    closing: bool,
+    /// The server sent a close frame, with its status code
+    closed: Option<Option<u16>>,
+    last_tx: Instant,
+    ping_sent: Option<Instant>,
+    /// The ping interval passed without sending anything
+    ping_due: bool,
+    /// Payload of the last ping, to be answered
+    pong: Option<heapless::Vec<u8, MAX_CONTROL_LEN>>,
+}
+
+impl<'b, T, R> Client<'b, T, R>
+where
+    T: Read + Write,
+    R: RngCore,
+{
+    /// Creates a new client.
+    ///
+    /// `rng` makes the masking keys. A ping is sent after `ping_interval`
+    /// without sending anything, zero disables pings. Frames must fit into
+    /// `rx`, whole messages into `message`, and sent messages are split
+    /// into frames that fit into `tx`.
+    pub fn new(
+        io: T,
+        rng: R,
+        ping_interval: Duration,
+        tx: &'b mut [u8],
+        rx: &'b mut [u8],
+        message: &'b mut [u8],
+    ) -> Self {
+        Self {
+            io,
+            rng,
+            ping_interval,
+            tx,
+            rx,
+            rx_len: 0,
+            message,
+            message_len: 0,
+            fragmented: None,
+            closing: false,
+            closed: None,
+            last_tx: Instant::now(),
+            ping_sent: None,
+            ping_due: false,
+            pong: None,
+        }
+    }
+
+    /// Send the upgrade request for `path` on `host` and check the
+    /// response.
+    pub async fn connect(&mut self, host: &str, path: &str) -> Result<(), Error<T::Error>> {
+        let mut random = [0; 16];
+        self.rng.fill_bytes(&mut random);
+        let key = handshake::key(random);
+
+        let n = handshake::encode_request(self.tx, host, path, &key)?;
+        self.send(n).await?;
+
+        let head_len = with_timeout(RESPONSE_TIMEOUT, self.read_head())
+            .await
+            .map_err(|_| Error::Timeout)??;
+        handshake::check_response(&self.rx[..head_len], &key)?;
+        // Frames the server sent right after the response stay.
+        self.consume(head_len);
+        Ok(())
+    }
+
+    pub async fn send_text(&mut self, text: &str) -> Result<(), Error<T::Error>> {
+        self.send_message(Opcode::Text, text.as_bytes()).await
+    }
+
+    pub async fn send_binary(&mut self, data: &[u8]) -> Result<(), Error<T::Error>> {
+        self.send_message(Opcode::Binary, data).await
+    }
+
+    /// Handle incoming frames and watch the ping interval.
+    ///
+    /// Returns a message once one is complete, or `None` when a control
+    /// frame is due. It only reads, pings, pongs and the close frame are
+    /// queued for [`flush`](Self::flush), so it can be raced against other
+    /// work with `select` without cutting a frame short.
+    pub async fn poll(&mut self) -> Result<Option<Message<'_>>, Error<T::Error>> {
+        let now = Instant::now();
+
+        if let Some(sent) = self.ping_sent {
+            if now >= sent + RESPONSE_TIMEOUT {
+                return Err(Error::Timeout);
+            }
+        } else if self.ping_interval.as_ticks() > 0 && now >= self.last_tx + self.ping_interval {
+            self.ping_due = true;
+        }
+
+        // Nothing more is read until the queued frames went out.
+        if self.ping_due || self.pong.is_some() {
+            return Ok(None);
+        }
+        match self.closed {
+            Some(code) if self.closing => return Err(Error::Closed(code)),
+            Some(_) => return Ok(None),
+            None => (),
+        }
+
+        let deadline = match (self.ping_sent, self.ping_interval.as_ticks()) {
+            (Some(sent), _) => sent + RESPONSE_TIMEOUT,
+            (None, 0) => Instant::MAX,
+            (None, _) => self.last_tx + self.ping_interval,
+        };
+
+        match with_deadline(deadline, self.receive()).await {
+            Ok(kind) => match kind? {
+                Some(kind) => self.message(kind).map(Some),
+                None => Ok(None),
+            },
+            Err(_) if self.ping_sent.is_some() => Err(Error::Timeout),
+            Err(_) => {
+                self.ping_due = true;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Send the control frames [`poll`](Self::poll) queued, call it before
+    /// every `poll` outside of the `select`.
+    pub async fn flush(&mut self) -> Result<(), Error<T::Error>> {
+        if let Some(data) = self.pong.take() {
+            self.send_frame(true, Opcode::Pong, &data).await?;
+        }
+
+        if self.ping_due {
+            self.send_frame(true, Opcode::Ping, &[]).await?;
+            self.ping_sent = Some(Instant::now());
+            self.ping_due = false;
+        }
+
+        if let (Some(code), false) = (self.closed, self.closing) {
+            // Echo the status code to complete the close handshake.
+            let mut data = [0; 2];
+            let n = match code {
+                Some(code) => frame::close_payload(&mut data, code, "")?,
+                None => 0,
+            };
+            self.send_frame(true, Opcode::Close, &data[..n]).await?;
+            self.closing = true;
+        }
+
+        Ok(())
+    }
+
+    /// Send a close frame and wait for the one of the server. Messages
+    /// still arriving are dropped and pings not answered.
+    pub async fn close(&mut self, code: u16, reason: &str) -> Result<(), Error<T::Error>> {
+        let mut payload = [0; MAX_CONTROL_LEN];
+        let n = frame::close_payload(&mut payload, code, reason)?;
+        self.send_frame(true, Opcode::Close, &payload[..n]).await?;
+        self.closing = true;
+
+        with_timeout(RESPONSE_TIMEOUT, async {
+            loop {
+                match self.receive().await {
+                    Ok(_) => self.pong = None,
+                    Err(Error::Closed(_)) => return Ok(()),
+                    Err(e) => return Err(e),
+                }
+            }
+        })
+        .await
+        .map_err(|_| Error::Timeout)?
+    }
+
+    /// Send `data` in as many frames as needed.
+    async fn send_message(&mut self, opcode: Opcode, data: &[u8]) -> Result<(), Error<T::Error>> {
+        let max = self.tx.len().saturating_sub(frame::MAX_HEADER_LEN);
+        if max == 0 {
+            return Err(frame::Error::BufferTooSmall.into());
+        }
+        if data.is_empty() {
+            return self.send_frame(true, opcode, &[]).await;
+        }
+
+        let mut opcode = opcode;
+        let mut chunks = data.chunks(max).peekable();
+        while let Some(chunk) = chunks.next() {
+            let fin = chunks.peek().is_none();
+            self.send_frame(fin, opcode, chunk).await?;
+            opcode = Opcode::Continuation;
+        }
+        Ok(())
+    }
+
+    async fn send_frame(
+        &mut self,
+        fin: bool,
+        opcode: Opcode,
+        payload: &[u8],
+    ) -> Result<(), Error<T::Error>> {
+        let mask = self.rng.next_u32().to_ne_bytes();
+        let n = frame::encode(self.tx, fin, opcode, Some(mask), payload)?;
+        self.send(n).await
+    }
+
+    async fn send(&mut self, len: usize) -> Result<(), Error<T::Error>> {
+        io::write_all(&mut self.io, &self.tx[..len]).await?;
+        self.io
+            .flush()
+            .await
+            .map_err(|e| Error::Io(io::Error::Io(e)))?;
+        self.last_tx = Instant::now();
+        Ok(())
+    }
+
+    /// Receive frames until a message is complete, or `None` once a control
+    /// frame has to be answered.
+    ///
+    /// Partial frames stay in the receive buffer and fragments in the
+    /// message buffer, so dropping this future doesn't lose data.
+    async fn receive(&mut self) -> Result<Option<Kind>, Error<T::Error>> {
+        loop {
+            let (header, header_len) = self.frame().await?;
+            let len = header.len as usize;
+            let payload = header_len..header_len + len;
+
+            match header.opcode {
+                Opcode::Text | Opcode::Binary | Opcode::Continuation => {
+                    let kind = match (header.opcode, self.fragmented) {
+                        (Opcode::Continuation, Some(kind)) => kind,
+                        (Opcode::Continuation, None) | (_, Some(_)) => {
+                            return Err(Error::UnexpectedFragment);
+                        }
+                        (Opcode::Text, None) => Kind::Text,
+                        (_, None) => Kind::Binary,
+                    };
+                    if header.opcode != Opcode::Continuation {
+                        self.message_len = 0;
+                    }
+                    let end = self.message_len + len;
+                    if end > self.message.len() {
+                        return Err(Error::MessageTooLarge);
+                    }
+                    self.message[self.message_len..end].copy_from_slice(&self.rx[payload]);
+                    self.message_len = end;
+                    self.consume(header_len + len);
+
+                    if header.fin {
+                        self.fragmented = None;
+                        return Ok(Some(kind));
+                    }
+                    self.fragmented = Some(kind);
+                }
+                Opcode::Ping => {
+                    // Control frames are never longer, see `Header::parse`.
+                    self.pong = heapless::Vec::from_slice(&self.rx[payload]).ok();
+                    self.consume(header_len + len);
+                    return Ok(None);
+                }
+                Opcode::Pong => {
+                    self.consume(header_len + len);
+                    self.ping_sent = None;
+                }
+                Opcode::Close => {
+                    let code = frame::close_code(&self.rx[payload]);
+                    self.consume(header_len + len);
+                    self.closed = Some(code);
+                    if !self.closing {
+                        return Ok(None);
+                    }
+                    return Err(Error::Closed(code));
+                }
+            }
+        }
+    }
+
+    /// Wait until a whole frame is in the receive buffer.
+    async fn frame(&mut self) -> Result<(Header, usize), Error<T::Error>> {
+        loop {
+            if let Some((header, header_len)) = Header::parse(&self.rx[..self.rx_len])? {
+                if header.mask.is_some() {
+                    return Err(frame::Error::Masked.into());
+                }
+                let len = header_len as u64 + header.len;
+                if len > self.rx.len() as u64 {
+                    return Err(Error::MessageTooLarge);
+                }
+                if self.rx_len as u64 >= len {
+                    return Ok((header, header_len));
+                }
+            }
+            self.fill().await?;
+        }
+    }
+
+    /// Read until the response head is complete, returns its length.
+    async fn read_head(&mut self) -> Result<usize, Error<T::Error>> {
+        loop {
+            if let Some(end) = self.rx[..self.rx_len]
+                .windows(4)
+                .position(|window| window == b"\r\n\r\n")
+            {
+                return Ok(end + 4);
+            }
+            if self.rx_len == self.rx.len() {
+                return Err(Error::MessageTooLarge);
+            }
+            self.fill().await?;
+        }
+    }
+
+    async fn fill(&mut self) -> Result<(), Error<T::Error>> {
+        let n = self
+            .io
+            .read(&mut self.rx[self.rx_len..])
+            .await
+            .map_err(|e| Error::Io(io::Error::Io(e)))?;
+        if n == 0 {
+            return Err(Error::Closed(None));
+        }
+        self.rx_len += n;
+        Ok(())
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.rx.copy_within(len..self.rx_len, 0);
+        self.rx_len -= len;
+    }
+
+    fn message(&self, kind: Kind) -> Result<Message<'_>, Error<T::Error>> {
+        let payload = &self.message[..self.message_len];
+        match kind {
+            Kind::Text => core::str::from_utf8(payload)
+                .map(Message::Text)
+                .map_err(|_| Error::InvalidUtf8),
+            Kind::Binary => Ok(Message::Binary(payload)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use core::convert::Infallible;
+
+    use embassy_futures::block_on;
+    use embedded_io_async::ErrorType;
+
+    use super::*;
+
+    /// Reads `input` a few bytes at a time and keeps what is written.
+    struct Script<'a> {
+        input: &'a [u8],
+        output: Vec<u8>,
+    }
+
+    impl ErrorType for Script<'_> {
+        type Error = Infallible;
+    }
+
+    impl Read for Script<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+            let n = buf.len().min(self.input.len()).min(7);
+            buf[..n].copy_from_slice(&self.input[..n]);
+            self.input = &self.input[n..];
+            Ok(n)
+        }
+    }
+
+    impl Write for Script<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    /// Masking keys of zero leave the payloads readable.
+    struct Zeros;
+
+    impl RngCore for Zeros {
+        fn next_u32(&mut self) -> u32 {
+            0
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            0
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            dest.fill(0);
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            dest.fill(0);
+            Ok(())
+        }
+    }
+
+    /// Split written frames into opcode, fin and payload.
+    fn frames(mut data: &[u8]) -> Vec<(Opcode, bool, Vec<u8>)> {
+        let mut frames = Vec::new();
+        while let Some((header, n)) = Header::parse(data).unwrap() {
+            assert!(header.mask.is_some());
+            let end = n + header.len as usize;
+            frames.push((header.opcode, header.fin, data[n..end].to_vec()));
+            data = &data[end..];
+        }
+        frames
+    }
+
+    #[test]
+    fn test_session() {
+        let key = handshake::key([0; 16]);
+        let mut input = Vec::new();
+        input.extend_from_slice(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n");
+        input.extend_from_slice(b"Connection: Upgrade\r\nSec-WebSocket-Accept: ");
+        input.extend_from_slice(&handshake::accept(&key));
+        input.extend_from_slice(b"\r\n\r\n");
+        // "Hel", a ping in between, "lo", then binary and a close.
+        input.extend_from_slice(&[0x01, 3, b'H', b'e', b'l']);
+        input.extend_from_slice(&[0x89, 2, b'h', b'i']);
+        input.extend_from_slice(&[0x80, 2, b'l', b'o']);
+        input.extend_from_slice(&[0x82, 2, 0xff, 0x00]);
+        input.extend_from_slice(&[0x88, 2, 0x03, 0xe8]);
+
+        let mut tx = [0; 256];
+        let mut rx = [0; 256];
+        let mut message = [0; 16];
+        let io = Script {
+            input: &input,
+            output: Vec::new(),
+        };
+        let mut client = Client::new(
+            io,
+            Zeros,
+            Duration::from_secs(0),
+            &mut tx,
+            &mut rx,
+            &mut message,
+        );
+
+        block_on(client.connect("dashboard.local", "/ws")).unwrap();
+        assert!(client.io.output.starts_with(b"GET /ws HTTP/1.1\r\n"));
+        client.io.output.clear();
+
+        // The ping is answered by flush, poll doesn't write.
+        assert_eq!(block_on(client.poll()).unwrap(), None);
+        assert!(client.io.output.is_empty());
+        block_on(client.flush()).unwrap();
+
+        assert_eq!(
+            block_on(client.poll()).unwrap(),
+            Some(Message::Text("Hello"))
+        );
+        assert_eq!(
+            block_on(client.poll()).unwrap(),
+            Some(Message::Binary(&[0xff, 0x00]))
+        );
+        assert_eq!(block_on(client.poll()).unwrap(), None);
+        block_on(client.flush()).unwrap();
+        assert!(matches!(
+            block_on(client.poll()),
+            Err(Error::Closed(Some(1000)))
+        ));
+        assert_eq!(
+            frames(&client.io.output),
+            [
+                (Opcode::Pong, true, b"hi".to_vec()),
+                (Opcode::Close, true, [0x03, 0xe8].to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fragment() {
+        let mut tx = [0; frame::MAX_HEADER_LEN + 5];
+        let mut rx = [0; 16];
+        let mut message = [0; 16];
+        let io = Script {
+            input: &[0x80, 0],
+            output: Vec::new(),
+        };
+        let mut client = Client::new(
+            io,
+            Zeros,
+            Duration::from_secs(0),
+            &mut tx,
+            &mut rx,
+            &mut message,
+        );
+
+        block_on(client.send_text("Hello world!")).unwrap();
+        assert_eq!(
+            frames(&client.io.output),
+            [
+                (Opcode::Text, false, b"Hello".to_vec()),
+                (Opcode::Continuation, false, b" worl".to_vec()),
+                (Opcode::Continuation, true, b"d!".to_vec()),
+            ]
+        );
+
+        // A continuation without a first fragment.
+        assert!(matches!(
+            block_on(client.poll()),
+            Err(Error::UnexpectedFragment)
+        ));
+    }
+}
@@ -0,0 +1,285 @@
+//! WebSocket frames (RFC 6455, section 5)
+//!
+//! Frames sent by a client are always masked, frames from the server never
+//! are. No extensions are negotiated, so the RSV bits must be clear.
+
+/// 2 bytes, 8 bytes of extended length and the masking key.
+pub const MAX_HEADER_LEN: usize = 14;
+
+/// Control frames carry at most this much.
+pub const MAX_CONTROL_LEN: usize = 125;
+
+const FIN: u8 = 0x80;
+const RSV: u8 = 0x70;
+const MASK: u8 = 0x80;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation = 0x0,
+    Text = 0x1,
+    Binary = 0x2,
+    Close = 0x8,
+    Ping = 0x9,
+    Pong = 0xa,
+}
+
+impl Opcode {
+    fn from_bits(bits: u8) -> Result<Self, Error> {
+        Ok(match bits {
+            0x0 => Opcode::Continuation,
+            0x1 => Opcode::Text,
+            0x2 => Opcode::Binary,
+            0x8 => Opcode::Close,
+            0x9 => Opcode::Ping,
+            0xa => Opcode::Pong,
+            _ => return Err(Error::UnknownOpcode(bits)),
+        })
+    }
+
+    pub fn is_control(self) -> bool {
+        self as u8 & 0x8 != 0
+    }
+}
+
+/// Errors
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The output buffer can't hold the frame
+    BufferTooSmall,
+    /// Reserved bits set without a negotiated extension
+    ReservedBits,
+    UnknownOpcode(u8),
+    /// A masked frame from the server
+    Masked,
+    /// A fragmented or too long control frame
+    InvalidControl,
+    /// A length that doesn't use the shortest encoding or overflows
+    InvalidLength,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub mask: Option<[u8; 4]>,
+    pub len: u64,
+}
+
+impl Header {
+    pub fn encoded_len(&self) -> usize {
+        let len = match self.len {
+            0..=125 => 2,
+            126..=0xffff => 4,
+            _ => 10,
+        };
+        if self.mask.is_some() { len + 4 } else { len }
+    }
+
+    /// Write the header into `buf`, returns its length.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let len = self.encoded_len();
+        let out = buf.get_mut(..len).ok_or(Error::BufferTooSmall)?;
+        out[0] = if self.fin { FIN } else { 0 } | self.opcode as u8;
+        let mask = if self.mask.is_some() { MASK } else { 0 };
+        let n = match self.len {
+            0..=125 => {
+                out[1] = mask | self.len as u8;
+                2
+            }
+            126..=0xffff => {
+                out[1] = mask | 126;
+                out[2..4].copy_from_slice(&(self.len as u16).to_be_bytes());
+                4
+            }
+            _ => {
+                out[1] = mask | 127;
+                out[2..10].copy_from_slice(&self.len.to_be_bytes());
+                10
+            }
+        };
+        if let Some(key) = self.mask {
+            out[n..n + 4].copy_from_slice(&key);
+        }
+        Ok(len)
+    }
+
+    /// Parse a header from the start of `buf`, returns it with its length,
+    /// or `None` if more bytes are needed.
+    pub fn parse(buf: &[u8]) -> Result<Option<(Self, usize)>, Error> {
+        let [first, second, ..] = *buf else {
+            return Ok(None);
+        };
+        if first & RSV != 0 {
+            return Err(Error::ReservedBits);
+        }
+        let fin = first & FIN != 0;
+        let opcode = Opcode::from_bits(first & 0x0f)?;
+
+        let (len, mut n) = match second & 0x7f {
+            126 => {
+                let Some(bytes) = buf.get(2..4) else {
+                    return Ok(None);
+                };
+                let len = u16::from_be_bytes(bytes.try_into().unwrap()) as u64;
+                if len < 126 {
+                    return Err(Error::InvalidLength);
+                }
+                (len, 4)
+            }
+            127 => {
+                let Some(bytes) = buf.get(2..10) else {
+                    return Ok(None);
+                };
+                let len = u64::from_be_bytes(bytes.try_into().unwrap());
+                if len <= 0xffff || len >> 63 != 0 {
+                    return Err(Error::InvalidLength);
+                }
+                (len, 10)
+            }
+            len => (len as u64, 2),
+        };
+
+        let mask = if second & MASK != 0 {
+            let Some(key) = buf.get(n..n + 4) else {
+                return Ok(None);
+            };
+            n += 4;
+            Some(key.try_into().unwrap())
+        } else {
+            None
+        };
+
+        if opcode.is_control() && (!fin || len > MAX_CONTROL_LEN as u64) {
+            return Err(Error::InvalidControl);
+        }
+
+        Ok(Some((
+            Self {
+                fin,
+                opcode,
+                mask,
+                len,
+            },
+            n,
+        )))
+    }
+}
+
+/// Mask or unmask `data` in place. `offset` is the position of `data` in
+/// the payload, for payloads handled in pieces.
+pub fn apply_mask(data: &mut [u8], key: [u8; 4], offset: usize) {
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte ^= key[(offset + i) % 4];
+    }
+}
+
+/// Write a whole frame, masking the payload with `mask` if set, returns its
+/// length.
+pub fn encode(
+    buf: &mut [u8],
+    fin: bool,
+    opcode: Opcode,
+    mask: Option<[u8; 4]>,
+    payload: &[u8],
+) -> Result<usize, Error> {
+    let header = Header {
+        fin,
+        opcode,
+        mask,
+        len: payload.len() as u64,
+    };
+    let n = header.encode(buf)?;
+    let out = buf
+        .get_mut(n..n + payload.len())
+        .ok_or(Error::BufferTooSmall)?;
+    out.copy_from_slice(payload);
+    if let Some(key) = mask {
+        apply_mask(out, key, 0);
+    }
+    Ok(n + payload.len())
+}
+
+/// Payload of a close frame: the status code followed by a UTF-8 reason.
+pub fn close_payload(buf: &mut [u8], code: u16, reason: &str) -> Result<usize, Error> {
+    let len = 2 + reason.len();
+    if len > MAX_CONTROL_LEN {
+        return Err(Error::InvalidControl);
+    }
+    let out = buf.get_mut(..len).ok_or(Error::BufferTooSmall)?;
+    out[..2].copy_from_slice(&code.to_be_bytes());
+    out[2..].copy_from_slice(reason.as_bytes());
+    Ok(len)
+}
+
+/// Status code of a close frame payload, `None` if it has none.
+pub fn close_code(payload: &[u8]) -> Option<u16> {
+    let code = payload.get(..2)?;
+    Some(u16::from_be_bytes([code[0], code[1]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame() {
+        // The masked "Hello" of RFC 6455, section 5.7.
+        let mut buf = [0; 16];
+        let key = [0x37, 0xfa, 0x21, 0x3d];
+        let n = encode(&mut buf, true, Opcode::Text, Some(key), b"Hello").unwrap();
+        assert_eq!(
+            buf[..n],
+            [
+                0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58
+            ]
+        );
+
+        let (header, len) = Header::parse(&buf[..n]).unwrap().unwrap();
+        assert_eq!(len, 6);
+        assert_eq!(header.opcode, Opcode::Text);
+        assert_eq!(header.mask, Some(key));
+        assert_eq!(header.len, 5);
+        apply_mask(&mut buf[len..n], key, 0);
+        assert_eq!(&buf[len..n], b"Hello");
+
+        // Unmasked fragments "Hel" and "lo", piece by piece.
+        assert_eq!(Header::parse(&[0x01]), Ok(None));
+        let (header, _) = Header::parse(&[0x01, 0x03, b'H']).unwrap().unwrap();
+        assert!(!header.fin);
+        let (header, _) = Header::parse(&[0x80, 0x02]).unwrap().unwrap();
+        assert_eq!((header.fin, header.opcode), (true, Opcode::Continuation));
+
+        // 256 bytes with a 16-bit length.
+        let header = Header {
+            fin: true,
+            opcode: Opcode::Binary,
+            mask: None,
+            len: 256,
+        };
+        assert_eq!(header.encode(&mut buf), Ok(4));
+        assert_eq!(buf[..4], [0x82, 0x7e, 0x01, 0x00]);
+        assert_eq!(Header::parse(&buf[..3]), Ok(None));
+        assert_eq!(Header::parse(&buf[..4]), Ok(Some((header, 4))));
+        assert_eq!(
+            Header::parse(&[0x82, 0x7e, 0x00, 0x05]),
+            Err(Error::InvalidLength)
+        );
+
+        assert_eq!(Header::parse(&[0xc1, 0x00]), Err(Error::ReservedBits));
+        assert_eq!(Header::parse(&[0x83, 0x00]), Err(Error::UnknownOpcode(3)));
+        assert_eq!(Header::parse(&[0x09, 0x00]), Err(Error::InvalidControl));
+        assert_eq!(
+            Header::parse(&[0x89, 0x7e, 0x00, 0x7e]),
+            Err(Error::InvalidControl)
+        );
+    }
+
+    #[test]
+    fn test_close() {
+        let mut buf = [0; 8];
+        let n = close_payload(&mut buf, 1000, "bye").unwrap();
+        assert_eq!(buf[..n], [0x03, 0xe8, b'b', b'y', b'e']);
+        assert_eq!(close_code(&buf[..n]), Some(1000));
+        assert_eq!(close_code(&[]), None);
+    }
+}
@@ -0,0 +1,200 @@
+//! The opening handshake, an HTTP/1.1 upgrade (RFC 6455, section 4)
+
+use core::fmt::Write;
+
+use sha1::{Digest, Sha1};
+
+/// Base64 of the 16 random bytes of `Sec-WebSocket-Key`.
+pub const KEY_LEN: usize = 24;
+
+/// Appended to the key before hashing it for `Sec-WebSocket-Accept`.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Errors
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The output buffer can't hold the request
+    BufferTooSmall,
+    /// Not an HTTP response
+    Malformed,
+    /// Any status but 101 Switching Protocols
+    Status(u16),
+    /// `Upgrade: websocket` or `Connection: Upgrade` is missing
+    NotUpgraded,
+    /// `Sec-WebSocket-Accept` doesn't match the key
+    InvalidAccept,
+}
+
+/// `Sec-WebSocket-Key` for 16 random bytes.
+pub fn key(random: [u8; 16]) -> [u8; KEY_LEN] {
+    let mut key = [0; KEY_LEN];
+    base64(&random, &mut key);
+    key
+}
+
+/// `Sec-WebSocket-Accept` the server answers `key` with.
+pub fn accept(key: &[u8]) -> [u8; 28] {
+    let mut sha1 = Sha1::new();
+    sha1.update(key);
+    sha1.update(GUID.as_bytes());
+    let mut accept = [0; 28];
+    base64(&sha1.finalize(), &mut accept);
+    accept
+}
+
+/// Write the upgrade request into `buf`, returns its length.
+pub fn encode_request(buf: &mut [u8], host: &str, path: &str, key: &[u8]) -> Result<usize, Error> {
+    let key = core::str::from_utf8(key).map_err(|_| Error::Malformed)?;
+    let mut cursor = Cursor { buf, len: 0 };
+    write!(
+        cursor,
+        "GET {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n",
+        path, host, key
+    )
+    .map_err(|_| Error::BufferTooSmall)?;
+    Ok(cursor.len)
+}
+
+/// Check the response head, up to and including the empty line, of the
+/// server to the request with `key`.
+pub fn check_response(head: &[u8], key: &[u8]) -> Result<(), Error> {
+    let head = core::str::from_utf8(head).map_err(|_| Error::Malformed)?;
+    let mut lines = head.split("\r\n");
+
+    let status = lines.next().ok_or(Error::Malformed)?;
+    let mut parts = status.splitn(3, ' ');
+    if !parts
+        .next()
+        .is_some_and(|version| version.starts_with("HTTP/1."))
+    {
+        return Err(Error::Malformed);
+    }
+    let code = parts
+        .next()
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or(Error::Malformed)?;
+    if code != 101 {
+        return Err(Error::Status(code));
+    }
+
+    let (mut upgrade, mut connection, mut accepted) = (false, false, false);
+    for line in lines.take_while(|line| !line.is_empty()) {
+        let (name, value) = line.split_once(':').ok_or(Error::Malformed)?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("upgrade") {
+            upgrade = value.eq_ignore_ascii_case("websocket");
+        } else if name.eq_ignore_ascii_case("connection") {
+            connection = value
+                .split(',')
+                .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+        } else if name.eq_ignore_ascii_case("sec-websocket-accept") {
+            if value.as_bytes() != accept(key) {
+                return Err(Error::InvalidAccept);
+            }
+            accepted = true;
+        }
+    }
+
+    if !upgrade || !connection {
+        return Err(Error::NotUpgraded);
+    }
+    if !accepted {
+        return Err(Error::InvalidAccept);
+    }
+    Ok(())
+}
+
+/// Standard base64 with padding, `out` must hold `4 * input.len().div_ceil(3)`.
+fn base64(input: &[u8], out: &mut [u8]) {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    for (chunk, out) in input.chunks(3).zip(out.chunks_mut(4)) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+            bits | (*byte as u32) << (16 - 8 * i)
+        });
+        for (i, out) in out.iter_mut().enumerate() {
+            *out = if i <= chunk.len() {
+                ALPHABET[(bits >> (18 - 6 * i)) as usize & 0x3f]
+            } else {
+                b'='
+            };
+        }
+    }
+}
+
+/// `core::fmt::Write` into a byte slice.
+struct Cursor<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for Cursor<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let out = self
+            .buf
+            .get_mut(self.len..self.len + s.len())
+            .ok_or(core::fmt::Error)?;
+        out.copy_from_slice(s.as_bytes());
+        self.len += s.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handshake() {
+        // The example of RFC 6455, section 1.3.
+        let sample = b"dGhlIHNhbXBsZSBub25jZQ==";
+        assert_eq!(&key(*b"the sample nonce"), sample);
+        assert_eq!(&accept(sample), b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+
+        let mut buf = [0; 256];
+        let n = encode_request(&mut buf, "dashboard.local", "/ws", sample).unwrap();
+        let request = core::str::from_utf8(&buf[..n]).unwrap();
+        assert!(request.starts_with("GET /ws HTTP/1.1\r\nHost: dashboard.local\r\n"));
+        assert!(request.contains("Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n"));
+        assert!(request.ends_with("\r\n\r\n"));
+        assert_eq!(
+            encode_request(&mut buf[..64], "dashboard.local", "/ws", sample),
+            Err(Error::BufferTooSmall)
+        );
+
+        let response = b"HTTP/1.1 101 Switching Protocols\r\n\
+                         Upgrade: websocket\r\n\
+                         Connection: keep-alive, Upgrade\r\n\
+                         Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+        assert_eq!(check_response(response, sample), Ok(()));
+        assert_eq!(
+            check_response(response, b"x3JJHMbDL1EzLkh9GBhXDw=="),
+            Err(Error::InvalidAccept)
+        );
+        assert_eq!(
+            check_response(b"HTTP/1.1 404 Not Found\r\n\r\n", sample),
+            Err(Error::Status(404))
+        );
+        assert_eq!(
+            check_response(
+                b"HTTP/1.1 101 Switching Protocols\r\n\
+                  Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n",
+                sample
+            ),
+            Err(Error::NotUpgraded)
+        );
+    }
+
+    #[test]
+    fn test_base64() {
+        let mut out = [0; 8];
+        base64(b"ab", &mut out[..4]);
+        assert_eq!(&out[..4], b"YWI=");
+        base64(b"abcd", &mut out);
+        assert_eq!(&out, b"YWJjZA==");
+    }
+}
@@ -0,0 +1,214 @@
+//! Real-time commands from the dashboard over a WebSocket
+//!
+//! The `websocket` task keeps a connection to [`PATH`] on the dashboard
+//! server, delivers the messages it pushes to [`INBOX`] and sends the ones
+//! other tasks put into [`OUTBOX`].
+//!
+//! `frame`, `handshake` and `client` don't depend on esp-hal or embassy-net,
+//! only on `embedded-io-async` and `embassy-time`, so their tests run on the
+//! host, see `host_tests`.
+//!
+//! With [`TLS`] set, the connection is wrapped with `crate::tls` and the
+//! server certificate must be issued by `tls::CA` for [`HOST`].
+
+use alloc::vec;
+
+use embassy_futures::select::{Either, select};
+use embassy_net::{Ipv4Address, Stack, tcp::TcpSocket};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Duration, Timer};
+use embedded_io_async::{Read, Write};
+use esp_hal::rng::Rng;
+use esp_println::println;
+use static_cell::ConstStaticCell;
+
+use crate::metrics::{Counter, Metric};
+use crate::status::{self, Wifi};
+use crate::tls::{self, HwRng};
+
+pub mod client;
+pub mod frame;
+pub mod handshake;
+
+pub use client::{Client, Error, Kind};
+
+pub const TLS: bool = false;
+pub const SERVER: (Ipv4Address, u16) = (
+    Ipv4Address::new(192, 168, 1, 100),
+    if TLS { 443 } else { 8080 },
+);
+/// Sent in the `Host` header, and as SNI with [`TLS`].
+pub const HOST: &str = "dashboard.local";
+pub const PATH: &str = "/devices/esp32-embassy";
+
+/// Ping after this long without sending anything.
+pub const PING_INTERVAL: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+pub const MAX_MESSAGE_LEN: usize = 512;
+
+/// A message received from, or to be sent to, the dashboard.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub kind: Kind,
+    pub payload: heapless::Vec<u8, MAX_MESSAGE_LEN>,
+}
+
+impl Message {
+    /// Returns `None` if the text is too long.
+    pub fn text(text: &str) -> Option<Self> {
+        Some(Self {
+            kind: Kind::Text,
+            payload: heapless::Vec::from_slice(text.as_bytes()).ok()?,
+        })
+    }
+
+    /// Returns `None` if the data is too long.
+    pub fn binary(data: &[u8]) -> Option<Self> {
+        Some(Self {
+            kind: Kind::Binary,
+            payload: heapless::Vec::from_slice(data).ok()?,
+        })
+    }
+}
+
+/// Connections that failed or were lost.
+static TCP_ERRORS: Counter = Counter::new("tcp_errors_total", "Failed connections and requests")
+    .with_labels("task=\"websocket\"");
+
+pub static METRICS: &[Metric] = &[Metric::Counter(&TCP_ERRORS)];
+
+/// Messages pushed by the dashboard.
+pub static INBOX: Channel<CriticalSectionRawMutex, Message, 4> = Channel::new();
+
+/// Messages other tasks want to send.
+pub static OUTBOX: Channel<CriticalSectionRawMutex, Message, 4> = Channel::new();
+
+#[embassy_executor::task]
+pub async fn websocket(stack: Stack<'static>, rng: Rng) {
+    // Static, too large for the task arena.
+    static RX_BUFFER: ConstStaticCell<[u8; 1536]> = ConstStaticCell::new([0; 1536]);
+    static TX_BUFFER: ConstStaticCell<[u8; 1536]> = ConstStaticCell::new([0; 1536]);
+    let rx_buffer = RX_BUFFER.take();
+    let tx_buffer = TX_BUFFER.take();
+    let mut network = status::STATUS.receiver().unwrap();
+
+    loop {
+        stack.wait_config_up().await;
+
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer[..], &mut tx_buffer[..]);
+        socket.set_timeout(Some(PING_INTERVAL * 2));
+
+        println!("websocket: connecting to {:?}", SERVER);
+        match socket.connect(SERVER).await {
+            Ok(()) => {
+                if let Either::Second(_) = select(
+                    run(&mut socket, rng),
+                    network.changed_and(|status| status.wifi != Wifi::Connected),
+                )
+                .await
+                {
+                    println!("websocket: wifi link lost");
+                }
+            }
+            Err(e) => {
+                println!("websocket: connect error: {:?}", e);
+                TCP_ERRORS.inc();
+            }
+        }
+
+        socket.abort();
+        Timer::after(RECONNECT_DELAY).await;
+    }
+}
+
+/// Run a session over the connected socket, through TLS if enabled.
+async fn run(socket: &mut TcpSocket<'_>, rng: Rng) {
+    if !TLS {
+        println!("websocket: session ended: {:?}", session(socket, rng).await);
+        return;
+    }
+
+    // Too large for the task arena, the buffers only live while connected.
+    let mut read_buffer = vec![0; tls::READ_BUFFER_LEN];
+    let mut write_buffer = vec![0; tls::WRITE_BUFFER_LEN];
+    match tls::connect(
+        socket,
+        HOST,
+        tls::CA,
+        rng,
+        &mut read_buffer,
+        &mut write_buffer,
+    )
+    .await
+    {
+        Ok(mut connection) => {
+            println!(
+                "websocket: session ended: {:?}",
+                session(&mut connection, rng).await
+            )
+        }
+        Err(e) => println!("websocket: TLS handshake failed: {:?}", e),
+    }
+}
+
+/// Run one connection until it fails or the server closes it.
+async fn session<T: Read + Write>(io: T, rng: Rng) -> Result<(), Error<T::Error>> {
+    let mut tx = [0; 512];
+    let mut rx = [0; 1024];
+    let mut message = [0; MAX_MESSAGE_LEN];
+
+    let mut client = Client::new(
+        io,
+        HwRng(rng),
+        PING_INTERVAL,
+        &mut tx,
+        &mut rx,
+        &mut message,
+    );
+    client.connect(HOST, PATH).await?;
+    println!("websocket: connected");
+
+    loop {
+        // Not raced, a control frame must not be cut short by a message.
+        client.flush().await?;
+
+        match select(client.poll(), OUTBOX.receive()).await {
+            Either::First(message) => {
+                let message = match message? {
+                    Some(client::Message::Text(text)) => Message::text(text),
+                    Some(client::Message::Binary(data)) => Message::binary(data),
+                    None => None,
+                };
+                if let Some(message) = message {
+                    if INBOX.try_send(message).is_err() {
+                        println!("websocket: inbox full, dropping message");
+                    }
+                }
+            }
+            Either::Second(message) => match message.kind {
+                Kind::Text => {
+                    // Only queued through `Message::text`.
+                    let text = core::str::from_utf8(&message.payload).unwrap_or_default();
+                    client.send_text(text).await?
+                }
+                Kind::Binary => client.send_binary(&message.payload).await?,
+            },
+        }
+    }
+}
+
+/// Print messages pushed by the dashboard.
+#[embassy_executor::task]
+pub async fn commands() {
+    loop {
+        let message = INBOX.receive().await;
+        match message.kind {
+            Kind::Text => println!(
+                "websocket: <- {}",
+                core::str::from_utf8(&message.payload).unwrap_or_default()
+            ),
+            Kind::Binary => println!("websocket: <- {:02x?}", message.payload),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Station configuration, disconnect reasons, and access point scans and
+//! reconnects on behalf of other tasks
+//!
+//! The `connection` task owns the `WifiController`, so anyone else asks it
+//! with [`scan`] or [`reconnect`]. Requests are served while connected, in
+//! between connection attempts a scan would have to wait for them anyway.
+//!
+//! [`station`] turns the `wifi` settings into the driver configuration,
+//! joins the access point and keeps the reason of every disconnect for
+//! [`last_disconnect`]. embassy_ble shares it.
+//!
+//! `reason` doesn't depend on esp-wifi and is tested on the host.
+
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::fmt::Write;
+
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex, signal::Signal};
+use embassy_time::{Duration, with_timeout};
+use esp_wifi::wifi::{AccessPointInfo, WifiController, WifiError};
+
+use crate::metrics::{Counter, Gauge, Metric};
+use crate::shell::{self, Command, Context, Reply};
+
+pub mod reason;
+pub mod station;
+
+pub use station::{
+    Disconnect, RECONNECT_DELAY, configuration, connect, init, last_disconnect, lost, skip_scan,
+};
+
+/// Most access points reported by a scan.
+pub const MAX_RESULTS: usize = 16;
+
+const SCAN_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug)]
+pub enum Error {
+    Wifi(WifiError),
+    /// The `connection` task didn't get to it, e.g. while connecting
+    Timeout,
+}
+
+/// Held during [`scan`], so results can't get mixed up.
+static SCANNING: Mutex<CriticalSectionRawMutex, ()> = Mutex::new(());
+static REQUEST: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static RECONNECT: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static RESULT: Signal<CriticalSectionRawMutex, Result<Vec<AccessPointInfo>, WifiError>> =
+    Signal::new();
+
+/// Connections lost, counted by the `connection` task.
+pub static RECONNECTS: Counter = Counter::new(
+    "wifi_reconnects_total",
+    "Connections to the access point lost",
+);
+static RSSI: Gauge = Gauge::computed(
+    "wifi_rssi_dbm",
+    "Signal strength of the access point",
+    || crate::rssi().map(i64::from),
+);
+
+pub static METRICS: &[Metric] = &[Metric::Counter(&RECONNECTS), Metric::Gauge(&RSSI)];
+
+pub static COMMANDS: &[Command<Context>] = &[Command::new(
+    "wifi scan",
+    "",
+    "list access points nearby",
+    scan_command,
+)];
+
+/// Scan for access points, strongest first.
+pub async fn scan() -> Result<Vec<AccessPointInfo>, Error> {
+    let _guard = SCANNING.lock().await;
+
+    // A late result of a scan that timed out.
+    RESULT.reset();
+    REQUEST.signal(());
+    match with_timeout(SCAN_TIMEOUT, RESULT.wait()).await {
+        Ok(result) => {
+            let mut access_points = result.map_err(Error::Wifi)?;
+            access_points.sort_by_key(|ap| -(ap.signal_strength as i16));
+            Ok(access_points)
+        }
+        Err(_) => {
+            REQUEST.reset();
+            Err(Error::Timeout)
+        }
+    }
+}
+
+/// Wait for a [`scan`] request, for the `connection` task.
+pub async fn requested() {
+    REQUEST.wait().await
+}
+
+/// Drop the association and connect again, e.g. when the access point
+/// stopped forwarding traffic without disconnecting us.
+pub fn reconnect() {
+    RECONNECT.signal(());
+}
+
+/// Wait for a [`reconnect`] request, for the `connection` task.
+pub async fn reconnect_requested() {
+    RECONNECT.wait().await
+}
+
+/// Run the requested scan, for the `connection` task.
+pub async fn serve(controller: &mut WifiController<'static>) {
+    RESULT.signal(controller.scan_n_async(MAX_RESULTS).await);
+}
+
+fn scan_command<'a>(_: &'a Context, args: &'a [&'a str], out: &'a mut String) -> Reply<'a> {
+    Box::pin(async move {
+        if !args.is_empty() {
+            return Err(shell::Error::Usage);
+        }
+        match scan().await {
+            Ok(access_points) => {
+                for ap in access_points {
+                    writeln!(
+                        out,
+                        "{:>4} dBm  ch {:>2}  {:02x?}  {:?}  {}",
+                        ap.signal_strength, ap.channel, ap.bssid, ap.auth_method, ap.ssid
+                    )
+                    .ok();
+                }
+                Ok(())
+            }
+            Err(Error::Timeout) => Err(shell::Error::Failed("timed out")),
+            Err(Error::Wifi(_)) => Err(shell::Error::Failed("scan failed")),
+        }
+    })
+}
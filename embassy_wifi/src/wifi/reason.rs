@@ -0,0 +1,61 @@
+//! Reason codes of the disconnect events of the Wi-Fi driver
+//!
+//! 802.11 reason codes (IEEE 802.11-2020, table 9-49) and the ones ESP-IDF
+//! adds from 200 on for failures that never got to an association.
+
+/// What a disconnect reason means, for reporting it.
+pub fn describe(reason: u16) -> &'static str {
+    match reason {
+        2 => "authentication expired",
+        3 | 8 => "the access point left",
+        4 => "disassociated for inactivity",
+        5 => "the access point has too many stations",
+        13 => "invalid information element",
+        14 => "message integrity check failed",
+        15 | 204 => "handshake timed out, wrong password?",
+        16 => "group key update timed out",
+        17 => "security settings changed during the handshake",
+        18 => "group cipher not supported",
+        19 => "pairwise cipher not supported",
+        20 => "key management not supported, e.g. SAE or 802.1X",
+        21 | 22 => "unsupported RSN settings",
+        23 => "802.1X authentication failed, check the EAP credentials",
+        24 => "cipher suite rejected",
+        200 => "beacons lost",
+        201 => "access point not found",
+        202 => "authentication failed",
+        203 => "association failed",
+        205 => "connection failed",
+        209 => "protected management frames query timed out",
+        210 => "no access point with a compatible security mode",
+        211 => "access point offers a weaker security mode than configured",
+        212 => "access point signal too weak",
+        _ => "disconnected",
+    }
+}
+
+/// Whether the access point turned down the credentials or the
+/// authentication method, so trying again the same way won't help.
+pub fn is_rejection(reason: u16) -> bool {
+    matches!(reason, 2 | 15 | 17..=24 | 202 | 204 | 210 | 211)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reason() {
+        assert_eq!(describe(201), "access point not found");
+        assert_eq!(
+            describe(210),
+            "no access point with a compatible security mode"
+        );
+        assert_eq!(describe(999), "disconnected");
+        assert!(is_rejection(15));
+        assert!(is_rejection(23));
+        assert!(is_rejection(211));
+        assert!(!is_rejection(201));
+        assert!(!is_rejection(200));
+    }
+}
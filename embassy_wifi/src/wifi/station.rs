@@ -0,0 +1,172 @@
+//! Joining an access point and telling why it failed
+//!
+//! The driver configuration for the `wifi` settings, and connecting with
+//! the reason of every disconnect kept, so a wrong password or an
+//! authentication method the access point doesn't offer can be told from
+//! a missing access point. embassy_ble has copies of this file and
+//! `reason.rs`, so it only depends on esp-wifi, the settings and `reason`.
+
+use alloc::vec::Vec;
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::Duration;
+use esp_println::println;
+use esp_wifi::wifi::{
+    AuthMethod, ClientConfiguration, Configuration, EapClientConfiguration, TtlsPhase2Method,
+    WifiController,
+    event::{EventExt, StaDisconnected},
+};
+use serde::Serialize;
+
+use super::reason;
+use crate::settings::{self, Auth, Eap};
+
+/// How long to wait before joining again after losing the access point or
+/// failing to join it.
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Name of the configured authentication method, for [`Disconnect`].
+static METHOD: Mutex<CriticalSectionRawMutex, Cell<&'static str>> = Mutex::new(Cell::new(""));
+static DISCONNECT: Mutex<CriticalSectionRawMutex, Cell<Option<Disconnect>>> =
+    Mutex::new(Cell::new(None));
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct Disconnect {
+    /// Reason code of the driver
+    pub reason: u16,
+    pub message: &'static str,
+    /// The configured authentication method
+    pub method: &'static str,
+    /// The access point turned down the credentials or the method
+    pub rejected: bool,
+}
+
+/// Start keeping the reasons of disconnects, before connecting.
+pub fn init() {
+    StaDisconnected::update_handler(|event| {
+        let reason = event.reason() as u16;
+        let disconnect = Disconnect {
+            reason,
+            message: reason::describe(reason),
+            method: METHOD.lock(Cell::get),
+            rejected: reason::is_rejection(reason),
+        };
+        DISCONNECT.lock(|cell| cell.set(Some(disconnect)));
+    });
+}
+
+/// Why the last connection attempt failed or the association was lost.
+pub fn last_disconnect() -> Option<Disconnect> {
+    DISCONNECT.lock(Cell::get)
+}
+
+/// Connect with the configuration set before, printing why it failed.
+pub async fn connect(controller: &mut WifiController<'static>) -> Result<(), Option<Disconnect>> {
+    match controller.connect_async().await {
+        Ok(()) => {
+            println!("Wifi connected!");
+            Ok(())
+        }
+        Err(e) => {
+            println!("Failed to connect to wifi: {e:?}");
+            let disconnect = last_disconnect();
+            match disconnect {
+                Some(disconnect) if disconnect.rejected => println!(
+                    "The access point rejected {}: {} (reason {})",
+                    disconnect.method, disconnect.message, disconnect.reason
+                ),
+                Some(disconnect) => {
+                    println!("{} (reason {})", disconnect.message, disconnect.reason)
+                }
+                None => {}
+            }
+            Err(disconnect)
+        }
+    }
+}
+
+/// Print and return why the association was lost, after the
+/// `StaDisconnected` event.
+pub fn lost() -> Option<Disconnect> {
+    let disconnect = last_disconnect();
+    if let Some(disconnect) = disconnect {
+        println!(
+            "Disconnected: {} (reason {})",
+            disconnect.message, disconnect.reason
+        );
+    }
+    disconnect
+}
+
+/// Driver configuration to join the access point of `wifi`. The driver
+/// keeps pointers to the certificates, so they are leaked.
+pub fn configuration(wifi: &settings::Wifi) -> Configuration {
+    METHOD.lock(|cell| cell.set(wifi.auth.name()));
+    let client = |auth_method, password: &str| {
+        Configuration::Client(ClientConfiguration {
+            ssid: wifi.ssid.clone(),
+            password: password.into(),
+            auth_method,
+            ..Default::default()
+        })
+    };
+    match &wifi.auth {
+        Auth::Open => client(AuthMethod::None, ""),
+        Auth::Wpa2Personal { password } => client(AuthMethod::WPA2Personal, password),
+        Auth::Wpa3Personal { password } => client(AuthMethod::WPA3Personal, password),
+        Auth::Wpa2Wpa3Personal { password } => client(AuthMethod::WPA2WPA3Personal, password),
+        // WPA3-Enterprise access points are joined the same way, the driver
+        // negotiates the protected management frames they require.
+        Auth::Enterprise(enterprise) => Configuration::EapClient(EapClientConfiguration {
+            ssid: wifi.ssid.clone(),
+            auth_method: AuthMethod::WPA2Enterprise,
+            identity: enterprise
+                .identity
+                .clone()
+                .or_else(|| enterprise.username.clone()),
+            username: enterprise.username.clone(),
+            password: enterprise.password.clone(),
+            ca_cert: enterprise.ca_cert.as_deref().map(leak_pem),
+            certificate_and_key: enterprise
+                .client_cert
+                .as_deref()
+                .zip(enterprise.client_key.as_deref())
+                .map(|(cert, key)| {
+                    (
+                        leak_pem(cert),
+                        leak_pem(key),
+                        enterprise
+                            .client_key_password
+                            .clone()
+                            .map(|password| &*password.into_bytes().leak()),
+                    )
+                }),
+            ttls_phase2_method: (enterprise.eap == Eap::Ttls).then_some(TtlsPhase2Method::Mschapv2),
+            ..Default::default()
+        }),
+    }
+}
+
+/// mbedTLS wants PEM with a terminating NUL.
+fn leak_pem(pem: &str) -> &'static [u8] {
+    let mut bytes = Vec::with_capacity(pem.len() + 1);
+    bytes.extend_from_slice(pem.as_bytes());
+    bytes.push(0);
+    bytes.leak()
+}
+
+/// Join the access point `bssid` on `channel` without scanning for it.
+pub fn skip_scan(configuration: &mut Configuration, bssid: [u8; 6], channel: u8) {
+    match configuration {
+        Configuration::Client(config) => {
+            config.bssid = Some(bssid);
+            config.channel = Some(channel);
+        }
+        Configuration::EapClient(config) => {
+            config.bssid = Some(bssid);
+            config.channel = Some(channel);
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,6 @@
+//! Unit tests of embassy_wifi and embassy_ble on the host
+//!
+//! The examples only build for the chip, so the modules that don't depend
+//! on esp-hal, esp-wifi, embassy-net or bleps are included with `#[path]`
+//! by `tests/embassy_wifi.rs` and `tests/embassy_ble.rs`, each with the
+//! module tree of its crate. Run them with `cargo test`.
@@ -0,0 +1,33 @@
+//! Files that an example keeps a copy of, so that every example builds on
+//! its own. The copies must stay the same as the original.
+
+/// The original and the copy, relative to this file.
+macro_rules! copies {
+    ($(($original:literal, $copy:literal)),* $(,)?) => {
+        &[$(($original, include_str!($original), $copy, include_str!($copy))),*]
+    };
+}
+
+static COPIES: &[(&str, &str, &str, &str)] = copies![
+    ("../../embassy_wifi/src/csi/format.rs", "../../csi_decode/src/format.rs"),
+    ("../../i2c-sht31/src/sht3x.rs", "../../embassy_ble/src/sht3x.rs"),
+    ("../../embassy_wifi/src/mem_flash.rs", "../../embassy_ble/src/mem_flash.rs"),
+    ("../../embassy_wifi/src/ota/partition.rs", "../../embassy_ble/src/ota/partition.rs"),
+    ("../../embassy_wifi/src/settings.rs", "../../embassy_ble/src/settings.rs"),
+    ("../../embassy_wifi/src/shell/command.rs", "../../embassy_ble/src/console/command.rs"),
+    ("../../embassy_wifi/src/shell/line.rs", "../../embassy_ble/src/console/line.rs"),
+    ("../../embassy_wifi/src/wifi/reason.rs", "../../embassy_ble/src/wifi/reason.rs"),
+    ("../../embassy_wifi/src/wifi/station.rs", "../../embassy_ble/src/wifi/station.rs"),
+];
+
+#[test]
+fn test_copies() {
+    for (original, original_text, copy, copy_text) in COPIES {
+        assert!(
+            original_text == copy_text,
+            "{} differs from {}, copy it again",
+            copy,
+            original
+        );
+    }
+}
@@ -0,0 +1,37 @@
+//! The modules of embassy_ble that are tested on the host, in the module
+//! tree of the crate so that `crate::` paths resolve.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+#[path = "../../embassy_ble/src/bas.rs"]
+mod bas;
+
+#[path = "../../embassy_ble/src/dis.rs"]
+mod dis;
+
+#[path = "../../embassy_ble/src/ess.rs"]
+mod ess;
+
+#[path = "../../embassy_ble/src/sht3x.rs"]
+mod sht3x;
+
+#[path = "../../embassy_ble/src/subscriptions.rs"]
+mod subscriptions;
+
+#[path = "../../embassy_ble/src/value.rs"]
+mod value;
+
+#[path = "../../embassy_ble/src/console"]
+mod console {
+    pub mod command;
+    pub mod line;
+    pub mod outbox;
+}
+
+#[path = "../../embassy_ble/src/provisioning"]
+mod provisioning {
+    pub mod protocol;
+    pub mod session;
+}
@@ -0,0 +1,116 @@
+//! The modules of embassy_wifi that are tested on the host, in the module
+//! tree of the crate so that `crate::` paths resolve.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+
+#[path = "../../embassy_wifi/src/clock.rs"]
+mod clock;
+
+#[path = "../../embassy_wifi/src/io.rs"]
+mod io;
+
+#[path = "../../embassy_wifi/src/mem_flash.rs"]
+mod mem_flash;
+
+#[path = "../../embassy_wifi/src/settings.rs"]
+mod settings;
+
+#[path = "../../embassy_wifi/src/coap"]
+mod coap {
+    pub mod message;
+    pub mod block;
+    pub mod server;
+}
+
+#[path = "../../embassy_wifi/src/espnow"]
+mod espnow {
+    pub mod envelope;
+    pub mod dedup;
+}
+
+#[path = "../../embassy_wifi/src/health"]
+mod health {
+    pub mod icmp;
+    pub mod stats;
+}
+
+#[path = "../../embassy_wifi/src/http"]
+mod http {
+    pub mod request;
+    pub mod response;
+    pub mod router;
+}
+
+#[path = "../../embassy_wifi/src/mdns"]
+mod mdns {
+    pub mod packet;
+    pub mod responder;
+}
+
+#[path = "../../embassy_wifi/src/metrics"]
+mod metrics {
+    pub mod metric;
+    pub mod registry;
+}
+
+#[path = "../../embassy_wifi/src/modbus"]
+mod modbus {
+    pub mod frame;
+    pub mod map;
+}
+
+#[path = "../../embassy_wifi/src/mqtt"]
+mod mqtt {
+    pub mod packet;
+    pub mod client;
+}
+
+#[path = "../../embassy_wifi/src/ota"]
+mod ota {
+    pub mod partition;
+    pub mod otadata;
+    pub mod updater;
+    pub mod download;
+}
+
+#[path = "../../embassy_wifi/src/power"]
+mod power {
+    pub mod retained;
+}
+
+#[path = "../../embassy_wifi/src/shell"]
+mod shell {
+    pub mod command;
+    pub mod line;
+}
+
+#[path = "../../embassy_wifi/src/sntp"]
+mod sntp {
+    pub mod packet;
+}
+
+#[path = "../../embassy_wifi/src/syslog"]
+mod syslog {
+    pub mod message;
+    pub mod limit;
+}
+
+#[path = "../../embassy_wifi/src/tls"]
+mod tls {
+    pub mod x509;
+    pub mod verify;
+}
+
+#[path = "../../embassy_wifi/src/websocket"]
+mod websocket {
+    pub mod frame;
+    pub mod handshake;
+    pub mod client;
+}
+
+#[path = "../../embassy_wifi/src/wifi"]
+mod wifi {
+    pub mod reason;
+}
@@ -1,6 +1,8 @@
 use bitflags::bitflags;
 use embedded_hal::delay::DelayNs;
 use embedded_hal::i2c::I2c;
+use embedded_hal_async::delay::DelayNs as AsyncDelayNs;
+use embedded_hal_async::i2c::I2c as AsyncI2c;
 
 #[derive(Debug, Clone)]
 pub struct Sht3x<D> {
@@ -16,12 +18,14 @@ const SOFT_RESET_TIME_MS: u8 = 1;
 // 4: Operation and Communication
 const COMMAND_WAIT_TIME_MS: u8 = 1;
 
-impl<D: DelayNs> Sht3x<D> {
+impl<D> Sht3x<D> {
     /// Creates a new driver.
     pub const fn new(address: Address, delay: D) -> Self {
         Self { address, delay }
     }
+}
 
+impl<D: DelayNs> Sht3x<D> {
     /// Send an I2C command.
     fn command<I2C: I2c>(
         &mut self,
@@ -49,14 +53,7 @@ impl<D: DelayNs> Sht3x<D> {
         self.command(i2c, Command::SingleShot(cs, rpt), Some(rpt.max_duration()))?;
         let mut buf = [0; 6];
         i2c.read(self.address as u8, &mut buf).map_err(Error::I2c)?;
-
-        let temperature = check_crc::<I2C>([buf[0], buf[1]], buf[2]).map(convert_temperature)?;
-        let humidity = check_crc::<I2C>([buf[3], buf[4]], buf[5]).map(convert_humidity)?;
-
-        Ok(Measurement {
-            temperature,
-            humidity,
-        })
+        parse_measurement(buf)
     }
 
     /// Soft reset the sensor.
@@ -70,7 +67,7 @@ impl<D: DelayNs> Sht3x<D> {
         let mut buf = [0; 3];
         i2c.read(self.address as u8, &mut buf).map_err(Error::I2c)?;
 
-        let status = check_crc::<I2C>([buf[0], buf[1]], buf[2])?;
+        let status = check_crc([buf[0], buf[1]], buf[2])?;
         Ok(Status::from_bits_truncate(status))
     }
 
@@ -80,6 +77,55 @@ impl<D: DelayNs> Sht3x<D> {
     }
 }
 
+/// The same with async I2C and delay, which let other tasks run meanwhile.
+impl<D: AsyncDelayNs> Sht3x<D> {
+    /// Send an I2C command.
+    async fn command_async<I2C: AsyncI2c>(
+        &mut self,
+        i2c: &mut I2C,
+        command: Command,
+        wait_time: Option<u8>,
+    ) -> Result<(), Error<I2C::Error>> {
+        let cmd_bytes = command.value().to_be_bytes();
+        i2c.write(self.address as u8, &cmd_bytes)
+            .await
+            .map_err(Error::I2c)?;
+
+        self.delay
+            .delay_ms(wait_time.unwrap_or(0).max(COMMAND_WAIT_TIME_MS).into())
+            .await;
+
+        Ok(())
+    }
+
+    /// Take a temperature and humidity measurement.
+    pub async fn measure_async<I2C: AsyncI2c>(
+        &mut self,
+        i2c: &mut I2C,
+        cs: ClockStretch,
+        rpt: Repeatability,
+    ) -> Result<Measurement, Error<I2C::Error>> {
+        self.command_async(i2c, Command::SingleShot(cs, rpt), Some(rpt.max_duration()))
+            .await?;
+        let mut buf = [0; 6];
+        i2c.read(self.address as u8, &mut buf)
+            .await
+            .map_err(Error::I2c)?;
+        parse_measurement(buf)
+    }
+}
+
+/// Temperature, humidity and their CRCs, as read after a measurement.
+fn parse_measurement<E>(buf: [u8; 6]) -> Result<Measurement, Error<E>> {
+    let temperature = check_crc([buf[0], buf[1]], buf[2]).map(convert_temperature)?;
+    let humidity = check_crc([buf[3], buf[4]], buf[5]).map(convert_humidity)?;
+
+    Ok(Measurement {
+        temperature,
+        humidity,
+    })
+}
+
 const fn convert_temperature(raw: u16) -> i32 {
     -4500 + (17500 * raw as i32) / 65535
 }
@@ -89,7 +135,7 @@ const fn convert_humidity(raw: u16) -> u16 {
 }
 
 /// Compare the CRC of the input array to the given CRC checksum.
-fn check_crc<I2C: I2c>(data: [u8; 2], crc: u8) -> Result<u16, Error<I2C::Error>> {
+fn check_crc<E>(data: [u8; 2], crc: u8) -> Result<u16, Error<E>> {
     let calculated_crc = crc8(data);
 
     if calculated_crc == crc {
@@ -254,7 +300,7 @@ impl Command {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Measurement {
     pub temperature: i32,
     pub humidity: u16,
@@ -289,4 +335,58 @@ mod tests {
     fn test_crc() {
         assert_eq!(crc8([0xBE, 0xEF]), 0x92);
     }
+
+    /// Answers every read with a measurement.
+    struct Bus([u8; 6]);
+
+    impl embedded_hal::i2c::ErrorType for Bus {
+        type Error = embedded_hal::i2c::ErrorKind;
+    }
+
+    impl AsyncI2c for Bus {
+        async fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [embedded_hal::i2c::Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            assert_eq!(address, Address::Low as u8);
+            for operation in operations {
+                if let embedded_hal::i2c::Operation::Read(buf) = operation {
+                    buf.copy_from_slice(&self.0[..buf.len()]);
+                }
+            }
+            Ok(())
+        }
+    }
+
+    struct NoDelay;
+
+    impl AsyncDelayNs for NoDelay {
+        async fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn test_measure_async() {
+        let mut sht3x = Sht3x::new(Address::Low, NoDelay);
+        let mut measure = |data| {
+            embassy_futures::block_on(sht3x.measure_async(
+                &mut Bus(data),
+                ClockStretch::Enabled,
+                Repeatability::High,
+            ))
+        };
+
+        let measurement = measure([0x66, 0x66, 0x93, 0x80, 0x00, 0xA2]).unwrap();
+        assert_eq!(
+            measurement,
+            Measurement {
+                temperature: 2500,
+                humidity: 5000
+            }
+        );
+        assert!(matches!(
+            measure([0x66, 0x66, 0x93, 0x80, 0x00, 0xA3]),
+            Err(Error::Crc)
+        ));
+    }
 }